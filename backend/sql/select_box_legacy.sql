@@ -0,0 +1,6 @@
+select idb, date, unread, sender, recipient, subject, content, attachments, source, security
+from emails.boxes
+where idu=$1 and source is null and message_id is null
+  and sender->>'address'=$2 and subject=$3
+  and date between $4 and $5
+;
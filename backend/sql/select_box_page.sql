@@ -1,7 +1,7 @@
-select idb, date, unread, sender, recipient, subject, content, attachments
+select idb, date, unread, sender, recipient, subject, content, attachments, source, security
 from emails.boxes
 where idu=$1 and box=$2
 order by date desc
-offset $3
-limit $4
+offset $3 * coalesce((select by_page from emails.users where idu=$1), $4)
+limit coalesce((select by_page from emails.users where idu=$1), $4)
 ;
\ No newline at end of file
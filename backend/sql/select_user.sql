@@ -1,4 +1,6 @@
-select name, signature, email
+select name, signature, email, display_name, by_page, timezone, default_box,
+       exists(select 1 from emails.smime_certs c where c.idu=emails.users.idu) as smime,
+       exists(select 1 from emails.pgp_keys k where k.idu=emails.users.idu and k.secret is not null) as pgp
 from emails.users
 where idu=$1 -- 1|2
 ;
\ No newline at end of file
@@ -1,4 +1,4 @@
-select idu
+select idu, password
 from emails.users
-where email=$1 and name=$2 and password=$3
+where email=$1 and name=$2
 ;
\ No newline at end of file
@@ -11,3 +11,120 @@ alter table emails.boxes alter column attachments drop not null;
 alter table emails.boxes alter column attachments drop default;
 update emails.boxes set attachments=null where attachments='{}'::jsonb;
 --
+-- emails.boxes: исходный файл письма и Message-ID
+alter table emails.boxes add column if not exists source text;
+alter table emails.boxes add column if not exists message_id text;
+create index if not exists boxes_idu_source on emails.boxes (idu, source);
+create index if not exists boxes_idu_message_id on emails.boxes (idu, message_id);
+--
+-- emails.identities: адреса отправителя пользователя
+create table if not exists emails.identities
+(
+    idi       serial primary key,
+    idu       integer not null,
+    email     text    not null,
+    name      text    not null default '',
+    signature text    not null default '',
+    reply_to  text,
+    position  integer not null default 0,
+    unique (idu, email)
+);
+insert into emails.identities (idu, email, name, signature)
+select idu, email, name, signature
+from emails.users
+on conflict (idu, email) do nothing;
+--
+-- emails.users: настройки пользователя
+alter table emails.users add column if not exists display_name text not null default '';
+alter table emails.users add column if not exists by_page integer not null default 30;
+alter table emails.users add column if not exists timezone text not null default '';
+alter table emails.users add column if not exists default_box integer not null default 0;
+--
+-- emails.sessions: сеансы пользователей, переживают перезапуск
+create table if not exists emails.sessions
+(
+    ids         serial primary key,
+    idu         integer     not null,
+    token       text        not null unique,
+    created     timestamptz not null default now(),
+    last_active timestamptz not null default now(),
+    user_agent  text        not null default '',
+    ip          text        not null default ''
+);
+create index if not exists sessions_idu on emails.sessions (idu);
+--
+-- emails.logins: журнал попыток входа по паролю
+create table if not exists emails.logins
+(
+    idl        serial primary key,
+    idu        integer,
+    email      text        not null default '',
+    created    timestamptz not null default now(),
+    ip         text        not null default '',
+    user_agent text        not null default '',
+    result     text        not null default ''
+);
+create index if not exists logins_email on emails.logins (email, created);
+create index if not exists logins_ip on emails.logins (ip, created);
+create index if not exists logins_idu on emails.logins (idu, created);
+--
+-- emails.users: двухфакторная аутентификация (TOTP)
+alter table emails.users add column if not exists totp_secret text;
+alter table emails.users add column if not exists totp_pending text;
+alter table emails.users add column if not exists totp_last bigint not null default 0;
+create table if not exists emails.recovery_codes
+(
+    idr  serial primary key,
+    idu  integer not null,
+    code text    not null
+);
+create index if not exists recovery_codes_idu on emails.recovery_codes (idu);
+--
+-- emails.blobs: файлы вложений по хешу содержимого; refs -- число ссылок из писем
+create table if not exists emails.blobs
+(
+    hash text    primary key,
+    size bigint  not null default 0,
+    refs integer not null default 0
+);
+--
+-- emails.crypt_keys: ключи данных, зашифрованные мастер-ключом из env.json; idu null -- ключ хранилища вложений
+create table if not exists emails.crypt_keys
+(
+    idk     serial primary key,
+    idu     integer,
+    master  text        not null,
+    wrapped text        not null,
+    created timestamptz not null default now()
+);
+create index if not exists crypt_keys_idu on emails.crypt_keys (idu, idk);
+--
+-- emails.boxes.security: подпись и шифрование письма (pgp-encrypted, pgp-signed), для отметки в просмотре
+alter table emails.boxes add column if not exists security text;
+--
+-- emails.smime_certs: сертификат S/MIME пользователя с цепочкой и закрытый ключ (зашифрован, если настроено шифрование)
+create table if not exists emails.smime_certs
+(
+    idu     integer primary key,
+    cert    text        not null,
+    key     text        not null,
+    created timestamptz not null default now()
+);
+--
+-- emails.boxes.search: полнотекстовый индекс по теме, адресам и тексту; строится из открытого текста и при шифровании,
+-- для записанных раньше писем -- "search reindex"
+alter table emails.boxes add column if not exists search tsvector;
+create index if not exists boxes_search on emails.boxes using gin (search);
+--
+-- emails.pgp_keys: ключи OpenPGP пользователя -- свои (с закрытой частью, только при настроенном шифровании) и получателей
+create table if not exists emails.pgp_keys
+(
+    idu         integer,
+    fingerprint text        not null,
+    emails      text[]      not null,
+    cert        text        not null,
+    secret      text,
+    created     timestamptz not null default now(),
+    primary key (idu, fingerprint)
+);
+--
@@ -0,0 +1,262 @@
+use bytes::Bytes;
+use chrono::{Datelike, Local, Timelike};
+use futures_util::StreamExt;
+use warp::hyper::Body;
+use warp::hyper::body::Sender;
+
+use crate::storage::Storage;
+
+// без сжатия: вложения -- в основном pdf и картинки, которые уже сжаты
+const METHOD_STORED: u16 = 0;
+// размеры и CRC идут после данных; имена в UTF-8
+const FLAGS: u16 = 0x0008 | 0x0800;
+const VERSION: u16 = 20;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |c, b| CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+// имена файлов в архиве: без путей, повторы получают номер -- "счет.pdf", "счет (2).pdf"
+pub fn archive_names(names: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        let name = name.trim().replace(['/', '\\'], "_");
+        let name = if name.is_empty() || name.chars().all(|c| c == '.') { "file".to_string() } else { name };
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{ext}")),
+            _ => (name.clone(), "".to_string())
+        };
+        let mut candidate = name.clone();
+        let mut ind = 2;
+        while result.iter().any(|known| known.to_lowercase() == candidate.to_lowercase()) {
+            candidate = format!("{stem} ({ind}){ext}");
+            ind += 1;
+        }
+        result.push(candidate);
+    }
+    result
+}
+
+pub struct ArchiveFile {
+    pub storage: &'static dyn Storage,
+    pub key: String,
+    pub name: String,
+}
+
+// архив собирается на лету: файлы читаются кусками и сразу уходят клиенту
+pub fn archive_body(files: Vec<ArchiveFile>) -> Body {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(err) = archive_write(&mut sender, files).await {
+            tracing::warn!("archive_body: {err}");
+            sender.abort();
+        }
+    });
+    body
+}
+
+async fn archive_write(sender: &mut Sender, files: Vec<ArchiveFile>) -> Result<(), String> {
+    let now = Local::now();
+    let time = ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16;
+    let date = (((now.year() - 1980).max(0) as u32) << 9 | (now.month() << 5) | now.day()) as u16;
+
+    let mut central = Vec::new();
+    let mut offset = 0u32;
+    let mut count = 0u16;
+    for file in files.iter() {
+        // без одного из файлов архив неполный -- обрываем ответ, чтобы клиент не принял его за целый
+        let mut stream = file.storage.get(&file.key, None).await.map_err(|err| format!("{} {err}", file.key))?;
+        let name = file.name.as_bytes();
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        put_u32(&mut header, 0x04034b50);
+        put_u16(&mut header, VERSION);
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, METHOD_STORED);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, 0);
+        header.extend_from_slice(name);
+        let header_len = header.len() as u32;
+        send(sender, header).await?;
+
+        let mut crc = 0u32;
+        let mut size = 0u32;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| err.to_string())?;
+            crc = crc_update(crc, &chunk);
+            size = u32::try_from(chunk.len()).ok().and_then(|len| size.checked_add(len)).ok_or("file too large")?;
+            send(sender, chunk).await?;
+        }
+
+        let mut descriptor = Vec::with_capacity(16);
+        put_u32(&mut descriptor, 0x08074b50);
+        put_u32(&mut descriptor, crc);
+        put_u32(&mut descriptor, size);
+        put_u32(&mut descriptor, size);
+        send(sender, descriptor).await?;
+
+        put_u32(&mut central, 0x02014b50);
+        put_u16(&mut central, VERSION);
+        put_u16(&mut central, VERSION);
+        put_u16(&mut central, FLAGS);
+        put_u16(&mut central, METHOD_STORED);
+        put_u16(&mut central, time);
+        put_u16(&mut central, date);
+        put_u32(&mut central, crc);
+        put_u32(&mut central, size);
+        put_u32(&mut central, size);
+        put_u16(&mut central, name.len() as u16);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, 0);
+        put_u32(&mut central, 0);
+        put_u32(&mut central, offset);
+        central.extend_from_slice(name);
+
+        offset = offset.checked_add(header_len + size + 16).ok_or("archive too large")?;
+        count += 1;
+    }
+
+    let central_len = central.len() as u32;
+    put_u32(&mut central, 0x06054b50);
+    put_u16(&mut central, 0);
+    put_u16(&mut central, 0);
+    put_u16(&mut central, count);
+    put_u16(&mut central, count);
+    put_u32(&mut central, central_len);
+    put_u32(&mut central, offset);
+    put_u16(&mut central, 0);
+    send(sender, central).await
+}
+
+async fn send(sender: &mut Sender, data: impl Into<Bytes>) -> Result<(), String> {
+    sender.send_data(data.into()).await.map_err(|err| err.to_string())
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        archive_names(&list.iter().map(|name| name.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn duplicates_numbered() {
+        assert_eq!(names(&["счет.pdf", "счет.pdf", "счет.pdf"]), ["счет.pdf", "счет (2).pdf", "счет (3).pdf"]);
+        assert_eq!(names(&["a.txt", "b.txt"]), ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn duplicates_ignore_case() {
+        assert_eq!(names(&["Photo.JPG", "photo.jpg"]), ["Photo.JPG", "photo (2).jpg"]);
+        assert_eq!(names(&["Счет.pdf", "счет.pdf"]), ["Счет.pdf", "счет (2).pdf"]);
+    }
+
+    #[test]
+    fn duplicates_with_numbered_name() {
+        assert_eq!(names(&["a (2).txt", "a.txt", "a.txt"]), ["a (2).txt", "a.txt", "a (3).txt"]);
+    }
+
+    #[test]
+    fn duplicates_without_extension() {
+        assert_eq!(names(&["README", "README", ".env", ".env"]), ["README", "README (2)", ".env", ".env (2)"]);
+        assert_eq!(names(&["a.tar.gz", "a.tar.gz"]), ["a.tar.gz", "a.tar (2).gz"]);
+    }
+
+    #[test]
+    fn paths_and_empty_names() {
+        assert_eq!(names(&["../../etc/passwd", "C:\\docs\\a.txt"]), [".._.._etc_passwd", "C:_docs_a.txt"]);
+        assert_eq!(names(&["", " ", "..", "."]), ["file", "file (2)", "file (3)", "file (4)"]);
+    }
+
+    fn storage() -> (&'static LocalStorage, String) {
+        let root = std::env::temp_dir().join(format!("archive-test-{}", uuid::Uuid::new_v4())).to_string_lossy().to_string();
+        std::fs::create_dir_all(&root).unwrap();
+        (Box::leak(Box::new(LocalStorage::new(&root))), root)
+    }
+
+    async fn write(files: Vec<ArchiveFile>) -> (Result<(), String>, Vec<u8>) {
+        let (mut sender, body) = Body::channel();
+        let collect = tokio::spawn(async move {
+            let mut body = body;
+            let mut data = vec![];
+            while let Some(Ok(chunk)) = body.next().await {
+                data.extend_from_slice(&chunk);
+            }
+            data
+        });
+        let result = archive_write(&mut sender, files).await;
+        drop(sender);
+        (result, collect.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn archive_layout() {
+        let (storage, root) = storage();
+        std::fs::write(storage.path("a"), b"hello").unwrap();
+        std::fs::write(storage.path("b"), b"").unwrap();
+        let files = vec![
+            ArchiveFile { storage, key: "a".to_string(), name: "a.txt".to_string() },
+            ArchiveFile { storage, key: "b".to_string(), name: "пусто.txt".to_string() },
+        ];
+        let (result, data) = write(files).await;
+        std::fs::remove_dir_all(root).ok();
+        assert!(result.is_ok());
+        assert_eq!(&data[..4], &0x04034b50u32.to_le_bytes());
+        // конец центрального каталога: два файла
+        let end = &data[data.len() - 22..];
+        assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        // CRC-32 строки "hello"
+        assert_eq!(crc_update(0, b"hello"), 0x3610a686);
+        assert!(data.windows(4).any(|window| window == 0x3610a686u32.to_le_bytes()));
+    }
+
+    #[tokio::test]
+    async fn missing_file_fails() {
+        let (storage, root) = storage();
+        std::fs::write(storage.path("a"), b"hello").unwrap();
+        let files = vec![
+            ArchiveFile { storage, key: "a".to_string(), name: "a.txt".to_string() },
+            ArchiveFile { storage, key: "missing".to_string(), name: "b.txt".to_string() },
+        ];
+        let (result, data) = write(files).await;
+        std::fs::remove_dir_all(root).ok();
+        assert!(result.is_err());
+        assert!(!data.windows(4).any(|window| window == 0x06054b50u32.to_le_bytes()));
+    }
+}
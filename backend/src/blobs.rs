@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+use crate::constants::{DIR_BLOBS, key_to_attachment, key_to_blob, key_to_saved};
+use crate::db::{db_conn, db_query};
+use crate::db_types::{DBBlob, DBMailAttachmentItem, DBMailAttachments};
+use crate::storage::{LOCAL, Storage, storage};
+use crate::thumbs::{thumb_key, thumb_source};
+
+const READ_BUF: usize = 64 * 1024;
+
+// файл без ссылок, моложе этого, может быть еще не записан в письмо
+const ORPHAN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+// где лежит вложение: новые -- в хранилище по хешу, старые -- на локальном диске по ключу письма
+pub fn attachment_object(email: &str, key: &str, item: &DBMailAttachmentItem) -> (&'static dyn Storage, String) {
+    match item.hash.as_deref().filter(|hash| is_valid_hash(hash)) {
+        Some(hash) => (storage(), key_to_blob(hash)),
+        None => (&*LOCAL, key_to_attachment(email, key, &item.id))
+    }
+}
+
+// исходный файл письма: новые -- в хранилище (при настроенном шифровании -- зашифрованными),
+// записанные раньше -- на локальном диске, пока их не перенесет "crypt encrypt"
+pub async fn source_read(email: &str, file_name: &str) -> io::Result<Vec<u8>> {
+    let key = key_to_saved(email, file_name);
+    match storage().read(&key).await {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => LOCAL.read(&key).await,
+        result => result
+    }
+}
+
+pub async fn source_save(email: &str, file_name: &str, data: Bytes) -> io::Result<()> {
+    storage().put_bytes(&key_to_saved(email, file_name), data).await
+}
+
+// входящие пользователя (без DIR_SENT) со временем записи, из хранилища и с локального диска
+pub async fn sources_list(email: &str) -> io::Result<Vec<(String, Option<SystemTime>)>> {
+    let prefix = key_to_saved(email, "");
+    let mut found = BTreeMap::new();
+    for list in [LOCAL.list(&prefix).await?, storage().list(&prefix).await?] {
+        for (key, meta) in list {
+            if let Some(name) = key.strip_prefix(&prefix).filter(|name| !name.contains('/')) {
+                found.insert(name.to_string(), meta.modified);
+            }
+        }
+    }
+    Ok(found.into_iter().collect())
+}
+
+// содержимое из памяти (разбор письма); если такой объект уже есть, второй раз не пишем
+// ссылка берется сразу: письмо, которое не удалось записать, должно ее вернуть (blobs_unref)
+pub async fn blob_save(data: Bytes) -> io::Result<String> {
+    let hash = format!("{:x}", Sha256::digest(&data));
+    let key = key_to_blob(&hash);
+    blob_ref(&hash, data.len() as u64).await?;
+    if storage().head(&key).await.is_err() {
+        if let Err(err) = storage().put_bytes(&key, data).await {
+            blob_unref(&hash).await;
+            return Err(err);
+        }
+    }
+    Ok(hash)
+}
+
+// файл с локального диска (черновик, старое вложение); исходный файл остается на месте, удаляет вызывающий
+// второе значение -- такого содержимого в хранилище еще не было; ссылка -- как в blob_save
+pub async fn blob_import(source: &str) -> io::Result<(String, bool)> {
+    let hash = file_hash(source).await?;
+    let key = key_to_blob(&hash);
+    blob_ref(&hash, fs::metadata(source).await?.len()).await?;
+    if storage().head(&key).await.is_ok() {
+        return Ok((hash, false));
+    }
+    if let Err(err) = storage().put(&key, source).await {
+        blob_unref(&hash).await;
+        return Err(err);
+    }
+    Ok((hash, true))
+}
+
+// ссылка берется до проверки объекта: удаление держит блокировку строки (blob_remove),
+// поэтому после ссылки объект либо уже есть и не пропадет, либо его нужно записать заново
+async fn blob_ref(hash: &str, size: u64) -> io::Result<()> {
+    let size = size as i64;
+    let rows = db_query(
+        DBBlob::from,
+        "insert into emails.blobs (hash, size, refs) values ($1, $2, 1) on conflict (hash) do update set refs=emails.blobs.refs+1 returning hash, refs;",
+        &[&hash, &size],
+    ).await;
+    if rows.is_empty() {
+        return Err(io::Error::other("blob_ref: database error"));
+    }
+    Ok(())
+}
+
+async fn blob_unref(hash: &str) {
+    let rows = db_query(DBBlob::from, "update emails.blobs set refs=refs-1 where hash=$1 returning hash, refs;", &[&hash]).await;
+    if rows.iter().any(|row| row.refs <= 0) {
+        blob_remove(hash).await;
+    }
+}
+
+pub async fn file_hash(path: &str) -> io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_BUF];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// записи по порядку хеша, для обслуживания хранилища
+pub async fn blobs_after(hash: &str, limit: &i64) -> Vec<DBBlob> {
+    db_query(DBBlob::from, "select hash, refs from emails.blobs where hash>$1 order by hash limit $2;", &[&hash, limit]).await
+}
+
+// файлы, сохраненные для письма, которое так и не записалось в базу
+pub async fn blobs_unref(attachments: &DBMailAttachments) {
+    for hash in attachments.list.iter().filter_map(|item| item.hash.as_ref()) {
+        blob_unref(hash).await;
+    }
+}
+
+// вложения больше не нужны письму: файл удаляется, когда на него не осталось ссылок
+pub async fn blobs_release(email: &str, attachments: &DBMailAttachments) {
+    for item in attachments.list.iter() {
+        match &item.hash {
+            Some(hash) => blob_unref(hash).await,
+            None => {
+                let key = key_to_attachment(email, &attachments.key, &item.id);
+                LOCAL.delete(&key).await.ok();
+                LOCAL.delete(&thumb_key(&key)).await.ok();
+            }
+        }
+    }
+}
+
+// запись удаляется, только если за это время на файл никто не сослался; строка заблокирована,
+// пока удаляется объект, -- blob_ref дождется конца и, не найдя объекта, запишет его снова
+async fn blob_remove(hash: &str) -> bool {
+    if !is_valid_hash(hash) {
+        return false;
+    }
+    match blob_remove_locked(hash).await {
+        Ok(removed) => removed,
+        Err(err) => {
+            tracing::error!("blob_remove: {hash} {err}");
+            false
+        }
+    }
+}
+
+async fn blob_remove_locked(hash: &str) -> Result<bool, String> {
+    let mut db = db_conn().await.map_err(|err| err.to_string())?;
+    let tx = db.transaction().await.map_err(|err| err.to_string())?;
+    let rows = tx.query("select refs from emails.blobs where hash=$1 and refs<=0 for update;", &[&hash]).await.map_err(|err| err.to_string())?;
+    if rows.is_empty() {
+        return Ok(false);
+    }
+    let key = key_to_blob(hash);
+    storage().delete(&key).await.map_err(|err| err.to_string())?;
+    storage().delete(&thumb_key(&key)).await.ok();
+    tx.execute("delete from emails.blobs where hash=$1;", &[&hash]).await.map_err(|err| err.to_string())?;
+    tx.commit().await.map_err(|err| err.to_string())?;
+    Ok(true)
+}
+
+// файлы без ссылок: записи с refs=0 и файлы, так и не попавшие в письмо (сбой между записью и базой)
+pub async fn blobs_gc() -> (usize, usize) {
+    let mut removed = 0;
+    let mut orphans = 0;
+    for row in db_query(DBBlob::from, "select hash, refs from emails.blobs where refs<=0;", &[]).await {
+        if blob_remove(&row.hash).await {
+            removed += 1;
+        }
+    }
+
+    let list = match storage().list(&format!("{DIR_BLOBS}/")).await {
+        Ok(list) => list,
+        Err(err) => {
+            tracing::error!("blobs_gc: {err}");
+            return (removed, orphans);
+        }
+    };
+    for (key, meta) in list {
+        let old = meta.modified
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .map(|age| age > ORPHAN_AGE)
+            .unwrap_or_default();
+        if !old {
+            continue;
+        }
+        // миниатюра живет, пока жив ее файл
+        let name = key.rsplit('/').next().unwrap_or_default();
+        let name = thumb_source(name).unwrap_or(name);
+        if !is_valid_hash(name) {
+            // временные файлы -- остатки прерванной записи
+            if storage().delete(&key).await.is_ok() {
+                orphans += 1;
+            }
+            continue;
+        }
+        // файл без записи занимаем пустой записью и удаляем обычным порядком: blob_ref в это время дождется удаления
+        let size = meta.size as i64;
+        let claimed = db_query(
+            DBBlob::from,
+            "insert into emails.blobs (hash, size, refs) values ($1, $2, 0) on conflict (hash) do nothing returning hash, refs;",
+            &[&name, &size],
+        ).await;
+        if !claimed.is_empty() && blob_remove(name).await {
+            orphans += 1;
+        }
+    }
+    (removed, orphans)
+}
@@ -0,0 +1,645 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use uuid::Uuid;
+
+use crate::blobs::{blob_import, blobs_after, blobs_gc, blobs_unref, file_hash};
+use crate::constants::{DIR_SOURCE, key_to_blob, path_to_attachment, path_to_temp_upload};
+use crate::crypt::{box_place, crypt_enabled, EncryptedStorage, current_key, key_create, key_latest, keys_rewrap, master_key_generate, secret_encrypt, text_key_id, text_open};
+use crate::db_boxes::{db_box_attachments_after, db_box_attachments_update, db_box_text_update, db_box_texts, db_box_texts_after};
+use crate::db_search::db_search_reindex;
+use crate::db_sessions::db_sessions_revoke;
+use crate::db_pgp::{db_pgp_secret_update, db_pgp_secrets, pgp_secret_place};
+use crate::db_smime::{db_smime_key_update, db_smime_keys, SMIME_KEY_PLACE};
+use crate::db_types::DBMailAttachments;
+use crate::db_user::{db_identities, db_identity_remove, db_identity_save, db_user_init, db_user_password_set, DBIdentity};
+use crate::db_totp::db_totp_reset;
+use crate::dkim::dkim_self_test;
+use crate::password::password_generate;
+use crate::reingest::reingest;
+use crate::state::{USER_BY_EMAIL, USER_BY_ID};
+use crate::storage::{LOCAL, Storage, storage, storage_encrypted};
+
+const IDENTITY_USAGE: &str = "identity list <email>
+identity add <email> <address> [--name NAME] [--reply-to ADDRESS] [--signature-file FILE]
+identity remove <email> <address>";
+
+const BLOBS_USAGE: &str = "blobs migrate [--dry-run]
+blobs gc";
+
+const CRYPT_USAGE: &str = "crypt genkey
+crypt encrypt [--dry-run]
+crypt rotate <email>|blobs
+crypt rewrap";
+
+const SEARCH_USAGE: &str = "search reindex";
+
+const MIGRATE_BATCH: i64 = 500;
+
+#[derive(Default)]
+struct MigrateStat {
+    files: usize,
+    unique: usize,
+    missing: usize,
+    failed: usize,
+    saved: u64,
+}
+
+#[derive(Default)]
+struct CryptStat {
+    messages: usize,
+    keys: usize,
+    search: usize,
+    blobs: usize,
+    sources: usize,
+    failed: usize,
+}
+
+pub async fn run_command(args: &[String]) {
+    match args[0].as_str() {
+        "reingest" => reingest(&args[1..]).await,
+        "dkim" => dkim(),
+        "identity" => identity(&args[1..]).await,
+        "password" => password(&args[1..]).await,
+        "totp-reset" => totp_reset(&args[1..]).await,
+        "blobs" => blobs(&args[1..]).await,
+        "storage-check" => storage_check().await,
+        "crypt" => crypt(&args[1..]).await,
+        "search" => search(&args[1..]).await,
+        command => eprintln!("unknown command: {command}")
+    }
+}
+
+// проверка ключей DKIM и записи для DNS
+fn dkim() {
+    let results = dkim_self_test();
+    if results.is_empty() {
+        println!("dkim: no keys configured");
+    }
+    for (domain, result) in results {
+        match result {
+            Ok(record) => println!("{domain}: ok\n  {record}"),
+            Err(err) => println!("{domain}: FAILED -- {err}")
+        }
+    }
+}
+
+// адреса отправителя пользователя
+async fn identity(args: &[String]) {
+    if args.len() < 2 {
+        eprintln!("usage: {IDENTITY_USAGE}");
+        return;
+    }
+    db_user_init().await;
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(&args[1]).cloned(),
+        Err(_) => None
+    };
+    let idu = match idu {
+        Some(idu) => idu,
+        None => {
+            eprintln!("identity: unknown email {}", args[1]);
+            return;
+        }
+    };
+
+    match (args[0].as_str(), args.get(2)) {
+        ("list", None) => {
+            for item in db_identities(&idu).await {
+                let reply_to = item.reply_to.as_ref().map(|reply_to| format!(" (reply-to {reply_to})")).unwrap_or_default();
+                println!("{}{reply_to}", item.mailbox());
+            }
+        }
+        ("add", Some(address)) => {
+            let mut data = DBIdentity { email: address.clone(), ..DBIdentity::default() };
+            let mut options = args[3..].iter();
+            while let Some(option) = options.next() {
+                let value = match options.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("usage: {IDENTITY_USAGE}");
+                        return;
+                    }
+                };
+                match option.as_str() {
+                    "--name" => data.name = value.clone(),
+                    "--reply-to" => data.reply_to = Some(value.clone()),
+                    "--signature-file" => match fs::read_to_string(value) {
+                        Ok(signature) => data.signature = signature,
+                        Err(err) => {
+                            eprintln!("identity: {value}: {err}");
+                            return;
+                        }
+                    },
+                    _ => {
+                        eprintln!("usage: {IDENTITY_USAGE}");
+                        return;
+                    }
+                }
+            }
+            if data.email.parse::<lettre::Address>().is_err() {
+                eprintln!("identity: invalid address {}", data.email);
+                return;
+            }
+            println!("{}", if db_identity_save(&idu, &data).await { "saved" } else { "FAILED" });
+        }
+        ("remove", Some(address)) => {
+            println!("{}", if db_identity_remove(&idu, address).await { "removed" } else { "FAILED" });
+        }
+        _ => eprintln!("usage: {IDENTITY_USAGE}")
+    }
+}
+
+// сброс пароля: новый пароль генерируется и выводится один раз
+async fn password(args: &[String]) {
+    if args.len() != 1 {
+        eprintln!("usage: password <email>");
+        return;
+    }
+    db_user_init().await;
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(&args[0]).cloned(),
+        Err(_) => None
+    };
+    let idu = match idu {
+        Some(idu) => idu,
+        None => {
+            eprintln!("password: unknown email {}", args[0]);
+            return;
+        }
+    };
+    let password = password_generate();
+    if db_user_password_set(&idu, &password).await {
+        // сервер перестанет принимать прежние сеансы при следующей сверке с базой
+        db_sessions_revoke(&idu, None).await;
+        println!("{}: {password}", args[0]);
+    } else {
+        println!("FAILED");
+    }
+}
+
+// сброс двухфакторной аутентификации, если пользователь потерял телефон и коды восстановления
+async fn totp_reset(args: &[String]) {
+    if args.len() != 1 {
+        eprintln!("usage: totp-reset <email>");
+        return;
+    }
+    db_user_init().await;
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(&args[0]).cloned(),
+        Err(_) => None
+    };
+    match idu {
+        Some(idu) => println!("{}", if db_totp_reset(&idu).await { "reset" } else { "FAILED" }),
+        None => eprintln!("totp-reset: unknown email {}", args[0])
+    }
+}
+
+// хранилище вложений по хешу: перенос старых файлов attachment/ и удаление файлов без ссылок
+async fn blobs(args: &[String]) {
+    match (args.first().map(String::as_str), args.get(1).map(String::as_str)) {
+        (Some("migrate"), None) => blobs_migrate(false).await,
+        (Some("migrate"), Some("--dry-run")) => blobs_migrate(true).await,
+        (Some("gc"), None) => {
+            let (removed, orphans) = blobs_gc().await;
+            println!("blobs gc: removed {removed}, orphans {orphans}");
+        }
+        _ => eprintln!("usage: {BLOBS_USAGE}")
+    }
+}
+
+// можно прерывать и запускать повторно: перенесенные вложения уже имеют хеш
+async fn blobs_migrate(dry_run: bool) {
+    db_user_init().await;
+    let mut stat = MigrateStat::default();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut last = 0i64;
+    loop {
+        let rows = db_box_attachments_after(&last, &MIGRATE_BATCH).await;
+        if rows.is_empty() {
+            break;
+        }
+        for row in rows {
+            last = row.idb;
+            let email = match USER_BY_ID.lock() {
+                Ok(users) => users.get(&row.idu).map(|user| user.email.clone()),
+                Err(_) => None
+            };
+            let (email, mut attachments) = match (email, row.attachments) {
+                (Some(email), Some(attachments)) => (email, attachments),
+                _ => continue
+            };
+
+            let mut migrated: Vec<usize> = vec![];
+            for item in attachments.list.iter_mut().filter(|item| item.hash.is_none()) {
+                let legacy = path_to_attachment(&email, &attachments.key, &item.id);
+                if fs::metadata(&legacy).is_err() {
+                    println!("idb={}: {legacy} not found", row.idb);
+                    stat.missing += 1;
+                    continue;
+                }
+                stat.files += 1;
+                let imported = if dry_run {
+                    match file_hash(&legacy).await {
+                        Ok(hash) => {
+                            let new = seen.insert(hash.clone()) && storage().head(&key_to_blob(&hash)).await.is_err();
+                            Ok((hash, new))
+                        }
+                        Err(err) => Err(err)
+                    }
+                } else {
+                    blob_import(&legacy).await
+                };
+                match imported {
+                    Ok((hash, new)) => {
+                        if new {
+                            stat.unique += 1;
+                        } else {
+                            stat.saved += item.size;
+                        }
+                        item.hash = Some(hash);
+                        migrated.push(item.id);
+                    }
+                    Err(err) => {
+                        println!("idb={}: {legacy} {err}", row.idb);
+                        stat.failed += 1;
+                    }
+                }
+            }
+            if migrated.is_empty() || dry_run {
+                continue;
+            }
+
+            // старые файлы удаляем, только когда письмо уже ссылается на хранилище
+            if db_box_attachments_update(&row.idb, &attachments).await {
+                for id in migrated.iter() {
+                    fs::remove_file(path_to_attachment(&email, &attachments.key, id)).ok();
+                }
+            } else {
+                let list = attachments.list.iter().filter(|item| migrated.contains(&item.id)).cloned().collect::<Vec<_>>();
+                blobs_unref(&DBMailAttachments { key: attachments.key.clone(), list }).await;
+                stat.failed += migrated.len();
+            }
+        }
+    }
+
+    let mode = if dry_run { " (dry run)" } else { "" };
+    println!(
+        "blobs migrate{mode}: files {}, unique {}, not found {}, failed {}, saved {} MB",
+        stat.files, stat.unique, stat.missing, stat.failed, stat.saved / 1024 / 1024
+    );
+}
+
+// проверка хранилища вложений пробным объектом: запись, чтение части, список, удаление
+async fn storage_check() {
+    match storage_check_run().await {
+        Ok(_) => println!("storage: ok"),
+        Err(err) => println!("storage: FAILED -- {err}")
+    }
+}
+
+async fn storage_check_run() -> Result<(), String> {
+    let key = format!("check/{}", Uuid::new_v4());
+    let data = b"storage check";
+    storage().put_bytes(&key, Bytes::from_static(data)).await.map_err(|err| format!("put: {err}"))?;
+
+    let meta = storage().head(&key).await.map_err(|err| format!("head: {err}"))?;
+    if meta.size != data.len() as u64 {
+        return Err(format!("head: size {}", meta.size));
+    }
+
+    let mut stream = storage().get(&key, Some((8, 12))).await.map_err(|err| format!("get: {err}"))?;
+    let mut read = vec![];
+    while let Some(chunk) = stream.next().await {
+        read.extend_from_slice(&chunk.map_err(|err| format!("get: {err}"))?);
+    }
+    if read != data[8..] {
+        return Err("get: range content mismatch".to_string());
+    }
+
+    let list = storage().list("check/").await.map_err(|err| format!("list: {err}"))?;
+    if !list.iter().any(|(item, _)| item == &key) {
+        return Err("list: object not found".to_string());
+    }
+
+    storage().delete(&key).await.map_err(|err| format!("delete: {err}"))?;
+    if storage().head(&key).await.is_ok() {
+        return Err("delete: object still exists".to_string());
+    }
+    Ok(())
+}
+
+// шифрование хранимых данных: ключи и перешифровка уже записанного
+async fn crypt(args: &[String]) {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    if let ["genkey"] = args[..] {
+        println!("{}", master_key_generate());
+        return;
+    }
+    if !crypt_enabled() {
+        eprintln!("crypt: encryption is not configured in env.json");
+        return;
+    }
+    match args[..] {
+        ["encrypt"] => crypt_encrypt(false).await,
+        ["encrypt", "--dry-run"] => crypt_encrypt(true).await,
+        ["rotate", "blobs"] => crypt_rotate(None, "blobs").await,
+        ["rotate", email] => {
+            db_user_init().await;
+            let idu = match USER_BY_EMAIL.lock() {
+                Ok(users) => users.get(email).cloned(),
+                Err(_) => None
+            };
+            match idu {
+                Some(idu) => crypt_rotate(Some(idu), email).await,
+                None => eprintln!("crypt: unknown email {email}")
+            }
+        }
+        ["rewrap"] => {
+            let (rewrapped, failed) = keys_rewrap().await;
+            println!("crypt rewrap: keys {rewrapped}, failed {failed}");
+        }
+        _ => eprintln!("usage: {CRYPT_USAGE}")
+    }
+}
+
+// старые данные остаются читаемыми, пока их не перешифрует "crypt encrypt"
+async fn crypt_rotate(idu: Option<i32>, name: &str) {
+    match key_create(idu).await {
+        Some(idk) => println!("{name}: new key {idk}"),
+        None => println!("{name}: FAILED")
+    }
+}
+
+// открытые и зашифрованные прежними ключами записи переводятся на текущие ключи
+// можно прерывать и запускать повторно; запись, которую не удалось расшифровать, не трогаем
+async fn crypt_encrypt(dry_run: bool) {
+    let mut stat = CryptStat::default();
+    let mut current: HashMap<i32, Option<i32>> = HashMap::new();
+    let mut last = 0i64;
+    loop {
+        let rows = db_box_texts_after(&last, &MIGRATE_BATCH).await;
+        if rows.is_empty() {
+            break;
+        }
+        for row in rows {
+            last = row.idb;
+            let idk = match current.get(&row.idu) {
+                Some(idk) => *idk,
+                None => {
+                    // в пробном запуске ключи не создаем
+                    let idk = if dry_run {
+                        key_latest(Some(row.idu)).await
+                    } else {
+                        current_key(Some(row.idu)).await.map(|(idk, _)| idk)
+                    };
+                    current.insert(row.idu, idk);
+                    idk
+                }
+            };
+            if idk.is_some() && text_key_id(&row.subject) == idk && text_key_id(&row.content) == idk {
+                continue;
+            }
+            stat.messages += 1;
+            if dry_run {
+                continue;
+            }
+            if idk.is_none() {
+                println!("idb={}: no key for user {}", row.idb, row.idu);
+                stat.failed += 1;
+                continue;
+            }
+            let subject = text_open(&row.idu, &box_place(&row.idb, "subject"), row.subject).await;
+            let content = text_open(&row.idu, &box_place(&row.idb, "content"), row.content).await;
+            let sealed = match (subject, content) {
+                (Ok(subject), Ok(content)) => db_box_texts(&row.idu, &row.idb, subject, content).await,
+                (Err(err), _) | (_, Err(err)) => Err(err)
+            };
+            match sealed {
+                Ok((subject, content)) if db_box_text_update(&row.idb, &subject, &content).await => {}
+                Ok(_) => stat.failed += 1,
+                Err(err) => {
+                    println!("idb={}: {err}", row.idb);
+                    stat.failed += 1;
+                }
+            }
+        }
+    }
+
+    // индекс, построенный по открытому тексту, пересобирается только по адресам
+    if !dry_run && crypt_enabled() {
+        let (indexed, failed) = db_search_reindex(&MIGRATE_BATCH).await;
+        for err in failed.iter() {
+            println!("{err}");
+        }
+        stat.search = indexed;
+        stat.failed += failed.len();
+    }
+
+    // закрытые ключи S/MIME, записанные открытым текстом до настройки шифрования или в прежнем формате
+    for (idu, key) in db_smime_keys().await {
+        if text_key_id(&key).is_some() {
+            continue;
+        }
+        stat.keys += 1;
+        if dry_run {
+            continue;
+        }
+        let sealed = match text_open(&idu, SMIME_KEY_PLACE, key).await {
+            Ok(key) => secret_encrypt(&idu, SMIME_KEY_PLACE, key).await,
+            Err(err) => {
+                println!("smime idu={idu}: {err}");
+                None
+            }
+        };
+        match sealed {
+            Some(key) if db_smime_key_update(&idu, &key).await => {}
+            _ => {
+                println!("smime idu={idu}: not encrypted");
+                stat.failed += 1;
+            }
+        }
+    }
+
+    // закрытые части OpenPGP в прежнем формате
+    for (idu, fingerprint, secret) in db_pgp_secrets().await {
+        if text_key_id(&secret).is_some() {
+            continue;
+        }
+        stat.keys += 1;
+        if dry_run {
+            continue;
+        }
+        let place = pgp_secret_place(&fingerprint);
+        let sealed = match text_open(&idu, &place, secret).await {
+            Ok(secret) => secret_encrypt(&idu, &place, secret).await,
+            Err(err) => {
+                println!("pgp idu={idu} {fingerprint}: {err}");
+                None
+            }
+        };
+        match sealed {
+            Some(secret) if db_pgp_secret_update(&idu, &fingerprint, &secret).await => {}
+            _ => {
+                println!("pgp idu={idu} {fingerprint}: not encrypted");
+                stat.failed += 1;
+            }
+        }
+    }
+
+    // старые вложения в attachment/ сюда не попадают: сначала "blobs migrate"
+    if let Some(encrypted) = storage_encrypted() {
+        let idk = if dry_run {
+            key_latest(None).await
+        } else {
+            current_key(None).await.map(|(idk, _)| idk)
+        };
+        let mut last = String::new();
+        loop {
+            let rows = blobs_after(&last, &MIGRATE_BATCH).await;
+            if rows.is_empty() {
+                break;
+            }
+            for row in rows {
+                last = row.hash.clone();
+                let key = key_to_blob(&row.hash);
+                match encrypted.key_id(&key).await {
+                    Ok(found) if found.is_some() && found == idk => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        println!("{key}: {err}");
+                        stat.failed += 1;
+                        continue;
+                    }
+                }
+                stat.blobs += 1;
+                if dry_run {
+                    continue;
+                }
+                // чтение расшифровывает прежним ключом, запись шифрует текущим
+                let temp = path_to_temp_upload(&Uuid::new_v4().to_string());
+                let result = match storage().fetch(&key, &temp).await {
+                    Ok(_) => storage().put(&key, &temp).await,
+                    Err(err) => Err(err)
+                };
+                fs::remove_file(&temp).ok();
+                if let Err(err) = result {
+                    println!("{key}: {err}");
+                    stat.failed += 1;
+                }
+            }
+        }
+
+        // исходные файлы писем: открытые с локального диска -- в хранилище, затем все -- на текущий ключ
+        let prefix = format!("{DIR_SOURCE}/");
+        let local = EncryptedStorage::new(&*LOCAL);
+        let list = match LOCAL.list(&prefix).await {
+            Ok(list) => list,
+            Err(err) => {
+                println!("{prefix}: {err}");
+                stat.failed += 1;
+                vec![]
+            }
+        };
+        for (key, _) in list {
+            // зашифрованный на диске файл -- это и есть объект хранилища, его перешифрует проход ниже
+            match local.key_id(&key).await {
+                Ok(None) => {}
+                Ok(Some(_)) => continue,
+                Err(err) => {
+                    println!("{key}: {err}");
+                    stat.failed += 1;
+                    continue;
+                }
+            }
+            let copied = match encrypted.key_id(&key).await {
+                // хранилище -- тот же локальный диск
+                Ok(None) => continue,
+                // уже перенесен, осталась открытая копия
+                Ok(Some(_)) => true,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+                Err(err) => {
+                    println!("{key}: {err}");
+                    stat.failed += 1;
+                    continue;
+                }
+            };
+            stat.sources += 1;
+            if dry_run {
+                continue;
+            }
+            let result = match copied {
+                true => Ok(()),
+                false => match LOCAL.read(&key).await {
+                    Ok(data) => storage().put_bytes(&key, Bytes::from(data)).await,
+                    Err(err) => Err(err)
+                }
+            };
+            // открытую копию удаляем только после записи в хранилище
+            let result = match result {
+                Ok(_) => LOCAL.delete(&key).await,
+                Err(err) => Err(err)
+            };
+            if let Err(err) = result {
+                println!("{key}: {err}");
+                stat.failed += 1;
+            }
+        }
+
+        let list = match storage().list(&prefix).await {
+            Ok(list) => list,
+            Err(err) => {
+                println!("{prefix}: {err}");
+                stat.failed += 1;
+                vec![]
+            }
+        };
+        for (key, _) in list {
+            match encrypted.key_id(&key).await {
+                Ok(found) if found.is_some() && found == idk => continue,
+                Ok(_) => {}
+                Err(err) => {
+                    println!("{key}: {err}");
+                    stat.failed += 1;
+                    continue;
+                }
+            }
+            stat.sources += 1;
+            if dry_run {
+                continue;
+            }
+            // чтение расшифровывает прежним ключом или отдает открытый файл как есть
+            let result = match storage().read(&key).await {
+                Ok(data) => storage().put_bytes(&key, Bytes::from(data)).await,
+                Err(err) => Err(err)
+            };
+            if let Err(err) = result {
+                println!("{key}: {err}");
+                stat.failed += 1;
+            }
+        }
+    }
+
+    let mode = if dry_run { " (dry run)" } else { "" };
+    println!(
+        "crypt encrypt{mode}: messages {}, keys {}, search {}, blobs {}, sources {}, failed {}",
+        stat.messages, stat.keys, stat.search, stat.blobs, stat.sources, stat.failed
+    );
+}
+
+// полнотекстовый индекс: заново для всех писем, можно прерывать и запускать повторно
+async fn search(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("reindex") => {
+            let (indexed, failed) = db_search_reindex(&MIGRATE_BATCH).await;
+            for err in failed.iter() {
+                println!("{err}");
+            }
+            println!("search reindex: messages {indexed}, failed {}", failed.len());
+        }
+        _ => eprintln!("usage: {SEARCH_USAGE}")
+    }
+}
@@ -3,9 +3,9 @@ use std::fs;
 use shared::constants::MAIL_ATTACH_EXT;
 
 #[cfg(target_os = "macos")]
-const MAIL_ROOT_PATH: &str = "/Users/mac-user/Documents/_cache/mail";
+pub const MAIL_ROOT_PATH: &str = "/Users/mac-user/Documents/_cache/mail";
 #[cfg(not(target_os = "macos"))]
-const MAIL_ROOT_PATH: &str = "/usr/local/www/cache/mail/";
+pub const MAIL_ROOT_PATH: &str = "/usr/local/www/cache/mail/";
 
 #[cfg(target_os = "macos")]
 pub const MAIL_SOURCE_PATH: &str = "/Users/mac-user/Documents/_cache/mail/virtual";
@@ -13,32 +13,58 @@ pub const MAIL_SOURCE_PATH: &str = "/Users/mac-user/Documents/_cache/mail/virtua
 pub const MAIL_SOURCE_PATH: &str = "/var/mail/virtual";
 
 const DIR_TEMP: &str = "temp";
-const DIR_SOURCE: &str = "source";
+pub const DIR_SOURCE: &str = "source";
 const DIR_ATTACHMENT: &str = "attachment";
+const DIR_UPLOADS: &str = "uploads";
+pub const DIR_BLOBS: &str = "blobs";
 
+pub const DIR_SENT: &str = "sent";
 
-pub fn path_to_attachment(email: &str, key: &str, ind: &usize) -> String {
-    format!("{MAIL_ROOT_PATH}/{DIR_ATTACHMENT}/{email}/{key}-{ind}{MAIL_ATTACH_EXT}")
+
+// ключи хранилища вложений -- пути относительно его корня
+pub fn key_to_attachment(email: &str, key: &str, ind: &usize) -> String {
+    format!("{DIR_ATTACHMENT}/{email}/{key}-{ind}{MAIL_ATTACH_EXT}")
+}
+
+// вложения по хешу содержимого: одна копия файла на все письма
+pub fn key_to_blob(hash: &str) -> String {
+    format!("{DIR_BLOBS}/{}/{hash}", &hash[..2])
 }
 
-pub fn path_to_attachment_with_email_and_key(email: &str, key: &str) -> String {
-    format!("{MAIL_ROOT_PATH}/{DIR_ATTACHMENT}/{email}/{key}{MAIL_ATTACH_EXT}")
+pub fn path_to_attachment(email: &str, key: &str, ind: &usize) -> String {
+    format!("{MAIL_ROOT_PATH}/{}", key_to_attachment(email, key, ind))
 }
 
 pub fn path_to_temp_upload(key: &str) -> String {
     format!("{MAIL_ROOT_PATH}/{DIR_TEMP}/{key}")
 }
 
-pub fn path_to_temp_with_ind(key: &str, ind: &usize) -> String {
-    format!("{MAIL_ROOT_PATH}/{DIR_TEMP}/{key}-{ind}{MAIL_ATTACH_EXT}")
+// вложения черновиков лежат в отдельной директории пользователя
+pub fn key_to_temp_with_ind(idu: &i32, key: &str, ind: &usize) -> String {
+    format!("{DIR_TEMP}/{idu}/{key}-{ind}{MAIL_ATTACH_EXT}")
 }
 
-pub fn path_to_temp(key: &str) -> String {
-    format!("{MAIL_ROOT_PATH}/{DIR_TEMP}/{key}{MAIL_ATTACH_EXT}")
+pub fn path_to_temp_with_ind(idu: &i32, key: &str, ind: &usize) -> String {
+    format!("{MAIL_ROOT_PATH}/{}", key_to_temp_with_ind(idu, key, ind))
+}
+
+// незавершенные возобновляемые загрузки: данные и описание рядом
+pub fn path_to_temp_resumable(idu: &i32, id: &str) -> String {
+    format!("{MAIL_ROOT_PATH}/{DIR_TEMP}/{idu}/{DIR_UPLOADS}/{id}")
+}
+
+// исходные файлы писем: отправленные -- в поддиректории DIR_SENT
+pub fn key_to_saved(email: &str, file_name: &str) -> String {
+    format!("{DIR_SOURCE}/{email}/{file_name}")
 }
 
 pub fn path_to_saved(email: &str, file_name: &str) -> String {
-    format!("{MAIL_ROOT_PATH}/{DIR_SOURCE}/{email}/{file_name}")
+    format!("{MAIL_ROOT_PATH}/{}", key_to_saved(email, file_name))
+}
+
+// ключ подписи cookie, если его нет в env.json
+pub fn path_to_cookie_secret() -> String {
+    format!("{MAIL_ROOT_PATH}/cookie_secret")
 }
 
 pub fn test_dirs() {
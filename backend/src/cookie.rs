@@ -0,0 +1,92 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::constants::path_to_cookie_secret;
+
+const ENV_PARAMS: &str = include_str!("../../env.json");
+
+pub const COOKIE_SESSION: &str = "session";
+
+const COOKIE_MAX_AGE: i64 = 90 * 24 * 60 * 60;
+
+const COOKIE_SECRET_MIN: usize = 32;
+
+// на машине разработчика сервер работает без https
+#[cfg(target_os = "macos")]
+const COOKIE_SECURE: &str = "";
+#[cfg(not(target_os = "macos"))]
+const COOKIE_SECURE: &str = "; Secure";
+
+#[derive(Deserialize, Default)]
+struct EnvParams {
+    #[serde(default)]
+    cookie_secret: String,
+}
+
+static COOKIE_SECRET: OnceCell<Vec<u8>> = OnceCell::new();
+
+// при запуске сервера: ключ из env.json, без него -- сгенерированный один раз и сохраненный в файле,
+// чтобы перезапуск не завершал сеансы; короткий ключ в настройках -- ошибка
+pub fn cookie_init() -> Result<(), String> {
+    let params = serde_json::from_str::<EnvParams>(ENV_PARAMS).map_err(|err| err.to_string())?;
+    let secret = match params.cookie_secret.len() {
+        0 => cookie_secret_file(&path_to_cookie_secret())?,
+        len if len < COOKIE_SECRET_MIN => return Err(format!("cookie_secret is shorter than {COOKIE_SECRET_MIN} chars")),
+        _ => params.cookie_secret.into_bytes()
+    };
+    COOKIE_SECRET.set(secret).ok();
+    Ok(())
+}
+
+fn cookie_secret_file(path: &str) -> Result<Vec<u8>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) if text.trim().len() >= COOKIE_SECRET_MIN => return Ok(text.trim().as_bytes().to_vec()),
+        Ok(_) => return Err(format!("{path}: key is shorter than {COOKIE_SECRET_MIN} chars")),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(format!("{path}: {err}"))
+    }
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let secret = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(secret.as_bytes()))
+        .map_err(|err| format!("{path}: {err}"))?;
+    tracing::info!("cookie_secret generated in {path}");
+    Ok(secret.into_bytes())
+}
+
+fn cookie_signature(token: &str) -> Option<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(COOKIE_SECRET.get()?).ok()?;
+    mac.update(token.as_bytes());
+    Some(mac.finalize().into_bytes().to_vec())
+}
+
+// значение cookie: <токен>.<подпись>
+pub fn cookie_verify(value: &str) -> Option<String> {
+    let (token, signature) = value.rsplit_once('.')?;
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(COOKIE_SECRET.get()?).ok()?;
+    mac.update(token.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+    Some(token.to_string())
+}
+
+pub fn cookie_session(token: &str) -> String {
+    let signature = cookie_signature(token).map(|signature| base64::encode_config(signature, base64::URL_SAFE_NO_PAD)).unwrap_or_default();
+    format!("{COOKIE_SESSION}={token}.{signature}; Path=/; Max-Age={COOKIE_MAX_AGE}; HttpOnly; SameSite=Strict{COOKIE_SECURE}")
+}
+
+pub fn cookie_session_clear() -> String {
+    format!("{COOKIE_SESSION}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict{COOKIE_SECURE}")
+}
@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures_util::{stream, StreamExt};
+use hmac::{Hmac, Mac};
+use once_cell::sync::{Lazy, OnceCell};
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::db::{db_query, db_update_query};
+use crate::db_types::{DBBox, DBCryptKey};
+use crate::storage::{Storage, StorageMeta, StorageStream};
+
+const ENV_PARAMS: &str = include_str!("../../env.json");
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+
+// зашифрованный текст в базе: "enc2:<idk>:<base64 nonce|шифртекст|тег>"
+const TEXT_PREFIX: &str = "enc2:";
+// прежний формат без места текста в проверяемых данных: читается, пока его не перешифрует "crypt encrypt"
+const TEXT_PREFIX_V1: &str = "enc1:";
+// показывается вместо текста, который не удалось расшифровать; в базу не пишется
+pub const TEXT_UNREADABLE: &str = "[не удалось расшифровать]";
+
+// вложение: "MENC" | версия | idk | соль, затем куски по 64 КБ со своим тегом -- чтение с любого места без расшифровки всего файла
+const BLOB_MAGIC: &[u8; 4] = b"MENC";
+const BLOB_VERSION: u8 = 1;
+const BLOB_HEADER: usize = 4 + 1 + 4 + SALT_LEN;
+const BLOB_CHUNK: usize = 64 * 1024;
+const BLOB_SEALED: u64 = (BLOB_CHUNK + TAG_LEN) as u64;
+
+type DataKey = [u8; KEY_LEN];
+
+// idk -> (владелец, ключ)
+type KeyCache = HashMap<i32, (Option<i32>, DataKey)>;
+
+#[derive(Deserialize)]
+struct EnvParams {
+    #[serde(default)]
+    encryption: Option<EncryptionParams>,
+}
+
+// "encryption": {"current": "2024", "master_keys": {"2024": "<base64, 32 байта>"}}
+// старые мастер-ключи остаются в списке, пока "crypt rewrap" не перешифрует ими обернутые ключи
+#[derive(Deserialize)]
+struct EncryptionParams {
+    current: String,
+    master_keys: HashMap<String, String>,
+}
+
+struct MasterKeys {
+    current: String,
+    keys: HashMap<String, DataKey>,
+}
+
+// заполняется в crypt_init; без него (тесты) шифрование выключено
+static MASTER: OnceCell<MasterKeys> = OnceCell::new();
+
+// при запуске сервера и команд: с неверной настройкой не запускаемся,
+// иначе новые письма молча писались бы открытым текстом
+pub fn crypt_init() -> Result<(), String> {
+    let params = serde_json::from_str::<EnvParams>(ENV_PARAMS).map_err(|err| err.to_string())?;
+    if let Some(master) = master_keys(params)? {
+        MASTER.set(master).ok();
+    }
+    Ok(())
+}
+
+fn master_keys(params: EnvParams) -> Result<Option<MasterKeys>, String> {
+    let params = match params.encryption {
+        Some(params) => params,
+        None => return Ok(None)
+    };
+    let mut keys = HashMap::new();
+    for (id, key) in params.master_keys {
+        match base64::decode(&key).ok().and_then(|key| DataKey::try_from(key.as_slice()).ok()) {
+            Some(key) => {
+                keys.insert(id, key);
+            }
+            None => return Err(format!("master key {id}: expected 32 bytes in base64"))
+        }
+    }
+    if !keys.contains_key(&params.current) {
+        return Err(format!("current master key {} not found", params.current));
+    }
+    Ok(Some(MasterKeys { current: params.current, keys }))
+}
+
+// ключи данных после расшифровки
+static KEYS: Lazy<Mutex<KeyCache>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// один ключ на пользователя, даже если первые письма пришли одновременно
+static KEYS_CREATE: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+pub fn crypt_enabled() -> bool {
+    MASTER.get().is_some()
+}
+
+pub fn master_key_generate() -> String {
+    base64::encode(random::<KEY_LEN>())
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// AES-256-GCM: шифртекст и тег подряд
+fn seal(key: &[u8], nonce: &[u8], aad: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let mut tag = [0u8; TAG_LEN];
+    let mut sealed = encrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), aad, data, &mut tag).ok()?;
+    sealed.extend_from_slice(&tag);
+    Some(sealed)
+}
+
+fn open(key: &[u8], nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < TAG_LEN {
+        return None;
+    }
+    let (data, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), aad, data, tag).ok()
+}
+
+fn wrap(master_id: &str, key: &DataKey) -> Option<String> {
+    let master = MASTER.get()?.keys.get(master_id)?;
+    let nonce = random::<NONCE_LEN>();
+    let sealed = seal(master, &nonce, master_id.as_bytes(), key)?;
+    Some(base64::encode([nonce.as_slice(), &sealed].concat()))
+}
+
+fn unwrap(row: &DBCryptKey) -> Option<DataKey> {
+    let master = MASTER.get()?.keys.get(&row.master)?;
+    let data = base64::decode(&row.wrapped).ok()?;
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let key = open(master, &data[..NONCE_LEN], row.master.as_bytes(), &data[NONCE_LEN..])?;
+    DataKey::try_from(key.as_slice()).ok()
+}
+
+async fn key_by_id(idk: i32) -> Option<(Option<i32>, DataKey)> {
+    if let Some(found) = KEYS.lock().ok().and_then(|keys| keys.get(&idk).copied()) {
+        return Some(found);
+    }
+    let row = db_query(DBCryptKey::from, "select idk, idu, master, wrapped from emails.crypt_keys where idk=$1;", &[&idk]).await.pop()?;
+    let key = match unwrap(&row) {
+        Some(key) => key,
+        None => {
+            tracing::error!("key_by_id: key {idk} can not be unwrapped with master key {}", row.master);
+            return None;
+        }
+    };
+    if let Ok(mut keys) = KEYS.lock() {
+        keys.insert(idk, (row.idu, key));
+    }
+    Some((row.idu, key))
+}
+
+// последний ключ пользователя; idu=None -- ключ хранилища вложений
+pub async fn key_latest(idu: Option<i32>) -> Option<i32> {
+    db_query(DBCryptKey::from, "select idk, idu, master, wrapped from emails.crypt_keys where idu is not distinct from $1 order by idk desc limit 1;", &[&idu])
+        .await
+        .pop()
+        .map(|row| row.idk)
+}
+
+// ключ для новых данных; при первом обращении создается
+pub async fn current_key(idu: Option<i32>) -> Option<(i32, DataKey)> {
+    let idk = match key_latest(idu).await {
+        Some(idk) => idk,
+        None => {
+            let _lock = KEYS_CREATE.lock().await;
+            match key_latest(idu).await {
+                Some(idk) => idk,
+                None => key_create(idu).await?
+            }
+        }
+    };
+    key_by_id(idk).await.map(|(_, key)| (idk, key))
+}
+
+// новый ключ становится текущим; данные на прежних ключах читаются, пока их не перешифрует "crypt encrypt"
+pub async fn key_create(idu: Option<i32>) -> Option<i32> {
+    let master = &MASTER.get()?.current;
+    let wrapped = wrap(master, &random::<KEY_LEN>())?;
+    db_query(DBCryptKey::from, "insert into emails.crypt_keys (idu, master, wrapped) values ($1, $2, $3) returning idk, idu, master, wrapped;", &[&idu, master, &wrapped])
+        .await
+        .pop()
+        .map(|row| row.idk)
+}
+
+// смена мастер-ключа: ключи данных перешифровываются текущим, сами данные не трогаем
+pub async fn keys_rewrap() -> (usize, usize) {
+    let current = match MASTER.get() {
+        Some(master) => master.current.clone(),
+        None => return (0, 0)
+    };
+    let mut rewrapped = 0;
+    let mut failed = 0;
+    for row in db_query(DBCryptKey::from, "select idk, idu, master, wrapped from emails.crypt_keys where master<>$1 order by idk;", &[&current]).await {
+        let wrapped = unwrap(&row).and_then(|key| wrap(&current, &key));
+        match wrapped {
+            Some(wrapped) if db_update_query("update emails.crypt_keys set master=$1, wrapped=$2 where idk=$3;", &[&current, &wrapped, &row.idk]).await => {
+                rewrapped += 1;
+            }
+            _ => {
+                tracing::error!("keys_rewrap: key {} (master {})", row.idk, row.master);
+                failed += 1;
+            }
+        }
+    }
+    (rewrapped, failed)
+}
+
+// ключ текста в текущем формате; открытый текст и прежний формат -- None
+pub fn text_key_id(text: &str) -> Option<i32> {
+    text.strip_prefix(TEXT_PREFIX)?.split_once(':')?.0.parse::<i32>().ok()
+}
+
+// место текста в базе: таблица, поле и строка
+pub fn box_place(idb: &i64, column: &str) -> String {
+    format!("boxes.{column}:{idb}")
+}
+
+// idu и место входят в проверяемые данные: шифртекст другого пользователя, письма или поля не расшифруется
+fn text_aad(idu: &i32, place: &str) -> Vec<u8> {
+    [idu.to_be_bytes().as_slice(), place.as_bytes()].concat()
+}
+
+// без настроенного шифрования текст пишется как есть; без ключа -- ошибка, открытым текстом не пишем
+pub async fn text_encrypt(idu: &i32, place: &str, text: String) -> Result<String, String> {
+    if !crypt_enabled() {
+        return Ok(text);
+    }
+    let (idk, key) = current_key(Some(*idu)).await.ok_or(format!("no data key idu={idu}"))?;
+    let nonce = random::<NONCE_LEN>();
+    let sealed = seal(&key, &nonce, &text_aad(idu, place), text.as_bytes()).ok_or("encrypt failed")?;
+    Ok(format!("{TEXT_PREFIX}{idk}:{}", base64::encode([nonce.as_slice(), &sealed].concat())))
+}
+
+// проверка до отправки: копия отправленного пишется зашифрованной
+pub async fn text_key_ready(idu: &i32) -> bool {
+    !crypt_enabled() || current_key(Some(*idu)).await.is_some()
+}
+
+// закрытые ключи пользователя: только зашифрованными, открытым текстом в базу не пишем
+pub async fn secret_encrypt(idu: &i32, place: &str, text: String) -> Option<String> {
+    if !crypt_enabled() {
+        return None;
+    }
+    match text_encrypt(idu, place, text).await {
+        Ok(sealed) => Some(sealed),
+        Err(err) => {
+            tracing::error!("secret_encrypt: {place} {err}");
+            None
+        }
+    }
+}
+
+pub async fn text_open(idu: &i32, place: &str, text: String) -> Result<String, String> {
+    let (rest, aad) = match (text.strip_prefix(TEXT_PREFIX), text.strip_prefix(TEXT_PREFIX_V1)) {
+        (Some(rest), _) => (rest, text_aad(idu, place)),
+        (_, Some(rest)) => (rest, idu.to_be_bytes().to_vec()),
+        _ => return Ok(text)
+    };
+    let (idk, data) = rest.split_once(':').ok_or("invalid format")?;
+    let idk = idk.parse::<i32>().map_err(|_| "invalid key id")?;
+    let (owner, key) = key_by_id(idk).await.ok_or(format!("key {idk} unavailable"))?;
+    if owner != Some(*idu) {
+        return Err(format!("key {idk} belongs to another user"));
+    }
+    let data = base64::decode(data).map_err(|err| err.to_string())?;
+    if data.len() < NONCE_LEN {
+        return Err("invalid format".to_string());
+    }
+    let text = open(&key, &data[..NONCE_LEN], &aad, &data[NONCE_LEN..]).ok_or("decrypt failed")?;
+    String::from_utf8(text).map_err(|err| err.to_string())
+}
+
+// шифртекст остается в базе как есть: письмо прочитается, когда вернется ключ
+pub async fn boxes_decrypt(idu: &i32, rows: &mut [DBBox]) {
+    for row in rows.iter_mut() {
+        for (column, text) in [("subject", &mut row.subject), ("content", &mut row.content)] {
+            match text_open(idu, &box_place(&row.idb, column), std::mem::take(text)).await {
+                Ok(plain) => *text = plain,
+                Err(err) => {
+                    tracing::error!("boxes_decrypt: idu={idu} idb={} {err}", row.idb);
+                    *text = TEXT_UNREADABLE.to_string();
+                    row.unreadable = true;
+                }
+            }
+        }
+    }
+}
+
+struct BlobHeader {
+    idk: i32,
+    salt: [u8; SALT_LEN],
+}
+
+fn blob_header(data: &[u8]) -> Option<BlobHeader> {
+    if data.len() < BLOB_HEADER || &data[..4] != BLOB_MAGIC || data[4] != BLOB_VERSION {
+        return None;
+    }
+    let idk = i32::from_be_bytes(data[5..9].try_into().ok()?);
+    let salt = data[9..BLOB_HEADER].try_into().ok()?;
+    Some(BlobHeader { idk, salt })
+}
+
+// у каждого файла свой ключ от ключа хранилища и соли: номера кусков в nonce не повторяются между файлами
+fn blob_file_key(key: &DataKey, salt: &[u8]) -> Option<DataKey> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).ok()?;
+    mac.update(salt);
+    DataKey::try_from(mac.finalize().into_bytes().as_slice()).ok()
+}
+
+// последний кусок помечен: обрезанный файл не расшифруется
+fn chunk_nonce(index: u64, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(BLOB_CHUNK as u64).max(1)
+}
+
+// размер содержимого по размеру зашифрованного объекта
+fn plain_size(stored: u64) -> u64 {
+    let sealed = stored.saturating_sub(BLOB_HEADER as u64);
+    let chunks = sealed.div_ceil(BLOB_SEALED).max(1);
+    sealed.saturating_sub(chunks * TAG_LEN as u64)
+}
+
+fn invalid_data(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text.to_string())
+}
+
+// вложения шифруются ключом хранилища, а не ключом пользователя: один файл может принадлежать письмам разных пользователей
+pub struct EncryptedStorage {
+    inner: &'static dyn Storage,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: &'static dyn Storage) -> Self {
+        Self { inner }
+    }
+
+    // None -- объект записан до включения шифрования и читается как есть
+    async fn header(&self, key: &str, stored: u64) -> io::Result<Option<BlobHeader>> {
+        if stored < BLOB_HEADER as u64 {
+            return Ok(None);
+        }
+        let mut stream = self.inner.get(key, Some((0, BLOB_HEADER as u64 - 1))).await?;
+        let mut data = vec![];
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(blob_header(&data))
+    }
+
+    async fn file_key(&self, header: &BlobHeader) -> io::Result<DataKey> {
+        match key_by_id(header.idk).await {
+            Some((None, key)) => blob_file_key(&key, &header.salt).ok_or_else(|| invalid_data("blob key")),
+            _ => Err(io::Error::other(format!("blob key {} unavailable", header.idk)))
+        }
+    }
+
+    async fn header_new(&self) -> io::Result<(Vec<u8>, DataKey)> {
+        let (idk, key) = current_key(None).await.ok_or_else(|| io::Error::other("no storage data key"))?;
+        let salt = random::<SALT_LEN>();
+        let key = blob_file_key(&key, &salt).ok_or_else(|| invalid_data("blob key"))?;
+        let header = [BLOB_MAGIC.as_slice(), &[BLOB_VERSION], &idk.to_be_bytes(), &salt].concat();
+        Ok((header, key))
+    }
+
+    // ключ, которым зашифрован объект; None -- не зашифрован
+    pub async fn key_id(&self, key: &str) -> io::Result<Option<i32>> {
+        let meta = self.inner.head(key).await?;
+        Ok(self.header(key, meta.size).await?.map(|header| header.idk))
+    }
+
+    // stored -- размер зашифрованного объекта
+    async fn get_sealed(&self, key: &str, stored: u64, file_key: DataKey, range: Option<(u64, u64)>) -> io::Result<StorageStream> {
+        let size = plain_size(stored);
+        let (start, end) = match range {
+            Some((start, end)) => (start, end.min(size.saturating_sub(1))),
+            None => (0, size.saturating_sub(1))
+        };
+        if size == 0 || start > end {
+            return Ok(Box::pin(stream::empty()));
+        }
+        let left = end - start + 1;
+
+        // читаем только куски, в которые попадает диапазон
+        let first = start / BLOB_CHUNK as u64;
+        let last = end / BLOB_CHUNK as u64;
+        let stored_start = BLOB_HEADER as u64 + first * BLOB_SEALED;
+        let stored_end = (BLOB_HEADER as u64 + (last + 1) * BLOB_SEALED).min(stored) - 1;
+        let inner = self.inner.get(key, Some((stored_start, stored_end))).await?;
+        Ok(open_stream(OpenState {
+            inner,
+            buf: BytesMut::new(),
+            key: file_key,
+            index: first,
+            last: chunk_count(size) - 1,
+            skip: (start - first * BLOB_CHUNK as u64) as usize,
+            left,
+        }))
+    }
+}
+
+async fn seal_file(key: &DataKey, header: &[u8], source: &str, target: &str) -> io::Result<()> {
+    let mut input = fs::File::open(source).await?;
+    let size = input.metadata().await?.len();
+    let count = chunk_count(size);
+    let mut output = fs::File::create(target).await?;
+    output.write_all(header).await?;
+    let mut buf = vec![0u8; BLOB_CHUNK];
+    for index in 0..count {
+        let len = (size - index * BLOB_CHUNK as u64).min(BLOB_CHUNK as u64) as usize;
+        input.read_exact(&mut buf[..len]).await?;
+        let sealed = seal(key, &chunk_nonce(index, index + 1 == count), &[], &buf[..len]).ok_or_else(|| invalid_data("encrypt failed"))?;
+        output.write_all(&sealed).await?;
+    }
+    output.flush().await
+}
+
+fn seal_bytes(key: &DataKey, header: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+    let count = chunk_count(data.len() as u64);
+    let mut output = header.to_vec();
+    for index in 0..count {
+        let start = index as usize * BLOB_CHUNK;
+        let part = &data[start..(start + BLOB_CHUNK).min(data.len())];
+        let sealed = seal(key, &chunk_nonce(index, index + 1 == count), &[], part).ok_or_else(|| invalid_data("encrypt failed"))?;
+        output.extend_from_slice(&sealed);
+    }
+    Ok(output)
+}
+
+struct OpenState {
+    inner: StorageStream,
+    buf: BytesMut,
+    key: DataKey,
+    index: u64,
+    last: u64,
+    // лишнее в начале первого куска и сколько всего отдать
+    skip: usize,
+    left: u64,
+}
+
+fn open_stream(state: OpenState) -> StorageStream {
+    Box::pin(stream::try_unfold(state, |mut state| async move {
+        if state.left == 0 {
+            return Ok(None);
+        }
+        while state.buf.len() < BLOB_SEALED as usize {
+            match state.inner.next().await {
+                Some(data) => state.buf.extend_from_slice(&data?),
+                None => break
+            }
+        }
+        let len = state.buf.len().min(BLOB_SEALED as usize);
+        let sealed = state.buf.split_to(len);
+        let plain = open(&state.key, &chunk_nonce(state.index, state.index == state.last), &[], &sealed)
+            .ok_or_else(|| invalid_data("blob chunk decrypt failed"))?;
+        state.index += 1;
+        let start = state.skip.min(plain.len());
+        state.skip = 0;
+        let len = ((plain.len() - start) as u64).min(state.left) as usize;
+        state.left -= len as u64;
+        Ok(Some((Bytes::copy_from_slice(&plain[start..start + len]), state)))
+    }))
+}
+
+#[async_trait]
+impl Storage for EncryptedStorage {
+    async fn put(&self, key: &str, source: &str) -> io::Result<()> {
+        let (header, file_key) = self.header_new().await?;
+        let temp = format!("{source}.{}", Uuid::new_v4());
+        let result = match seal_file(&file_key, &header, source, &temp).await {
+            Ok(_) => self.inner.put(key, &temp).await,
+            Err(err) => Err(err)
+        };
+        fs::remove_file(&temp).await.ok();
+        result
+    }
+
+    async fn put_bytes(&self, key: &str, data: Bytes) -> io::Result<()> {
+        let (header, file_key) = self.header_new().await?;
+        let sealed = seal_bytes(&file_key, &header, &data)?;
+        self.inner.put_bytes(key, Bytes::from(sealed)).await
+    }
+
+    async fn head(&self, key: &str) -> io::Result<StorageMeta> {
+        let mut meta = self.inner.head(key).await?;
+        if self.header(key, meta.size).await?.is_some() {
+            meta.size = plain_size(meta.size);
+        }
+        Ok(meta)
+    }
+
+    async fn get(&self, key: &str, range: Option<(u64, u64)>) -> io::Result<StorageStream> {
+        let stored = self.inner.head(key).await?.size;
+        let header = match self.header(key, stored).await? {
+            Some(header) => header,
+            None => return self.inner.get(key, range).await
+        };
+        let file_key = self.file_key(&header).await?;
+        self.get_sealed(key, stored, file_key, range).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    // размеры -- как объекты лежат в хранилище, с заголовком и тегами
+    async fn list(&self, prefix: &str) -> io::Result<Vec<(String, StorageMeta)>> {
+        self.inner.list(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    const KEY: DataKey = [7u8; KEY_LEN];
+    const SIZES: [usize; 5] = [0, 1, BLOB_CHUNK, BLOB_CHUNK + 1, 3 * BLOB_CHUNK + 100];
+
+    fn header() -> Vec<u8> {
+        [BLOB_MAGIC.as_slice(), &[BLOB_VERSION], &1i32.to_be_bytes(), &[0u8; SALT_LEN]].concat()
+    }
+
+    fn plain(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn storage() -> (String, EncryptedStorage) {
+        let root = std::env::temp_dir().join(format!("crypt-test-{}", Uuid::new_v4())).to_string_lossy().to_string();
+        let inner: &'static LocalStorage = Box::leak(Box::new(LocalStorage::new(&root)));
+        (root, EncryptedStorage::new(inner))
+    }
+
+    async fn collect(stream: io::Result<StorageStream>) -> io::Result<Vec<u8>> {
+        let mut stream = stream?;
+        let mut data = vec![];
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+
+    async fn read(storage: &EncryptedStorage, key: &str, range: Option<(u64, u64)>) -> io::Result<Vec<u8>> {
+        let stored = storage.inner.head(key).await?.size;
+        collect(storage.get_sealed(key, stored, KEY, range).await).await
+    }
+
+    fn params(json: &str) -> Result<Option<MasterKeys>, String> {
+        master_keys(serde_json::from_str::<EnvParams>(json).unwrap())
+    }
+
+    #[test]
+    fn master_keys_checked() {
+        let key = base64::encode(KEY);
+        assert!(params("{}").unwrap().is_none());
+        let master = params(&format!(r#"{{"encryption": {{"current": "2", "master_keys": {{"1": "{key}", "2": "{key}"}}}}}}"#)).unwrap().unwrap();
+        assert_eq!(master.current, "2");
+        assert_eq!(master.keys.len(), 2);
+        let missing = params(&format!(r#"{{"encryption": {{"current": "2", "master_keys": {{"1": "{key}"}}}}}}"#));
+        assert_eq!(missing.err().unwrap(), "current master key 2 not found");
+        let short = params(r#"{"encryption": {"current": "1", "master_keys": {"1": "c2hvcnQ="}}}"#);
+        assert_eq!(short.err().unwrap(), "master key 1: expected 32 bytes in base64");
+    }
+
+    #[test]
+    fn text_place_bound() {
+        let nonce = random::<NONCE_LEN>();
+        let sealed = seal(&KEY, &nonce, &text_aad(&1, &box_place(&10, "subject")), b"text").unwrap();
+        assert_eq!(open(&KEY, &nonce, &text_aad(&1, &box_place(&10, "subject")), &sealed).unwrap(), b"text");
+        // другое поле, письмо или пользователь
+        assert!(open(&KEY, &nonce, &text_aad(&1, &box_place(&10, "content")), &sealed).is_none());
+        assert!(open(&KEY, &nonce, &text_aad(&1, &box_place(&11, "subject")), &sealed).is_none());
+        assert!(open(&KEY, &nonce, &text_aad(&2, &box_place(&10, "subject")), &sealed).is_none());
+        // прежний формат перешифровывается
+        assert_eq!(text_key_id("enc2:5:AAAA"), Some(5));
+        assert_eq!(text_key_id("enc1:5:AAAA"), None);
+        assert_eq!(text_key_id("text"), None);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(chunk_count(0), 1);
+        assert_eq!(chunk_count(1), 1);
+        assert_eq!(chunk_count(BLOB_CHUNK as u64), 1);
+        assert_eq!(chunk_count(BLOB_CHUNK as u64 + 1), 2);
+        for size in SIZES {
+            let sealed = seal_bytes(&KEY, &header(), &plain(size)).unwrap();
+            assert_eq!(sealed.len() as u64, BLOB_HEADER as u64 + size as u64 + chunk_count(size as u64) * TAG_LEN as u64);
+            assert_eq!(plain_size(sealed.len() as u64), size as u64, "size {size}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sealed_round_trip() {
+        for size in SIZES {
+            let data = plain(size);
+            let sealed = seal_bytes(&KEY, &header(), &data).unwrap();
+            // поток из хранилища приходит кусками произвольной длины
+            let parts = sealed[BLOB_HEADER..].chunks(1000).map(|part| Ok(Bytes::copy_from_slice(part))).collect::<Vec<_>>();
+            let opened = collect(Ok(open_stream(OpenState {
+                inner: Box::pin(stream::iter(parts)),
+                buf: BytesMut::new(),
+                key: KEY,
+                index: 0,
+                last: chunk_count(size as u64) - 1,
+                skip: 0,
+                left: size as u64,
+            })))
+            .await
+            .unwrap();
+            assert_eq!(opened, data, "size {size}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ranges() {
+        let (root, storage) = storage();
+        let size = 3 * BLOB_CHUNK + 100;
+        let data = plain(size);
+        storage.inner.put_bytes("file", Bytes::from(seal_bytes(&KEY, &header(), &data).unwrap())).await.unwrap();
+        let chunk = BLOB_CHUNK as u64;
+        let cases = [
+            (0, 0),
+            (0, chunk - 1),
+            (chunk - 1, chunk),
+            (chunk - 10, 2 * chunk + 10),
+            (chunk, chunk),
+            (2 * chunk + 5, size as u64 - 1),
+            (3 * chunk, size as u64 + 1000),
+        ];
+        for (start, end) in cases {
+            let result = read(&storage, "file", Some((start, end))).await.unwrap();
+            let end = end.min(size as u64 - 1);
+            assert_eq!(result, data[start as usize..=end as usize], "range {start}-{end}");
+        }
+        assert_eq!(read(&storage, "file", None).await.unwrap(), data);
+        assert!(read(&storage, "file", Some((size as u64, size as u64 + 10))).await.unwrap().is_empty());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[tokio::test]
+    async fn empty_file() {
+        let (root, storage) = storage();
+        storage.inner.put_bytes("file", Bytes::from(seal_bytes(&KEY, &header(), &[]).unwrap())).await.unwrap();
+        assert!(read(&storage, "file", None).await.unwrap().is_empty());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let (root, storage) = storage();
+        let size = 2 * BLOB_CHUNK + 100;
+        let data = plain(size);
+        let sealed = seal_bytes(&KEY, &header(), &data).unwrap();
+        // целиком отрезанный последний кусок и обрезанный тег
+        let whole = BLOB_HEADER + 2 * BLOB_SEALED as usize;
+        storage.inner.put_bytes("chunk", Bytes::copy_from_slice(&sealed[..whole])).await.unwrap();
+        storage.inner.put_bytes("tag", Bytes::copy_from_slice(&sealed[..sealed.len() - 1])).await.unwrap();
+        assert!(read(&storage, "chunk", None).await.is_err());
+        assert!(read(&storage, "chunk", Some((BLOB_CHUNK as u64 + 1, BLOB_CHUNK as u64 + 10))).await.is_err());
+        assert!(read(&storage, "tag", None).await.is_err());
+        // первые куски обрезанного файла по-прежнему читаются
+        assert_eq!(read(&storage, "tag", Some((0, 99))).await.unwrap(), data[..100]);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[tokio::test]
+    async fn wrong_key() {
+        let (root, storage) = storage();
+        storage.inner.put_bytes("file", Bytes::from(seal_bytes(&KEY, &header(), &plain(100)).unwrap())).await.unwrap();
+        let stored = storage.inner.head("file").await.unwrap().size;
+        assert!(collect(storage.get_sealed("file", stored, [8u8; KEY_LEN], None).await).await.is_err());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -1,3 +1,6 @@
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
 use postgres_types::ToSql;
 use tokio::fs;
 use uuid::Uuid;
@@ -6,19 +9,34 @@ use shared::constants::BY_PAGE;
 use shared::types::{BoxMailAttachmentItem, BoxMailAttachments, MailBoxes, MessageRequest, MessagesRequest, NotesChannel};
 use shared::utils::box_type_index;
 
-use crate::constants::{path_to_attachment, path_to_temp_with_ind};
+use crate::blobs::{attachment_object, blob_import, blob_save, blobs_unref, source_read, source_save};
+use crate::constants::{DIR_SENT, key_to_blob, path_to_temp_with_ind};
+use crate::crypt::{box_place, boxes_decrypt, text_encrypt, text_key_ready};
 use crate::db::{db_query, db_update_query};
 use crate::db_notes::db_notes_route;
-use crate::db_types::{DBBox, DBMailAddress, DBMailAttachments, DBPageResponse};
-use crate::db_user::db_user_email;
+use crate::db_pgp::db_pgp_keyring;
+use crate::db_smime::db_smime_signer;
+use crate::db_types::{DBBox, DBBoxAttachments, DBBoxInsert, DBBoxSource, DBBoxText, DBMailAddress, DBMailAttachmentItem, DBMailAttachments, DBPageResponse};
+use crate::db_user::{db_identity, db_user_email};
+use crate::pgp::Cert;
+use crate::pgp_mime::{pgp_own_cert, pgp_recipient_key};
 use crate::receive::get_email;
-use crate::send::send_message;
+use crate::search::{search_document, search_vector};
+use crate::send::{DraftAttachments, inline_images, MessageForwarded, MessageSender, send_message};
 use crate::sse::{Message, sse_channel, sse_personal_channel};
 use crate::state::{USER_BY_EMAIL, USER_BY_ID};
+use crate::storage::storage;
+use crate::thumbs::{thumb_key, thumb_move_file};
 use crate::types::SessionStruct;
-use crate::utils::get_dir_path;
+use crate::utils::{get_dir_path, is_valid_key};
 
-pub async fn db_message_route(session: &SessionStruct, data: MessageRequest) {
+pub async fn db_message_route(session: &SessionStruct, mut data: MessageRequest) {
+    if let Some(attachments) = &data.attachments {
+        if !attachments.key.is_empty() && !is_valid_key(&attachments.key) {
+            tracing::warn!("db_message_route: invalid attachments key idu={}", session.idu);
+            data.attachments = None;
+        }
+    }
     if data.send.is_some() {
         let session = session.clone();
         tokio::task::spawn(async move {
@@ -34,10 +52,10 @@ pub async fn db_message_route(session: &SessionStruct, data: MessageRequest) {
 }
 
 async fn send_message_init(session: &SessionStruct, data: &MessageRequest) {
-    let sender = match USER_BY_ID.lock() {
+    let (sender, email) = match USER_BY_ID.lock() {
         Ok(users) => {
             match users.get(&session.idu) {
-                Some(user) => format!("{} <{}>", user.name, user.email),
+                Some(user) => (format!("{} <{}>", user.name, user.email), user.email.clone()),
                 None => {
                     return;
                 }
@@ -47,6 +65,21 @@ async fn send_message_init(session: &SessionStruct, data: &MessageRequest) {
             return;
         }
     };
+    // отправитель -- выбранный адрес пользователя; файлы письма остаются в основном ящике
+    // чужой или удаленный адрес: не подменяем основным, письмо не отправляем
+    let identity = match &data.identity {
+        Some(idi) => match db_identity(&session.idu, idi).await {
+            Some(identity) => Some(identity),
+            None => {
+                let send_error = Some("Адрес отправителя не найден, письмо не отправлено".to_string());
+                message_personal(session, MessageRequest { send: Some(false), send_error, ..MessageRequest::default() });
+                return;
+            }
+        },
+        None => None
+    };
+    let sender = identity.as_ref().map(|identity| identity.mailbox()).unwrap_or(sender);
+    let reply_to = identity.and_then(|identity| identity.reply_to).filter(|reply_to| !reply_to.trim().is_empty());
     let recipient = match &data.recipient {
         Some(val) => val.clone(),
         None => "".to_string()
@@ -55,24 +88,110 @@ async fn send_message_init(session: &SessionStruct, data: &MessageRequest) {
         Some(val) => val.clone(),
         None => "".to_string()
     };
+    let attachments = data.attachments.clone();
     let content = match &data.content {
-        Some(val) => val.clone(),
+        Some(val) => inline_images(val, &attachments),
         None => "".to_string()
     };
-    let attachments = data.attachments.clone();
+    // без пересылаемого письма не отправляем: получатель не увидит того, что ему пересылают
+    let forwarded = match &data.forward_idb {
+        Some(idb) => match db_message_forwarded(session, idb).await {
+            Some(forwarded) => Some(forwarded),
+            None => {
+                let send_error = Some("Пересылаемое письмо не найдено, письмо не отправлено".to_string());
+                message_personal(session, MessageRequest { send: Some(false), send_error, ..MessageRequest::default() });
+                return;
+            }
+        },
+        None => None
+    };
 
-    let send_result = send_message(&sender, &recipient, &subject, &content, &attachments).await;
+    // подпись S/MIME: без сертификата письмо не отправляем
+    let smime = match data.smime_sign {
+        Some(true) => match db_smime_signer(&session.idu).await {
+            Some(signer) => Some(signer),
+            None => {
+                let send_error = Some("Нет сертификата S/MIME для подписи, письмо не отправлено".to_string());
+                message_personal(session, MessageRequest { send: Some(false), send_error, ..MessageRequest::default() });
+                return;
+            }
+        },
+        _ => None
+    };
 
-    if send_result {
+    // копия отправленного пишется зашифрованной: без ключа письмо не уходит
+    if !text_key_ready(&session.idu).await {
+        let send_error = Some("Нет ключа шифрования, письмо не отправлено".to_string());
+        message_personal(session, MessageRequest { send: Some(false), send_error, ..MessageRequest::default() });
+        return;
+    }
+
+    // OpenPGP: подпись своим ключом; шифрование ключом получателя и своим, чтобы отправленное читалось и у отправителя
+    let pgp_sign = data.pgp_sign.unwrap_or_default();
+    let pgp_encrypt = data.pgp_encrypt.unwrap_or_default();
+    let keyring = if pgp_sign || pgp_encrypt { db_pgp_keyring(&session.idu).await } else { vec![] };
+    let (_, sender_address) = get_email(&sender);
+    let (_, recipient_address) = get_email(&recipient);
+    let own = pgp_own_cert(&keyring, &sender_address);
+    let recipient_key = pgp_recipient_key(&keyring, &recipient_address);
+    let pgp_error = if (pgp_sign || pgp_encrypt) && smime.is_some() {
+        Some("S/MIME и OpenPGP в одном письме не поддерживаются, письмо не отправлено".to_string())
+    } else if pgp_sign && own.is_none() {
+        Some("Нет своего ключа OpenPGP для подписи, письмо не отправлено".to_string())
+    } else if pgp_encrypt && recipient_key.is_none() {
+        Some(format!("Нет ключа OpenPGP для {recipient_address}, письмо не отправлено"))
+    } else {
+        None
+    };
+    if let Some(send_error) = pgp_error {
+        message_personal(session, MessageRequest { send: Some(false), send_error: Some(send_error), ..MessageRequest::default() });
+        return;
+    }
+    let recipients = recipient_key.into_iter().chain(own.and_then(Cert::encryption_key)).collect::<Vec<_>>();
+
+    let security = [
+        smime.as_ref().map(|_| "smime-signed"),
+        pgp_sign.then_some("pgp-sent-signed"),
+        pgp_encrypt.then_some("pgp-sent-encrypted"),
+    ].into_iter().flatten().collect::<Vec<_>>().join(" ");
+    let security = Some(security).filter(|security| !security.is_empty());
+
+    let from = MessageSender {
+        mailbox: &sender,
+        reply_to: &reply_to,
+        smime: smime.as_ref(),
+        pgp_sign: own.filter(|_| pgp_sign),
+        pgp_encrypt: Some(recipients.as_slice()).filter(|_| pgp_encrypt),
+    };
+    let draft = DraftAttachments { idu: &session.idu, attachments: &attachments };
+    let send_result = send_message(from, &recipient, &subject, &content, draft, &forwarded).await;
+
+    if let Some(formatted) = &send_result {
         let (name, address) = get_email(&sender);
         let sender: DBMailAddress = DBMailAddress { name, address };
-        if let Some(attachments) = &attachments {
-            let key = attachments.key.clone();
-            for item in attachments.list.iter() {
-                let target_file = path_to_attachment(&sender.address, &key, &item.id);
-                let source_file = path_to_temp_with_ind(&key, &item.id);
-                if (fs::create_dir_all(get_dir_path(&target_file)).await).is_ok() {
-                    if let Err(err) = fs::rename(&source_file, &target_file).await {
+
+        // исходный текст отправленного письма, в отдельной поддиректории от входящих
+        let source = format!("{DIR_SENT}/{}.eml", Uuid::new_v4());
+        let source = match source_save(&email, &source, Bytes::copy_from_slice(formatted)).await {
+            Ok(_) => Some(source),
+            Err(err) => {
+                tracing::error!("send_message_init {err}");
+                None
+            }
+        };
+
+        // файлы черновика переходят в хранилище по хешу; одинаковые файлы хранятся один раз
+        let mut attachments = attachments.as_ref().map(DBMailAttachments::from);
+        if let Some(attachments) = &mut attachments {
+            for item in attachments.list.iter_mut() {
+                let source_file = path_to_temp_with_ind(&session.idu, &attachments.key, &item.id);
+                match blob_import(&source_file).await {
+                    Ok((hash, _)) => {
+                        thumb_move_file(storage(), &key_to_blob(&hash), &source_file).await;
+                        item.hash = Some(hash);
+                        (fs::remove_file(&source_file).await).ok();
+                    }
+                    Err(err) => {
                         tracing::error!("send_message_init {err}");
                     }
                 }
@@ -81,25 +200,71 @@ async fn send_message_init(session: &SessionStruct, data: &MessageRequest) {
 
         let (name, address) = get_email(&recipient);
         let recipient: DBMailAddress = DBMailAddress { name, address };
-        let attachments = attachments.as_ref().map(DBMailAttachments::from);
+        let attachments = match &forwarded {
+            Some(forwarded) => attachments_with_forwarded(attachments, forwarded).await,
+            None => attachments
+        };
         db_box_add(
             session.idu,
             box_type_index(&MailBoxes::Sent),
             true,
-            sender,
-            recipient,
-            subject,
-            content,
-            attachments,
+            DBBoxInsert { sender, recipient, subject, content, attachments, source, security, ..DBBoxInsert::default() },
         );
     }
 
     message_personal(
         session,
-        MessageRequest { send: Some(send_result), ..MessageRequest::default() },
+        MessageRequest { send: Some(send_result.is_some()), ..MessageRequest::default() },
     );
 }
 
+async fn db_message_forwarded(session: &SessionStruct, idb: &u64) -> Option<MessageForwarded> {
+    let row = get_attachments(session, idb).await?;
+    let source = db_box_source(&session.idu, &row.idb).await?;
+    let email = db_user_email(&session.idu).await?;
+    match source_read(&email, &source).await {
+        Ok(source) => Some(MessageForwarded { file_name: forwarded_file_name(if row.unreadable { "" } else { &row.subject }), source }),
+        Err(err) => {
+            tracing::error!("db_message_forwarded {err}");
+            None
+        }
+    }
+}
+
+fn forwarded_file_name(subject: &str) -> String {
+    let name = subject
+        .chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .take(60)
+        .collect::<String>();
+    let name = name.trim();
+    if name.is_empty() { "message.eml".to_string() } else { format!("{name}.eml") }
+}
+
+// пересланное письмо сохраняем среди вложений отправленного
+async fn attachments_with_forwarded(attachments: Option<DBMailAttachments>, forwarded: &MessageForwarded) -> Option<DBMailAttachments> {
+    let mut attachments = attachments.unwrap_or_default();
+    if attachments.key.is_empty() {
+        attachments.key = Uuid::new_v4().to_string();
+    }
+    let id = attachments.list.iter().map(|item| item.id).max().unwrap_or_default() + 1;
+    match blob_save(Bytes::from(forwarded.source.clone())).await {
+        Ok(hash) => {
+            attachments.list.push(DBMailAttachmentItem {
+                id,
+                file_name: forwarded.file_name.clone(),
+                size: forwarded.source.len() as u64,
+                cid: None,
+                hash: Some(hash),
+            });
+        }
+        Err(err) => {
+            tracing::error!("attachments_with_forwarded {err}");
+        }
+    }
+    if attachments.list.is_empty() { None } else { Some(attachments) }
+}
+
 fn message_personal(session: &SessionStruct, data: MessageRequest) {
     match serde_json::to_string(&data) {
         Ok(text) => {
@@ -118,7 +283,9 @@ async fn db_message_attachments(session: &SessionStruct, data: &MessageRequest)
             if !attachments.list.is_empty() {
                 let key = attachments.key.clone();
                 for item in attachments.list.iter() {
-                    (fs::remove_file(&path_to_temp_with_ind(&key, &item.id)).await).ok();
+                    let path = path_to_temp_with_ind(&session.idu, &key, &item.id);
+                    (fs::remove_file(&path).await).ok();
+                    (fs::remove_file(&thumb_key(&path)).await).ok();
                 }
                 return;
             }
@@ -136,7 +303,9 @@ async fn db_message_attachments(session: &SessionStruct, data: &MessageRequest)
             let key = attachments.key.clone();
             let mut list = attachments.list.clone();
             let list = if let Some(pos) = attachments.list.iter().position(|row| &row.id == remove_id) {
-                (fs::remove_file(&path_to_temp_with_ind(&key, remove_id)).await).ok();
+                let path = path_to_temp_with_ind(&session.idu, &key, remove_id);
+                (fs::remove_file(&path).await).ok();
+                (fs::remove_file(&thumb_key(&path)).await).ok();
                 list.remove(pos);
                 list.clone()
             } else {
@@ -149,17 +318,21 @@ async fn db_message_attachments(session: &SessionStruct, data: &MessageRequest)
         if let Some(row) = get_attachments(session, &data.idb).await {
             if let Some(email) = db_user_email(&session.idu).await {
                 if let Some(prev) = row.attachments {
-                    if !prev.list.is_empty() {
+                    if !prev.list.is_empty() && is_valid_key(&prev.key) {
                         let mut ind: usize = 0;
                         let mut list: Vec<BoxMailAttachmentItem> = vec![];
+                        let target_dir = get_dir_path(&path_to_temp_with_ind(&session.idu, &key, &0));
+                        (fs::create_dir_all(&target_dir).await).ok();
                         for item in prev.list.iter() {
-                            let source = path_to_attachment(&email, &prev.key, &item.id);
-                            if (fs::copy(source, &path_to_temp_with_ind(&key, &(ind + 1))).await).is_ok() {
+                            // на локальном диске вместо копии -- жесткая ссылка; при отправке файл снова попадет в хранилище
+                            let (storage, source) = attachment_object(&email, &prev.key, item);
+                            if (storage.fetch(&source, &path_to_temp_with_ind(&session.idu, &key, &(ind + 1))).await).is_ok() {
                                 ind += 1;
                                 list.push(BoxMailAttachmentItem {
                                     id: ind,
                                     size: item.size,
                                     file_name: item.file_name.clone(),
+                                    cid: item.cid.clone(),
                                 });
                             }
                         }
@@ -178,8 +351,9 @@ async fn db_message_attachments(session: &SessionStruct, data: &MessageRequest)
 
 async fn get_attachments(session: &SessionStruct, idb: &u64) -> Option<DBBox> {
     let idu = &session.idu;
-    let rows = db_query(DBBox::from, &format!("select * from emails.boxes where idu={idu} and idb={idb};"), &[]).await;
+    let mut rows = db_query(DBBox::from, &format!("select * from emails.boxes where idu={idu} and idb={idb};"), &[]).await;
     if rows.len() == 1 {
+        boxes_decrypt(idu, &mut rows).await;
         Some(rows[0].clone())
     } else {
         None
@@ -188,6 +362,11 @@ async fn get_attachments(session: &SessionStruct, idb: &u64) -> Option<DBBox> {
 
 async fn db_message_to_notes(session: &SessionStruct, notes_idp: i32, idb: u64) {
     if let Some(data) = get_attachments(session, &idb).await {
+        // заглушку вместо текста в заметку не переносим
+        if data.unreadable {
+            tracing::error!("db_message_to_notes: idb={idb} unreadable");
+            return;
+        }
         let content = data.content.clone();
         let sender = &data.sender;
 
@@ -246,53 +425,84 @@ pub async fn db_messages_route(session: &SessionStruct, data: MessagesRequest) {
 }
 
 async fn db_box_page(idu: &i32, email_box: &i32, page: &usize) -> Vec<DBBox> {
-    let limit: i64 = (*page as i64) * BY_PAGE;
-    db_query(DBBox::from, include_str!("../sql/select_box_page.sql"), &[idu, email_box, &limit, &BY_PAGE]).await
+    // размер страницы -- из настроек пользователя, BY_PAGE по умолчанию
+    let page = *page as i64;
+    let mut rows = db_query(DBBox::from, include_str!("../sql/select_box_page.sql"), &[idu, email_box, &page, &BY_PAGE]).await;
+    boxes_decrypt(idu, &mut rows).await;
+    rows
 }
 
-pub fn db_box_add_received(flag_spam: bool, current_email: String, sender: DBMailAddress, recipient: DBMailAddress, subject: String, content: String, attachments: Option<DBMailAttachments>) {
+pub fn db_box_add_received(flag_spam: bool, current_email: String, data: DBBoxInsert) {
     let box_num = box_type_index(if flag_spam { &MailBoxes::Trash } else { &MailBoxes::Inbox });
     let unread = !flag_spam;
     let idu = match USER_BY_EMAIL.lock() {
-        Ok(users) => {
-            match users.get(&current_email) {
-                Some(val) => *val,
-                None => {
-                    return;
-                }
-            }
-        }
+        Ok(users) => users.get(&current_email).copied(),
         Err(err) => {
             tracing::error!("db_box_insert[1]: {:?}", err);
-            return;
+            None
         }
     };
-    db_box_add(idu, box_num, unread, sender, recipient, subject, content, attachments);
+    match idu {
+        Some(idu) => db_box_add(idu, box_num, unread, data),
+        None => {
+            if let Some(attachments) = data.attachments {
+                tokio::task::spawn(async move { blobs_unref(&attachments).await });
+            }
+        }
+    }
 }
 
-pub fn db_box_add(idu: i32, box_num: usize, unread: bool, sender: DBMailAddress, recipient: DBMailAddress, subject: String, content: String, attachments: Option<DBMailAttachments>) {
+pub fn db_box_add(idu: i32, box_num: usize, unread: bool, data: DBBoxInsert) {
     tokio::task::spawn(async move {
         let mut fields: Vec<String> = Vec::new();
         let mut linked: Vec<String> = Vec::new();
         let mut values: Vec<String> = Vec::new();
 
-        if let Ok(txt) = serde_json::to_string(&sender) {
+        // json -- только параметром: в именах файлов и адресах может быть что угодно
+        if let Ok(txt) = serde_json::to_string(&data.sender) {
             fields.push("sender".to_string());
-            values.push(format!("$${}$$", txt));
+            linked.push(txt);
+            values.push(format!("${}::text::jsonb", linked.len()));
         }
 
-        if let Ok(txt) = serde_json::to_string(&recipient) {
+        if let Ok(txt) = serde_json::to_string(&data.recipient) {
             fields.push("recipient".to_string());
-            values.push(format!("$${}$$", txt));
+            linked.push(txt);
+            values.push(format!("${}::text::jsonb", linked.len()));
         }
 
-        if let Some(attachments) = attachments {
-            if let Ok(txt) = serde_json::to_string(&attachments) {
+        if let Some(attachments) = &data.attachments {
+            if let Ok(txt) = serde_json::to_string(attachments) {
                 fields.push("attachments".to_string());
-                values.push(format!("$${}$$", txt));
+                linked.push(txt);
+                values.push(format!("${}::text::jsonb", linked.len()));
             }
         }
 
+        // индекс строится по открытому тексту до шифрования
+        let (head, text) = search_document(&data.sender, &data.recipient, &data.subject, &data.content);
+        linked.push(head);
+        linked.push(text);
+        fields.push("search".to_string());
+        values.push(search_vector(linked.len() - 1, linked.len()));
+
+        // idb входит в проверяемые данные шифртекста, поэтому номер берется до записи;
+        // зашифровать не удалось -- письмо не записываем, открытым текстом в базу не пишем
+        let texts = match db_box_next_idb().await {
+            Some(idb) => db_box_texts(&idu, &idb, data.subject, data.content).await.map(|(subject, content)| (idb, subject, content)),
+            None => Err("no idb".to_string())
+        };
+        let (idb, subject, content) = match texts {
+            Ok(texts) => texts,
+            Err(err) => {
+                tracing::error!("db_box_add: idu={idu} {err}");
+                if let Some(attachments) = &data.attachments {
+                    blobs_unref(attachments).await;
+                }
+                return;
+            }
+        };
+
         fields.push("subject".to_string());
         linked.push(subject);
         values.push(format!("${}", linked.len()));
@@ -301,6 +511,27 @@ pub fn db_box_add(idu: i32, box_num: usize, unread: bool, sender: DBMailAddress,
         linked.push(content);
         values.push(format!("${}", linked.len()));
 
+        if let Some(source) = data.source {
+            fields.push("source".to_string());
+            linked.push(source);
+            values.push(format!("${}", linked.len()));
+        }
+
+        if let Some(message_id) = data.message_id {
+            fields.push("message_id".to_string());
+            linked.push(message_id);
+            values.push(format!("${}", linked.len()));
+        }
+
+        if let Some(security) = data.security {
+            fields.push("security".to_string());
+            linked.push(security);
+            values.push(format!("${}", linked.len()));
+        }
+
+        fields.push("idb".to_string());
+        values.push(idb.to_string());
+
         fields.push("idu".to_string());
         values.push(idu.to_string());
 
@@ -312,13 +543,149 @@ pub fn db_box_add(idu: i32, box_num: usize, unread: bool, sender: DBMailAddress,
 
         let prepared_linked = linked.iter().map(|a| a as &(dyn ToSql + Sync)).collect::<Vec<_>>();
 
-        let rows = db_query(DBBox::from, &format!("insert into emails.boxes ({}) values ({}) returning idb, date, unread, sender, recipient, subject, content, attachments;", fields.join(","), values.join(",")), &prepared_linked[..]).await;
+        let mut rows = db_query(DBBox::from, &format!("insert into emails.boxes ({}) values ({}) returning idb, date, unread, sender, recipient, subject, content, attachments, source, security;", fields.join(","), values.join(",")), &prepared_linked[..]).await;
         if rows.len() == 1 {
+            boxes_decrypt(&idu, &mut rows).await;
             send_to_user(&idu, box_num as i32, rows);
+        } else if let Some(attachments) = &data.attachments {
+            // ссылки на файлы взяты при их записи; письма нет -- возвращаем
+            blobs_unref(attachments).await;
         }
     });
 }
 
+async fn db_box_next_idb() -> Option<i64> {
+    db_query(|row| row.get(0), "select nextval(pg_get_serial_sequence('emails.boxes', 'idb'));", &[]).await.pop()
+}
+
+// тема и текст для записи в базу
+pub async fn db_box_texts(idu: &i32, idb: &i64, subject: String, content: String) -> Result<(String, String), String> {
+    let subject = text_encrypt(idu, &box_place(idb, "subject"), subject).await?;
+    let content = text_encrypt(idu, &box_place(idb, "content"), content).await?;
+    Ok((subject, content))
+}
+
+// второе значение -- письмо уже связано с исходным файлом
+pub async fn db_box_find(idu: &i32, source: &str, message_id: &Option<String>, data: &DBBoxInsert, received: &SystemTime) -> Option<(DBBox, bool)> {
+    let mut rows = db_query(DBBox::from, "select * from emails.boxes where idu=$1 and source=$2;", &[idu, &source]).await;
+    if rows.len() == 1 {
+        boxes_decrypt(idu, &mut rows).await;
+        return Some((rows[0].clone(), true));
+    }
+    if let Some(message_id) = message_id {
+        let mut rows = db_query(DBBox::from, "select * from emails.boxes where idu=$1 and message_id=$2;", &[idu, message_id]).await;
+        if rows.len() == 1 {
+            boxes_decrypt(idu, &mut rows).await;
+            return Some((rows[0].clone(), false));
+        }
+    }
+    // старые записи без source и message_id: отправитель, тема и время получения
+    // (по зашифрованной теме не найдется -- такие записи появились позже source)
+    let delta = Duration::from_secs(60 * 60);
+    let date_from = *received - delta;
+    let date_to = *received + delta;
+    let mut rows = db_query(DBBox::from, include_str!("../sql/select_box_legacy.sql"), &[idu, &data.sender.address, &data.subject, &date_from, &date_to]).await;
+    if rows.len() == 1 {
+        boxes_decrypt(idu, &mut rows).await;
+        Some((rows[0].clone(), false))
+    } else {
+        None
+    }
+}
+
+pub async fn db_box_source(idu: &i32, idb: &i64) -> Option<String> {
+    let rows = db_query(DBBoxSource::from, "select source from emails.boxes where idu=$1 and idb=$2;", &[idu, idb]).await;
+    if rows.len() == 1 {
+        rows[0].source.clone()
+    } else {
+        None
+    }
+}
+
+// владельца возвращаем, чтобы отличить чужое письмо (403) от несуществующего (404)
+pub async fn db_box_attachments(idb: &i64) -> Option<DBBoxAttachments> {
+    db_query(DBBoxAttachments::from, "select idb, idu, attachments from emails.boxes where idb=$1;", &[idb]).await.pop()
+}
+
+// письма с вложениями по порядку idb, для обслуживания хранилища
+pub async fn db_box_attachments_after(idb: &i64, limit: &i64) -> Vec<DBBoxAttachments> {
+    db_query(DBBoxAttachments::from, "select idb, idu, attachments from emails.boxes where idb>$1 and attachments is not null order by idb limit $2;", &[idb, limit]).await
+}
+
+pub async fn db_box_attachments_update(idb: &i64, attachments: &DBMailAttachments) -> bool {
+    match serde_json::to_string(attachments) {
+        Ok(txt) => db_update_query("update emails.boxes set attachments=$1::text::jsonb where idb=$2;", &[&txt, idb]).await,
+        Err(err) => {
+            tracing::error!("db_box_attachments_update {:?}", err);
+            false
+        }
+    }
+}
+
+// тема и текст писем по порядку idb, для перешифровки
+pub async fn db_box_texts_after(idb: &i64, limit: &i64) -> Vec<DBBoxText> {
+    db_query(DBBoxText::from, "select idb, idu, subject, content from emails.boxes where idb>$1 order by idb limit $2;", &[idb, limit]).await
+}
+
+pub async fn db_box_text_update(idb: &i64, subject: &str, content: &str) -> bool {
+    db_update_query("update emails.boxes set subject=$1, content=$2 where idb=$3;", &[&subject, &content, idb]).await
+}
+
+pub async fn db_box_update(idu: &i32, idb: &i64, data: DBBoxInsert) -> bool {
+    let mut fields: Vec<String> = Vec::new();
+    let mut linked: Vec<Option<String>> = Vec::new();
+
+    if let Ok(txt) = serde_json::to_string(&data.sender) {
+        linked.push(Some(txt));
+        fields.push(format!("sender=${}::text::jsonb", linked.len()));
+    }
+    if let Ok(txt) = serde_json::to_string(&data.recipient) {
+        linked.push(Some(txt));
+        fields.push(format!("recipient=${}::text::jsonb", linked.len()));
+    }
+    match data.attachments {
+        Some(attachments) => {
+            if let Ok(txt) = serde_json::to_string(&attachments) {
+                linked.push(Some(txt));
+                fields.push(format!("attachments=${}::text::jsonb", linked.len()));
+            }
+        }
+        None => {
+            fields.push("attachments=null".to_string());
+        }
+    }
+
+    let (head, text) = search_document(&data.sender, &data.recipient, &data.subject, &data.content);
+    linked.push(Some(head));
+    linked.push(Some(text));
+    fields.push(format!("search={}", search_vector(linked.len() - 1, linked.len())));
+
+    let (subject, content) = match db_box_texts(idu, idb, data.subject, data.content).await {
+        Ok(texts) => texts,
+        Err(err) => {
+            tracing::error!("db_box_update: idb={idb} {err}");
+            return false;
+        }
+    };
+    linked.push(Some(subject));
+    fields.push(format!("subject=${}", linked.len()));
+
+    linked.push(Some(content));
+    fields.push(format!("content=${}", linked.len()));
+
+    linked.push(data.source);
+    fields.push(format!("source=${}", linked.len()));
+
+    linked.push(data.message_id);
+    fields.push(format!("message_id=${}", linked.len()));
+
+    linked.push(data.security);
+    fields.push(format!("security=${}", linked.len()));
+
+    let prepared_linked = linked.iter().map(|a| a as &(dyn ToSql + Sync)).collect::<Vec<_>>();
+    db_update_query(&format!("update emails.boxes set {} where idb={idb} and idu={idu};", fields.join(",")), &prepared_linked[..]).await
+}
+
 fn send_to_user(idu: &i32, email_box: i32, data: Vec<DBBox>) {
     let result = DBPageResponse { email_box, page: 0, data, news: true };
     match serde_json::to_string(&result) {
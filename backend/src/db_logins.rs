@@ -0,0 +1,118 @@
+use serde::Serialize;
+use tokio_postgres::Row;
+
+use crate::db::{db_query, db_update_query};
+use crate::state::USER_BY_EMAIL;
+use crate::types::{DeviceInfo, SessionStruct};
+
+pub const LOGIN_SUCCESS: &str = "success";
+pub const LOGIN_FAILURE: &str = "failure";
+pub const LOGIN_LOCKED: &str = "locked";
+
+// бесплатные попытки, после них блокировка удваивается с каждой ошибкой
+const ACCOUNT_FREE_ATTEMPTS: i64 = 5;
+const IP_FREE_ATTEMPTS: i64 = 20;
+const LOCK_BASE_SECS: i64 = 30;
+const LOCK_MAX_SECS: i64 = 60 * 60;
+
+const LOGINS_SHOW: i64 = 30;
+const LOGINS_KEEP_DAYS: i32 = 90;
+
+// ошибки ящика считаем с последнего успешного входа, ошибки адреса -- за сутки
+const SELECT_FAILURES_ACCOUNT: &str = "select count(*) as fails, coalesce(extract(epoch from now() - max(created)), 0)::bigint as since
+from emails.logins
+where email=$1 and result='failure' and created > now() - interval '1 day'
+and created > coalesce((select max(created) from emails.logins where email=$1 and result='success'), '-infinity');";
+
+const SELECT_FAILURES_IP: &str = "select count(*) as fails, coalesce(extract(epoch from now() - max(created)), 0)::bigint as since
+from emails.logins
+where ip=$1 and result='failure' and created > now() - interval '1 day';";
+
+#[derive(Debug, Clone)]
+struct DBLoginFailures {
+    fails: i64,
+    since: i64,
+}
+
+impl From<Row> for DBLoginFailures {
+    fn from(row: Row) -> Self {
+        Self {
+            fails: row.get("fails"),
+            since: row.get("since"),
+        }
+    }
+}
+
+impl DBLoginFailures {
+    fn retry_after(&self, free: i64) -> i64 {
+        if self.fails < free {
+            return 0;
+        }
+        let lock = LOCK_BASE_SECS.saturating_mul(1 << (self.fails - free).min(16)).min(LOCK_MAX_SECS);
+        (lock - self.since).max(0)
+    }
+}
+
+// сколько секунд ждать до следующей попытки (0 -- можно входить)
+pub async fn db_login_retry_after(mail_box: &str, device: &DeviceInfo) -> i64 {
+    let account = db_query(DBLoginFailures::from, SELECT_FAILURES_ACCOUNT, &[&mail_box]).await
+        .pop().map(|row| row.retry_after(ACCOUNT_FREE_ATTEMPTS)).unwrap_or_default();
+    // неизвестный адрес не должен объединять всех в одну очередь блокировки
+    if device.ip.is_empty() {
+        return account;
+    }
+    let ip = db_query(DBLoginFailures::from, SELECT_FAILURES_IP, &[&device.ip]).await
+        .pop().map(|row| row.retry_after(IP_FREE_ATTEMPTS)).unwrap_or_default();
+    account.max(ip)
+}
+
+pub async fn db_login_record(mail_box: &str, device: &DeviceInfo, result: &str) {
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(mail_box).cloned(),
+        Err(_) => None
+    };
+    if result != LOGIN_SUCCESS {
+        tracing::warn!("db_login_record: {result} {mail_box} ip={}", device.ip);
+    }
+    db_update_query(
+        "insert into emails.logins (idu, email, ip, user_agent, result) values ($1, $2, $3, $4, $5);",
+        &[&idu, &mail_box, &device.ip, &device.user_agent, &result],
+    ).await;
+}
+
+// ===
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DBLogin {
+    pub created: String,
+    pub ip: String,
+    pub user_agent: String,
+    pub result: String,
+}
+
+impl From<Row> for DBLogin {
+    fn from(row: Row) -> Self {
+        Self {
+            created: row.get("created"),
+            ip: row.get("ip"),
+            user_agent: row.get("user_agent"),
+            result: row.get("result"),
+        }
+    }
+}
+
+pub async fn db_logins_route(session: &SessionStruct) -> Vec<DBLogin> {
+    db_query(
+        DBLogin::from,
+        "select to_char(created, 'DD.MM.YYYY HH24:MI') as created, ip, user_agent, result
+        from emails.logins
+        where idu=$1
+        order by created desc
+        limit $2;",
+        &[&session.idu, &LOGINS_SHOW],
+    ).await
+}
+
+pub async fn db_logins_cleanup() {
+    db_update_query("delete from emails.logins where created < now() - make_interval(days => $1);", &[&LOGINS_KEEP_DAYS]).await;
+}
@@ -149,7 +149,8 @@ async fn db_update(session: &SessionStruct, data: NotesChannel) {
         if event_current.date.is_empty() {
             fields.push("event=null".to_string());
         } else if let Ok(txt) = serde_json::to_string(&event_current) {
-            fields.push(format!("event=$${}$$", txt));
+            values.push(txt);
+            fields.push(format!("event=${}::text::jsonb", values.len()));
         }
     }
     if !fields.is_empty() {
@@ -0,0 +1,210 @@
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio_postgres::Row;
+
+use shared::types::PgpRequest;
+
+use crate::blobs::attachment_object;
+use crate::crypt::{secret_encrypt, text_open};
+use crate::db::{db_query, db_update_query};
+use crate::db_boxes::db_box_attachments;
+use crate::db_user::db_user_email;
+use crate::pgp::{armor, certs_read, certs_read_public, Cert};
+use crate::state::USER_BY_EMAIL;
+use crate::types::SessionStruct;
+use crate::utils::is_valid_key;
+
+// файл ключа в base64 или вложение: с подключами и подписями ключ бывает в десятки килобайт
+const KEY_MAX: u64 = 256 * 1024;
+
+// место закрытой части для шифрования: строка ключа пользователя
+pub fn pgp_secret_place(fingerprint: &str) -> String {
+    format!("pgp_keys.secret:{fingerprint}")
+}
+
+#[derive(Debug, Clone)]
+struct DBPgpKey {
+    fingerprint: String,
+    cert: String,
+    secret: Option<String>,
+}
+
+impl From<Row> for DBPgpKey {
+    fn from(row: Row) -> Self {
+        Self {
+            fingerprint: row.get("fingerprint"),
+            cert: row.get("cert"),
+            secret: row.get("secret"),
+        }
+    }
+}
+
+// все ключи пользователя: свои -- с закрытой частью
+pub async fn db_pgp_keyring(idu: &i32) -> Vec<Cert> {
+    let rows = db_query(DBPgpKey::from, "select fingerprint, cert, secret from emails.pgp_keys where idu=$1 order by created;", &[idu]).await;
+    let mut result = vec![];
+    for row in rows {
+        let data = match row.secret {
+            Some(secret) => match text_open(idu, &pgp_secret_place(&row.fingerprint), secret).await {
+                Ok(secret) => secret,
+                Err(err) => {
+                    // закрытая часть недоступна -- ключ остается открытым
+                    tracing::error!("db_pgp_keyring: idu={idu} {err}");
+                    row.cert
+                }
+            },
+            None => row.cert
+        };
+        match certs_read(data.as_bytes(), None) {
+            Ok(certs) => result.extend(certs),
+            Err(err) => tracing::error!("db_pgp_keyring: idu={idu} {err}")
+        }
+    }
+    result
+}
+
+// связка получателя для расшифровки входящего
+pub async fn db_pgp_keyring_by_email(email: &str) -> Vec<Cert> {
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(email).cloned(),
+        Err(_) => None
+    };
+    match idu {
+        Some(idu) => db_pgp_keyring(&idu).await,
+        None => vec![]
+    }
+}
+
+// закрытая часть хранится без пароля, поэтому только зашифрованной
+async fn db_pgp_save(idu: &i32, cert: &Cert) -> Result<(), String> {
+    let fingerprint = cert.primary.fingerprint_hex();
+    let secret = match cert.has_secret() {
+        true => match secret_encrypt(idu, &pgp_secret_place(&fingerprint), armor("PRIVATE KEY BLOCK", &cert.to_bytes(true))).await {
+            Some(secret) => Some(secret),
+            None => return Err("Закрытый ключ можно сохранить только при настроенном шифровании".to_string())
+        },
+        false => None
+    };
+    let public = armor("PUBLIC KEY BLOCK", &cert.to_bytes(false));
+    // открытый ключ поверх своего не стирает сохраненную закрытую часть
+    let saved = db_update_query(
+        "insert into emails.pgp_keys (idu, fingerprint, emails, cert, secret) values ($1, $2, $3, $4, $5) \
+        on conflict (idu, fingerprint) do update set emails=$3, cert=$4, secret=coalesce($5, emails.pgp_keys.secret);",
+        &[idu, &fingerprint, &cert.emails(), &public, &secret],
+    ).await;
+    if saved { Ok(()) } else { Err("Ключ не сохранен".to_string()) }
+}
+
+// закрытые части для перешифровки в "crypt encrypt": idu, отпечаток, шифртекст
+pub async fn db_pgp_secrets() -> Vec<(i32, String, String)> {
+    db_query(|row| (row.get("idu"), row.get("fingerprint"), row.get("secret")), "select idu, fingerprint, secret from emails.pgp_keys where secret is not null order by idu, fingerprint;", &[]).await
+}
+
+pub async fn db_pgp_secret_update(idu: &i32, fingerprint: &str, secret: &str) -> bool {
+    db_update_query("update emails.pgp_keys set secret=$3 where idu=$1 and fingerprint=$2;", &[idu, &fingerprint, &secret]).await
+}
+
+// из письма берем только открытые ключи: чужую закрытую часть не сохраняем
+async fn db_pgp_import(idu: &i32, data: &[u8], passphrase: Option<&str>, public_only: bool) -> Result<usize, String> {
+    let certs = if public_only { certs_read_public(data) } else { certs_read(data, passphrase) };
+    let certs = certs.map_err(|err| {
+        tracing::warn!("db_pgp_import: idu={idu} {err}");
+        match err.as_str() {
+            "passphrase required" | "wrong passphrase" => "Неверный пароль закрытого ключа".to_string(),
+            _ => "Файл не содержит ключей OpenPGP".to_string()
+        }
+    })?;
+    if certs.is_empty() {
+        return Err("В файле нет ключей с подтвержденным адресом".to_string());
+    }
+    for cert in certs.iter() {
+        db_pgp_save(idu, cert).await?;
+    }
+    Ok(certs.len())
+}
+
+// ключ из вложения письма
+async fn db_pgp_attachment(session: &SessionStruct, idb: &u64, id: &usize) -> Option<Vec<u8>> {
+    let row = db_box_attachments(&(*idb as i64)).await?;
+    if row.idu != session.idu {
+        tracing::warn!("db_pgp_attachment: idu={} requested idb={idb}", session.idu);
+        return None;
+    }
+    let attachments = row.attachments.filter(|attachments| is_valid_key(&attachments.key))?;
+    let item = attachments.list.iter().find(|item| item.id == *id && item.size <= KEY_MAX)?;
+    let email = db_user_email(&session.idu).await?;
+    let (storage, key) = attachment_object(&email, &attachments.key, item);
+    let mut stream = match storage.get(&key, None).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            tracing::error!("db_pgp_attachment {err}");
+            return None;
+        }
+    };
+    let mut data = vec![];
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) if data.len() as u64 + chunk.len() as u64 <= KEY_MAX => data.extend_from_slice(&chunk),
+            Ok(_) => return None,
+            Err(err) => {
+                tracing::error!("db_pgp_attachment {err}");
+                return None;
+            }
+        }
+    }
+    Some(data)
+}
+
+// ===
+
+#[derive(Debug, Default, Serialize)]
+pub struct PgpKeyItem {
+    fingerprint: String,
+    emails: Vec<String>,
+    secret: bool,
+    usable: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PgpResult {
+    result: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    keys: Vec<PgpKeyItem>,
+}
+
+pub async fn db_pgp_route(session: &SessionStruct, data: PgpRequest) -> PgpResult {
+    let idu = &session.idu;
+    let mut result = PgpResult::default();
+
+    let imported = if let Some(fingerprint) = data.remove {
+        match db_update_query("delete from emails.pgp_keys where idu=$1 and fingerprint=$2;", &[idu, &fingerprint]).await {
+            true => Ok(0),
+            false => Err("Ключ не найден".to_string())
+        }
+    } else if let Some(key) = data.key {
+        match base64::decode(key.trim()) {
+            Ok(data_key) if data_key.len() as u64 <= KEY_MAX => db_pgp_import(idu, &data_key, data.passphrase.as_deref().filter(|passphrase| !passphrase.is_empty()), false).await,
+            _ => Err("Файл ключа не подходит".to_string())
+        }
+    } else if let (Some(idb), Some(id)) = (data.idb, data.id) {
+        match db_pgp_attachment(session, &idb, &id).await {
+            Some(data) => db_pgp_import(idu, &data, None, true).await,
+            None => Err("Вложение не найдено".to_string())
+        }
+    } else {
+        Ok(0)
+    };
+    match imported {
+        Ok(_) => result.result = true,
+        Err(err) => result.error = Some(err)
+    }
+
+    result.keys = db_pgp_keyring(idu).await.iter().map(|cert| PgpKeyItem {
+        fingerprint: cert.primary.fingerprint_hex(),
+        emails: cert.emails(),
+        secret: cert.has_secret(),
+        usable: cert.usable(),
+    }).collect();
+    result
+}
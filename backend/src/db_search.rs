@@ -0,0 +1,105 @@
+use tokio_postgres::types::ToSql;
+
+use shared::constants::BY_PAGE;
+use shared::types::{MailBoxes, SearchRequest};
+use shared::utils::box_type_index;
+
+use crate::crypt::{box_place, boxes_decrypt, crypt_enabled, text_open};
+use crate::db::{db_query, db_update_query};
+use crate::db_types::{DBBox, DBBoxSearch, DBPageResponse};
+use crate::search::{search_document, search_like, search_parse, search_vector, SearchQuery};
+use crate::types::SessionStruct;
+
+// результаты приходят в том же виде, что страница ящика
+pub async fn db_search_route(session: &SessionStruct, data: SearchRequest) -> DBPageResponse {
+    let query = search_parse(&data.query);
+    let rows = if query.is_empty() { vec![] } else { db_search(&session.idu, &query, &data.page).await };
+    DBPageResponse { email_box: box_type_index(&MailBoxes::Search) as i32, page: data.page, news: false, data: rows }
+}
+
+async fn db_search(idu: &i32, query: &SearchQuery, page: &usize) -> Vec<DBBox> {
+    // $1..$3 -- пользователь и страница, дальше -- значения фильтров
+    let mut linked: Vec<String> = Vec::new();
+    let mut param = |value: String| {
+        linked.push(value);
+        format!("${}", linked.len() + 3)
+    };
+    let mut conditions = vec!["idu=$1".to_string()];
+
+    if !query.words.is_empty() {
+        let words = param(query.words.join(" "));
+        conditions.push(format!("search @@ (websearch_to_tsquery('russian', {words}) || websearch_to_tsquery('english', {words}))"));
+    }
+    for value in query.from.iter() {
+        let value = param(search_like(value));
+        conditions.push(format!("(sender->>'address' ilike {value} or sender->>'name' ilike {value})"));
+    }
+    for value in query.to.iter() {
+        let value = param(search_like(value));
+        conditions.push(format!("(recipient->>'address' ilike {value} or recipient->>'name' ilike {value})"));
+    }
+    if query.has_attachment {
+        conditions.push("jsonb_array_length(attachments->'list')>0".to_string());
+    }
+    if let Some(before) = query.before {
+        conditions.push(format!("date<to_date({}, 'YYYY-MM-DD')", param(before.to_string())));
+    }
+    if let Some(after) = query.after {
+        conditions.push(format!("date>=to_date({}, 'YYYY-MM-DD')", param(after.to_string())));
+    }
+    if let Some(email_box) = query.email_box {
+        conditions.push(format!("box={email_box}"));
+    }
+
+    let sql = format!(
+        "select idb, date, unread, sender, recipient, subject, content, attachments, source, security from emails.boxes where {} order by date desc offset $2 * coalesce((select by_page from emails.users where idu=$1), $3) limit coalesce((select by_page from emails.users where idu=$1), $3);",
+        conditions.join(" and ")
+    );
+    let page = *page as i64;
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![idu, &page, &BY_PAGE];
+    params.extend(linked.iter().map(|a| a as &(dyn ToSql + Sync)));
+
+    let mut rows = db_query(DBBox::from, &sql, &params[..]).await;
+    boxes_decrypt(idu, &mut rows).await;
+    rows
+}
+
+async fn db_search_after(idb: &i64, limit: &i64) -> Vec<DBBoxSearch> {
+    db_query(DBBoxSearch::from, "select idb, idu, sender, recipient, subject, content from emails.boxes where idb>$1 order by idb limit $2;", &[idb, limit]).await
+}
+
+// индекс для писем, записанных до его появления; зашифрованные расшифровываются на время построения,
+// при шифровании индексируются только адреса; возвращает число писем и ошибки по письмам
+pub async fn db_search_reindex(batch: &i64) -> (usize, Vec<String>) {
+    let mut indexed = 0;
+    let mut failed = vec![];
+    let mut last = 0i64;
+    loop {
+        let rows = db_search_after(&last, batch).await;
+        if rows.is_empty() {
+            break;
+        }
+        for row in rows {
+            last = row.idb;
+            let (subject, content) = if crypt_enabled() {
+                (String::new(), String::new())
+            } else {
+                match (text_open(&row.idu, &box_place(&row.idb, "subject"), row.subject).await, text_open(&row.idu, &box_place(&row.idb, "content"), row.content).await) {
+                    (Ok(subject), Ok(content)) => (subject, content),
+                    (Err(err), _) | (_, Err(err)) => {
+                        failed.push(format!("idb={}: {err}", row.idb));
+                        continue;
+                    }
+                }
+            };
+            let (head, text) = search_document(&row.sender, &row.recipient, &subject, &content);
+            let sql = format!("update emails.boxes set search={} where idb=$3;", search_vector(1, 2));
+            if db_update_query(&sql, &[&head, &text, &row.idb]).await {
+                indexed += 1;
+            } else {
+                failed.push(format!("idb={}: not updated", row.idb));
+            }
+        }
+    }
+    (indexed, failed)
+}
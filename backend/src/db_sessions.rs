@@ -0,0 +1,219 @@
+use std::time::{Duration, SystemTime};
+
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+use tokio_postgres::Row;
+
+use shared::types::SessionsRequest;
+
+use crate::cookie::cookie_verify;
+use crate::db::{db_query, db_update_query};
+use crate::sse::{sse_active_sessions, sse_close_sessions, sse_close_sids};
+use crate::state::USER_AUTH;
+use crate::types::{DeviceInfo, SessionCached, SessionStruct};
+use crate::utils::get_hash;
+
+// сеанс завершается после двух недель без активности и в любом случае через 90 дней
+const SESSION_IDLE_DAYS: i32 = 14;
+const SESSION_ABSOLUTE_DAYS: i32 = 90;
+// сеанс из кеша сверяется с базой не реже раза в минуту: так действуют простой
+// и отзыв сеанса из другого процесса (команда password)
+const SESSION_RECHECK_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DBSession {
+    pub ids: i32,
+    pub created: String,
+    pub last_active: String,
+    pub user_agent: String,
+    pub ip: String,
+    pub current: bool,
+}
+
+impl From<Row> for DBSession {
+    fn from(row: Row) -> Self {
+        Self {
+            ids: row.get("ids"),
+            created: row.get("created"),
+            last_active: row.get("last_active"),
+            user_agent: row.get("user_agent"),
+            ip: row.get("ip"),
+            current: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBSessionIdu {
+    pub ids: i32,
+    pub idu: i32,
+}
+
+impl From<Row> for DBSessionIdu {
+    fn from(row: Row) -> Self {
+        Self {
+            ids: row.get("ids"),
+            idu: row.get("idu"),
+        }
+    }
+}
+
+// в базе хранится только хеш токена
+pub async fn db_session_create(idu: &i32, device: &DeviceInfo) -> Option<(i32, String)> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    let rows = db_query(
+        DBSessionIdu::from,
+        "insert into emails.sessions (idu, token, user_agent, ip) values ($1, $2, $3, $4) returning ids, idu;",
+        &[idu, &get_hash(token.clone()), &device.user_agent, &device.ip],
+    ).await;
+    rows.first().map(|row| (row.ids, token))
+}
+
+#[derive(Debug, Clone)]
+pub struct DBSessionFound {
+    pub ids: i32,
+    pub idu: i32,
+    pub created: SystemTime,
+}
+
+impl From<Row> for DBSessionFound {
+    fn from(row: Row) -> Self {
+        Self {
+            ids: row.get("ids"),
+            idu: row.get("idu"),
+            created: row.get("created"),
+        }
+    }
+}
+
+// действующий сеанс по токену, заодно отмечаем активность
+pub async fn db_session_find(token: &str, device: &DeviceInfo) -> Option<DBSessionFound> {
+    if token.is_empty() {
+        return None;
+    }
+    db_query(
+        DBSessionFound::from,
+        "update emails.sessions set last_active=now(), ip=$2
+        where token=$1 and last_active > now() - make_interval(days => $3) and created > now() - make_interval(days => $4)
+        returning ids, idu, created;",
+        &[&get_hash(token.to_string()), &device.ip, &SESSION_IDLE_DAYS, &SESSION_ABSOLUTE_DAYS],
+    ).await.pop()
+}
+
+// сеанс по подписанной cookie: сначала из кеша, затем из базы
+pub async fn db_session_by_cookie(cookie: &Option<String>, device: &DeviceInfo) -> Option<SessionStruct> {
+    let token = cookie_verify(cookie.as_deref()?)?;
+    let key = get_hash(token.clone());
+    let now = SystemTime::now();
+    if let Ok(mut user_auth) = USER_AUTH.lock() {
+        match user_auth.get(&key) {
+            Some(cached) if session_fresh(cached, now) => return Some(cached.session.clone()),
+            Some(_) => {
+                user_auth.remove(&key);
+            }
+            None => {}
+        }
+    }
+    let found = db_session_find(&token, device).await?;
+    let session = SessionStruct { sid: found.ids, ..SessionStruct::new(&found.idu) };
+    if let Ok(mut user_auth) = USER_AUTH.lock() {
+        user_auth.insert(key, SessionCached { session: session.clone(), created: found.created, checked: now });
+    }
+    Some(session)
+}
+
+// кеш действует до полного срока сеанса и не дольше SESSION_RECHECK_SECS после сверки с базой,
+// сверка отмечает активность, поэтому простой больше двух недель в кеше не пропускается
+fn session_fresh(cached: &SessionCached, now: SystemTime) -> bool {
+    let absolute = Duration::from_secs(SESSION_ABSOLUTE_DAYS as u64 * 24 * 60 * 60);
+    let recheck = Duration::from_secs(SESSION_RECHECK_SECS);
+    cached.created + absolute > now && cached.checked + recheck > now && cached.checked <= now
+}
+
+// после смены пароля остальные сеансы пользователя завершаются; keep -- текущий, если пароль сменен в нем
+pub async fn db_sessions_revoke(idu: &i32, keep: Option<i32>) -> bool {
+    let keep = keep.unwrap_or_default();
+    let removed = db_update_query("delete from emails.sessions where idu=$1 and ids<>$2;", &[idu, &keep]).await;
+    sessions_drop(idu, |sid| sid != keep);
+    removed
+}
+
+pub async fn db_sessions_route(session: &SessionStruct, data: SessionsRequest) -> Vec<DBSession> {
+    let idu = &session.idu;
+    if data.logout.unwrap_or_default() {
+        db_update_query("delete from emails.sessions where idu=$1 and ids=$2;", &[idu, &session.sid]).await;
+        sessions_drop(idu, |sid| sid == session.sid);
+        return vec![];
+    }
+    if data.logout_others.unwrap_or_default() {
+        db_update_query("delete from emails.sessions where idu=$1 and ids<>$2;", &[idu, &session.sid]).await;
+        sessions_drop(idu, |sid| sid != session.sid);
+    }
+    let mut rows = db_query(
+        DBSession::from,
+        "select ids, to_char(created, 'DD.MM.YYYY HH24:MI') as created, to_char(last_active, 'DD.MM.YYYY HH24:MI') as last_active, user_agent, ip
+        from emails.sessions
+        where idu=$1
+        order by last_active desc;",
+        &[idu],
+    ).await;
+    for row in rows.iter_mut() {
+        row.current = row.ids == session.sid;
+    }
+    rows
+}
+
+// раз в час: подключенные сеансы считаем активными, просроченные удаляем,
+// кеш сбрасываем, чтобы следующий запрос снова отметил активность в базе
+pub async fn db_sessions_cleanup() {
+    let active = sse_active_sessions();
+    if let Ok(mut user_auth) = USER_AUTH.lock() {
+        user_auth.clear();
+    }
+    if !active.is_empty() {
+        // каналы сеансов, удаленных из другого процесса, закрываются здесь
+        let found = db_query(|row| row.get::<_, i32>("ids"), "update emails.sessions set last_active=now() where ids = any($1) returning ids;", &[&active]).await;
+        sse_close_sids(|sid| !found.contains(&sid));
+    }
+    let removed = db_query(
+        DBSessionIdu::from,
+        "delete from emails.sessions
+        where last_active < now() - make_interval(days => $1) or created < now() - make_interval(days => $2)
+        returning ids, idu;",
+        &[&SESSION_IDLE_DAYS, &SESSION_ABSOLUTE_DAYS],
+    ).await;
+    for row in removed.iter() {
+        sessions_drop(&row.idu, |sid| sid == row.ids);
+    }
+    tracing::info!("db_sessions_cleanup: removed {}", removed.len());
+}
+
+// кеш и открытые каналы завершенных сеансов
+fn sessions_drop(idu: &i32, filter: impl Fn(i32) -> bool) {
+    if let Ok(mut user_auth) = USER_AUTH.lock() {
+        user_auth.retain(|_, cached| cached.session.idu != *idu || !filter(cached.session.sid));
+    }
+    sse_close_sessions(idu, filter);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_session_expiry() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let cached = |created: SystemTime, checked: SystemTime| SessionCached { session: SessionStruct::new(&1), created, checked };
+
+        assert!(session_fresh(&cached(now - day, now - Duration::from_secs(10)), now));
+        // давно не сверялся с базой: простой и отзыв проверяет база
+        assert!(!session_fresh(&cached(now - day, now - Duration::from_secs(SESSION_RECHECK_SECS)), now));
+        // полный срок истек, хотя сверка была только что
+        assert!(!session_fresh(&cached(now - day * SESSION_ABSOLUTE_DAYS as u32, now), now));
+        // часы сдвинулись назад
+        assert!(!session_fresh(&cached(now, now + Duration::from_secs(10)), now));
+    }
+}
@@ -0,0 +1,131 @@
+use serde::Serialize;
+use tokio_postgres::Row;
+
+use shared::types::SmimeRequest;
+
+use crate::crypt::{crypt_enabled, secret_encrypt, text_open};
+use crate::db::{db_query, db_update_query};
+use crate::smime::{cert_email, cert_expires, cert_subject, pkcs12_import, SmimeSigner};
+use crate::state::USER_BY_EMAIL;
+use crate::types::SessionStruct;
+
+// PKCS#12 в base64 с цепочкой сертификатов
+const PKCS12_MAX: usize = 48 * 1024;
+
+// место закрытого ключа для шифрования: одна строка на пользователя
+pub const SMIME_KEY_PLACE: &str = "smime_certs.key";
+
+#[derive(Debug, Clone)]
+struct DBSmimeCert {
+    cert: String,
+    key: String,
+}
+
+impl From<Row> for DBSmimeCert {
+    fn from(row: Row) -> Self {
+        Self {
+            cert: row.get("cert"),
+            key: row.get("key"),
+        }
+    }
+}
+
+pub async fn db_smime_signer(idu: &i32) -> Option<SmimeSigner> {
+    let row = db_query(DBSmimeCert::from, "select cert, key from emails.smime_certs where idu=$1;", &[idu]).await.pop()?;
+    let key = match text_open(idu, SMIME_KEY_PLACE, row.key).await {
+        Ok(key) => key,
+        Err(err) => {
+            tracing::error!("db_smime_signer: idu={idu} {err}");
+            return None;
+        }
+    };
+    match SmimeSigner::from_pem(&row.cert, &key) {
+        Ok(signer) => Some(signer),
+        Err(err) => {
+            tracing::error!("db_smime_signer: idu={idu} {err}");
+            None
+        }
+    }
+}
+
+// ключ получателя для расшифровки входящего
+pub async fn db_smime_signer_by_email(email: &str) -> Option<SmimeSigner> {
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(email).cloned(),
+        Err(_) => None
+    }?;
+    db_smime_signer(&idu).await
+}
+
+async fn db_smime_save(idu: &i32, signer: &SmimeSigner) -> Result<(), String> {
+    let (cert, key) = signer.to_pem().map_err(|err| {
+        tracing::error!("db_smime_save: {err}");
+        "Сертификат не сохранен".to_string()
+    })?;
+    let key = secret_encrypt(idu, SMIME_KEY_PLACE, key).await.ok_or("Нет ключа шифрования, сертификат не сохранен")?;
+    let saved = db_update_query(
+        "insert into emails.smime_certs (idu, cert, key) values ($1, $2, $3) on conflict (idu) do update set cert=$2, key=$3, created=now();",
+        &[idu, &cert, &key],
+    ).await;
+    if saved { Ok(()) } else { Err("Сертификат не сохранен".to_string()) }
+}
+
+// ключи, записанные открытым текстом до настройки шифрования, -- для "crypt encrypt"
+pub async fn db_smime_keys() -> Vec<(i32, String)> {
+    db_query(|row| (row.get("idu"), row.get("key")), "select idu, key from emails.smime_certs order by idu;", &[]).await
+}
+
+pub async fn db_smime_key_update(idu: &i32, key: &str) -> bool {
+    db_update_query("update emails.smime_certs set key=$2 where idu=$1;", &[idu, &key]).await
+}
+
+// ===
+
+#[derive(Debug, Default, Serialize)]
+pub struct SmimeResult {
+    result: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    present: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<String>,
+}
+
+pub async fn db_smime_route(session: &SessionStruct, data: SmimeRequest) -> SmimeResult {
+    let idu = &session.idu;
+    let mut result = SmimeResult::default();
+
+    if data.remove.unwrap_or_default() {
+        result.result = db_update_query("delete from emails.smime_certs where idu=$1;", &[idu]).await;
+    } else if data.pkcs12.is_some() && !crypt_enabled() {
+        // закрытый ключ хранится без пароля файла: без шифрования базы его не принимаем
+        result.error = Some("Сертификат с закрытым ключом можно сохранить только при настроенном шифровании".to_string());
+    } else if let Some(pkcs12) = data.pkcs12.filter(|pkcs12| pkcs12.len() <= PKCS12_MAX) {
+        let imported = base64::decode(pkcs12.trim())
+            .map_err(|err| err.to_string())
+            .and_then(|der| pkcs12_import(&der, &data.password.unwrap_or_default()));
+        let saved = match imported {
+            Ok(signer) => db_smime_save(idu, &signer).await,
+            Err(err) => {
+                tracing::warn!("db_smime_route: idu={idu} {err}");
+                Err("Неверный файл или пароль".to_string())
+            }
+        };
+        match saved {
+            Ok(_) => result.result = true,
+            Err(err) => result.error = Some(err)
+        }
+    }
+
+    if let Some(signer) = db_smime_signer(idu).await {
+        result.present = true;
+        result.subject = Some(cert_subject(&signer.cert));
+        result.email = cert_email(&signer.cert);
+        result.expires = Some(cert_expires(&signer.cert));
+    }
+    result
+}
@@ -0,0 +1,135 @@
+use serde::Serialize;
+use tokio_postgres::Row;
+
+use shared::types::TotpRequest;
+
+use crate::db::{db_query, db_update_query};
+use crate::db_user::db_user_email;
+use crate::totp::{recovery_codes_generate, totp_qr_svg, totp_secret_generate, totp_uri, totp_verify};
+use crate::types::SessionStruct;
+use crate::utils::get_hash;
+
+#[derive(Debug, Clone)]
+struct DBTotp {
+    secret: Option<String>,
+    pending: Option<String>,
+    last: i64,
+}
+
+impl From<Row> for DBTotp {
+    fn from(row: Row) -> Self {
+        Self {
+            secret: row.get("totp_secret"),
+            pending: row.get("totp_pending"),
+            last: row.get("totp_last"),
+        }
+    }
+}
+
+async fn db_totp_select(idu: &i32) -> Option<DBTotp> {
+    db_query(DBTotp::from, "select totp_secret, totp_pending, totp_last from emails.users where idu=$1;", &[idu]).await.pop()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TotpCheck {
+    Disabled,
+    Required,
+    Passed,
+    Failed,
+}
+
+// второй шаг входа: код из приложения или один из кодов восстановления
+pub async fn db_totp_check(idu: &i32, code: Option<&String>) -> TotpCheck {
+    let totp = match db_totp_select(idu).await {
+        Some(totp) => totp,
+        None => return TotpCheck::Failed
+    };
+    let secret = match totp.secret {
+        Some(secret) => secret,
+        None => return TotpCheck::Disabled
+    };
+    let code = match code.map(|code| code.trim()).filter(|code| !code.is_empty()) {
+        Some(code) => code,
+        None => return TotpCheck::Required
+    };
+    if let Some(step) = totp_verify(&secret, code, totp.last) {
+        // параллельный вход с тем же кодом уже сдвинул totp_last -- повтор отклоняем
+        let updated = db_query(
+            |row| row.get::<_, i32>("idu"),
+            "update emails.users set totp_last=$2 where idu=$1 and totp_last<$2 returning idu;",
+            &[idu, &step],
+        ).await;
+        return if updated.is_empty() { TotpCheck::Failed } else { TotpCheck::Passed };
+    }
+    let recovery = get_hash(code.to_lowercase().replace([' ', '-'], ""));
+    let used = db_query(
+        |row| row.get::<_, i32>("idr"),
+        "delete from emails.recovery_codes where idu=$1 and code=$2 returning idr;",
+        &[idu, &recovery],
+    ).await;
+    if used.is_empty() {
+        TotpCheck::Failed
+    } else {
+        tracing::info!("db_totp_check: recovery code used idu={idu}");
+        TotpCheck::Passed
+    }
+}
+
+pub async fn db_totp_reset(idu: &i32) -> bool {
+    db_update_query("delete from emails.recovery_codes where idu=$1;", &[idu]).await
+        && db_update_query("update emails.users set totp_secret=null, totp_pending=null where idu=$1;", &[idu]).await
+}
+
+// ===
+
+#[derive(Debug, Default, Serialize)]
+pub struct TotpResult {
+    result: bool,
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codes: Option<Vec<String>>,
+}
+
+pub async fn db_totp_route(session: &SessionStruct, data: TotpRequest) -> TotpResult {
+    let idu = &session.idu;
+    let mut result = TotpResult::default();
+
+    if data.enroll.unwrap_or_default() {
+        // секрет подключается только после подтверждения кодом
+        if let Some(email) = db_user_email(idu).await {
+            let secret = totp_secret_generate();
+            if db_update_query("update emails.users set totp_pending=$2 where idu=$1;", &[idu, &secret]).await {
+                let uri = totp_uri(&secret, &email);
+                result.qr = Some(totp_qr_svg(&uri));
+                result.uri = Some(uri);
+                result.result = true;
+            }
+        }
+    } else if let Some(code) = data.confirm {
+        if let Some(pending) = db_totp_select(idu).await.and_then(|totp| totp.pending) {
+            if let Some(step) = totp_verify(&pending, &code, 0) {
+                let codes = recovery_codes_generate();
+                db_update_query(
+                    "update emails.users set totp_secret=totp_pending, totp_pending=null, totp_last=$2 where idu=$1;",
+                    &[idu, &step],
+                ).await;
+                db_update_query("delete from emails.recovery_codes where idu=$1;", &[idu]).await;
+                let hashes = codes.iter().map(|code| get_hash(code.clone())).collect::<Vec<_>>();
+                db_update_query("insert into emails.recovery_codes (idu, code) select $1, unnest($2::text[]);", &[idu, &hashes]).await;
+                result.codes = Some(codes);
+                result.result = true;
+            }
+        }
+    } else if let Some(code) = data.disable {
+        if db_totp_check(idu, Some(&code)).await == TotpCheck::Passed {
+            result.result = db_totp_reset(idu).await;
+        }
+    }
+
+    result.enabled = db_totp_select(idu).await.map(|totp| totp.secret.is_some()).unwrap_or_default();
+    result
+}
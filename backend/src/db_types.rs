@@ -1,5 +1,5 @@
 use std::error::Error as StdError;
-use std::time::SystemTime;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -123,6 +123,7 @@ pub struct DBPageResponse {
 pub struct DBBox {
     pub idb: i64,
     pub date: String,
+    pub timestamp: i64,
     pub order: u64,
     pub unread: bool,
     pub sender: DBMailAddress,
@@ -130,9 +131,27 @@ pub struct DBBox {
     pub subject: String,
     pub content: String,
     pub attachments: Option<DBMailAttachments>,
+    pub source: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security: Option<String>,
+    // тема или текст не расшифровались: вместо них показывается заглушка
+    #[serde(skip)]
+    pub unreadable: bool,
 }
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default)]
+pub struct DBBoxInsert {
+    pub sender: DBMailAddress,
+    pub recipient: DBMailAddress,
+    pub subject: String,
+    pub content: String,
+    pub attachments: Option<DBMailAttachments>,
+    pub source: Option<String>,
+    pub message_id: Option<String>,
+    pub security: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct DBMailAddress {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
@@ -160,6 +179,11 @@ pub struct DBMailAttachmentItem {
     #[serde(rename = "fileName")]
     pub file_name: String,
     pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+    // файл в хранилище по хешу; у старых записей -- путь по ключу письма
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
 }
 
 impl From<&BoxMailAttachmentItem> for DBMailAttachmentItem {
@@ -168,6 +192,8 @@ impl From<&BoxMailAttachmentItem> for DBMailAttachmentItem {
             id: row.id,
             size: row.size,
             file_name: row.file_name.clone(),
+            cid: row.cid.clone(),
+            hash: None,
         }
     }
 }
@@ -182,6 +208,7 @@ impl From<Row> for DBBox {
         Self {
             idb: row.get("idb"),
             date: datetime,
+            timestamp: date.duration_since(UNIX_EPOCH).map(|time| time.as_secs() as i64).unwrap_or_default(),
             order: date.elapsed().unwrap_or_default().as_secs(),
             unread: row.get("unread"),
             sender: row.get("sender"),
@@ -189,6 +216,115 @@ impl From<Row> for DBBox {
             subject: row.get("subject"),
             content: row.get("content"),
             attachments: row.get("attachments"),
+            source: row.get::<_, Option<String>>("source").is_some(),
+            security: row.get("security"),
+            unreadable: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBBoxSource {
+    pub source: Option<String>,
+}
+
+impl From<Row> for DBBoxSource {
+    fn from(row: Row) -> Self {
+        Self {
+            source: row.get("source"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBBoxAttachments {
+    pub idb: i64,
+    pub idu: i32,
+    pub attachments: Option<DBMailAttachments>,
+}
+
+impl From<Row> for DBBoxAttachments {
+    fn from(row: Row) -> Self {
+        Self {
+            idb: row.get("idb"),
+            idu: row.get("idu"),
+            attachments: row.get("attachments"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBBoxText {
+    pub idb: i64,
+    pub idu: i32,
+    pub subject: String,
+    pub content: String,
+}
+
+impl From<Row> for DBBoxText {
+    fn from(row: Row) -> Self {
+        Self {
+            idb: row.get("idb"),
+            idu: row.get("idu"),
+            subject: row.get("subject"),
+            content: row.get("content"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBBoxSearch {
+    pub idb: i64,
+    pub idu: i32,
+    pub sender: DBMailAddress,
+    pub recipient: DBMailAddress,
+    pub subject: String,
+    pub content: String,
+}
+
+impl From<Row> for DBBoxSearch {
+    fn from(row: Row) -> Self {
+        Self {
+            idb: row.get("idb"),
+            idu: row.get("idu"),
+            sender: row.get("sender"),
+            recipient: row.get("recipient"),
+            subject: row.get("subject"),
+            content: row.get("content"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBCryptKey {
+    pub idk: i32,
+    pub idu: Option<i32>,
+    pub master: String,
+    pub wrapped: String,
+}
+
+impl From<Row> for DBCryptKey {
+    fn from(row: Row) -> Self {
+        Self {
+            idk: row.get("idk"),
+            idu: row.get("idu"),
+            master: row.get("master"),
+            wrapped: row.get("wrapped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DBBlob {
+    pub hash: String,
+    pub refs: i32,
+}
+
+impl From<Row> for DBBlob {
+    fn from(row: Row) -> Self {
+        Self {
+            hash: row.get("hash"),
+            refs: row.get("refs"),
         }
     }
 }
@@ -1,13 +1,32 @@
 use serde::{Deserialize, Serialize};
 use tokio_postgres::Row;
 
-use crate::db::db_query;
+use shared::types::{PasswordRequest, SettingsRequest};
+
+use crate::db::{db_query, db_update_query};
+use crate::db_sessions::db_sessions_revoke;
+use crate::password::{password_hash, password_is_hash, password_verify, PASSWORD_MIN};
+use crate::sse::{Message, sse_channel};
 use crate::state::{USER_BY_EMAIL, USER_BY_ID};
+use crate::types::SessionStruct;
+
+// имя отправителя: отображаемое, если задано, иначе имя пользователя
+const SELECT_USER_INIT: &str = "select idu, email, coalesce(nullif(display_name, ''), name) as name from emails.users";
+
+const DISPLAY_NAME_MAX: usize = 100;
+const BY_PAGE_MIN: i32 = 10;
+const BY_PAGE_MAX: i32 = 200;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct DBUserSelect {
     pub prefix: String,
     pub signature: String,
+    pub display_name: String,
+    pub by_page: i32,
+    pub timezone: String,
+    pub default_box: i32,
+    pub smime: bool,
+    pub pgp: bool,
 }
 
 impl From<Row> for DBUserSelect {
@@ -15,19 +34,12 @@ impl From<Row> for DBUserSelect {
         Self {
             prefix: row.get("name"),
             signature: row.get("signature"),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
-pub struct DBUserIdu {
-    pub idu: i32,
-}
-
-impl From<Row> for DBUserIdu {
-    fn from(row: Row) -> Self {
-        Self {
-            idu: row.get("idu"),
+            display_name: row.get("display_name"),
+            by_page: row.get("by_page"),
+            timezone: row.get("timezone"),
+            default_box: row.get("default_box"),
+            smime: row.get("smime"),
+            pgp: row.get("pgp"),
         }
     }
 }
@@ -57,7 +69,11 @@ impl From<Row> for DBUserInit {
 }
 
 pub async fn db_user_init() {
-    let rows = db_query(DBUserInit::from, "select idu, email, name from emails.users;", &[]).await;
+    let rows = db_query(DBUserInit::from, &format!("{SELECT_USER_INIT};"), &[]).await;
+    db_user_cache(&rows);
+}
+
+fn db_user_cache(rows: &[DBUserInit]) {
     for row in rows.iter() {
         if let Ok(mut users) = USER_BY_EMAIL.lock() {
             users.insert(row.email.clone(), row.idu);
@@ -72,15 +88,57 @@ pub async fn db_user_init() {
     }
 }
 
+#[derive(Debug, Clone)]
+struct DBUserPassword {
+    idu: i32,
+    password: String,
+}
+
+impl From<Row> for DBUserPassword {
+    fn from(row: Row) -> Self {
+        Self {
+            idu: row.get("idu"),
+            password: row.get("password"),
+        }
+    }
+}
+
 pub async fn db_user_login(mail_box: String, user_name: String, user_pass: String) -> i32 {
-    let rows = db_query(DBUserIdu::from, include_str!("../sql/select_user_login.sql"), &[&mail_box, &user_name, &user_pass]).await;
-    if rows.len() == 1 {
-        rows[0].idu
-    } else {
-        0
+    let rows = db_query(DBUserPassword::from, include_str!("../sql/select_user_login.sql"), &[&mail_box, &user_name]).await;
+    if rows.len() != 1 || !password_verify(&rows[0].password, &user_pass).await {
+        return 0;
+    }
+    // пароль открытым текстом заменяем хешем при первом успешном входе
+    if !password_is_hash(&rows[0].password) {
+        db_user_password_set(&rows[0].idu, &user_pass).await;
+    }
+    rows[0].idu
+}
+
+pub async fn db_user_password_set(idu: &i32, password: &str) -> bool {
+    match password_hash(password).await {
+        Some(hash) => db_update_query("update emails.users set password=$2 where idu=$1;", &[idu, &hash]).await,
+        None => false
     }
 }
 
+pub async fn db_password_route(session: &SessionStruct, data: PasswordRequest) -> bool {
+    if data.password.chars().count() < PASSWORD_MIN {
+        return false;
+    }
+    let rows = db_query(DBUserPassword::from, "select idu, password from emails.users where idu=$1;", &[&session.idu]).await;
+    if rows.len() != 1 || !password_verify(&rows[0].password, &data.current).await {
+        tracing::warn!("db_password_route: wrong password idu={}", session.idu);
+        return false;
+    }
+    if !db_user_password_set(&session.idu, &data.password).await {
+        return false;
+    }
+    // старый пароль мог быть известен другому: его сеансы больше не действуют
+    db_sessions_revoke(&session.idu, Some(session.sid)).await;
+    true
+}
+
 // ===
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -99,4 +157,118 @@ impl From<Row> for DBUserEmail {
 pub async fn db_user_email(idu: &i32) -> Option<String> {
     let rows = db_query(DBUserEmail::from, include_str!("../sql/select_user.sql"), &[idu]).await;
     if rows.len() == 1 { Some(rows[0].email.clone()) } else { None }
-}
\ No newline at end of file
+}
+// ===
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct DBIdentity {
+    pub idi: i32,
+    pub email: String,
+    pub name: String,
+    pub signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+}
+
+impl From<Row> for DBIdentity {
+    fn from(row: Row) -> Self {
+        Self {
+            idi: row.get("idi"),
+            email: row.get("email"),
+            name: row.get("name"),
+            signature: row.get("signature"),
+            reply_to: row.get("reply_to"),
+        }
+    }
+}
+
+impl DBIdentity {
+    pub fn mailbox(&self) -> String {
+        if self.name.is_empty() { self.email.clone() } else { format!("{} <{}>", self.name, self.email) }
+    }
+}
+
+pub async fn db_identities(idu: &i32) -> Vec<DBIdentity> {
+    db_query(
+        DBIdentity::from,
+        "select idi, email, name, signature, reply_to from emails.identities where idu=$1 order by position, idi;",
+        &[idu],
+    ).await
+}
+
+pub async fn db_identity(idu: &i32, idi: &i32) -> Option<DBIdentity> {
+    db_query(
+        DBIdentity::from,
+        "select idi, email, name, signature, reply_to from emails.identities where idu=$1 and idi=$2;",
+        &[idu, idi],
+    ).await.pop()
+}
+
+pub async fn db_identity_save(idu: &i32, data: &DBIdentity) -> bool {
+    db_update_query(
+        "insert into emails.identities (idu, email, name, signature, reply_to, position)
+        values ($1, $2, $3, $4, $5, (select coalesce(max(position), -1) + 1 from emails.identities where idu=$1))
+        on conflict (idu, email) do update set name=excluded.name, signature=excluded.signature, reply_to=excluded.reply_to;",
+        &[idu, &data.email, &data.name, &data.signature, &data.reply_to],
+    ).await
+}
+
+pub async fn db_identity_remove(idu: &i32, email: &str) -> bool {
+    db_update_query("delete from emails.identities where idu=$1 and email=$2;", &[idu, &email]).await
+}
+
+// === настройки
+
+#[derive(Serialize)]
+struct SettingsResponse {
+    user: DBUserSelect,
+    identities: Vec<DBIdentity>,
+}
+
+pub async fn db_settings_route(session: &SessionStruct, data: SettingsRequest) {
+    let idu = &session.idu;
+    let display_name = data.display_name.map(|name| name.trim().chars().take(DISPLAY_NAME_MAX).collect::<String>());
+    let by_page = data.by_page.map(|by_page| by_page.clamp(BY_PAGE_MIN, BY_PAGE_MAX));
+    let default_box = data.default_box.filter(|default_box| (0..=4).contains(default_box));
+    let timezone = match data.timezone.map(|timezone| timezone.trim().to_string()) {
+        Some(timezone) if !timezone.is_empty() && !db_timezone_exists(&timezone).await => {
+            tracing::warn!("db_settings_route: unknown timezone {timezone}");
+            None
+        }
+        timezone => timezone
+    };
+
+    let updated = db_update_query(
+        "update emails.users set display_name=coalesce($2, display_name), signature=coalesce($3, signature),
+        by_page=coalesce($4, by_page), timezone=coalesce($5, timezone), default_box=coalesce($6, default_box)
+        where idu=$1;",
+        &[idu, &display_name, &data.signature, &by_page, &timezone, &default_box],
+    ).await;
+    if updated {
+        // основной адрес отправителя следует за именем и подписью пользователя
+        db_update_query(
+            "update emails.identities i set name=coalesce(nullif(u.display_name, ''), u.name), signature=u.signature
+            from emails.users u
+            where i.idu=u.idu and i.email=u.email and u.idu=$1;",
+            &[idu],
+        ).await;
+        db_user_cache(&db_query(DBUserInit::from, &format!("{SELECT_USER_INIT} where idu=$1;"), &[idu]).await);
+    }
+
+    let result = SettingsResponse {
+        user: db_user_select(idu).await,
+        identities: db_identities(idu).await,
+    };
+    match serde_json::to_string(&result) {
+        Ok(text) => {
+            sse_channel(session, Message::Settings(text));
+        }
+        Err(err) => {
+            tracing::error!("serde_json[db_settings_route] {:?}", err);
+        }
+    }
+}
+
+async fn db_timezone_exists(timezone: &str) -> bool {
+    !db_query(|row| row.get::<_, String>("name"), "select name from pg_timezone_names where name=$1;", &[&timezone]).await.is_empty()
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fs;
+
+use lettre::Message;
+use lettre::message::dkim::{DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm, DkimSigningKey};
+use lettre::message::header::HeaderName;
+use once_cell::sync::Lazy;
+use rsa::{Hash, PaddingScheme, PublicKey, RsaPrivateKey, RsaPublicKey};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::EncodePublicKey;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const ENV_PARAMS: &str = include_str!("../../env.json");
+
+// подписываемые заголовки; отсутствующие в письме lettre пропускает
+const SIGNED_HEADERS: &[&str] = &["From", "To", "Subject", "Date", "Message-ID", "MIME-Version", "Content-Type"];
+
+#[derive(Deserialize, Default)]
+struct EnvParams {
+    #[serde(default)]
+    dkim: Vec<DkimParams>,
+}
+
+// ключ RSA в формате PKCS#1 PEM ("BEGIN RSA PRIVATE KEY")
+#[derive(Deserialize)]
+struct DkimParams {
+    domain: String,
+    selector: String,
+    key: String,
+}
+
+pub struct DkimKey {
+    pub selector: String,
+    config: DkimConfig,
+    public: RsaPublicKey,
+}
+
+static DKIM_KEYS: Lazy<HashMap<String, DkimKey>> = Lazy::new(|| {
+    let params = match serde_json::from_str::<EnvParams>(ENV_PARAMS) {
+        Ok(params) => params,
+        Err(err) => {
+            tracing::error!("dkim_keys[1] {err}");
+            EnvParams::default()
+        }
+    };
+    let mut keys = HashMap::new();
+    for item in params.dkim {
+        match dkim_key(&item) {
+            Ok(key) => {
+                keys.insert(item.domain.to_lowercase(), key);
+            }
+            Err(err) => tracing::error!("dkim_keys[2] {}: {err}", item.domain)
+        }
+    }
+    keys
+});
+
+fn dkim_key(params: &DkimParams) -> Result<DkimKey, String> {
+    let pem = fs::read_to_string(&params.key).map_err(|err| format!("{}: {err}", params.key))?;
+    dkim_key_pem(params, &pem)
+}
+
+fn dkim_key_pem(params: &DkimParams, pem: &str) -> Result<DkimKey, String> {
+    let private = RsaPrivateKey::from_pkcs1_pem(pem).map_err(|err| err.to_string())?;
+    let signing_key = DkimSigningKey::new(pem, DkimSigningAlgorithm::Rsa).map_err(|err| err.to_string())?;
+    let config = DkimConfig::new(
+        params.selector.clone(),
+        params.domain.clone(),
+        signing_key,
+        SIGNED_HEADERS.iter().map(|name| HeaderName::new_from_ascii_str(name)).collect(),
+        DkimCanonicalization { header: DkimCanonicalizationType::Relaxed, body: DkimCanonicalizationType::Relaxed },
+    );
+    Ok(DkimKey { selector: params.selector.clone(), config, public: RsaPublicKey::from(&private) })
+}
+
+// подпись перед передачей в sendmail; письмо с неверной подписью хуже неподписанного,
+// поэтому при ошибке проверки отправляем без подписи
+pub fn dkim_sign(message: Message) -> Message {
+    let domain = match message.envelope().from() {
+        Some(address) => address.domain().to_lowercase(),
+        None => return message
+    };
+    let key = match DKIM_KEYS.get(&domain) {
+        Some(key) => key,
+        None => return message
+    };
+    let mut signed = message.clone();
+    signed.sign(&key.config);
+    match dkim_verify(&signed.formatted(), &key.public) {
+        Ok(_) => signed,
+        Err(err) => {
+            tracing::error!("dkim_sign {domain}: {err}");
+            message
+        }
+    }
+}
+
+// проверка ключей всех доменов на тестовом письме, возвращает (домен, результат)
+pub fn dkim_self_test() -> Vec<(String, Result<String, String>)> {
+    let mut results = DKIM_KEYS.iter()
+        .map(|(domain, key)| (domain.clone(), dkim_test_domain(domain, key)))
+        .collect::<Vec<_>>();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+fn dkim_test_domain(domain: &str, key: &DkimKey) -> Result<String, String> {
+    let message = Message::builder()
+        .from(format!("DKIM test <postmaster@{domain}>").parse().map_err(|err| format!("{err}"))?)
+        .to(format!("postmaster@{domain}").parse().map_err(|err| format!("{err}"))?)
+        .subject("DKIM self-test")
+        .body("Проверка подписи DKIM.\r\n".to_string())
+        .map_err(|err| err.to_string())?;
+    let mut signed = message;
+    signed.sign(&key.config);
+    dkim_verify(&signed.formatted(), &key.public)?;
+    dkim_dns_record(domain, key)
+}
+
+// запись для DNS: <selector>._domainkey.<domain> TXT "v=DKIM1; k=rsa; p=..."
+fn dkim_dns_record(domain: &str, key: &DkimKey) -> Result<String, String> {
+    let der = key.public.to_public_key_der().map_err(|err| err.to_string())?;
+    Ok(format!("{}._domainkey.{domain} TXT \"v=DKIM1; k=rsa; p={}\"", key.selector, base64::encode(der.as_ref())))
+}
+
+// === проверка подписи (RFC 6376) независимо от кода подписи
+
+pub fn dkim_verify(formatted: &[u8], public: &RsaPublicKey) -> Result<(), String> {
+    let split = formatted.windows(4).position(|w| w == b"\r\n\r\n").ok_or("no header/body separator")?;
+    let header = std::str::from_utf8(&formatted[..split + 2]).map_err(|err| err.to_string())?;
+    let body = &formatted[split + 4..];
+
+    let fields = header_fields(header);
+    let signature = fields.iter()
+        .find(|field| field_name(field).eq_ignore_ascii_case("DKIM-Signature"))
+        .ok_or("DKIM-Signature not found")?;
+    let tags = signature_tags(&signature[signature.find(':').unwrap_or_default() + 1..]);
+    let tag = |name: &str| tags.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+    if tag("a") != Some("rsa-sha256") {
+        return Err(format!("unsupported algorithm {:?}", tag("a")));
+    }
+    let (canon_header, canon_body) = match tag("c").unwrap_or("simple/simple").split_once('/') {
+        Some((header, body)) => (header.to_string(), body.to_string()),
+        None => (tag("c").unwrap_or("simple").to_string(), "simple".to_string())
+    };
+
+    let body_hash = base64::encode(Sha256::digest(canonicalize_body(body, canon_body == "relaxed")));
+    if Some(body_hash.as_str()) != tag("bh") {
+        return Err("body hash mismatch".to_string());
+    }
+
+    // заголовки из h= берутся снизу вверх, повторяющиеся -- по одному на каждое упоминание
+    let mut used = vec![false; fields.len()];
+    let mut hashed = Sha256::new();
+    for name in tag("h").ok_or("h= not found")?.split(':') {
+        let name = name.trim();
+        if let Some(ind) = (0..fields.len()).rev().find(|ind| !used[*ind] && field_name(&fields[*ind]).eq_ignore_ascii_case(name)) {
+            used[ind] = true;
+            hashed.update(canonicalize_header(&fields[ind], canon_header == "relaxed").as_bytes());
+        }
+    }
+    let without_b = remove_b_value(signature);
+    let without_b = canonicalize_header(&without_b, canon_header == "relaxed");
+    hashed.update(without_b.trim_end_matches("\r\n").as_bytes());
+    let hashed = hashed.finalize();
+
+    let sign = base64::decode(tag("b").ok_or("b= not found")?).map_err(|err| err.to_string())?;
+    public.verify(PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)), &hashed, &sign)
+        .map_err(|err| format!("signature: {err}"))
+}
+
+// поля заголовка вместе с продолжениями строк и завершающим CRLF
+fn header_fields(header: &str) -> Vec<String> {
+    let mut fields: Vec<String> = vec![];
+    for line in header.split_inclusive("\r\n") {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = fields.last_mut() {
+                last.push_str(line);
+                continue;
+            }
+        }
+        fields.push(line.to_string());
+    }
+    fields
+}
+
+fn field_name(field: &str) -> &str {
+    field.split(':').next().unwrap_or_default().trim()
+}
+
+fn signature_tags(value: &str) -> Vec<(String, String)> {
+    value.split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.chars().filter(|c| !c.is_whitespace()).collect()))
+        .collect()
+}
+
+fn remove_b_value(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let (name, value) = field.split_at(field.find(':').map(|pos| pos + 1).unwrap_or_default());
+    out.push_str(name);
+    let parts = value.split(';').collect::<Vec<_>>();
+    for (ind, part) in parts.iter().enumerate() {
+        let key = part.split('=').next().unwrap_or_default().trim();
+        if key == "b" {
+            let pos = part.find('=').unwrap_or_default();
+            out.push_str(&part[..pos + 1]);
+            if part.ends_with("\r\n") {
+                out.push_str("\r\n");
+            }
+        } else {
+            out.push_str(part);
+        }
+        if ind < parts.len() - 1 {
+            out.push(';');
+        }
+    }
+    out
+}
+
+fn canonicalize_header(field: &str, relaxed: bool) -> String {
+    if !relaxed {
+        return field.to_string();
+    }
+    let (name, value) = field.split_once(':').unwrap_or((field, ""));
+    let value = value.replace("\r\n", "");
+    let value = value.split([' ', '\t']).filter(|part| !part.is_empty()).collect::<Vec<_>>().join(" ");
+    format!("{}:{}\r\n", name.trim_end().to_lowercase(), value)
+}
+
+fn canonicalize_body(body: &[u8], relaxed: bool) -> Vec<u8> {
+    let mut lines = body.split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect::<Vec<_>>();
+    if relaxed {
+        lines = lines.into_iter().map(|line| {
+            let mut out: Vec<u8> = vec![];
+            for b in line {
+                if b == b' ' || b == b'\t' {
+                    if out.last() != Some(&b' ') {
+                        out.push(b' ');
+                    }
+                } else {
+                    out.push(b);
+                }
+            }
+            while out.last() == Some(&b' ') {
+                out.pop();
+            }
+            out
+        }).collect();
+    }
+    while lines.last().map(|line| line.is_empty()).unwrap_or_default() {
+        lines.pop();
+    }
+    if lines.is_empty() && !relaxed {
+        return b"\r\n".to_vec();
+    }
+    let mut out = vec![];
+    for line in lines {
+        out.extend_from_slice(&line);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::rsa::Rsa;
+
+    use super::*;
+
+    fn test_key() -> DkimKey {
+        let pem = Rsa::generate(2048).and_then(|rsa| rsa.private_key_to_pem()).unwrap();
+        let params = DkimParams { domain: "example.com".to_string(), selector: "mail".to_string(), key: "".to_string() };
+        dkim_key_pem(&params, &String::from_utf8(pem).unwrap()).unwrap()
+    }
+
+    fn signed_message(key: &DkimKey) -> Vec<u8> {
+        let mut message = Message::builder()
+            .from("Отправитель <user@example.com>".parse().unwrap())
+            .to("someone@example.org".parse().unwrap())
+            .subject("Проверка подписи")
+            .body("first  line with  spaces \r\nsecond\r\n\r\n\r\n".to_string())
+            .unwrap();
+        message.sign(&key.config);
+        message.formatted()
+    }
+
+    #[test]
+    fn sign_verify_round_trip() {
+        let key = test_key();
+        let formatted = signed_message(&key);
+        assert_eq!(dkim_verify(&formatted, &key.public), Ok(()));
+        assert!(dkim_dns_record("example.com", &key).unwrap().starts_with("mail._domainkey.example.com TXT \"v=DKIM1; k=rsa; p="));
+    }
+
+    #[test]
+    fn other_key_rejected() {
+        let formatted = signed_message(&test_key());
+        assert!(dkim_verify(&formatted, &test_key().public).unwrap_err().starts_with("signature"));
+    }
+
+    #[test]
+    fn tampered_body_detected() {
+        let key = test_key();
+        let formatted = String::from_utf8(signed_message(&key)).unwrap();
+        let split = formatted.find("\r\n\r\n").unwrap();
+        let (header, body) = formatted.split_at(split);
+        let tampered = format!("{header}{}", body.replacen("second", "third", 1));
+        assert_ne!(tampered, formatted);
+        assert_eq!(dkim_verify(tampered.as_bytes(), &key.public), Err("body hash mismatch".to_string()));
+    }
+
+    #[test]
+    fn tampered_header_detected() {
+        let key = test_key();
+        let formatted = String::from_utf8(signed_message(&key)).unwrap();
+        let tampered = formatted.replacen("To: someone@example.org", "To: other@example.org", 1);
+        assert_ne!(tampered, formatted);
+        assert!(dkim_verify(tampered.as_bytes(), &key.public).unwrap_err().starts_with("signature"));
+    }
+
+    #[test]
+    fn whitespace_changes_allowed_by_relaxed() {
+        let key = test_key();
+        let formatted = String::from_utf8(signed_message(&key)).unwrap();
+        // пересылка может менять пробелы и добавлять пустые строки в конце
+        let changed = formatted.replacen("first  line with  spaces ", "first line\twith spaces", 1) + "\r\n\r\n";
+        assert_ne!(changed, formatted);
+        assert_eq!(dkim_verify(changed.as_bytes(), &key.public), Ok(()));
+    }
+
+    #[test]
+    fn missing_signature() {
+        let key = test_key();
+        assert_eq!(dkim_verify(b"From: a@b.c\r\n\r\ntext\r\n", &key.public), Err("DKIM-Signature not found".to_string()));
+    }
+
+    // примеры из RFC 6376, 3.4.5
+    #[test]
+    fn relaxed_header_canonicalization() {
+        assert_eq!(canonicalize_header("A: X\r\n", true), "a:X\r\n");
+        assert_eq!(canonicalize_header("B : Y\t\r\n\tZ  \r\n", true), "b:Y Z\r\n");
+        assert_eq!(canonicalize_header("B : Y\t\r\n\tZ  \r\n", false), "B : Y\t\r\n\tZ  \r\n");
+    }
+
+    #[test]
+    fn relaxed_body_canonicalization() {
+        assert_eq!(canonicalize_body(b" C \r\nD \t E\r\n\r\n\r\n", true), b" C\r\nD E\r\n");
+        assert_eq!(canonicalize_body(b" C \r\nD \t E\r\n\r\n\r\n", false), b" C \r\nD \t E\r\n");
+    }
+
+    #[test]
+    fn empty_body_canonicalization() {
+        assert_eq!(canonicalize_body(b"", false), b"\r\n");
+        assert_eq!(canonicalize_body(b"\r\n\r\n", true), b"");
+    }
+
+    #[test]
+    fn header_fields_unfolded_and_b_removed() {
+        let fields = header_fields("DKIM-Signature: v=1; a=rsa-sha256;\r\n b=abc\r\n def; bh=xyz\r\nFrom: a@b.c\r\n");
+        assert_eq!(fields.len(), 2);
+        assert_eq!(field_name(&fields[0]), "DKIM-Signature");
+        assert_eq!(remove_b_value(&fields[0]), "DKIM-Signature: v=1; a=rsa-sha256;\r\n b=; bh=xyz\r\n");
+        let tags = signature_tags(&fields[0][15..]);
+        assert!(tags.contains(&("b".to_string(), "abcdef".to_string())));
+    }
+}
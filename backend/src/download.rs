@@ -0,0 +1,198 @@
+use std::ops::Bound;
+use std::time::UNIX_EPOCH;
+
+use headers::{AcceptRanges, ContentLength, ContentRange, ContentType, ETag, HeaderMapExt, IfNoneMatch, IfRange, LastModified, Range};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use warp::http::header::{CACHE_CONTROL, CONTENT_DISPOSITION, HeaderMap, HeaderValue, X_CONTENT_TYPE_OPTIONS};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reject;
+use warp::reply::Response;
+use warp::Rejection;
+
+use crate::storage::Storage;
+
+// RFC 5987: attr-char без кодирования
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+').remove(b'-')
+    .remove(b'.').remove(b'^').remove(b'_').remove(b'`').remove(b'|').remove(b'~');
+
+// RFC 6266: ascii-имя для старых клиентов и filename* в UTF-8
+pub fn content_disposition(inline: bool, file_name: &str) -> Option<HeaderValue> {
+    let kind = if inline { "inline" } else { "attachment" };
+    let fallback = file_name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect::<String>();
+    let encoded = utf8_percent_encode(file_name, ATTR_CHAR);
+    HeaderValue::from_str(&format!("{kind}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")).ok()
+}
+
+// показываем в браузере только то, что не исполняет скрипты на нашем домене
+fn is_viewable(mime: &mime_guess::Mime) -> bool {
+    match (mime.type_(), mime.subtype()) {
+        (mime_guess::mime::IMAGE, mime_guess::mime::SVG) => false,
+        (mime_guess::mime::IMAGE, _) | (mime_guess::mime::AUDIO, _) | (mime_guess::mime::VIDEO, _) => true,
+        (mime_guess::mime::APPLICATION, mime_guess::mime::PDF) => true,
+        (mime_guess::mime::TEXT, mime_guess::mime::PLAIN) => true,
+        _ => false
+    }
+}
+
+// файл отдается потоком, без чтения в память; поддерживается один диапазон Range
+pub async fn file_response(storage: &dyn Storage, key: &str, file_name: &str, inline: bool, headers: &HeaderMap) -> Result<Response, Rejection> {
+    let meta = storage.head(key).await.map_err(|_| reject::not_found())?;
+    let size = meta.size;
+    let modified = meta.modified;
+
+    let mtime = modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or_default();
+    let etag = format!("\"{size:x}-{mtime:x}\"").parse::<ETag>().ok();
+    let last_modified = modified.map(LastModified::from);
+
+    let mime = mime_guess::from_path(file_name).first_or_octet_stream();
+    let inline = inline && is_viewable(&mime);
+
+    let mut resp = Response::new(Body::empty());
+    let resp_headers = resp.headers_mut();
+    if let Some(etag) = &etag {
+        resp_headers.typed_insert(etag.clone());
+    }
+    if let Some(last_modified) = last_modified {
+        resp_headers.typed_insert(last_modified);
+    }
+    resp_headers.typed_insert(AcceptRanges::bytes());
+    // вложение под своим номером не меняется; миниатюры в просмотре письма берутся из кеша браузера
+    resp_headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, max-age=86400"));
+
+    if let (Some(if_none_match), Some(etag)) = (headers.typed_get::<IfNoneMatch>(), &etag) {
+        if !if_none_match.precondition_passes(etag) {
+            *resp.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(resp);
+        }
+    }
+
+    resp_headers.typed_insert(ContentType::from(mime));
+    resp_headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    if let Some(value) = content_disposition(inline, file_name) {
+        resp_headers.insert(CONTENT_DISPOSITION, value);
+    }
+
+    // If-Range с другой версией файла -- отдаем файл целиком
+    let range_actual = headers.typed_get::<IfRange>()
+        .map(|if_range| !if_range.is_modified(etag.as_ref(), last_modified.as_ref()))
+        .unwrap_or(true);
+    let range = headers.typed_get::<Range>().filter(|_| range_actual).and_then(|range| {
+        let mut ranges = range.iter();
+        match (ranges.next(), ranges.next()) {
+            (Some(bounds), None) => Some(range_bounds(bounds, size)),
+            _ => None
+        }
+    });
+
+    let (range, len) = match range {
+        Some(Some((start, end))) => {
+            if let Ok(content_range) = ContentRange::bytes(start..=end, size) {
+                resp_headers.typed_insert(content_range);
+            }
+            *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
+            (Some((start, end)), end - start + 1)
+        }
+        Some(None) => {
+            resp_headers.typed_insert(ContentRange::unsatisfied_bytes(size));
+            *resp.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            return Ok(resp);
+        }
+        None => (None, size)
+    };
+
+    let stream = match storage.get(key, range).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            tracing::error!("file_response: {key} {err}");
+            return Err(reject::not_found());
+        }
+    };
+    resp.headers_mut().typed_insert(ContentLength(len));
+    *resp.body_mut() = Body::wrap_stream(stream);
+    Ok(resp)
+}
+
+// границы диапазона включительно; None -- диапазон вне файла
+fn range_bounds(bounds: (Bound<u64>, Bound<u64>), size: u64) -> Option<(u64, u64)> {
+    let (start, end) = match bounds {
+        (Bound::Included(start), Bound::Included(end)) => (start, end.min(size.saturating_sub(1))),
+        (Bound::Included(start), Bound::Unbounded) => (start, size.saturating_sub(1)),
+        // последние n байт
+        (Bound::Unbounded, Bound::Included(last)) => (size.saturating_sub(last), size.saturating_sub(1)),
+        _ => return None
+    };
+    if size == 0 || start > end || start >= size {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(inline: bool, file_name: &str) -> String {
+        content_disposition(inline, file_name).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn disposition_ascii() {
+        assert_eq!(header(false, "report 1.pdf"), "attachment; filename=\"report 1.pdf\"; filename*=UTF-8''report%201.pdf");
+        assert_eq!(header(true, "a.png"), "inline; filename=\"a.png\"; filename*=UTF-8''a.png");
+    }
+
+    #[test]
+    fn disposition_cyrillic() {
+        assert_eq!(
+            header(false, "Отчёт.pdf"),
+            "attachment; filename=\"_____.pdf\"; filename*=UTF-8''%D0%9E%D1%82%D1%87%D1%91%D1%82.pdf"
+        );
+    }
+
+    #[test]
+    fn disposition_quotes() {
+        assert_eq!(
+            header(false, "a\"b\\c.txt"),
+            "attachment; filename=\"a_b_c.txt\"; filename*=UTF-8''a%22b%5Cc.txt"
+        );
+        // перевод строки не должен попасть в заголовок
+        assert_eq!(header(false, "a\r\nb"), "attachment; filename=\"a__b\"; filename*=UTF-8''a%0D%0Ab");
+    }
+
+    #[test]
+    fn range_explicit() {
+        assert_eq!(range_bounds((Bound::Included(0), Bound::Included(9)), 100), Some((0, 9)));
+        assert_eq!(range_bounds((Bound::Included(90), Bound::Included(200)), 100), Some((90, 99)));
+        assert_eq!(range_bounds((Bound::Included(10), Bound::Unbounded), 100), Some((10, 99)));
+        assert_eq!(range_bounds((Bound::Included(9), Bound::Included(0)), 100), None);
+    }
+
+    #[test]
+    fn range_suffix() {
+        assert_eq!(range_bounds((Bound::Unbounded, Bound::Included(10)), 100), Some((90, 99)));
+        // больше размера -- весь файл
+        assert_eq!(range_bounds((Bound::Unbounded, Bound::Included(500)), 100), Some((0, 99)));
+        // "-0" удовлетворить нельзя
+        assert_eq!(range_bounds((Bound::Unbounded, Bound::Included(0)), 100), None);
+    }
+
+    #[test]
+    fn range_outside() {
+        assert_eq!(range_bounds((Bound::Included(100), Bound::Unbounded), 100), None);
+        assert_eq!(range_bounds((Bound::Included(100), Bound::Included(150)), 100), None);
+        assert_eq!(range_bounds((Bound::Excluded(0), Bound::Unbounded), 100), None);
+    }
+
+    #[test]
+    fn range_empty_file() {
+        assert_eq!(range_bounds((Bound::Included(0), Bound::Unbounded), 0), None);
+        assert_eq!(range_bounds((Bound::Included(0), Bound::Included(0)), 0), None);
+        assert_eq!(range_bounds((Bound::Unbounded, Bound::Included(5)), 0), None);
+    }
+}
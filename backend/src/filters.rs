@@ -1,34 +1,80 @@
+use std::net::{IpAddr, SocketAddr};
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
 use warp::{Filter, http, Rejection};
+use warp::http::HeaderMap;
 use warp::hyper::body::Bytes;
 
-use crate::NotUtf8;
-use crate::utils::get_hash;
+use shared::constants::HEADER_CHANNEL;
+
+use crate::{NotUtf8, Unauthorized};
+use crate::cookie::COOKIE_SESSION;
+use crate::db_sessions::db_session_by_cookie;
+use crate::sse::sse_channel_owner;
+use crate::types::{DeviceInfo, SessionStruct};
+
+const ENV_PARAMS: &str = include_str!("../../env.json");
+
+#[derive(Deserialize, Default)]
+struct EnvParams {
+    #[serde(default)]
+    proxy_ip_header: String,
+}
+
+// заголовок с адресом клиента ("x-real-ip"), который выставляет свой прокси; без настройки -- адрес соединения
+static PROXY_IP_HEADER: Lazy<String> = Lazy::new(|| match serde_json::from_str::<EnvParams>(ENV_PARAMS) {
+    Ok(params) => params.proxy_ip_header.trim().to_lowercase(),
+    Err(err) => {
+        tracing::error!("proxy_ip_header {err}");
+        String::new()
+    }
+});
+
+// заголовку верим, только если соединение пришло с этой же машины, то есть от прокси
+fn device_ip(remote: Option<SocketAddr>, headers: &HeaderMap) -> String {
+    let remote = match remote {
+        Some(remote) => remote.ip(),
+        None => return String::new()
+    };
+    if PROXY_IP_HEADER.is_empty() || !remote.is_loopback() {
+        return remote.to_string();
+    }
+    headers.get(PROXY_IP_HEADER.as_str())
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next_back())
+        .and_then(|value| value.trim().parse::<IpAddr>().ok())
+        .map(|ip| ip.to_string())
+        .unwrap_or_default()
+}
 
-pub fn with_hash() -> impl Filter<Extract=(String, ), Error=Rejection> + Clone {
+pub fn with_device() -> impl Filter<Extract=(DeviceInfo, ), Error=Rejection> + Clone {
     warp::any()
         .and(warp::header::optional::<String>(
             http::header::USER_AGENT.as_str(),
         ))
-        .and(warp::header::optional::<String>("remote-addr"))
-        .and(warp::header::optional::<String>(
-            http::header::ACCEPT_LANGUAGE.as_str(),
-        ))
-        .and(warp::header::optional::<String>(
-            http::header::ACCEPT_ENCODING.as_str(),
-        ))
-        .map(
-            move |user_agent: Option<String>,
-                  ip: Option<String>,
-                  accept_language: Option<String>,
-                  accept_encoding: Option<String>| {
-                let browser = user_agent.unwrap_or_default();
-                let ip = ip.unwrap_or_default();
-                let accept_language = accept_language.unwrap_or_default();
-                let accept_encoding = accept_encoding.unwrap_or_default();
-
-                get_hash(format!("{browser}-{ip}-{accept_language}-{accept_encoding}"))
-            },
-        )
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
+        .map(|user_agent: Option<String>, remote: Option<SocketAddr>, headers: HeaderMap| DeviceInfo {
+            user_agent: user_agent.unwrap_or_default(),
+            ip: device_ip(remote, &headers),
+        })
+}
+
+// единая проверка входа: подписанная cookie сеанса и канал sse вкладки, если он принадлежит сеансу
+pub fn with_session() -> impl Filter<Extract=(SessionStruct, ), Error=Rejection> + Clone {
+    warp::cookie::optional::<String>(COOKIE_SESSION)
+        .and(warp::header::optional::<usize>(HEADER_CHANNEL))
+        .and(with_device())
+        .and_then(|cookie: Option<String>, channel_id: Option<usize>, device: DeviceInfo| async move {
+            match db_session_by_cookie(&cookie, &device).await {
+                Some(session) => {
+                    let channel_id = channel_id.filter(|channel_id| sse_channel_owner(channel_id, &session)).unwrap_or_default();
+                    Ok(SessionStruct { channel_id, ..session })
+                }
+                None => Err(warp::reject::custom(Unauthorized))
+            }
+        })
 }
 
 pub fn with_body_filter() -> impl Filter<Extract=(String, ), Error=Rejection> + Clone {
@@ -0,0 +1,851 @@
+// Текстовая версия письма из html: заголовки, списки, таблицы, цитаты.
+// Результат переносится по 72 символа в формате format=flowed (RFC 3676).
+
+const WIDTH: usize = 72;
+const WIDTH_MIN: usize = 20;
+// разбор и вывод рекурсивны: глубже этого теги не вкладываются, их содержимое остается в последнем элементе
+const DEPTH_MAX: usize = 64;
+
+const VOID: &[&str] = &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+const RAW: &[&str] = &["script", "style", "title", "textarea"];
+const CLOSES_P: &[&str] = &[
+    "address", "article", "blockquote", "div", "dl", "fieldset", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+    "header", "hr", "ol", "p", "pre", "section", "table", "ul",
+];
+
+pub fn html_to_text(html: &str) -> String {
+    let root = parse(html);
+    let mut renderer = Renderer::default();
+    renderer.children(&root);
+    renderer.flush();
+    output(&renderer.lines)
+}
+
+// === разбор html
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Default)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+fn parse(html: &str) -> Element {
+    let mut stack: Vec<Element> = vec![Element::default()];
+    let mut rest = html;
+    while !rest.is_empty() {
+        let pos = match rest.find('<') {
+            Some(pos) => pos,
+            None => {
+                append_text(&mut stack, rest);
+                break;
+            }
+        };
+        if pos > 0 {
+            append_text(&mut stack, &rest[..pos]);
+            rest = &rest[pos..];
+        }
+
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+        } else if let Some(tail) = rest.strip_prefix("</") {
+            let end = tail.find('>').unwrap_or(tail.len());
+            let name = tail[..end].trim().to_ascii_lowercase();
+            if name == "br" {
+                append_element(&mut stack, Element { name, ..Element::default() });
+            } else {
+                close(&mut stack, &name);
+            }
+            rest = tail.get(end + 1..).unwrap_or("");
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let (mut element, self_closing, len) = parse_tag(rest);
+            rest = &rest[len..];
+            if RAW.contains(&element.name.as_str()) {
+                let close_tag = format!("</{}", element.name);
+                let end = find_ignore_case(rest, &close_tag).unwrap_or(rest.len());
+                element.children.push(Node::Text(decode(&rest[..end])));
+                rest = &rest[end..];
+                rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+                implicit_close(&mut stack, &element.name);
+                append_element(&mut stack, element);
+            } else if self_closing || VOID.contains(&element.name.as_str()) {
+                implicit_close(&mut stack, &element.name);
+                append_element(&mut stack, element);
+            } else {
+                implicit_close(&mut stack, &element.name);
+                if stack.len() < DEPTH_MAX {
+                    stack.push(element);
+                }
+            }
+        } else {
+            append_text(&mut stack, "<");
+            rest = &rest[1..];
+        }
+    }
+    while stack.len() > 1 {
+        pop_into_parent(&mut stack);
+    }
+    stack.pop().unwrap_or_default()
+}
+
+// закрывающий тег без учета регистра, без копии оставшегося документа
+fn find_ignore_case(text: &str, needle: &str) -> Option<usize> {
+    text.as_bytes().windows(needle.len()).position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// разбор открывающего тега, возвращает элемент, признак "/>" и длину тега
+fn parse_tag(source: &str) -> (Element, bool, usize) {
+    let bytes = source.as_bytes();
+    let len = bytes.len();
+    let is_space = |b: u8| b.is_ascii_whitespace();
+
+    let mut i = 1;
+    while i < len && !is_space(bytes[i]) && bytes[i] != b'/' && bytes[i] != b'>' {
+        i += 1;
+    }
+    let mut element = Element { name: source[1..i].to_ascii_lowercase(), ..Element::default() };
+    let mut self_closing = false;
+
+    while i < len {
+        if is_space(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        if bytes[i] == b'>' {
+            i += 1;
+            break;
+        }
+        if bytes[i] == b'/' {
+            self_closing = true;
+            i += 1;
+            continue;
+        }
+        self_closing = false;
+
+        let start = i;
+        while i < len && !is_space(bytes[i]) && bytes[i] != b'=' && bytes[i] != b'>' && bytes[i] != b'/' {
+            i += 1;
+        }
+        let name = source[start..i].to_ascii_lowercase();
+        while i < len && is_space(bytes[i]) {
+            i += 1;
+        }
+        let mut value = "";
+        if i < len && bytes[i] == b'=' {
+            i += 1;
+            while i < len && is_space(bytes[i]) {
+                i += 1;
+            }
+            if i < len && (bytes[i] == b'"' || bytes[i] == b'\'') {
+                let quote = bytes[i];
+                i += 1;
+                let start = i;
+                while i < len && bytes[i] != quote {
+                    i += 1;
+                }
+                value = &source[start..i];
+                i = (i + 1).min(len);
+            } else {
+                let start = i;
+                while i < len && !is_space(bytes[i]) && bytes[i] != b'>' {
+                    i += 1;
+                }
+                value = &source[start..i];
+            }
+        }
+        if !name.is_empty() {
+            element.attrs.push((name, decode(value)));
+        }
+    }
+    (element, self_closing, i.min(len))
+}
+
+fn append_text(stack: &mut [Element], text: &str) {
+    if let Some(parent) = stack.last_mut() {
+        parent.children.push(Node::Text(decode(text)));
+    }
+}
+
+fn append_element(stack: &mut [Element], element: Element) {
+    if let Some(parent) = stack.last_mut() {
+        parent.children.push(Node::Element(element));
+    }
+}
+
+fn pop_into_parent(stack: &mut Vec<Element>) {
+    if let Some(element) = stack.pop() {
+        append_element(stack, element);
+    }
+}
+
+fn close(stack: &mut Vec<Element>, name: &str) {
+    if let Some(ind) = stack.iter().rposition(|element| element.name == name) {
+        while ind > 0 && stack.len() > ind {
+            pop_into_parent(stack);
+        }
+    }
+}
+
+// закрываем элементы, у которых в html можно не указывать закрывающий тег
+fn implicit_close(stack: &mut Vec<Element>, name: &str) {
+    match name {
+        "li" => close_within(stack, &["li"], &["ul", "ol"]),
+        "dt" | "dd" => close_within(stack, &["dt", "dd"], &["dl"]),
+        "tr" => close_within(stack, &["tr"], &["table"]),
+        "td" | "th" => close_within(stack, &["td", "th"], &["tr", "table"]),
+        "thead" | "tbody" | "tfoot" => close_within(stack, &["thead", "tbody", "tfoot"], &["table"]),
+        _ => {}
+    }
+    if CLOSES_P.contains(&name) && stack.last().map(|element| element.name == "p").unwrap_or_default() {
+        pop_into_parent(stack);
+    }
+}
+
+fn close_within(stack: &mut Vec<Element>, names: &[&str], boundary: &[&str]) {
+    for ind in (1..stack.len()).rev() {
+        let current = stack[ind].name.as_str();
+        if names.contains(&current) {
+            while stack.len() > ind {
+                pop_into_parent(stack);
+            }
+            return;
+        }
+        if boundary.contains(&current) {
+            return;
+        }
+    }
+}
+
+fn decode(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let end = rest[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+            .map(|end| end + 1)
+            .unwrap_or(rest.len());
+        match entity(&rest[1..end]) {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end..];
+                if let Some(tail) = rest.strip_prefix(';') {
+                    rest = tail;
+                }
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn entity(name: &str) -> Option<char> {
+    if let Some(code) = name.strip_prefix('#') {
+        let value = match code.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => code.parse::<u32>().ok()
+        };
+        return value.and_then(char::from_u32);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "shy" => '\u{ad}',
+        "laquo" => '«',
+        "raquo" => '»',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bdquo" => '„',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        "bull" => '•',
+        "middot" => '·',
+        "times" => '×',
+        "deg" => '°',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "euro" => '€',
+        "numero" => '№',
+        _ => return None
+    })
+}
+
+// === построение текста
+
+struct Line {
+    quote: usize,
+    first: String,
+    rest: String,
+    text: String,
+    wrap: bool,
+}
+
+impl Line {
+    fn blank(quote: usize) -> Self {
+        Self { quote, first: "".to_string(), rest: "".to_string(), text: "".to_string(), wrap: false }
+    }
+}
+
+#[derive(Default)]
+struct Renderer {
+    lines: Vec<Line>,
+    inline: String,
+    quote: usize,
+    indent: Vec<String>,
+    marker: Option<String>,
+    lists: Vec<(bool, usize)>,
+    pre: usize,
+    spaced: bool,
+}
+
+impl Renderer {
+    fn children(&mut self, element: &Element) {
+        for node in element.children.iter() {
+            match node {
+                Node::Element(element) => self.element(element),
+                Node::Text(text) => self.text(text),
+            }
+        }
+    }
+
+    fn element(&mut self, element: &Element) {
+        match element.name.as_str() {
+            "script" | "style" | "head" | "title" | "meta" | "link" | "base" | "textarea" => {}
+            "br" => self.line_break(),
+            "hr" => {
+                self.block(true);
+                self.push("-".repeat(3), false);
+                self.spaced = true;
+            }
+            "p" => {
+                self.block(true);
+                self.children(element);
+                self.block(true);
+            }
+            "h1" | "h2" => {
+                self.block(true);
+                self.children(element);
+                let text = self.take_inline();
+                if !text.is_empty() {
+                    let underline = if element.name == "h1" { "=" } else { "-" };
+                    let len = text.chars().count().min(WIDTH - self.prefix_width());
+                    self.push(text, true);
+                    self.push(underline.repeat(len), false);
+                }
+                self.spaced = true;
+            }
+            "h3" | "h4" | "h5" | "h6" => {
+                self.block(true);
+                self.children(element);
+                let text = self.take_inline();
+                if !text.is_empty() {
+                    let level = element.name[1..].parse::<usize>().unwrap_or(3);
+                    self.push(format!("{} {text}", "#".repeat(level)), true);
+                }
+                self.spaced = true;
+            }
+            "blockquote" => {
+                self.block(true);
+                self.quote += 1;
+                self.children(element);
+                self.flush();
+                self.quote -= 1;
+                self.spaced = true;
+            }
+            "pre" => {
+                self.block(true);
+                self.pre += 1;
+                self.children(element);
+                self.pre -= 1;
+                if self.pre == 0 {
+                    let text = std::mem::take(&mut self.inline);
+                    for line in text.trim_matches('\n').lines() {
+                        self.push(line.trim_end().to_string(), false);
+                    }
+                }
+                self.spaced = true;
+            }
+            "ul" | "ol" => {
+                self.flush();
+                let nested = !self.lists.is_empty();
+                if !nested {
+                    self.spaced = true;
+                }
+                let start = element.attr("start").and_then(|start| start.trim().parse::<usize>().ok()).unwrap_or(1);
+                self.lists.push((element.name == "ol", start));
+                self.children(element);
+                self.flush();
+                self.lists.pop();
+                if !nested {
+                    self.spaced = true;
+                }
+            }
+            "li" => {
+                self.flush();
+                if self.marker.is_some() {
+                    // пустой пункт перед вложенным списком
+                    self.push("".to_string(), false);
+                }
+                let marker = match self.lists.last_mut() {
+                    Some((true, number)) => {
+                        let marker = format!("{number}. ");
+                        *number += 1;
+                        marker
+                    }
+                    _ => "* ".to_string()
+                };
+                self.indent.push(" ".repeat(marker.chars().count()));
+                self.marker = Some(marker);
+                self.children(element);
+                self.flush();
+                self.marker = None;
+                self.indent.pop();
+            }
+            "table" => {
+                self.block(true);
+                self.table(element);
+                self.block(true);
+            }
+            "div" | "section" | "article" | "header" | "footer" | "address" | "center" | "dl" | "dt" | "dd"
+            | "figure" | "figcaption" | "caption" | "form" | "fieldset" | "tr" | "thead" | "tbody" | "tfoot" => {
+                self.block(false);
+                self.children(element);
+                self.block(false);
+            }
+            "a" => {
+                let start = self.inline.len();
+                self.children(element);
+                if let Some(href) = element.attr("href") {
+                    let href = href.trim();
+                    let target = href.strip_prefix("mailto:").unwrap_or(href);
+                    let label = self.inline.get(start..).unwrap_or_default().trim().to_string();
+                    if !href.is_empty() && !href.starts_with('#') && !href.starts_with("javascript:") && label != target {
+                        if label.is_empty() {
+                            self.text(target);
+                        } else {
+                            if !self.inline.ends_with(' ') {
+                                self.inline.push(' ');
+                            }
+                            self.inline.push_str(&format!("[{href}]"));
+                        }
+                    }
+                }
+            }
+            "img" => {
+                if let Some(alt) = element.attr("alt") {
+                    if !alt.trim().is_empty() {
+                        self.text(&format!("[{}]", alt.trim()));
+                    }
+                }
+            }
+            _ => self.children(element),
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.pre > 0 {
+            self.inline.push_str(text);
+            return;
+        }
+        let mut prev_space = self.inline.is_empty() || self.inline.ends_with(' ');
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !prev_space {
+                    self.inline.push(' ');
+                    prev_space = true;
+                }
+            } else if c != '\u{ad}' {
+                self.inline.push(c);
+                prev_space = false;
+            }
+        }
+    }
+
+    fn line_break(&mut self) {
+        if self.pre > 0 {
+            self.inline.push('\n');
+            return;
+        }
+        let text = self.take_inline();
+        if !text.is_empty() || self.marker.is_some() {
+            self.push(text, true);
+        } else if !self.lines.is_empty() {
+            self.lines.push(Line::blank(self.quote));
+        }
+    }
+
+    fn block(&mut self, spaced: bool) {
+        self.flush();
+        if spaced {
+            self.spaced = true;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pre > 0 {
+            return;
+        }
+        let text = self.take_inline();
+        if !text.is_empty() {
+            self.push(text, true);
+        }
+    }
+
+    fn take_inline(&mut self) -> String {
+        let text = self.inline.trim().to_string();
+        self.inline.clear();
+        text
+    }
+
+    fn prefix_width(&self) -> usize {
+        let quote = if self.quote > 0 { self.quote + 1 } else { 0 };
+        quote + self.indent.concat().chars().count()
+    }
+
+    fn push(&mut self, text: String, wrap: bool) {
+        if self.spaced {
+            self.spaced = false;
+            if let Some(last) = self.lines.last() {
+                if !last.text.is_empty() || !last.first.trim().is_empty() {
+                    let quote = last.quote.min(self.quote);
+                    self.lines.push(Line::blank(quote));
+                }
+            }
+        }
+        let rest = self.indent.concat();
+        let first = match self.marker.take() {
+            Some(marker) => format!("{}{marker}", self.indent[..self.indent.len().saturating_sub(1)].concat()),
+            None => rest.clone()
+        };
+        self.lines.push(Line { quote: self.quote, first, rest, text, wrap });
+    }
+
+    fn table(&mut self, table: &Element) {
+        let mut rows: Vec<Vec<&Element>> = vec![];
+        table_rows(table, &mut rows);
+        let rows = rows.into_iter().filter(|row| !row.is_empty()).collect::<Vec<_>>();
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or_default();
+        if columns == 0 {
+            return;
+        }
+
+        let cells = rows.iter()
+            .map(|row| row.iter().map(|cell| cell_text(cell)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut widths = vec![0; columns];
+        for row in cells.iter() {
+            for (ind, cell) in row.iter().enumerate() {
+                widths[ind] = widths[ind].max(cell.chars().count());
+            }
+        }
+        let total = widths.iter().sum::<usize>() + 2 * (columns - 1) + self.prefix_width();
+
+        if columns == 1 || total > WIDTH {
+            // не помещается в строку: ячейки выводим отдельными блоками
+            for row in rows.iter() {
+                for cell in row.iter() {
+                    self.block(false);
+                    self.children(cell);
+                    self.block(false);
+                }
+                if columns > 1 {
+                    self.spaced = true;
+                }
+            }
+            return;
+        }
+
+        let with_header = rows[0].iter().all(|cell| cell.name == "th");
+        for (ind, row) in cells.iter().enumerate() {
+            let line = widths.iter().enumerate()
+                .map(|(col, width)| {
+                    let cell = row.get(col).map(|cell| cell.as_str()).unwrap_or_default();
+                    let pad = " ".repeat(width - cell.chars().count());
+                    if is_numeric(cell) { format!("{pad}{cell}") } else { format!("{cell}{pad}") }
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            self.push(line.trim_end().to_string(), false);
+            if ind == 0 && with_header {
+                let line = widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>().join("  ");
+                self.push(line, false);
+            }
+        }
+    }
+}
+
+fn table_rows<'a>(element: &'a Element, rows: &mut Vec<Vec<&'a Element>>) {
+    for node in element.children.iter() {
+        if let Node::Element(child) = node {
+            match child.name.as_str() {
+                "tr" => {
+                    rows.push(child.children.iter()
+                        .filter_map(|node| match node {
+                            Node::Element(cell) if cell.name == "td" || cell.name == "th" => Some(cell),
+                            _ => None
+                        })
+                        .collect());
+                }
+                "thead" | "tbody" | "tfoot" => table_rows(child, rows),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn cell_text(cell: &Element) -> String {
+    let mut renderer = Renderer::default();
+    renderer.children(cell);
+    renderer.flush();
+    renderer.lines.iter()
+        .map(|line| line.text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_numeric(text: &str) -> bool {
+    text.chars().any(|c| c.is_ascii_digit())
+        && text.chars().all(|c| c.is_ascii_digit() || " .,-+%$€₽\u{a0}".contains(c))
+}
+
+// === вывод с переносами (format=flowed)
+
+fn output(lines: &[Line]) -> String {
+    let mut out: Vec<String> = vec![];
+    for line in lines.iter() {
+        let quote = ">".repeat(line.quote);
+        if line.text.is_empty() && line.first.trim().is_empty() {
+            if out.last().map(|last| last != &quote).unwrap_or(true) {
+                out.push(quote);
+            }
+            continue;
+        }
+
+        let quote_width = if line.quote > 0 { line.quote + 1 } else { 0 };
+        let prefix_width = line.first.chars().count().max(line.rest.chars().count());
+        let width = WIDTH.saturating_sub(quote_width + prefix_width).max(WIDTH_MIN);
+        let pieces = if line.wrap { wrap(&line.text, width) } else { vec![line.text.clone()] };
+
+        let last = pieces.len() - 1;
+        for (ind, piece) in pieces.iter().enumerate() {
+            let prefix = if ind == 0 { &line.first } else { &line.rest };
+            let mut text = format!("{prefix}{piece}").replace('\u{a0}', " ").trim_end().to_string();
+            // "-- " -- разделитель подписи, пробел в конце обязателен
+            if text == "--" {
+                text.push(' ');
+            }
+            if ind < last {
+                text.push(' ');
+            }
+            out.push(stuff(&quote, text));
+        }
+    }
+
+    while out.last().map(|line| line.is_empty()).unwrap_or_default() {
+        out.pop();
+    }
+    let first = out.iter().position(|line| !line.is_empty()).unwrap_or(out.len());
+    out[first..].join("\n")
+}
+
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut current_width = 0;
+    for word in text.split(' ').filter(|word| !word.is_empty()) {
+        let word_width = word.chars().count();
+        if current_width > 0 && current_width + 1 + word_width > width {
+            lines.push(std::mem::take(&mut current));
+            current_width = 0;
+        }
+        if current_width > 0 {
+            current.push(' ');
+            current_width += 1;
+        }
+        current.push_str(word);
+        current_width += word_width;
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+// space-stuffing: пробел после знаков цитаты и перед строками, которые читатель
+// format=flowed иначе примет за цитату или за "From " из mbox
+fn stuff(quote: &str, text: String) -> String {
+    if !quote.is_empty() {
+        format!("{quote} {text}")
+    } else if text.starts_with(' ') || text.starts_with('>') || text.starts_with("From ") {
+        format!(" {text}")
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_and_breaks() {
+        let text = html_to_text("<p>Первый абзац</p><p>Второй<br>с переносом</p>");
+        assert_eq!(text, "Первый абзац\n\nВторой\nс переносом");
+    }
+
+    #[test]
+    fn entities_and_hidden_elements() {
+        let text = html_to_text("<style>p{color:red}</style><p>a &amp; b &lt;c&gt;&nbsp;d&#33; &#x41;</p><script>alert(1)</script><!-- x -->");
+        assert_eq!(text, "a & b <c> d! A");
+    }
+
+    #[test]
+    fn links() {
+        let text = html_to_text(r#"<p><a href="https://example.com/">сайт</a>, <a href="https://example.com/">https://example.com/</a>, <a href="mailto:a@b.ru">a@b.ru</a></p>"#);
+        assert_eq!(text, "сайт [https://example.com/], https://example.com/, a@b.ru");
+    }
+
+    #[test]
+    fn headings() {
+        let text = html_to_text("<h1>Отчет</h1><h2>Итоги</h2><h3>Детали</h3><p>текст</p>");
+        assert_eq!(text, "Отчет\n=====\n\nИтоги\n-----\n\n### Детали\n\nтекст");
+    }
+
+    #[test]
+    fn ordered_and_nested_lists() {
+        let html = r#"<p>Список:</p><ol start="3"><li>три<ul><li>вложенный</li><li>еще</li></ul></li><li>четыре</ol><p>конец</p>"#;
+        let text = html_to_text(html);
+        // строки с отступом получают space-stuffing
+        assert_eq!(text, "Список:\n\n3. три\n    * вложенный\n    * еще\n4. четыре\n\nконец");
+    }
+
+    #[test]
+    fn blockquotes() {
+        let html = "<p>ответ</p><blockquote><p>цитата</p><blockquote>вложенная</blockquote></blockquote><p>после</p>";
+        let text = html_to_text(html);
+        assert_eq!(text, "ответ\n\n> цитата\n>\n>> вложенная\n\nпосле");
+    }
+
+    #[test]
+    fn table_aligned() {
+        let html = "<table><tr><th>Товар</th><th>Кол-во</th></tr><tr><td>Бумага</td><td>12</td></tr><tr><td>Ручки</td><td>150</td></tr></table>";
+        let text = html_to_text(html);
+        assert_eq!(text, "Товар   Кол-во\n------  ------\nБумага      12\nРучки      150");
+    }
+
+    #[test]
+    fn wide_table_falls_back_to_blocks() {
+        let long = "слово ".repeat(20);
+        let html = format!("<table><tr><td>{long}</td><td>второй</td></tr></table>");
+        let text = html_to_text(&html);
+        assert!(text.contains("второй"));
+        assert!(text.lines().all(|line| line.chars().count() <= WIDTH + 1));
+    }
+
+    #[test]
+    fn wrap_flowed() {
+        let html = format!("<p>{}</p>", "съешь же ещё этих мягких французских булок, да выпей чаю. ".repeat(4));
+        let text = html_to_text(&html);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines.len() > 1);
+        for line in lines[..lines.len() - 1].iter() {
+            assert!(line.ends_with(' '), "soft break expected: {line:?}");
+            assert!(line.chars().count() <= WIDTH + 1);
+        }
+        assert!(!lines[lines.len() - 1].ends_with(' '));
+        assert_eq!(text.replace(" \n", " ").trim_end(), html[3..html.len() - 4].trim_end());
+    }
+
+    #[test]
+    fn wrap_quoted_list_item() {
+        let html = format!("<blockquote><ul><li>{}</li></ul></blockquote>", "длинный текст пункта ".repeat(6));
+        let text = html_to_text(&html);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("> * длинный"));
+        assert!(lines[1].starts_with(">   длинный"));
+        assert!(lines.iter().all(|line| line.chars().count() <= WIDTH + 1));
+    }
+
+    #[test]
+    fn pre_kept() {
+        let text = html_to_text("<pre>  a  b\n    c   </pre>");
+        assert_eq!(text, "   a  b\n     c");
+    }
+
+    #[test]
+    fn space_stuffing() {
+        let text = html_to_text("<p>From Moscow</p><p>&gt; not a quote</p>");
+        assert_eq!(text, " From Moscow\n\n > not a quote");
+    }
+
+    #[test]
+    fn signature_separator() {
+        let text = html_to_text("<p>текст</p><p>--&nbsp;<br>Иван</p>");
+        assert_eq!(text, "текст\n\n-- \nИван");
+    }
+
+    #[test]
+    fn editor_markup() {
+        let text = html_to_text("<p>Добрый день!</p><p><br></p><div>Подпись</div>");
+        assert_eq!(text, "Добрый день!\n\nПодпись");
+    }
+
+    #[test]
+    fn deep_nesting_flattened() {
+        // сотни тысяч вложенных тегов из письма не должны переполнить стек
+        let depth = 100_000;
+        for tag in ["div", "blockquote", "li"] {
+            let html = format!("{}глубоко{}", format!("<{tag}>").repeat(depth), format!("</{tag}>").repeat(depth));
+            assert!(html_to_text(&html).ends_with("глубоко"), "{tag}");
+        }
+        let html = format!("{}текст", "<span><b>".repeat(depth));
+        assert_eq!(html_to_text(&html), "текст");
+
+        // до предела структура сохраняется
+        let html = format!("{}цитата{}", "<blockquote>".repeat(3), "</blockquote>".repeat(3));
+        assert_eq!(html_to_text(&html), ">>> цитата");
+    }
+
+    #[test]
+    fn raw_elements_case_insensitive() {
+        let text = html_to_text("<STYLE>p{}</StYlE><p>видно</p><Script>x</SCRIPT>");
+        assert_eq!(text, "видно");
+        assert_eq!(find_ignore_case("ab</Style>", "</style"), Some(2));
+        assert_eq!(find_ignore_case("ёж</style", "</style"), Some(4));
+        assert_eq!(find_ignore_case("<st", "</style"), None);
+
+        // много коротких стилей подряд: разбор линейный
+        let html = format!("{}<p>конец</p>", "<style>a{}</style>".repeat(50_000));
+        assert_eq!(html_to_text(&html), "конец");
+    }
+}
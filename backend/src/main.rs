@@ -4,21 +4,25 @@ use tracing::Level;
 use tracing_subscriber::fmt::format::FmtSpan;
 use warp::{Filter, http, Rejection, Reply};
 use warp::http::StatusCode;
-use warp::reject::Reject;
+use warp::reject::{PayloadTooLarge, Reject};
 
-use shared::constants::{API_EVENT, API_FILE, API_FILES, API_LOGIN, API_NOTES, CHANNEL_MESSAGE, CHANNEL_MESSAGES, HEADER_USER_KEY, ROOT_API};
-use state::USER_AUTH;
+use shared::constants::{API_EVENT, API_FILE, API_FILE_TEMP, API_FILE_THUMB, API_FILE_ZIP, API_FILES, API_LOGIN, API_LOGINS, API_NOTES, API_PASSWORD, API_PGP, API_SEARCH, API_SESSIONS, API_SETTINGS, API_SMIME, API_SOURCE, API_TOTP, API_UPLOADS, CHANNEL_MESSAGE, CHANNEL_MESSAGES, HEADER_UPLOAD_OFFSET, ROOT_API, UPLOAD_CHUNK_MAX, UPLOAD_REQUEST_MAX};
 
+use crate::commands::run_command;
 use crate::constants::test_dirs;
 use crate::db::db_conn;
 use crate::db_types::DBNotes;
 use crate::db_user::db_user_init;
-use crate::filters::{with_body_filter, with_hash};
+use crate::dkim::dkim_self_test;
+use crate::cookie::{cookie_init, COOKIE_SESSION};
+use crate::filters::{with_body_filter, with_device, with_session};
 use crate::receive::mail_watcher;
-use crate::routes::{file_handler, files_handler, route_login, route_message, route_messages, route_notes_update};
+use crate::routes::{file_handler, files_handler, route_login, route_logins, route_message, route_messages, route_notes_update, route_password, route_pgp, route_search, route_sessions, route_settings, route_smime, route_totp, route_upload_append, route_upload_cancel, route_upload_create, route_upload_finish, route_upload_offset, source_handler, temp_file_handler, temp_thumb_handler, thumb_handler, zip_handler};
 use crate::sse::user_sse_connected;
+use crate::crypt::crypt_init;
+use crate::storage::storage_init;
 use crate::tasks::run_tasks;
-use crate::types::DownloadStruct;
+use crate::types::{DownloadStruct, SourceStruct};
 
 mod db;
 mod db_types;
@@ -26,6 +30,9 @@ pub mod db_notes;
 mod sse;
 mod routes;
 mod db_user;
+mod db_sessions;
+mod db_logins;
+mod db_totp;
 mod types;
 mod filters;
 mod utils;
@@ -35,21 +42,32 @@ mod constants;
 mod upload;
 mod receive;
 mod send;
+mod html_text;
 mod tasks;
+mod commands;
+mod reingest;
+mod dkim;
+mod password;
+mod cookie;
+mod totp;
+mod download;
+mod archive;
+mod multipart;
+mod resumable;
+mod blobs;
+mod storage;
+mod crypt;
+mod smime;
+mod db_smime;
+mod pgp;
+mod db_pgp;
+mod pgp_mime;
+mod search;
+mod db_search;
+mod thumbs;
 
 #[tokio::main(worker_threads = 2)]
 async fn main() {
-    tokio::task::spawn(async {
-        db_user_init().await;
-        test_dirs();
-        mail_watcher().await;
-        //if let Err(_) = mail_watcher_() {}
-    });
-
-    tokio::task::spawn(async {
-        run_tasks().await;
-    });
-
     let with_ansi = cfg!(target_os = "macos");
 
     let subscriber = tracing_subscriber::fmt()
@@ -62,30 +80,118 @@ async fn main() {
         .finish();
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
+    // команды тоже работают с хранилищем вложений
+    if let Err(err) = storage_init() {
+        tracing::error!("storage: {err}");
+        std::process::exit(1);
+    }
+    if let Err(err) = crypt_init() {
+        tracing::error!("encryption: {err}");
+        std::process::exit(1);
+    }
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        run_command(&args).await;
+        return;
+    }
+
+    // без ключа подписи сеансы не проверить: не запускаемся
+    if let Err(err) = cookie_init() {
+        tracing::error!("cookie_secret: {err}");
+        std::process::exit(1);
+    }
+
+    for (domain, result) in dkim_self_test() {
+        match result {
+            Ok(_) => tracing::info!("dkim {domain}: ok"),
+            Err(err) => tracing::error!("dkim {domain}: {err}")
+        }
+    }
+
+    tokio::task::spawn(async {
+        db_user_init().await;
+        test_dirs();
+        mail_watcher().await;
+        //if let Err(_) = mail_watcher_() {}
+    });
+
+    tokio::task::spawn(async {
+        run_tasks().await;
+    });
+
     let user_login = warp::path(API_LOGIN)
-        .and(with_hash())
+        .and(with_device())
+        .and(warp::cookie::optional::<String>(COOKIE_SESSION))
         .and(warp::body::content_length_limit(512))
         .and(warp::body::json())
         .and_then(route_login);
 
     let message_filter = warp::path(CHANNEL_MESSAGE)
         .and(warp::body::content_length_limit(1024 * 1024))
-        .and(warp::header::<String>(HEADER_USER_KEY))
+        .and(with_session())
         .and(with_body_filter())
         .and_then(route_message);
 
     let messages_filter = warp::path(CHANNEL_MESSAGES)
         .and(warp::body::content_length_limit(1024 * 100))
-        .and(warp::header::<String>(HEADER_USER_KEY))
+        .and(with_session())
         .and(with_body_filter())
         .and_then(route_messages);
 
     let notes_filter = warp::path(API_NOTES)
         .and(warp::body::content_length_limit(1024 * 100))
-        .and(warp::header::<String>(HEADER_USER_KEY))
+        .and(with_session())
         .and(with_body_filter())
         .and_then(route_notes_update);
 
+    let settings_filter = warp::path(API_SETTINGS)
+        .and(warp::body::content_length_limit(1024 * 100))
+        .and(with_session())
+        .and(with_body_filter())
+        .and_then(route_settings);
+
+    let password_filter = warp::path(API_PASSWORD)
+        .and(with_session())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and_then(route_password);
+
+    let sessions_filter = warp::path(API_SESSIONS)
+        .and(with_session())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and_then(route_sessions);
+
+    let totp_filter = warp::path(API_TOTP)
+        .and(with_session())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and_then(route_totp);
+
+    let smime_filter = warp::path(API_SMIME)
+        .and(with_session())
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and_then(route_smime);
+
+    // файл ключа в base64 (до 256K)
+    let pgp_filter = warp::path(API_PGP)
+        .and(with_session())
+        .and(warp::body::content_length_limit(384 * 1024))
+        .and(warp::body::json())
+        .and_then(route_pgp);
+
+    let search_filter = warp::path(API_SEARCH)
+        .and(with_session())
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .and_then(route_search);
+
+    let logins_filter = warp::path(API_LOGINS)
+        .and(with_session())
+        .and_then(route_logins);
+
     let cors = warp::cors()
         .allow_any_origin()
         .allow_headers(vec!["*"])
@@ -93,44 +199,138 @@ async fn main() {
 
     let file_filter = warp::path(ROOT_API)
         .and(warp::path(API_FILE))
-        .and(warp::path::tail())
+        .and(warp::path::param::<i64>())
+        .and(warp::path::param::<usize>())
+        .and(warp::path::end())
         .and(warp::query::<DownloadStruct>())
+        .and(with_session())
+        .and(warp::header::headers_cloned())
         .and_then(file_handler)
         ;
 
+    let thumb_filter = warp::path(ROOT_API)
+        .and(warp::path(API_FILE))
+        .and(warp::path::param::<i64>())
+        .and(warp::path::param::<usize>())
+        .and(warp::path(API_FILE_THUMB))
+        .and(warp::path::end())
+        .and(with_session())
+        .and(warp::header::headers_cloned())
+        .and_then(thumb_handler)
+        ;
+
+    let zip_filter = warp::path(ROOT_API)
+        .and(warp::path(API_FILE))
+        .and(warp::path::param::<i64>())
+        .and(warp::path(API_FILE_ZIP))
+        .and(warp::path::end())
+        .and(with_session())
+        .and_then(zip_handler)
+        ;
+
+    let temp_file_filter = warp::path(ROOT_API)
+        .and(warp::path(API_FILE))
+        .and(warp::path(API_FILE_TEMP))
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<usize>())
+        .and(warp::path::end())
+        .and(warp::query::<DownloadStruct>())
+        .and(with_session())
+        .and(warp::header::headers_cloned())
+        .and_then(temp_file_handler)
+        ;
+
+    let temp_thumb_filter = warp::path(ROOT_API)
+        .and(warp::path(API_FILE))
+        .and(warp::path(API_FILE_TEMP))
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<usize>())
+        .and(warp::path(API_FILE_THUMB))
+        .and(warp::path::end())
+        .and(warp::query::<DownloadStruct>())
+        .and(with_session())
+        .and(warp::header::headers_cloned())
+        .and_then(temp_thumb_handler)
+        ;
+
+    let source_filter = warp::path(ROOT_API)
+        .and(warp::path(API_SOURCE))
+        .and(warp::path::param::<i64>())
+        .and(warp::query::<SourceStruct>())
+        .and(with_session())
+        .and_then(source_handler)
+        ;
+
     let files_filter = warp::path(API_FILES)
-        .and(warp::header::<String>(HEADER_USER_KEY))
-        .and(warp::multipart::form().max_length(50 * 1024 * 1024))
+        .and(with_session())
+        .and(warp::header::<String>("content-type"))
+        .and(warp::body::content_length_limit(UPLOAD_REQUEST_MAX))
+        .and(warp::body::stream())
         .and_then(files_handler)
         ;
 
+    // возобновляемая загрузка: POST создает, HEAD сообщает смещение, PATCH дописывает кусок,
+    // POST с номером переносит готовый файл во вложения, DELETE отменяет
+    let upload_create_filter = warp::path(API_UPLOADS)
+        .and(warp::path::end())
+        .and(with_session())
+        .and(warp::body::content_length_limit(1024 * 4))
+        .and(warp::body::json())
+        .and_then(route_upload_create);
+
+    let upload_finish_filter = warp::path(API_UPLOADS)
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_session())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and_then(route_upload_finish);
+
+    let upload_offset_filter = warp::path(ROOT_API)
+        .and(warp::path(API_UPLOADS))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_session())
+        .and_then(route_upload_offset);
+
+    let upload_append_filter = warp::path(ROOT_API)
+        .and(warp::path(API_UPLOADS))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_session())
+        .and(warp::header::<u64>(HEADER_UPLOAD_OFFSET))
+        .and(warp::body::content_length_limit(UPLOAD_CHUNK_MAX))
+        .and(warp::body::stream())
+        .and_then(route_upload_append);
+
+    let upload_cancel_filter = warp::path(ROOT_API)
+        .and(warp::path(API_UPLOADS))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_session())
+        .and_then(route_upload_cancel);
+
     let event = warp::path(ROOT_API)
         .and(warp::path(API_EVENT))
-        .and(with_hash()
-            .and_then(|hash: String| async move {
-                let res = if let Ok(mut user_auth) = USER_AUTH.lock() {
-                    if let Some(session) = user_auth.remove(&hash) {
-                        Ok(session.idu)
-                    } else { Err(()) }
-                } else { Err(()) };
-                res.map_err(|_| warp::reject::custom(NotUtf8))
-            })
-        )
-        .map(|idu: i32| {
-            let stream = user_sse_connected(idu);
+        .and(with_session())
+        .map(|session| {
+            let stream = user_sse_connected(session);
             warp::sse::reply(warp::sse::keep_alive().stream(stream))
         });
 
     let routes_dir = warp::fs::dir("/Users/mac-user/Documents/development/rs-app-mail/frontend/dist");
 
     let routes = warp::get()
-        .and(event.or(file_filter).or(routes_dir))
+        .and(event.or(file_filter).or(thumb_filter).or(zip_filter).or(temp_file_filter).or(temp_thumb_filter).or(source_filter).or(routes_dir))
         .or(
             warp::post().and(
                 warp::path(ROOT_API)
-                    .and(notes_filter.or(files_filter).or(message_filter).or(messages_filter).or(user_login))
+                    .and(notes_filter.or(files_filter).or(message_filter).or(messages_filter).or(settings_filter).or(password_filter).or(sessions_filter).or(logins_filter).or(totp_filter).or(smime_filter).or(pgp_filter).or(search_filter).or(upload_create_filter).or(upload_finish_filter).or(user_login))
             )
-        );
+        )
+        .or(warp::head().and(upload_offset_filter))
+        .or(warp::patch().and(upload_append_filter))
+        .or(warp::delete().and(upload_cancel_filter));
 
     warp::serve(
         routes
@@ -143,7 +343,22 @@ async fn main() {
 
 
 #[tracing::instrument]
-async fn handle_rejection(_err: Rejection) -> Result<impl Reply, Infallible> {
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    if err.find::<Unauthorized>().is_some() {
+        return Ok(http::Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body("unauthorized"));
+    }
+    if err.find::<PayloadTooLarge>().is_some() {
+        return Ok(http::Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body("payload too large"));
+    }
+    if err.find::<Forbidden>().is_some() {
+        return Ok(http::Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("forbidden"));
+    }
     Ok(http::Response::builder()
         .status(StatusCode::NOT_FOUND)
         .body("not found"))
@@ -159,4 +374,14 @@ impl Reject for AppErr {}
 #[derive(Debug)]
 struct NotUtf8;
 
-impl Reject for NotUtf8 {}
\ No newline at end of file
+impl Reject for NotUtf8 {}
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl Reject for Forbidden {}
\ No newline at end of file
@@ -0,0 +1,332 @@
+use std::pin::Pin;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+
+const HEADERS_MAX: usize = 16 * 1024;
+
+type BodyStream = Pin<Box<dyn Stream<Item=Result<Bytes, warp::Error>> + Send>>;
+
+#[derive(Debug, Default)]
+pub struct PartHeaders {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+}
+
+// multipart/form-data разбирается по мере поступления тела запроса, в памяти держится только текущий кусок
+pub struct Multipart {
+    body: BodyStream,
+    buf: BytesMut,
+    delimiter: Vec<u8>,
+    in_part: bool,
+    finished: bool,
+}
+
+impl Multipart {
+    pub fn new<S, B>(body: S, boundary: &str) -> Self
+        where
+            S: Stream<Item=Result<B, warp::Error>> + Send + 'static,
+            B: Buf, {
+        Self {
+            body: Box::pin(body.map_ok(|mut chunk| chunk.copy_to_bytes(chunk.remaining()))),
+            // перед первой границей CRLF нет, добавляем его, чтобы все границы искались одинаково
+            buf: BytesMut::from(&b"\r\n"[..]),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            in_part: false,
+            finished: false,
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), String> {
+        match self.body.next().await {
+            Some(Ok(chunk)) => {
+                self.buf.extend_from_slice(&chunk);
+                Ok(())
+            }
+            Some(Err(err)) => Err(err.to_string()),
+            None => Err("unexpected end of form".to_string())
+        }
+    }
+
+    // заголовки следующей части; None -- форма закончилась
+    pub async fn next_part(&mut self) -> Result<Option<PartHeaders>, String> {
+        // непрочитанный остаток текущей части пропускаем
+        while self.next_chunk().await?.is_some() {}
+        if self.finished {
+            return Ok(None);
+        }
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                self.buf.advance(pos + self.delimiter.len());
+                break;
+            }
+            let skip = self.buf.len().saturating_sub(self.delimiter.len());
+            self.buf.advance(skip);
+            self.fill().await?;
+        }
+        while self.buf.len() < 2 {
+            self.fill().await?;
+        }
+        if self.buf.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        let end = loop {
+            if let Some(pos) = find(&self.buf, b"\r\n\r\n") {
+                break pos;
+            }
+            if self.buf.len() > HEADERS_MAX {
+                return Err("part headers too long".to_string());
+            }
+            self.fill().await?;
+        };
+        let head = self.buf.split_to(end + 4);
+        self.in_part = true;
+        Ok(Some(part_headers(&String::from_utf8_lossy(&head))))
+    }
+
+    // очередной кусок данных текущей части; None -- часть закончилась
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>, String> {
+        if !self.in_part {
+            return Ok(None);
+        }
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                if pos > 0 {
+                    return Ok(Some(self.buf.split_to(pos).freeze()));
+                }
+                self.in_part = false;
+                return Ok(None);
+            }
+            // хвост, в котором может начинаться граница, оставляем до следующего куска
+            let ready = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+            if ready > 0 {
+                return Ok(Some(self.buf.split_to(ready).freeze()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    // текстовое поле формы целиком, но не больше limit байт
+    pub async fn read_text(&mut self, limit: usize) -> Result<String, String> {
+        let mut text = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            if text.len() + chunk.len() > limit {
+                return Err("form field too long".to_string());
+            }
+            text.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8_lossy(&text).to_string())
+    }
+}
+
+fn find(buf: &[u8], pattern: &[u8]) -> Option<usize> {
+    buf.windows(pattern.len()).position(|window| window == pattern)
+}
+
+fn part_headers(head: &str) -> PartHeaders {
+    let mut headers = PartHeaders::default();
+    for line in head.split("\r\n") {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "content-disposition" => {
+                    for (key, value) in disposition_params(value) {
+                        match key.as_str() {
+                            "name" => headers.name = value,
+                            // старые браузеры присылают полный путь к файлу
+                            "filename" => headers.file_name = value.rsplit(['/', '\\']).next().map(|name| name.replace("%22", "\"")),
+                            _ => {}
+                        }
+                    }
+                }
+                "content-type" => headers.content_type = Some(value.trim().to_lowercase()),
+                _ => {}
+            }
+        }
+    }
+    headers
+}
+
+// параметры form-data; name="files"; filename="..."
+// кавычки в именах браузеры кодируют как %22, экранирования нет
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = value;
+    while let Some(pos) = rest.find(';') {
+        rest = &rest[pos + 1..];
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => break
+        };
+        let key = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_start();
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or_default();
+            &quoted[..end]
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value.trim()
+        };
+        params.push((key, value.to_string()));
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----WebKitFormBoundary7MA4YWxk";
+
+    fn form(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, file_name, data) in parts {
+            body.extend_from_slice(format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"").as_bytes());
+            if let Some(file_name) = file_name {
+                body.extend_from_slice(format!("; filename=\"{file_name}\"\r\nContent-Type: application/octet-stream").as_bytes());
+            }
+            body.extend_from_slice(b"\r\n\r\n");
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    fn split(body: &[u8], sizes: impl Fn(usize) -> usize) -> Vec<Bytes> {
+        let mut chunks = vec![];
+        let mut pos = 0;
+        while pos < body.len() {
+            let len = sizes(chunks.len()).clamp(1, body.len() - pos);
+            chunks.push(Bytes::copy_from_slice(&body[pos..pos + len]));
+            pos += len;
+        }
+        chunks
+    }
+
+    async fn parse(chunks: Vec<Bytes>) -> Result<Vec<(PartHeaders, Vec<u8>)>, String> {
+        let mut form = Multipart::new(futures_util::stream::iter(chunks.into_iter().map(Ok::<_, warp::Error>)), BOUNDARY);
+        let mut parts = vec![];
+        while let Some(headers) = form.next_part().await? {
+            let mut data = vec![];
+            while let Some(chunk) = form.next_chunk().await? {
+                data.extend_from_slice(&chunk);
+            }
+            parts.push((headers, data));
+        }
+        Ok(parts)
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 13) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn single_chunk() {
+        let file = data(1000);
+        let body = form(&[("path", None, b"/docs"), ("files", Some("a.bin"), &file)]);
+        let parts = parse(vec![Bytes::from(body)]).await.unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0.name, "path");
+        assert_eq!(parts[0].1, b"/docs");
+        assert_eq!(parts[1].0.file_name.as_deref(), Some("a.bin"));
+        assert_eq!(parts[1].0.content_type.as_deref(), Some("application/octet-stream"));
+        assert_eq!(parts[1].1, file);
+    }
+
+    #[tokio::test]
+    async fn one_byte_chunks() {
+        let file = data(300);
+        let body = form(&[("path", None, b"x"), ("files", Some("a.bin"), &file)]);
+        let parts = parse(split(&body, |_| 1)).await.unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].1, b"x");
+        assert_eq!(parts[1].1, file);
+    }
+
+    #[tokio::test]
+    async fn random_chunks() {
+        let file = data(5000);
+        let body = form(&[("files", Some("a.bin"), &file), ("files", Some("b.bin"), b"second")]);
+        for seed in 1..50u64 {
+            // xorshift: воспроизводимые размеры кусков от 1 до 100 байт
+            let sizes = move |i: usize| {
+                let mut x = seed.wrapping_mul(0x9E3779B97F4A7C15).wrapping_add(i as u64 + 1);
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                (x % 100) as usize + 1
+            };
+            let parts = parse(split(&body, sizes)).await.unwrap();
+            assert_eq!(parts.len(), 2, "seed={seed}");
+            assert_eq!(parts[0].1, file, "seed={seed}");
+            assert_eq!(parts[1].1, b"second", "seed={seed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn boundary_split_across_chunks() {
+        let body = form(&[("files", Some("a.bin"), b"0123456789")]);
+        let delimiter = find(&body[2..], format!("\r\n--{BOUNDARY}").as_bytes()).unwrap() + 2;
+        // разрез внутри каждой позиции закрывающей границы
+        for cut in delimiter..delimiter + BOUNDARY.len() + 4 {
+            let chunks = vec![Bytes::copy_from_slice(&body[..cut]), Bytes::copy_from_slice(&body[cut..])];
+            let parts = parse(chunks).await.unwrap();
+            assert_eq!(parts.len(), 1, "cut={cut}");
+            assert_eq!(parts[0].1, b"0123456789", "cut={cut}");
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_part() {
+        let body = form(&[("path", None, b""), ("files", Some("empty.txt"), b"")]);
+        let parts = parse(split(&body, |_| 3)).await.unwrap();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].1.is_empty());
+        assert_eq!(parts[1].0.file_name.as_deref(), Some("empty.txt"));
+        assert!(parts[1].1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn crlf_inside_data() {
+        let file = format!("line\r\n\r\n--not-boundary\r\n--{}x\r\n-", &BOUNDARY[..10]).into_bytes();
+        let body = form(&[("files", Some("a.txt"), &file)]);
+        for size in [1, 2, 5, 64] {
+            let parts = parse(split(&body, |_| size)).await.unwrap();
+            assert_eq!(parts[0].1, file, "size={size}");
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_closing_delimiter() {
+        let mut body = form(&[("files", Some("a.bin"), b"data")]);
+        body.truncate(body.len() - BOUNDARY.len() - 6);
+        assert!(parse(split(&body, |_| 4)).await.is_err());
+
+        // форма оборвалась посреди данных
+        let body = form(&[("files", Some("a.bin"), &data(100))]);
+        assert!(parse(vec![Bytes::copy_from_slice(&body[..150])]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unread_part_skipped() {
+        let body = form(&[("files", Some("a.bin"), &data(500)), ("path", None, b"/x")]);
+        let mut form = Multipart::new(futures_util::stream::iter(split(&body, |_| 7).into_iter().map(Ok::<_, warp::Error>)), BOUNDARY);
+        assert_eq!(form.next_part().await.unwrap().unwrap().name, "files");
+        assert_eq!(form.next_part().await.unwrap().unwrap().name, "path");
+        assert_eq!(form.read_text(10).await.unwrap(), "/x");
+        assert!(form.next_part().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn disposition_file_name() {
+        let headers = part_headers("Content-Disposition: form-data; name=\"files\"; filename=\"C:\\tmp\\a%22b;c.txt\"\r\nContent-Type: Text/Plain");
+        assert_eq!(headers.name, "files");
+        assert_eq!(headers.file_name.as_deref(), Some("a\"b;c.txt"));
+        assert_eq!(headers.content_type.as_deref(), Some("text/plain"));
+    }
+}
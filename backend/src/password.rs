@@ -0,0 +1,120 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use rand_core::{OsRng, RngCore};
+
+pub const PASSWORD_MIN: usize = 8;
+
+const GENERATED_LEN: usize = 16;
+const GENERATED_CHARS: &[u8] = b"abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// Argon2id занимает процессор на десятки миллисекунд: считаем вне рабочих потоков tokio
+pub async fn password_hash(password: &str) -> Option<String> {
+    let password = password.to_string();
+    match tokio::task::spawn_blocking(move || hash_blocking(&password)).await {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::error!("password_hash {:?}", err);
+            None
+        }
+    }
+}
+
+pub async fn password_verify(stored: &str, password: &str) -> bool {
+    let (stored, password) = (stored.to_string(), password.to_string());
+    match tokio::task::spawn_blocking(move || verify_blocking(&stored, &password)).await {
+        Ok(result) => result,
+        Err(err) => {
+            tracing::error!("password_verify {:?}", err);
+            false
+        }
+    }
+}
+
+// Argon2id с параметрами по умолчанию и своей солью для каждого пароля
+fn hash_blocking(password: &str) -> Option<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Some(hash.to_string()),
+        Err(err) => {
+            tracing::error!("password_hash {:?}", err);
+            None
+        }
+    }
+}
+
+pub fn password_is_hash(stored: &str) -> bool {
+    stored.starts_with("$argon2")
+}
+
+// старые пароли хранятся открытым текстом -- их сравниваем напрямую
+fn verify_blocking(stored: &str, password: &str) -> bool {
+    if !password_is_hash(stored) {
+        return !stored.is_empty() && constant_time_eq(stored.as_bytes(), password.as_bytes());
+    }
+    match PasswordHash::new(stored) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(err) => {
+            tracing::error!("password_verify {:?}", err);
+            false
+        }
+    }
+}
+
+pub fn password_generate() -> String {
+    let mut bytes = [0u8; GENERATED_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| GENERATED_CHARS[*b as usize % GENERATED_CHARS.len()] as char).collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_and_verify() {
+        let hash = hash_blocking("correct horse").unwrap();
+        assert!(password_is_hash(&hash));
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_blocking(&hash, "correct horse"));
+        assert!(!verify_blocking(&hash, "correct horse "));
+        assert!(!verify_blocking(&hash, ""));
+    }
+
+    #[test]
+    fn salt_differs() {
+        assert_ne!(hash_blocking("пароль").unwrap(), hash_blocking("пароль").unwrap());
+    }
+
+    #[test]
+    fn legacy_plaintext() {
+        assert!(!password_is_hash("secret"));
+        assert!(verify_blocking("secret", "secret"));
+        assert!(!verify_blocking("secret", "Secret"));
+        assert!(!verify_blocking("secret", "secret1"));
+        // пустой пароль в базе не подходит ни к чему
+        assert!(!verify_blocking("", ""));
+    }
+
+    #[test]
+    fn broken_hash_rejected() {
+        assert!(!verify_blocking("$argon2id$broken", "secret"));
+    }
+
+    #[tokio::test]
+    async fn async_wrappers() {
+        let hash = password_hash("пароль123").await.unwrap();
+        assert!(password_verify(&hash, "пароль123").await);
+        assert!(!password_verify(&hash, "пароль124").await);
+    }
+
+    #[test]
+    fn generated() {
+        let password = password_generate();
+        assert_eq!(password.len(), GENERATED_LEN);
+        assert!(password.bytes().all(|b| GENERATED_CHARS.contains(&b)));
+    }
+}
@@ -0,0 +1,1719 @@
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{ExpandedSecretKey, Verifier};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use openssl::aes::{AesKey, unwrap_key, wrap_key};
+use openssl::symm::{Cipher, decrypt, encrypt};
+use rand_core::{OsRng, RngCore};
+use rsa::{BigUint, Hash, PaddingScheme, PublicKey, PublicKeyParts, RsaPrivateKey, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use sha2::digest::DynDigest;
+
+// OpenPGP (RFC 4880, RFC 6637): ключи v4 RSA, Ed25519 и Cv25519, сообщения SEIPD с MDC и AES.
+// Этого хватает для ключей GnuPG, Thunderbird и OpenKeychain последних лет
+//
+// Модель угроз. Свой разбор вместо библиотеки: в зависимостях нет поддерживаемой реализации OpenPGP.
+// Криптографию делают openssl, rsa и dalek, здесь только формат пакетов и проверки поверх него.
+// - Входящее письмо и ключ из вложения -- данные злоумышленника: длины проверяются при каждом чтении,
+//   вложенность сообщения не больше NESTING_MAX, распакованное -- не больше UNCOMPRESSED_MAX.
+// - Шифрование без MDC (SED) не расшифровывается вовсе: измененный шифртекст не должен дать открытый текст.
+//   Ошибки MDC, ключа и разбора не различаются для отправителя: результат только в отметке письма.
+// - Ключ принимается только с проверенной самоподписью идентификатора, подключ -- с подписью привязки.
+//   Доверие к чужим ключам не строится (нет web of trust): "верна" означает "ключом из связки пользователя",
+//   а совпадение адреса с From проверяет pgp_mime.
+// - Отозванный ключ не шифрует, не подписывает и не подтверждает подписи, даже сделанные до отзыва:
+//   причина отзыва может быть компрометацией. Истекший ключ подтверждает подписи, сделанные до срока.
+// - Подпись с истекшим сроком и подпись с непонятным критичным подпакетом неверны.
+// - Не защищаемся от побочных каналов по времени: расшифровка идет на сервере, а не на стороне атакующего.
+// - Закрытые ключи хранятся без пароля и только зашифрованными ключом пользователя (db_pgp_save).
+
+const TAG_PKESK: u8 = 1;
+const TAG_SIGNATURE: u8 = 2;
+const TAG_ONE_PASS: u8 = 4;
+const TAG_SECRET_KEY: u8 = 5;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_SECRET_SUBKEY: u8 = 7;
+const TAG_COMPRESSED: u8 = 8;
+const TAG_SED: u8 = 9;
+const TAG_LITERAL: u8 = 11;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+const TAG_USER_ATTRIBUTE: u8 = 17;
+const TAG_SEIPD: u8 = 18;
+
+const ALGO_RSA: u8 = 1;
+const ALGO_RSA_ENCRYPT: u8 = 2;
+const ALGO_RSA_SIGN: u8 = 3;
+const ALGO_ECDH: u8 = 18;
+const ALGO_EDDSA: u8 = 22;
+
+const HASH_SHA1: u8 = 2;
+const HASH_SHA256: u8 = 8;
+const SYM_AES128: u8 = 7;
+const SYM_AES256: u8 = 9;
+
+const OID_ED25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+const OID_CV25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01];
+
+pub const SIG_BINARY: u8 = 0x00;
+pub const SIG_TEXT: u8 = 0x01;
+const SIG_CERT_GENERIC: u8 = 0x10;
+const SIG_CERT_POSITIVE: u8 = 0x13;
+const SIG_SUBKEY_BINDING: u8 = 0x18;
+const SIG_DIRECT_KEY: u8 = 0x1F;
+const SIG_KEY_REVOCATION: u8 = 0x20;
+const SIG_SUBKEY_REVOCATION: u8 = 0x28;
+
+const FLAG_CERTIFY: u8 = 0x01;
+const FLAG_SIGN: u8 = 0x02;
+const FLAG_ENCRYPT: u8 = 0x0C;
+
+// подпакеты подписи, которые разбираются или без вреда пропускаются; уведомления (20) сюда не входят
+const SUBPACKETS_KNOWN: &[u8] = &[2, 3, 4, 5, 7, 9, 11, 12, 16, 21, 22, 23, 25, 27, 29, 30, 31, 33, 34, 35];
+
+// сжатые данные внутри сообщения: не больше, чем письмо, которое мы готовы принять
+const UNCOMPRESSED_MAX: u64 = 64 * 1024 * 1024;
+const NESTING_MAX: usize = 8;
+
+type Result<T> = std::result::Result<T, String>;
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs() as u32).unwrap_or_default()
+}
+
+// === разбор
+
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len()).ok_or("unexpected end of data")?;
+        let result = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let data = self.take(2)?;
+        Ok(u16::from_be_bytes([data[0], data[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let data = self.take(4)?;
+        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    // число: длина в битах, затем байты старшими вперед
+    fn mpi(&mut self) -> Result<&'a [u8]> {
+        let bits = self.u16()? as usize;
+        self.take(bits.div_ceil(8))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let result = &self.data[self.pos..];
+        self.pos = self.data.len();
+        result
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+struct Packet {
+    tag: u8,
+    body: Vec<u8>,
+}
+
+// длина нового формата; частичная длина (224..254) -- кусок, за которым следует еще одна длина
+fn new_length(input: &mut Input) -> Result<(usize, bool)> {
+    let first = input.u8()? as usize;
+    Ok(match first {
+        0..=191 => (first, false),
+        192..=223 => (((first - 192) << 8) + input.u8()? as usize + 192, false),
+        255 => (input.u32()? as usize, false),
+        _ => (1 << (first & 0x1F), true)
+    })
+}
+
+fn packets(data: &[u8]) -> Result<Vec<Packet>> {
+    let mut input = Input::new(data);
+    let mut result = vec![];
+    while !input.is_empty() {
+        let header = input.u8()?;
+        if header & 0x80 == 0 {
+            return Err("invalid packet header".to_string());
+        }
+        if header & 0x40 != 0 {
+            let tag = header & 0x3F;
+            let mut body = vec![];
+            loop {
+                let (len, partial) = new_length(&mut input)?;
+                body.extend_from_slice(input.take(len)?);
+                if !partial {
+                    break;
+                }
+            }
+            result.push(Packet { tag, body });
+        } else {
+            let tag = (header >> 2) & 0x0F;
+            let body = match header & 0x03 {
+                0 => {
+                    let len = input.u8()? as usize;
+                    input.take(len)?
+                }
+                1 => {
+                    let len = input.u16()? as usize;
+                    input.take(len)?
+                }
+                2 => {
+                    let len = input.u32()? as usize;
+                    input.take(len)?
+                }
+                _ => input.rest()
+            };
+            result.push(Packet { tag, body: body.to_vec() });
+        }
+    }
+    Ok(result)
+}
+
+// === запись
+
+fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut result = vec![0xC0 | tag];
+    let len = body.len();
+    if len < 192 {
+        result.push(len as u8);
+    } else if len < 8384 {
+        let len = len - 192;
+        result.extend_from_slice(&[((len >> 8) + 192) as u8, (len & 0xFF) as u8]);
+    } else {
+        result.push(0xFF);
+        result.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    result.extend_from_slice(body);
+    result
+}
+
+fn mpi(value: &[u8]) -> Vec<u8> {
+    let start = value.iter().position(|byte| *byte != 0).unwrap_or(value.len());
+    let value = &value[start..];
+    let bits = match value.first() {
+        Some(first) => (value.len() - 1) * 8 + (8 - first.leading_zeros() as usize),
+        None => 0
+    };
+    [(bits as u16).to_be_bytes().as_slice(), value].concat()
+}
+
+// число фиксированной длины: ведущие нули в MPI не хранятся
+fn padded<const N: usize>(value: &[u8]) -> Result<[u8; N]> {
+    if value.len() > N {
+        return Err("invalid value length".to_string());
+    }
+    let mut result = [0u8; N];
+    result[N - value.len()..].copy_from_slice(value);
+    Ok(result)
+}
+
+// === ASCII armor
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xB704CE;
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864CFB;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+pub fn armor(kind: &str, data: &[u8]) -> String {
+    let encoded = base64::encode(data);
+    let mut result = format!("-----BEGIN PGP {kind}-----\r\n\r\n");
+    for line in encoded.as_bytes().chunks(64) {
+        result.push_str(&String::from_utf8_lossy(line));
+        result.push_str("\r\n");
+    }
+    result.push('=');
+    result.push_str(&base64::encode(&crc24(data).to_be_bytes()[1..]));
+    result.push_str(&format!("\r\n-----END PGP {kind}-----\r\n"));
+    result
+}
+
+// все блоки "-----BEGIN PGP ...-----" текста: вид блока и данные
+pub fn dearmor(text: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut result = vec![];
+    let mut lines = text.lines().map(str::trim_end);
+    while let Some(line) = lines.next() {
+        let kind = match line.trim_start().strip_prefix("-----BEGIN PGP ").and_then(|rest| rest.strip_suffix("-----")) {
+            Some(kind) => kind.to_string(),
+            None => continue
+        };
+        // заголовки блока до пустой строки
+        let mut body = String::new();
+        let mut in_headers = true;
+        let mut checksum = None;
+        let mut closed = false;
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line.starts_with("-----END PGP ") {
+                closed = true;
+                break;
+            }
+            if in_headers {
+                if line.is_empty() {
+                    in_headers = false;
+                    continue;
+                }
+                if line.contains(": ") {
+                    continue;
+                }
+                in_headers = false;
+            }
+            match line.strip_prefix('=') {
+                Some(crc) if line.len() == 5 => checksum = Some(crc.to_string()),
+                _ => body.push_str(line)
+            }
+        }
+        if !closed {
+            return Err(format!("unterminated {kind} block"));
+        }
+        let data = base64::decode(&body).map_err(|err| err.to_string())?;
+        if let Some(checksum) = checksum {
+            let expected = base64::decode(&checksum).map_err(|err| err.to_string())?;
+            if expected != crc24(&data).to_be_bytes()[1..] {
+                return Err("armor checksum mismatch".to_string());
+            }
+        }
+        result.push((kind, data));
+    }
+    Ok(result)
+}
+
+// ключи и подписи приходят и в armor, и в двоичном виде
+fn binary_or_armored(data: &[u8], kinds: &[&str]) -> Result<Vec<u8>> {
+    if data.first().map(|byte| byte & 0x80 != 0).unwrap_or_default() {
+        return Ok(data.to_vec());
+    }
+    let blocks = dearmor(&String::from_utf8_lossy(data))?;
+    let data = blocks.into_iter()
+        .filter(|(kind, _)| kinds.contains(&kind.as_str()))
+        .flat_map(|(_, data)| data)
+        .collect::<Vec<_>>();
+    if data.is_empty() {
+        return Err("no OpenPGP data".to_string());
+    }
+    Ok(data)
+}
+
+// === хеши и симметричное шифрование
+
+fn hasher(id: u8) -> Result<Box<dyn DynDigest>> {
+    Ok(match id {
+        HASH_SHA1 => Box::new(Sha1::new()),
+        HASH_SHA256 => Box::new(Sha256::new()),
+        9 => Box::new(Sha384::new()),
+        10 => Box::new(Sha512::new()),
+        11 => Box::new(Sha224::new()),
+        _ => return Err(format!("unsupported hash {id}"))
+    })
+}
+
+fn rsa_hash(id: u8) -> Result<Hash> {
+    Ok(match id {
+        HASH_SHA1 => Hash::SHA1,
+        HASH_SHA256 => Hash::SHA2_256,
+        9 => Hash::SHA2_384,
+        10 => Hash::SHA2_512,
+        11 => Hash::SHA2_224,
+        _ => return Err(format!("unsupported hash {id}"))
+    })
+}
+
+fn cipher(sym: u8) -> Result<(Cipher, usize)> {
+    Ok(match sym {
+        SYM_AES128 => (Cipher::aes_128_cfb128(), 16),
+        8 => (Cipher::aes_192_cfb128(), 24),
+        SYM_AES256 => (Cipher::aes_256_cfb128(), 32),
+        _ => return Err(format!("unsupported cipher {sym}"))
+    })
+}
+
+fn cfb_decrypt(sym: u8, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    decrypt(cipher(sym)?.0, key, Some(iv), data).map_err(|err| err.to_string())
+}
+
+// пароль закрытого ключа (RFC 4880, 3.7)
+fn s2k(input: &mut Input, passphrase: &str, key_len: usize) -> Result<Vec<u8>> {
+    let kind = input.u8()?;
+    let hash = input.u8()?;
+    let (salt, count) = match kind {
+        0 => (&[][..], 0),
+        1 => (input.take(8)?, 0),
+        3 => {
+            let salt = input.take(8)?;
+            let coded = input.u8()? as usize;
+            (salt, (16 + (coded & 15)) << ((coded >> 4) + 6))
+        }
+        _ => return Err(format!("unsupported s2k {kind}"))
+    };
+    let seed = [salt, passphrase.as_bytes()].concat();
+    let mut result = vec![];
+    let mut round = 0;
+    while result.len() < key_len {
+        let mut digest = hasher(hash)?;
+        digest.update(&vec![0u8; round]);
+        let mut left = count.max(seed.len());
+        while left > 0 {
+            let len = left.min(seed.len());
+            digest.update(&seed[..len]);
+            left -= len;
+        }
+        result.extend_from_slice(&digest.finalize());
+        round += 1;
+    }
+    result.truncate(key_len);
+    Ok(result)
+}
+
+// === ключи
+
+#[derive(Clone)]
+enum PublicParams {
+    Rsa { n: BigUint, e: BigUint },
+    EdDsa { point: [u8; 32] },
+    Ecdh { point: [u8; 32], hash: u8, sym: u8 },
+}
+
+#[derive(Clone)]
+enum SecretParams {
+    Rsa(Box<RsaPrivateKey>),
+    EdDsa([u8; 32]),
+    // скаляр Cv25519 в порядке байтов X25519 (в пакете он хранится перевернутым)
+    Ecdh([u8; 32]),
+}
+
+#[derive(Clone)]
+pub struct Key {
+    algo: u8,
+    created: u32,
+    params: PublicParams,
+    body: Vec<u8>,
+    secret: Option<SecretParams>,
+    secret_mpis: Vec<u8>,
+    pub fingerprint: [u8; 20],
+    flags: u8,
+    expires: Option<u32>,
+    revoked: bool,
+}
+
+impl Key {
+    pub fn key_id(&self) -> [u8; 8] {
+        self.fingerprint[12..].try_into().unwrap_or_default()
+    }
+
+    pub fn fingerprint_hex(&self) -> String {
+        hex(&self.fingerprint)
+    }
+
+    pub fn has_secret(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    fn usable(&self, at: u32) -> bool {
+        !self.revoked && self.expires.map(|expires| self.created.saturating_add(expires) > at).unwrap_or(true)
+    }
+
+    fn can_sign(&self) -> bool {
+        self.flags & FLAG_SIGN != 0 && matches!(self.algo, ALGO_RSA | ALGO_RSA_SIGN | ALGO_EDDSA)
+    }
+
+    fn can_encrypt(&self) -> bool {
+        self.flags & FLAG_ENCRYPT != 0 && matches!(self.algo, ALGO_RSA | ALGO_RSA_ENCRYPT | ALGO_ECDH)
+    }
+
+    // для подписи ключа: 0x99, длина, тело открытого ключа
+    fn hash_into(&self, digest: &mut dyn DynDigest) {
+        digest.update(&[0x99]);
+        digest.update(&(self.body.len() as u16).to_be_bytes());
+        digest.update(&self.body);
+    }
+
+    fn packet(&self, tag: u8, with_secret: bool) -> Vec<u8> {
+        if with_secret && self.secret.is_some() {
+            let checksum = self.secret_mpis.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+            packet(tag, &[self.body.as_slice(), &[0], &self.secret_mpis, &checksum.to_be_bytes()].concat())
+        } else {
+            packet(tag, &self.body)
+        }
+    }
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+fn default_flags(algo: u8) -> u8 {
+    match algo {
+        ALGO_RSA => FLAG_CERTIFY | FLAG_SIGN | FLAG_ENCRYPT,
+        ALGO_RSA_ENCRYPT | ALGO_ECDH => FLAG_ENCRYPT,
+        _ => FLAG_CERTIFY | FLAG_SIGN
+    }
+}
+
+fn curve_point(input: &mut Input, oid: &[u8]) -> Result<[u8; 32]> {
+    let len = input.u8()? as usize;
+    if input.take(len)? != oid {
+        return Err("unsupported curve".to_string());
+    }
+    let point = input.mpi()?;
+    if point.len() != 33 || point[0] != 0x40 {
+        return Err("invalid curve point".to_string());
+    }
+    padded::<32>(&point[1..])
+}
+
+// открытая часть ключа; возвращает ее длину в теле пакета
+fn key_public(body: &[u8]) -> Result<(Key, usize)> {
+    let mut input = Input::new(body);
+    let version = input.u8()?;
+    if version != 4 {
+        return Err(format!("unsupported key version {version}"));
+    }
+    let created = input.u32()?;
+    let algo = input.u8()?;
+    let params = match algo {
+        ALGO_RSA | ALGO_RSA_ENCRYPT | ALGO_RSA_SIGN => {
+            let n = BigUint::from_bytes_be(input.mpi()?);
+            let e = BigUint::from_bytes_be(input.mpi()?);
+            PublicParams::Rsa { n, e }
+        }
+        ALGO_EDDSA => PublicParams::EdDsa { point: curve_point(&mut input, OID_ED25519)? },
+        ALGO_ECDH => {
+            let point = curve_point(&mut input, OID_CV25519)?;
+            let kdf_len = input.u8()? as usize;
+            let kdf = input.take(kdf_len)?;
+            if kdf.len() != 3 || kdf[0] != 1 {
+                return Err("invalid kdf parameters".to_string());
+            }
+            PublicParams::Ecdh { point, hash: kdf[1], sym: kdf[2] }
+        }
+        _ => return Err(format!("unsupported key algorithm {algo}"))
+    };
+    let len = input.pos;
+    let public = &body[..len];
+    let mut digest = Sha1::new();
+    Digest::update(&mut digest, [0x99]);
+    Digest::update(&mut digest, (len as u16).to_be_bytes());
+    Digest::update(&mut digest, public);
+    let fingerprint = digest.finalize().into();
+    Ok((Key {
+        algo,
+        created,
+        params,
+        body: public.to_vec(),
+        secret: None,
+        secret_mpis: vec![],
+        fingerprint,
+        flags: default_flags(algo),
+        expires: None,
+        revoked: false,
+    }, len))
+}
+
+// закрытая часть: без защиты или под паролем (S2K usage 254/255); заглушки GnuPG -- ключ без закрытой части
+fn key_secret(key: &mut Key, data: &[u8], passphrase: Option<&str>) -> Result<()> {
+    let mut input = Input::new(data);
+    let usage = input.u8()?;
+    let mpis = match usage {
+        0 => {
+            let rest = input.rest();
+            if rest.len() < 2 {
+                return Err("invalid secret key".to_string());
+            }
+            let (mpis, checksum) = rest.split_at(rest.len() - 2);
+            let sum = mpis.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+            if sum.to_be_bytes() != checksum {
+                return Err("secret key checksum mismatch".to_string());
+            }
+            mpis.to_vec()
+        }
+        254 | 255 => {
+            let sym = input.u8()?;
+            // gnu-dummy: закрытой части нет (например, основной ключ хранится отдельно)
+            if data.get(2) == Some(&101) {
+                return Ok(());
+            }
+            let passphrase = passphrase.ok_or("passphrase required")?;
+            let (_, key_len) = cipher(sym)?;
+            let unlock = s2k(&mut input, passphrase, key_len)?;
+            let iv = input.take(16)?;
+            let plain = cfb_decrypt(sym, &unlock, iv, input.rest())?;
+            let tail = if usage == 254 { 20 } else { 2 };
+            if plain.len() < tail {
+                return Err("wrong passphrase".to_string());
+            }
+            let (mpis, check) = plain.split_at(plain.len() - tail);
+            let valid = if usage == 254 {
+                Sha1::digest(mpis).as_slice() == check
+            } else {
+                mpis.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16)).to_be_bytes() == check
+            };
+            if !valid {
+                return Err("wrong passphrase".to_string());
+            }
+            mpis.to_vec()
+        }
+        _ => return Err(format!("unsupported secret key protection {usage}"))
+    };
+    let mut secret = Input::new(&mpis);
+    let params = match &key.params {
+        PublicParams::Rsa { n, e } => {
+            let d = BigUint::from_bytes_be(secret.mpi()?);
+            let p = BigUint::from_bytes_be(secret.mpi()?);
+            let q = BigUint::from_bytes_be(secret.mpi()?);
+            if &(&p * &q) != n {
+                return Err("secret key does not match".to_string());
+            }
+            SecretParams::Rsa(Box::new(RsaPrivateKey::from_components(n.clone(), e.clone(), d, vec![p, q])))
+        }
+        PublicParams::EdDsa { point } => {
+            let seed = padded::<32>(secret.mpi()?)?;
+            let public = ed25519_dalek::SecretKey::from_bytes(&seed)
+                .map(|secret| ed25519_dalek::PublicKey::from(&secret).to_bytes())
+                .map_err(|err| err.to_string())?;
+            if &public != point {
+                return Err("secret key does not match".to_string());
+            }
+            SecretParams::EdDsa(seed)
+        }
+        PublicParams::Ecdh { point, .. } => {
+            let mut scalar = padded::<32>(secret.mpi()?)?;
+            scalar.reverse();
+            if (X25519_BASEPOINT * x25519_scalar(&scalar)).to_bytes() != *point {
+                return Err("secret key does not match".to_string());
+            }
+            SecretParams::Ecdh(scalar)
+        }
+    };
+    key.secret = Some(params);
+    key.secret_mpis = mpis;
+    Ok(())
+}
+
+fn x25519_scalar(bytes: &[u8; 32]) -> Scalar {
+    let mut bytes = *bytes;
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Scalar::from_bits(bytes)
+}
+
+pub struct UserId {
+    pub value: String,
+    body: Vec<u8>,
+    sigs: Vec<Vec<u8>>,
+}
+
+struct Subkey {
+    key: Key,
+    sigs: Vec<Vec<u8>>,
+}
+
+// ключ с идентификаторами и подключами; остаются только проверенные самоподписи
+pub struct Cert {
+    pub primary: Key,
+    pub user_ids: Vec<UserId>,
+    subkeys: Vec<Subkey>,
+    sigs: Vec<Vec<u8>>,
+}
+
+impl Cert {
+    // адреса из идентификаторов "Имя <адрес>", в нижнем регистре
+    pub fn emails(&self) -> Vec<String> {
+        let mut result = vec![];
+        for user_id in self.user_ids.iter() {
+            let value = user_id.value.trim();
+            let email = match (value.rfind('<'), value.rfind('>')) {
+                (Some(start), Some(end)) if start < end => &value[start + 1..end],
+                _ => value
+            };
+            if email.contains('@') && !result.contains(&email.to_lowercase()) {
+                result.push(email.to_lowercase());
+            }
+        }
+        result
+    }
+
+    pub fn has_secret(&self) -> bool {
+        self.keys().any(Key::has_secret)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.primary).chain(self.subkeys.iter().map(|subkey| &subkey.key))
+    }
+
+    fn find(&self, key_id: &[u8]) -> Option<&Key> {
+        self.keys().find(|key| key.key_id() == key_id || key.fingerprint == key_id)
+    }
+
+    pub fn usable(&self) -> bool {
+        self.primary.usable(now())
+    }
+
+    // самый новый действующий ключ для шифрования
+    pub fn encryption_key(&self) -> Option<&Key> {
+        let at = now();
+        if !self.primary.usable(at) {
+            return None;
+        }
+        self.keys().filter(|key| key.can_encrypt() && key.usable(at)).max_by_key(|key| key.created)
+    }
+
+    pub fn signing_key(&self) -> Option<&Key> {
+        let at = now();
+        if !self.primary.usable(at) {
+            return None;
+        }
+        self.keys().filter(|key| key.can_sign() && key.has_secret() && key.usable(at)).max_by_key(|key| key.created)
+    }
+
+    // для хранения: ключ в двоичном виде, с закрытой частью без пароля или только открытый
+    pub fn to_bytes(&self, with_secret: bool) -> Vec<u8> {
+        let mut result = self.primary.packet(if with_secret && self.primary.has_secret() { TAG_SECRET_KEY } else { TAG_PUBLIC_KEY }, with_secret);
+        for sig in self.sigs.iter() {
+            result.extend(packet(TAG_SIGNATURE, sig));
+        }
+        for user_id in self.user_ids.iter() {
+            result.extend(packet(TAG_USER_ID, &user_id.body));
+            for sig in user_id.sigs.iter() {
+                result.extend(packet(TAG_SIGNATURE, sig));
+            }
+        }
+        for subkey in self.subkeys.iter() {
+            let tag = if with_secret && subkey.key.has_secret() { TAG_SECRET_SUBKEY } else { TAG_PUBLIC_SUBKEY };
+            result.extend(subkey.key.packet(tag, with_secret));
+            for sig in subkey.sigs.iter() {
+                result.extend(packet(TAG_SIGNATURE, sig));
+            }
+        }
+        result
+    }
+}
+
+// ключи из файла (armor или двоичный), закрытые -- с паролем; ключи без проверенного идентификатора пропускаются
+pub fn certs_read(data: &[u8], passphrase: Option<&str>) -> Result<Vec<Cert>> {
+    certs_parse(data, Some(passphrase))
+}
+
+// только открытая часть, даже если в файле есть закрытые ключи
+pub fn certs_read_public(data: &[u8]) -> Result<Vec<Cert>> {
+    certs_parse(data, None)
+}
+
+// unlock: None -- закрытую часть не читаем, Some(пароль) -- расшифровываем
+fn certs_parse(data: &[u8], unlock: Option<Option<&str>>) -> Result<Vec<Cert>> {
+    let data = binary_or_armored(data, &["PUBLIC KEY BLOCK", "PRIVATE KEY BLOCK"])?;
+    let mut result = vec![];
+    let mut current: Option<RawCert> = None;
+    for packet in packets(&data)? {
+        match packet.tag {
+            TAG_PUBLIC_KEY | TAG_SECRET_KEY => {
+                if let Some(raw) = current.take() {
+                    result.push(raw);
+                }
+                let (mut key, len) = key_public(&packet.body)?;
+                if let (TAG_SECRET_KEY, Some(passphrase)) = (packet.tag, unlock) {
+                    key_secret(&mut key, &packet.body[len..], passphrase)?;
+                }
+                current = Some(RawCert { primary: key, sigs: vec![], user_ids: vec![], subkeys: vec![], target: Target::Primary });
+            }
+            _ => {
+                if let Some(raw) = current.as_mut() {
+                    raw.push(packet, unlock)?;
+                }
+            }
+        }
+    }
+    if let Some(raw) = current.take() {
+        result.push(raw);
+    }
+    Ok(result.into_iter().filter_map(RawCert::verify).collect())
+}
+
+enum Target {
+    Primary,
+    UserId,
+    Skip,
+    Subkey,
+}
+
+// ключ как он пришел, до проверки подписей
+struct RawCert {
+    primary: Key,
+    sigs: Vec<Vec<u8>>,
+    user_ids: Vec<UserId>,
+    subkeys: Vec<Subkey>,
+    target: Target,
+}
+
+impl RawCert {
+    fn push(&mut self, packet: Packet, unlock: Option<Option<&str>>) -> Result<()> {
+        match packet.tag {
+            TAG_USER_ID => {
+                self.user_ids.push(UserId { value: String::from_utf8_lossy(&packet.body).to_string(), body: packet.body, sigs: vec![] });
+                self.target = Target::UserId;
+            }
+            TAG_USER_ATTRIBUTE => self.target = Target::Skip,
+            TAG_PUBLIC_SUBKEY | TAG_SECRET_SUBKEY => {
+                // подключ неизвестного алгоритма не мешает остальным
+                match key_public(&packet.body) {
+                    Ok((mut key, len)) => {
+                        if let (TAG_SECRET_SUBKEY, Some(passphrase)) = (packet.tag, unlock) {
+                            key_secret(&mut key, &packet.body[len..], passphrase)?;
+                        }
+                        self.subkeys.push(Subkey { key, sigs: vec![] });
+                        self.target = Target::Subkey;
+                    }
+                    Err(err) => {
+                        tracing::warn!("certs_read: subkey {err}");
+                        self.target = Target::Skip;
+                    }
+                }
+            }
+            TAG_SIGNATURE => match self.target {
+                Target::Primary => self.sigs.push(packet.body),
+                Target::UserId => {
+                    if let Some(user_id) = self.user_ids.last_mut() {
+                        user_id.sigs.push(packet.body);
+                    }
+                }
+                Target::Subkey => {
+                    if let Some(subkey) = self.subkeys.last_mut() {
+                        subkey.sigs.push(packet.body);
+                    }
+                }
+                Target::Skip => {}
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // подпись основного ключа над данными
+    fn self_signed(&self, body: &[u8], types: &[u8], data: &dyn Fn(&mut dyn DynDigest)) -> Option<Signature> {
+        let sig = Signature::parse(body).ok()?;
+        if !types.contains(&sig.sig_type) || !sig.issued_by(&self.primary) || sig.expired(now()) {
+            return None;
+        }
+        let mut digest = hasher(sig.hash).ok()?;
+        data(digest.as_mut());
+        sig.verify_digest(&self.primary, digest).ok()?;
+        Some(sig)
+    }
+
+    fn verify(self) -> Option<Cert> {
+        let primary_data = |digest: &mut dyn DynDigest| self.primary.hash_into(digest);
+        let mut primary = self.primary.clone();
+        let mut sigs = vec![];
+        let mut newest = 0;
+        for body in self.sigs.iter() {
+            if let Some(sig) = self.self_signed(body, &[SIG_KEY_REVOCATION, SIG_DIRECT_KEY], &primary_data) {
+                if sig.sig_type == SIG_KEY_REVOCATION {
+                    primary.revoked = true;
+                } else if sig.created >= newest {
+                    newest = sig.created;
+                    sig.apply(&mut primary);
+                }
+                sigs.push(body.clone());
+            }
+        }
+        let mut user_ids = vec![];
+        let mut newest = 0;
+        for user_id in self.user_ids.iter() {
+            let data = |digest: &mut dyn DynDigest| {
+                self.primary.hash_into(digest);
+                digest.update(&[0xB4]);
+                digest.update(&(user_id.body.len() as u32).to_be_bytes());
+                digest.update(&user_id.body);
+            };
+            let valid = user_id.sigs.iter()
+                .filter_map(|body| self.self_signed(body, &[SIG_CERT_GENERIC, 0x11, 0x12, SIG_CERT_POSITIVE, 0x30], &data).map(|sig| (body, sig)))
+                .collect::<Vec<_>>();
+            // отозванный идентификатор: новее его последней подписи отзыв
+            let certified = valid.iter().filter(|(_, sig)| sig.sig_type != 0x30).map(|(_, sig)| sig.created).max();
+            let revoked = valid.iter().filter(|(_, sig)| sig.sig_type == 0x30).map(|(_, sig)| sig.created).max();
+            let certified = match (certified, revoked) {
+                (Some(certified), revoked) if revoked.map(|revoked| revoked < certified).unwrap_or(true) => certified,
+                _ => continue
+            };
+            for (_, sig) in valid.iter() {
+                if sig.sig_type != 0x30 && sig.created == certified && sig.created >= newest {
+                    newest = sig.created;
+                    sig.apply(&mut primary);
+                }
+            }
+            user_ids.push(UserId {
+                value: user_id.value.clone(),
+                body: user_id.body.clone(),
+                sigs: valid.into_iter().map(|(body, _)| body.clone()).collect(),
+            });
+        }
+        if user_ids.is_empty() {
+            tracing::warn!("certs_read: {} has no valid user id", primary.fingerprint_hex());
+            return None;
+        }
+        let mut subkeys = vec![];
+        for subkey in self.subkeys.iter() {
+            let data = |digest: &mut dyn DynDigest| {
+                self.primary.hash_into(digest);
+                subkey.key.hash_into(digest);
+            };
+            let mut key = subkey.key.clone();
+            key.flags = default_flags(key.algo) & !FLAG_CERTIFY;
+            let mut bound = false;
+            let mut newest = 0;
+            let mut sigs = vec![];
+            for body in subkey.sigs.iter() {
+                if let Some(sig) = self.self_signed(body, &[SIG_SUBKEY_BINDING, SIG_SUBKEY_REVOCATION], &data) {
+                    if sig.sig_type == SIG_SUBKEY_REVOCATION {
+                        key.revoked = true;
+                    } else if sig.created >= newest {
+                        newest = sig.created;
+                        bound = true;
+                        sig.apply(&mut key);
+                    }
+                    sigs.push(body.clone());
+                }
+            }
+            if bound {
+                subkeys.push(Subkey { key, sigs });
+            }
+        }
+        Some(Cert { primary, user_ids, subkeys, sigs })
+    }
+}
+
+// === подписи
+
+pub struct Signature {
+    sig_type: u8,
+    algo: u8,
+    hash: u8,
+    hashed: Vec<u8>,
+    created: u32,
+    issuer: Option<[u8; 8]>,
+    issuer_fingerprint: Option<[u8; 20]>,
+    flags: Option<u8>,
+    key_expires: Option<u32>,
+    expires: Option<u32>,
+    prefix: [u8; 2],
+    values: Vec<Vec<u8>>,
+}
+
+impl Signature {
+    fn parse(body: &[u8]) -> Result<Self> {
+        let mut input = Input::new(body);
+        let version = input.u8()?;
+        if version != 4 {
+            return Err(format!("unsupported signature version {version}"));
+        }
+        let sig_type = input.u8()?;
+        let algo = input.u8()?;
+        let hash = input.u8()?;
+        let hashed_len = input.u16()? as usize;
+        let hashed = input.take(hashed_len)?.to_vec();
+        let unhashed_len = input.u16()? as usize;
+        let unhashed = input.take(unhashed_len)?;
+        let prefix = [input.u8()?, input.u8()?];
+        let mut values = vec![];
+        while !input.is_empty() {
+            values.push(input.mpi()?.to_vec());
+        }
+        let mut sig = Signature {
+            sig_type,
+            algo,
+            hash,
+            hashed: hashed.clone(),
+            created: 0,
+            issuer: None,
+            issuer_fingerprint: None,
+            flags: None,
+            key_expires: None,
+            expires: None,
+            prefix,
+            values,
+        };
+        sig.subpackets(&hashed, true)?;
+        sig.subpackets(unhashed, false)?;
+        Ok(sig)
+    }
+
+    // в нехешированной части доверяем только издателю: по нему лишь ищется ключ
+    fn subpackets(&mut self, data: &[u8], hashed: bool) -> Result<()> {
+        let mut input = Input::new(data);
+        while !input.is_empty() {
+            let (len, _) = new_length(&mut input)?;
+            let body = input.take(len)?;
+            let (kind, value) = body.split_first().ok_or("invalid subpacket")?;
+            // непонятый критичный подпакет делает подпись неверной (RFC 4880, 5.2.3.1)
+            if kind & 0x80 != 0 && hashed && !SUBPACKETS_KNOWN.contains(&(kind & 0x7F)) {
+                return Err(format!("unknown critical subpacket {}", kind & 0x7F));
+            }
+            match (kind & 0x7F, hashed) {
+                (2, true) if value.len() == 4 => self.created = u32::from_be_bytes(value.try_into().unwrap_or_default()),
+                (3, true) if value.len() == 4 => self.expires = Some(u32::from_be_bytes(value.try_into().unwrap_or_default())).filter(|v| *v > 0),
+                (9, true) if value.len() == 4 => self.key_expires = Some(u32::from_be_bytes(value.try_into().unwrap_or_default())).filter(|v| *v > 0),
+                (27, true) => self.flags = value.first().copied(),
+                (16, _) if value.len() == 8 => self.issuer = value.try_into().ok(),
+                (33, _) if value.len() == 21 && value[0] == 4 => self.issuer_fingerprint = value[1..].try_into().ok(),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn issuer_ids(&self) -> Vec<Vec<u8>> {
+        let mut result = vec![];
+        if let Some(fingerprint) = self.issuer_fingerprint {
+            result.push(fingerprint.to_vec());
+        }
+        if let Some(issuer) = self.issuer {
+            result.push(issuer.to_vec());
+        }
+        result
+    }
+
+    fn expired(&self, at: u32) -> bool {
+        self.expires.map(|expires| self.created.saturating_add(expires) <= at).unwrap_or_default()
+    }
+
+    fn issued_by(&self, key: &Key) -> bool {
+        let ids = self.issuer_ids();
+        ids.is_empty() || ids.iter().any(|id| id.as_slice() == key.fingerprint || id.as_slice() == key.key_id())
+    }
+
+    // флаги и срок действия из самоподписи
+    fn apply(&self, key: &mut Key) {
+        if let Some(flags) = self.flags {
+            key.flags = flags;
+        }
+        key.expires = self.key_expires;
+    }
+
+    fn trailer(&self) -> Vec<u8> {
+        let mut result = vec![4, self.sig_type, self.algo, self.hash];
+        result.extend_from_slice(&(self.hashed.len() as u16).to_be_bytes());
+        result.extend_from_slice(&self.hashed);
+        result.extend_from_slice(&[4, 0xFF]);
+        result.extend_from_slice(&(self.hashed.len() as u32 + 6).to_be_bytes());
+        result
+    }
+
+    fn verify_digest(&self, key: &Key, mut digest: Box<dyn DynDigest>) -> Result<()> {
+        if self.algo != key.algo && !(key.algo == ALGO_RSA && self.algo == ALGO_RSA_SIGN) {
+            return Err("signature algorithm mismatch".to_string());
+        }
+        digest.update(&self.trailer());
+        let digest = digest.finalize();
+        if digest[..2] != self.prefix {
+            return Err("signature digest mismatch".to_string());
+        }
+        match &key.params {
+            PublicParams::Rsa { n, e } => {
+                let public = RsaPublicKey::new(n.clone(), e.clone()).map_err(|err| err.to_string())?;
+                let value = self.values.first().ok_or("invalid signature")?;
+                let mut sig = vec![0u8; public.size().saturating_sub(value.len())];
+                sig.extend_from_slice(value);
+                public.verify(PaddingScheme::new_pkcs1v15_sign(Some(rsa_hash(self.hash)?)), &digest, &sig).map_err(|err| err.to_string())
+            }
+            PublicParams::EdDsa { point } => {
+                let (r, s) = match self.values.as_slice() {
+                    [r, s] => (padded::<32>(r)?, padded::<32>(s)?),
+                    _ => return Err("invalid signature".to_string())
+                };
+                let public = ed25519_dalek::PublicKey::from_bytes(point).map_err(|err| err.to_string())?;
+                let sig = ed25519_dalek::Signature::try_from(&[r, s].concat()[..]).map_err(|err| err.to_string())?;
+                public.verify(&digest, &sig).map_err(|err| err.to_string())
+            }
+            PublicParams::Ecdh { .. } => Err("encryption key cannot sign".to_string())
+        }
+    }
+}
+
+// подписанный текст: концы строк -- CRLF
+pub fn canonical_text(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + data.len() / 32);
+    let mut prev = 0u8;
+    for byte in data {
+        if *byte == b'\n' && prev != b'\r' {
+            result.push(b'\r');
+        }
+        result.push(*byte);
+        prev = *byte;
+    }
+    result
+}
+
+pub enum Verification {
+    // адреса из идентификаторов ключа подписи
+    Valid(Vec<String>),
+    // ключа подписи нет в связке: его идентификатор
+    Unknown(String),
+    Invalid,
+}
+
+fn signature_verify(sig: &Signature, data: &[u8], certs: &[Cert]) -> Verification {
+    if sig.sig_type != SIG_BINARY && sig.sig_type != SIG_TEXT {
+        return Verification::Invalid;
+    }
+    let ids = sig.issuer_ids();
+    let found = certs.iter().find_map(|cert| ids.iter().find_map(|id| cert.find(id)).map(|key| (cert, key)));
+    let (cert, key) = match found {
+        Some(found) => found,
+        None => return Verification::Unknown(sig.issuer.map(|id| hex(&id)).or(sig.issuer_fingerprint.map(|id| hex(&id[12..]))).unwrap_or_default())
+    };
+    // ключ действовал, когда подпись была сделана
+    let expired = [key, &cert.primary].iter()
+        .any(|key| key.expires.map(|expires| key.created.saturating_add(expires) < sig.created).unwrap_or_default());
+    if key.revoked || cert.primary.revoked || expired || sig.expired(now()) || !key.can_sign() {
+        return Verification::Invalid;
+    }
+    let mut digest = match hasher(sig.hash) {
+        Ok(digest) => digest,
+        Err(_) => return Verification::Invalid
+    };
+    if sig.sig_type == SIG_TEXT {
+        digest.update(&canonical_text(data));
+    } else {
+        digest.update(data);
+    }
+    match sig.verify_digest(key, digest) {
+        Ok(_) => Verification::Valid(cert.emails()),
+        Err(err) => {
+            tracing::warn!("signature_verify: {err}");
+            Verification::Invalid
+        }
+    }
+}
+
+// из нескольких подписей достаточно одной проверенной
+pub fn signatures_verify(sigs: &[Signature], data: &[u8], certs: &[Cert]) -> Verification {
+    let mut result = Verification::Invalid;
+    for sig in sigs {
+        match signature_verify(sig, data, certs) {
+            Verification::Valid(emails) => return Verification::Valid(emails),
+            Verification::Unknown(id) => result = Verification::Unknown(id),
+            Verification::Invalid => {}
+        }
+    }
+    result
+}
+
+// отдельная подпись (application/pgp-signature)
+pub fn detached_verify(data: &[u8], signature: &[u8], certs: &[Cert]) -> Verification {
+    let sigs = binary_or_armored(signature, &["SIGNATURE"])
+        .and_then(|signature| packets(&signature))
+        .map(|packets| packets.into_iter()
+            .filter(|packet| packet.tag == TAG_SIGNATURE)
+            .filter_map(|packet| Signature::parse(&packet.body).ok())
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+    signatures_verify(&sigs, data, certs)
+}
+
+fn signature_create(data: &[u8], key: &Key, sig_type: u8) -> Result<Vec<u8>> {
+    let mut hashed = vec![5, 2];
+    hashed.extend_from_slice(&now().to_be_bytes());
+    hashed.extend_from_slice(&[22, 33, 4]);
+    hashed.extend_from_slice(&key.fingerprint);
+    let sig = Signature {
+        sig_type,
+        algo: key.algo,
+        hash: HASH_SHA256,
+        hashed,
+        created: 0,
+        issuer: None,
+        issuer_fingerprint: None,
+        flags: None,
+        key_expires: None,
+        expires: None,
+        prefix: [0, 0],
+        values: vec![],
+    };
+    let mut digest = Sha256::new();
+    if sig_type == SIG_TEXT {
+        Digest::update(&mut digest, canonical_text(data));
+    } else {
+        Digest::update(&mut digest, data);
+    }
+    Digest::update(&mut digest, sig.trailer());
+    let digest = digest.finalize();
+    let values = match &key.secret {
+        Some(SecretParams::Rsa(secret)) => {
+            mpi(&secret.sign(PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)), &digest).map_err(|err| err.to_string())?)
+        }
+        Some(SecretParams::EdDsa(seed)) => {
+            let secret = ed25519_dalek::SecretKey::from_bytes(seed).map_err(|err| err.to_string())?;
+            let public = ed25519_dalek::PublicKey::from(&secret);
+            let signature = ExpandedSecretKey::from(&secret).sign(&digest, &public).to_bytes();
+            [mpi(&signature[..32]), mpi(&signature[32..])].concat()
+        }
+        _ => return Err("no signing key".to_string())
+    };
+    let mut body = sig.trailer();
+    body.truncate(6 + sig.hashed.len());
+    body.extend_from_slice(&[0, 10, 9, 16]);
+    body.extend_from_slice(&key.key_id());
+    body.extend_from_slice(&digest[..2]);
+    body.extend_from_slice(&values);
+    Ok(packet(TAG_SIGNATURE, &body))
+}
+
+// отдельная подпись в armor, для multipart/signed
+pub fn detached_sign(data: &[u8], cert: &Cert) -> Result<String> {
+    let key = cert.signing_key().ok_or("no signing key")?;
+    Ok(armor("SIGNATURE", &signature_create(data, key, SIG_BINARY)?))
+}
+
+// === сообщения
+
+// открытое сообщение: содержимое и подписи внутри него
+pub struct Opened {
+    pub data: Vec<u8>,
+    pub signatures: Vec<Signature>,
+}
+
+fn literal(body: &[u8]) -> Result<Vec<u8>> {
+    let mut input = Input::new(body);
+    input.u8()?;
+    let name_len = input.u8()? as usize;
+    input.take(name_len)?;
+    input.u32()?;
+    Ok(input.rest().to_vec())
+}
+
+fn decompress(body: &[u8]) -> Result<Vec<u8>> {
+    let (algo, data) = body.split_first().ok_or("invalid compressed packet")?;
+    let mut result = vec![];
+    let read = match algo {
+        0 => {
+            result.extend_from_slice(data);
+            Ok(data.len())
+        }
+        1 => DeflateDecoder::new(data).take(UNCOMPRESSED_MAX + 1).read_to_end(&mut result),
+        2 => ZlibDecoder::new(data).take(UNCOMPRESSED_MAX + 1).read_to_end(&mut result),
+        _ => return Err(format!("unsupported compression {algo}"))
+    };
+    read.map_err(|err| err.to_string())?;
+    if result.len() as u64 > UNCOMPRESSED_MAX {
+        return Err("message too large".to_string());
+    }
+    Ok(result)
+}
+
+// содержимое сообщения: сжатое, с подписями (в том числе one-pass), литерал
+fn message_open(data: &[u8], depth: usize) -> Result<Opened> {
+    if depth > NESTING_MAX {
+        return Err("message nesting too deep".to_string());
+    }
+    let mut content = None;
+    let mut signatures = vec![];
+    for packet in packets(data)? {
+        match packet.tag {
+            TAG_COMPRESSED => return message_open(&decompress(&packet.body)?, depth + 1),
+            TAG_LITERAL => content = Some(literal(&packet.body)?),
+            TAG_SIGNATURE => {
+                if let Ok(sig) = Signature::parse(&packet.body) {
+                    signatures.push(sig);
+                }
+            }
+            TAG_ONE_PASS => {}
+            tag => return Err(format!("unexpected packet {tag}"))
+        }
+    }
+    Ok(Opened { data: content.ok_or("no literal data")?, signatures })
+}
+
+// сеансовый ключ: алгоритм, ключ, контрольная сумма
+fn session_unpack(data: &[u8]) -> Result<(u8, Vec<u8>)> {
+    let (sym, rest) = data.split_first().ok_or("invalid session key")?;
+    let (_, key_len) = cipher(*sym)?;
+    if rest.len() != key_len + 2 {
+        return Err("invalid session key".to_string());
+    }
+    let (key, checksum) = rest.split_at(key_len);
+    let sum = key.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+    if sum.to_be_bytes() != checksum {
+        return Err("session key checksum mismatch".to_string());
+    }
+    Ok((*sym, key.to_vec()))
+}
+
+fn session_pack(sym: u8, key: &[u8]) -> Vec<u8> {
+    let sum = key.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+    [&[sym], key, &sum.to_be_bytes()].concat()
+}
+
+// параметры KDF для ECDH (RFC 6637, 8)
+fn ecdh_kek(key: &Key, shared: &[u8], hash: u8, sym: u8) -> Result<Vec<u8>> {
+    let mut param = vec![OID_CV25519.len() as u8];
+    param.extend_from_slice(OID_CV25519);
+    param.extend_from_slice(&[ALGO_ECDH, 3, 1, hash, sym]);
+    param.extend_from_slice(b"Anonymous Sender    ");
+    param.extend_from_slice(&key.fingerprint);
+    let mut digest = hasher(hash)?;
+    digest.update(&[0, 0, 0, 1]);
+    digest.update(shared);
+    digest.update(&param);
+    let (_, kek_len) = cipher(sym)?;
+    let kek = digest.finalize();
+    if kek.len() < kek_len {
+        return Err("kdf hash too short".to_string());
+    }
+    Ok(kek[..kek_len].to_vec())
+}
+
+fn pkesk_decrypt(body: &[u8], key: &Key) -> Result<(u8, Vec<u8>)> {
+    let mut input = Input::new(body);
+    input.take(10)?;
+    match (&key.secret, &key.params) {
+        (Some(SecretParams::Rsa(secret)), _) => {
+            let value = input.mpi()?;
+            let plain = secret.decrypt_blinded(&mut OsRng, PaddingScheme::new_pkcs1v15_encrypt(), value).map_err(|err| err.to_string())?;
+            session_unpack(&plain)
+        }
+        (Some(SecretParams::Ecdh(scalar)), PublicParams::Ecdh { hash, sym, .. }) => {
+            let ephemeral = input.mpi()?;
+            if ephemeral.len() != 33 || ephemeral[0] != 0x40 {
+                return Err("invalid ephemeral key".to_string());
+            }
+            let ephemeral = MontgomeryPoint(padded::<32>(&ephemeral[1..])?);
+            let shared = (ephemeral * x25519_scalar(scalar)).to_bytes();
+            let kek = ecdh_kek(key, &shared, *hash, *sym)?;
+            let wrapped_len = input.u8()? as usize;
+            let wrapped = input.take(wrapped_len)?;
+            let mut plain = vec![0u8; wrapped.len().saturating_sub(8)];
+            let kek = AesKey::new_decrypt(&kek).map_err(|_| "invalid kek")?;
+            unwrap_key(&kek, None, &mut plain, wrapped).map_err(|_| "session key unwrap failed")?;
+            // дополнение PKCS#5 до кратного 8
+            let pad = *plain.last().ok_or("invalid session key")? as usize;
+            if pad == 0 || pad > 8 || pad > plain.len() || plain[plain.len() - pad..].iter().any(|byte| *byte as usize != pad) {
+                return Err("invalid session key padding".to_string());
+            }
+            plain.truncate(plain.len() - pad);
+            session_unpack(&plain)
+        }
+        _ => Err("key cannot decrypt".to_string())
+    }
+}
+
+fn pkesk_encrypt(key: &Key, session: &[u8]) -> Result<Vec<u8>> {
+    let mut body = vec![3];
+    body.extend_from_slice(&key.key_id());
+    body.push(key.algo);
+    match &key.params {
+        PublicParams::Rsa { n, e } => {
+            let public = RsaPublicKey::new(n.clone(), e.clone()).map_err(|err| err.to_string())?;
+            let value = public.encrypt(&mut OsRng, PaddingScheme::new_pkcs1v15_encrypt(), session).map_err(|err| err.to_string())?;
+            body.extend(mpi(&value));
+        }
+        PublicParams::Ecdh { point, hash, sym } => {
+            let mut secret = [0u8; 32];
+            OsRng.fill_bytes(&mut secret);
+            let secret = x25519_scalar(&secret);
+            let ephemeral = (X25519_BASEPOINT * secret).to_bytes();
+            let shared = (MontgomeryPoint(*point) * secret).to_bytes();
+            let kek = ecdh_kek(key, &shared, *hash, *sym)?;
+            let pad = 8 - session.len() % 8;
+            let mut plain = session.to_vec();
+            plain.extend(std::iter::repeat_n(pad as u8, pad));
+            let mut wrapped = vec![0u8; plain.len() + 8];
+            let kek = AesKey::new_encrypt(&kek).map_err(|_| "invalid kek")?;
+            wrap_key(&kek, None, &mut wrapped, &plain).map_err(|_| "session key wrap failed")?;
+            body.extend(mpi(&[&[0x40], ephemeral.as_slice()].concat()));
+            body.push(wrapped.len() as u8);
+            body.extend(wrapped);
+        }
+        PublicParams::EdDsa { .. } => return Err("signing key cannot encrypt".to_string())
+    }
+    Ok(packet(TAG_PKESK, &body))
+}
+
+// зашифрованное сообщение (armor или двоичное) ключами из связки
+pub fn message_decrypt(data: &[u8], certs: &[Cert]) -> Result<Opened> {
+    let data = binary_or_armored(data, &["MESSAGE"])?;
+    let packets = packets(&data)?;
+    let encrypted = match packets.iter().find(|packet| matches!(packet.tag, TAG_SEIPD | TAG_SED | 20)) {
+        Some(packet) if packet.tag == TAG_SEIPD => &packet.body,
+        Some(packet) if packet.tag == TAG_SED => return Err("encryption without integrity protection".to_string()),
+        Some(_) => return Err("unsupported encrypted data".to_string()),
+        None => return Err("message is not encrypted".to_string())
+    };
+    let keys = certs.iter().flat_map(|cert| cert.keys()).filter(|key| key.has_secret()).collect::<Vec<_>>();
+    let mut session = None;
+    for packet in packets.iter().filter(|packet| packet.tag == TAG_PKESK) {
+        let key_id = match packet.body.get(1..9) {
+            Some(key_id) if packet.body[0] == 3 => key_id,
+            _ => continue
+        };
+        // нулевой идентификатор: получатель скрыт, пробуем все ключи
+        let wildcard = key_id.iter().all(|byte| *byte == 0);
+        for key in keys.iter().filter(|key| wildcard || key.key_id() == key_id) {
+            match pkesk_decrypt(&packet.body, key) {
+                Ok(found) => {
+                    session = Some(found);
+                    break;
+                }
+                Err(err) => tracing::warn!("message_decrypt: {} {err}", key.fingerprint_hex())
+            }
+        }
+        if session.is_some() {
+            break;
+        }
+    }
+    let (sym, key) = session.ok_or("no key to decrypt")?;
+    let (version, encrypted) = encrypted.split_first().ok_or("invalid encrypted data")?;
+    if *version != 1 {
+        return Err(format!("unsupported encrypted data version {version}"));
+    }
+    let plain = cfb_decrypt(sym, &key, &[0u8; 16], encrypted)?;
+    // 16 случайных байт, повтор двух последних, пакеты, MDC (0xD3 0x14 и SHA-1 всего предыдущего)
+    if plain.len() < 18 + 22 {
+        return Err("invalid encrypted data".to_string());
+    }
+    let (body, mdc) = plain.split_at(plain.len() - 20);
+    if !body.ends_with(&[0xD3, 0x14]) || Sha1::digest(body).as_slice() != mdc {
+        return Err("integrity check failed".to_string());
+    }
+    message_open(&body[18..body.len() - 2], 0)
+}
+
+// шифрование ключами получателей; письмо целиком, поэтому без сжатия и имени файла
+pub fn message_encrypt(data: &[u8], recipients: &[&Key]) -> Result<String> {
+    if recipients.is_empty() {
+        return Err("no recipients".to_string());
+    }
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    let session = session_pack(SYM_AES256, &key);
+    let mut result = vec![];
+    for recipient in recipients {
+        result.extend(pkesk_encrypt(recipient, &session)?);
+    }
+    let mut literal = vec![b'b', 0];
+    literal.extend_from_slice(&now().to_be_bytes());
+    literal.extend_from_slice(data);
+
+    let mut prefix = [0u8; 18];
+    OsRng.fill_bytes(&mut prefix[..16]);
+    prefix[16] = prefix[14];
+    prefix[17] = prefix[15];
+    let mut plain = prefix.to_vec();
+    plain.extend(packet(TAG_LITERAL, &literal));
+    plain.extend_from_slice(&[0xD3, 0x14]);
+    let mdc = Sha1::digest(&plain);
+    plain.extend_from_slice(&mdc);
+    let encrypted = encrypt(cipher(SYM_AES256)?.0, &key, Some(&[0u8; 16]), &plain).map_err(|err| err.to_string())?;
+    result.extend(packet(TAG_SEIPD, &[&[1], encrypted.as_slice()].concat()));
+    Ok(armor("MESSAGE", &result))
+}
+
+// === подпись открытым текстом (RFC 4880, 7)
+
+// подписанный текст без экранирования и подписи из блока "BEGIN PGP SIGNED MESSAGE"
+pub fn cleartext_open(text: &str) -> Option<(String, Vec<Signature>)> {
+    let start = text.find("-----BEGIN PGP SIGNED MESSAGE-----")?;
+    let mut lines = text[start..].lines().skip(1);
+    // заголовки Hash: до пустой строки
+    for line in lines.by_ref() {
+        if line.trim().is_empty() {
+            break;
+        }
+    }
+    let mut signed = vec![];
+    for line in lines.by_ref() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.starts_with("-----BEGIN PGP SIGNATURE-----") {
+            break;
+        }
+        signed.push(line.strip_prefix("- ").unwrap_or(line));
+    }
+    let rest = &text[start..];
+    let signature_start = rest.find("-----BEGIN PGP SIGNATURE-----")?;
+    let signature = dearmor(&rest[signature_start..]).ok()?.into_iter().find(|(kind, _)| kind == "SIGNATURE")?.1;
+    let sigs = packets(&signature).ok()?.into_iter()
+        .filter(|packet| packet.tag == TAG_SIGNATURE)
+        .filter_map(|packet| Signature::parse(&packet.body).ok())
+        .collect::<Vec<_>>();
+    Some((signed.join("\n"), sigs))
+}
+
+// подписываются строки без пробелов в конце, через CRLF, без перевода строки перед подписью
+pub fn cleartext_verify(signed: &str, sigs: &[Signature], certs: &[Cert]) -> Verification {
+    let canonical = signed.lines().map(|line| line.trim_end_matches([' ', '\t', '\r'])).collect::<Vec<_>>().join("\r\n");
+    signatures_verify(sigs, canonical.as_bytes(), certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE_PUBLIC: &str = include_str!("../testdata/pgp/alice_public.asc");
+    const ALICE_SECRET: &str = include_str!("../testdata/pgp/alice_secret.asc");
+    const BOB_PUBLIC: &str = include_str!("../testdata/pgp/bob_public.asc");
+    const BOB_SECRET: &str = include_str!("../testdata/pgp/bob_secret.asc");
+    const INNER: &str = include_str!("../testdata/pgp/inner.txt");
+
+    fn cert(data: &str, passphrase: Option<&str>) -> Cert {
+        certs_read(data.as_bytes(), passphrase).unwrap().pop().unwrap()
+    }
+
+    fn emails(result: Verification) -> Vec<String> {
+        match result {
+            Verification::Valid(emails) => emails,
+            Verification::Unknown(id) => panic!("unknown {id}"),
+            Verification::Invalid => panic!("invalid")
+        }
+    }
+
+    #[test]
+    fn armor_round_trip() {
+        let data = (0..200u8).collect::<Vec<_>>();
+        let blocks = dearmor(&armor("MESSAGE", &data)).unwrap();
+        assert_eq!(blocks, vec![("MESSAGE".to_string(), data)]);
+        // испорченная контрольная сумма
+        let armored = armor("MESSAGE", b"hello");
+        let checksum = armored.lines().find(|line| line.starts_with('=')).unwrap();
+        assert!(dearmor(&armored.replace(checksum, "=AAAA")).is_err());
+        assert_eq!(crc24(b""), 0xB704CE);
+    }
+
+    #[test]
+    fn length_encoding() {
+        for len in [0, 1, 191, 192, 8383, 8384, 100_000] {
+            let body = vec![7u8; len];
+            let parsed = packets(&packet(TAG_LITERAL, &body)).unwrap();
+            assert_eq!((parsed[0].tag, parsed[0].body.len()), (TAG_LITERAL, len));
+        }
+        // частичные длины: 512 байт кусками, затем остаток
+        let mut data = vec![0xC0 | TAG_LITERAL, 0xE9];
+        data.extend(vec![1u8; 512]);
+        data.extend([3, 2, 2, 2]);
+        let parsed = packets(&data).unwrap();
+        assert_eq!(parsed[0].body.len(), 515);
+        assert_eq!(mpi(&[0, 0x40, 1]), vec![0, 15, 0x40, 1]);
+    }
+
+    #[test]
+    fn read_public_keys() {
+        let alice = cert(ALICE_PUBLIC, None);
+        assert_eq!(alice.primary.fingerprint_hex(), "4F81DF26708C6B81338DDDBAEF2618582833D09F");
+        assert_eq!(alice.emails(), vec!["alice@example.com".to_string()]);
+        assert!(!alice.has_secret());
+        assert_eq!(alice.encryption_key().map(|key| hex(&key.key_id())), Some("C6D6E38DC871C665".to_string()));
+
+        let bob = cert(BOB_PUBLIC, None);
+        assert_eq!(bob.primary.fingerprint_hex(), "B5B381FBA174D98941B03DD669DD352AEA6C2A70");
+        assert_eq!(bob.encryption_key().map(|key| hex(&key.key_id())), Some("968D52C26B461AD6".to_string()));
+    }
+
+    #[test]
+    fn read_secret_keys() {
+        assert!(certs_read(ALICE_SECRET.as_bytes(), None).is_err());
+        assert!(certs_read(ALICE_SECRET.as_bytes(), Some("wrong")).is_err());
+        assert!(!certs_read_public(ALICE_SECRET.as_bytes()).unwrap()[0].has_secret());
+        let alice = cert(ALICE_SECRET, Some("alice-pass"));
+        assert!(alice.has_secret());
+        assert!(alice.signing_key().is_some());
+
+        // сохраненный без пароля ключ читается снова и остается тем же
+        let stored = alice.to_bytes(true);
+        let again = certs_read(&stored, None).unwrap().pop().unwrap();
+        assert!(again.has_secret());
+        assert_eq!(again.primary.fingerprint, alice.primary.fingerprint);
+        let public = certs_read(&alice.to_bytes(false), None).unwrap().pop().unwrap();
+        assert!(!public.has_secret());
+        assert_eq!(public.emails(), alice.emails());
+    }
+
+    #[test]
+    fn forged_user_id_dropped() {
+        // чужой идентификатор с подписью от другого идентификатора не принимается
+        let alice = cert(ALICE_PUBLIC, None);
+        let mut data = alice.primary.packet(TAG_PUBLIC_KEY, false);
+        data.extend(packet(TAG_USER_ID, b"Mallory <mallory@example.net>"));
+        data.extend(packet(TAG_SIGNATURE, &alice.user_ids[0].sigs[0]));
+        assert!(certs_read(&data, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decrypt_gnupg_messages() {
+        let alice = cert(ALICE_SECRET, Some("alice-pass"));
+        let bob = cert(BOB_SECRET, Some("bob-pass"));
+
+        // Cv25519 для Алисы, подпись RSA Боба
+        let opened = message_decrypt(include_bytes!("../testdata/pgp/to_alice_from_bob.asc"), std::slice::from_ref(&alice)).unwrap();
+        assert_eq!(opened.data, INNER.as_bytes());
+        let certs = [cert(BOB_PUBLIC, None)];
+        assert_eq!(emails(signatures_verify(&opened.signatures, &opened.data, &certs)), vec!["bob@example.org".to_string()]);
+        assert!(matches!(signatures_verify(&opened.signatures, &opened.data, &[]), Verification::Unknown(_)));
+
+        // RSA для Боба, подпись Ed25519 Алисы
+        let opened = message_decrypt(include_bytes!("../testdata/pgp/to_bob_from_alice.asc"), std::slice::from_ref(&bob)).unwrap();
+        assert_eq!(opened.data, INNER.as_bytes());
+        assert_eq!(emails(signatures_verify(&opened.signatures, &opened.data, &[cert(ALICE_PUBLIC, None)])), vec!["alice@example.com".to_string()]);
+
+        // не тот ключ
+        assert!(message_decrypt(include_bytes!("../testdata/pgp/to_bob_from_alice.asc"), &[alice]).is_err());
+    }
+
+    #[test]
+    fn verify_gnupg_signatures() {
+        let certs = [cert(ALICE_PUBLIC, None), cert(BOB_PUBLIC, None)];
+        for signature in [&include_bytes!("../testdata/pgp/inner.alice.sig")[..], include_bytes!("../testdata/pgp/inner.bob.sig")] {
+            assert!(matches!(detached_verify(INNER.as_bytes(), signature, &certs), Verification::Valid(_)));
+            assert!(matches!(detached_verify(b"changed", signature, &certs), Verification::Invalid));
+        }
+
+        let (signed, sigs) = cleartext_open(include_str!("../testdata/pgp/clear.bob.asc")).unwrap();
+        assert!(signed.starts_with("Hello, Alice.\n- dashed line"));
+        assert!(matches!(cleartext_verify(&signed, &sigs, &certs), Verification::Valid(_)));
+        assert!(matches!(cleartext_verify(&signed.replace("Alice", "Eve"), &sigs, &certs), Verification::Invalid));
+    }
+
+    #[test]
+    fn encrypt_and_sign_round_trip() {
+        let alice = cert(ALICE_SECRET, Some("alice-pass"));
+        let bob = cert(BOB_SECRET, Some("bob-pass"));
+        let data = "Content-Type: text/plain\r\n\r\nтест\r\n".repeat(500);
+
+        let recipients = [alice.encryption_key().unwrap(), bob.encryption_key().unwrap()];
+        let encrypted = message_encrypt(data.as_bytes(), &recipients).unwrap();
+        assert_eq!(message_decrypt(encrypted.as_bytes(), std::slice::from_ref(&alice)).unwrap().data, data.as_bytes());
+        assert_eq!(message_decrypt(encrypted.as_bytes(), std::slice::from_ref(&bob)).unwrap().data, data.as_bytes());
+
+        // измененный шифртекст не расшифровывается
+        let mut binary = dearmor(&encrypted).unwrap().pop().unwrap().1;
+        let last = binary.len() - 30;
+        binary[last] ^= 1;
+        assert!(message_decrypt(&binary, &[alice]).is_err());
+
+        for signer in [&cert(ALICE_SECRET, Some("alice-pass")), &bob] {
+            let signature = detached_sign(data.as_bytes(), signer).unwrap();
+            let certs = [cert(ALICE_PUBLIC, None), cert(BOB_PUBLIC, None)];
+            assert_eq!(emails(detached_verify(data.as_bytes(), signature.as_bytes(), &certs)), signer.emails());
+        }
+    }
+
+    // пакеты сообщения после замены зашифрованного пакета
+    fn rebuild(data: &[u8], change: impl Fn(&Packet) -> Option<(u8, Vec<u8>)>) -> Vec<u8> {
+        let binary = binary_or_armored(data, &["MESSAGE"]).unwrap();
+        packets(&binary).unwrap().iter()
+            .flat_map(|item| match change(item) {
+                Some((tag, body)) => packet(tag, &body),
+                None => packet(item.tag, &item.body)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decrypt_tampered_gnupg_messages() {
+        let bob = [cert(BOB_SECRET, Some("bob-pass"))];
+        let message = include_bytes!("../testdata/pgp/to_bob_from_alice.asc");
+        let seipd = |change: fn(&mut Vec<u8>)| rebuild(message, move |item| (item.tag == TAG_SEIPD).then(|| {
+            let mut body = item.body.clone();
+            change(&mut body);
+            (TAG_SEIPD, body)
+        }));
+        assert!(message_decrypt(&seipd(|_| {}), &bob).is_ok());
+
+        // последний байт -- MDC, середина -- данные: обе правки ловит проверка целостности
+        let tampered = seipd(|body| *body.last_mut().unwrap() ^= 1);
+        assert_eq!(message_decrypt(&tampered, &bob).err().unwrap(), "integrity check failed");
+        let tampered = seipd(|body| {
+            let middle = body.len() / 2;
+            body[middle] ^= 0x80;
+        });
+        assert_eq!(message_decrypt(&tampered, &bob).err().unwrap(), "integrity check failed");
+        // MDC отрезан
+        let tampered = seipd(|body| body.truncate(body.len() - 22));
+        assert_eq!(message_decrypt(&tampered, &bob).err().unwrap(), "integrity check failed");
+
+        // тот же шифртекст как SED без MDC не расшифровывается
+        let downgraded = rebuild(message, |item| (item.tag == TAG_SEIPD).then(|| (TAG_SED, item.body[1..].to_vec())));
+        assert_eq!(message_decrypt(&downgraded, &bob).err().unwrap(), "encryption without integrity protection");
+
+        // не тот ключ и ключ без закрытой части
+        let alice = [cert(ALICE_SECRET, Some("alice-pass"))];
+        assert_eq!(message_decrypt(message, &alice).err().unwrap(), "no key to decrypt");
+        assert_eq!(message_decrypt(message, &[cert(BOB_PUBLIC, None)]).err().unwrap(), "no key to decrypt");
+    }
+
+    #[test]
+    fn expired_key() {
+        // ключ Кэрол действовал в 2020 году, подпись сделана тогда же
+        let carol = cert(include_str!("../testdata/pgp/carol_public.asc"), None);
+        assert_eq!(carol.emails(), vec!["carol@example.com".to_string()]);
+        assert!(!carol.usable());
+        assert!(carol.encryption_key().is_none());
+        let signature = include_bytes!("../testdata/pgp/inner.carol.sig");
+        assert!(matches!(detached_verify(INNER.as_bytes(), signature, std::slice::from_ref(&carol)), Verification::Valid(_)));
+
+        // подпись позже срока ключа
+        let mut expired = carol;
+        expired.primary.expires = Some(60);
+        assert!(matches!(detached_verify(INNER.as_bytes(), signature, &[expired]), Verification::Invalid));
+
+        // у подписи Эрин свой срок -- сутки, он давно прошел
+        let erin = [cert(include_str!("../testdata/pgp/erin_public.asc"), None)];
+        assert!(erin[0].usable());
+        assert!(matches!(detached_verify(INNER.as_bytes(), include_bytes!("../testdata/pgp/inner.erin.sig"), &erin), Verification::Invalid));
+    }
+
+    #[test]
+    fn revoked_key() {
+        let signature = include_bytes!("../testdata/pgp/inner.dave.sig");
+        let dave = cert(include_str!("../testdata/pgp/dave_public.asc"), None);
+        assert!(dave.usable() && dave.encryption_key().is_some());
+        assert!(matches!(detached_verify(INNER.as_bytes(), signature, &[dave]), Verification::Valid(_)));
+
+        // после отзыва ключ читается, но не шифрует и не подтверждает даже прежние подписи
+        let dave = cert(include_str!("../testdata/pgp/dave_revoked.asc"), None);
+        assert!(!dave.usable());
+        assert!(dave.encryption_key().is_none());
+        assert!(matches!(detached_verify(INNER.as_bytes(), signature, &[dave]), Verification::Invalid));
+    }
+
+    #[test]
+    fn critical_subpacket() {
+        let signature = |kind: u8| [&[4, SIG_BINARY, ALGO_EDDSA, HASH_SHA256, 0, 9, 5, 2, 0, 0, 0, 1, 2, kind, 0][..], &[0, 0, 0, 0]].concat();
+        assert!(Signature::parse(&signature(100)).is_ok());
+        assert!(Signature::parse(&signature(0x80 | 100)).is_err());
+        // уведомление без флага "критичный" пропускается, с флагом -- нет
+        assert!(Signature::parse(&signature(20)).is_ok());
+        assert!(Signature::parse(&signature(0x80 | 20)).is_err());
+        assert!(Signature::parse(&signature(0x80 | 2)).is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+use lettre::message::{header, MultiPart, SinglePart};
+use mail_parser::{Message, MessagePart, MimeHeaders};
+
+use crate::pgp::{canonical_text, cleartext_open, cleartext_verify, detached_sign, detached_verify, message_decrypt, message_encrypt, signatures_verify, Cert, Key, Verification};
+use crate::smime::outer_headers;
+
+// PGP/MIME (RFC 3156) и старый формат -- блоки прямо в тексте письма
+
+// вид multipart с протоколом OpenPGP: encrypted или signed
+fn pgp_protocol(part: &MessagePart<'_>) -> Option<String> {
+    let content_type = part.get_content_type()?;
+    if !content_type.get_type().eq_ignore_ascii_case("multipart") {
+        return None;
+    }
+    let protocol = content_type.get_attribute("protocol")?.to_lowercase();
+    let subtype = content_type.get_subtype().unwrap_or_default().to_lowercase();
+    match (subtype.as_str(), protocol.as_str()) {
+        ("encrypted", "application/pgp-encrypted") | ("signed", "application/pgp-signature") => Some(subtype),
+        _ => None
+    }
+}
+
+// связка нужна только письмам с OpenPGP
+pub fn pgp_present(message: &Message<'_>) -> bool {
+    message.parts.iter().any(|part| pgp_protocol(part).is_some())
+        || message.raw_message.windows(15).any(|window| window == b"-----BEGIN PGP ")
+}
+
+pub fn pgp_wrapped(message: &Message<'_>) -> bool {
+    message.parts.first().and_then(pgp_protocol).map(|subtype| subtype == "encrypted").unwrap_or_default()
+}
+
+// отметка подписи: адрес ключа должен совпасть с отправителем
+fn pgp_status(verification: Verification, from: &str) -> String {
+    match verification {
+        Verification::Valid(emails) if emails.iter().any(|email| email.eq_ignore_ascii_case(from)) => format!("pgp-valid:{from}"),
+        Verification::Valid(emails) => format!("pgp-mismatch:{}", emails.first().cloned().unwrap_or_default()),
+        Verification::Unknown(key_id) => format!("pgp-unknown:{key_id}"),
+        Verification::Invalid => "pgp-invalid".to_string()
+    }
+}
+
+// письмо целиком в multipart/encrypted: заголовки исходного с расшифрованным содержимым и отметки для просмотра
+pub fn pgp_unwrap(message: &Message<'_>, keyring: &[Cert], from: &str) -> Option<(Vec<u8>, Vec<String>)> {
+    let part = message.parts.first()?;
+    let encrypted = part.get_sub_parts()?.get(1).and_then(|id| message.parts.get(*id))?;
+    let opened = match message_decrypt(encrypted.get_contents(), keyring) {
+        Ok(opened) => opened,
+        Err(err) => {
+            tracing::warn!("pgp_unwrap: {err}");
+            return None;
+        }
+    };
+    let mut security = vec!["pgp-decrypted".to_string()];
+    // подпись внутри зашифрованного (GnuPG: --sign --encrypt); подпись в multipart/signed проверит разбор содержимого
+    if !opened.signatures.is_empty() {
+        security.push(pgp_status(signatures_verify(&opened.signatures, &opened.data, keyring), from));
+    }
+    Some(([outer_headers(&message.raw_message), opened.data].concat(), security))
+}
+
+// подписано содержимое первой части с ее заголовками, концы строк -- CRLF
+pub fn pgp_verify_part(message: &Message<'_>, part: &MessagePart<'_>, keyring: &[Cert], from: &str) -> String {
+    let parts = part.get_sub_parts()
+        .filter(|ids| ids.len() == 2)
+        .and_then(|ids| Some((message.parts.get(ids[0])?, message.parts.get(ids[1])?)));
+    let (signed, signature) = match parts {
+        Some(parts) => parts,
+        None => return "pgp-invalid".to_string()
+    };
+    match message.raw_message.get(signed.offset_header..signed.offset_end) {
+        Some(data) => pgp_status(detached_verify(&canonical_text(data), signature.get_contents(), keyring), from),
+        None => "pgp-invalid".to_string()
+    }
+}
+
+// блок OpenPGP в тексте письма: расшифрованный или проверенный текст вместо блока
+pub fn pgp_inline(text: &str, keyring: &[Cert], from: &str) -> Option<(String, Vec<String>)> {
+    if let Some(start) = text.find("-----BEGIN PGP MESSAGE-----") {
+        let end = text[start..].find("-----END PGP MESSAGE-----").map(|end| start + end + 25)?;
+        let opened = match message_decrypt(&text.as_bytes()[start..end], keyring) {
+            Ok(opened) => opened,
+            Err(err) => {
+                tracing::warn!("pgp_inline: {err}");
+                return None;
+            }
+        };
+        let mut security = vec!["pgp-decrypted".to_string()];
+        if !opened.signatures.is_empty() {
+            security.push(pgp_status(signatures_verify(&opened.signatures, &opened.data, keyring), from));
+        }
+        let plain = String::from_utf8_lossy(&opened.data);
+        return Some((format!("{}{plain}{}", &text[..start], &text[end..]), security));
+    }
+    let start = text.find("-----BEGIN PGP SIGNED MESSAGE-----")?;
+    let end = text[start..].find("-----END PGP SIGNATURE-----").map(|end| start + end + 27)?;
+    let (signed, sigs) = cleartext_open(&text[start..end])?;
+    let status = pgp_status(cleartext_verify(&signed, &sigs, keyring), from);
+    Some((format!("{}{signed}{}", &text[..start], &text[end..]), vec![status]))
+}
+
+// свой ключ для подписи: с адресом отправителя, иначе любой свой
+pub fn pgp_own_cert<'a>(keyring: &'a [Cert], address: &str) -> Option<&'a Cert> {
+    let own = keyring.iter().filter(|cert| cert.signing_key().is_some()).collect::<Vec<_>>();
+    own.iter().find(|cert| cert.emails().contains(&address.to_lowercase())).or(own.first()).copied()
+}
+
+pub fn pgp_recipient_key<'a>(keyring: &'a [Cert], address: &str) -> Option<&'a Key> {
+    keyring.iter().filter(|cert| cert.emails().contains(&address.to_lowercase())).find_map(Cert::encryption_key)
+}
+
+// подписывается содержимое вместе с заголовками, без перевода строки перед границей
+pub fn pgp_sign(cert: &Cert, content: MultiPart) -> Result<MultiPart, String> {
+    let formatted = content.formatted();
+    let signed = formatted.strip_suffix(b"\r\n").unwrap_or(&formatted);
+    let signature = detached_sign(signed, cert)?;
+    let content_type = header::ContentType::parse("application/pgp-signature; name=signature.asc").map_err(|err| err.to_string())?;
+    Ok(MultiPart::signed("application/pgp-signature".to_string(), "pgp-sha256".to_string())
+        .multipart(content)
+        .singlepart(
+            SinglePart::builder()
+                .header(content_type)
+                .header(header::ContentDisposition::attachment("signature.asc"))
+                .body(signature)
+        ))
+}
+
+pub fn pgp_encrypt(recipients: &[&Key], content: MultiPart) -> Result<MultiPart, String> {
+    let encrypted = message_encrypt(&content.formatted(), recipients)?;
+    let control_type = header::ContentType::parse("application/pgp-encrypted").map_err(|err| err.to_string())?;
+    let content_type = header::ContentType::parse("application/octet-stream; name=encrypted.asc").map_err(|err| err.to_string())?;
+    Ok(MultiPart::encrypted("application/pgp-encrypted".to_string())
+        .singlepart(SinglePart::builder().header(control_type).body("Version: 1\r\n".to_string()))
+        .singlepart(
+            SinglePart::builder()
+                .header(content_type)
+                .header(header::ContentDisposition::inline_with_name("encrypted.asc"))
+                .body(encrypted)
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use lettre::Message as Email;
+
+    use crate::pgp::certs_read;
+
+    use super::*;
+
+    fn cert(data: &[u8], passphrase: Option<&str>) -> Cert {
+        certs_read(data, passphrase).unwrap().pop().unwrap()
+    }
+
+    fn email(content: MultiPart) -> Vec<u8> {
+        Email::builder()
+            .from("Alice <alice@example.com>".parse().unwrap())
+            .to("bob@example.org".parse().unwrap())
+            .subject("тест")
+            .multipart(content)
+            .unwrap()
+            .formatted()
+    }
+
+    fn content() -> MultiPart {
+        MultiPart::alternative()
+            .singlepart(SinglePart::builder().header(header::ContentType::TEXT_PLAIN).body("Привет, Боб!\nвторая строка ".to_string()))
+            .singlepart(SinglePart::builder().header(header::ContentType::TEXT_HTML).body("<p>Привет, Боб!</p>".to_string()))
+    }
+
+    #[test]
+    fn gnupg_signed_part() {
+        // подпись GnuPG над частью с заголовками, как ее вкладывает почтовый клиент
+        let raw = [
+            "From: Alice <alice@example.com>\r\nSubject: test\r\nMIME-Version: 1.0\r\n",
+            "Content-Type: multipart/signed; boundary=\"b1\"; micalg=pgp-sha256; protocol=\"application/pgp-signature\"\r\n\r\n",
+            "--b1\r\n", include_str!("../testdata/pgp/inner.txt"), "\r\n--b1\r\n",
+            "Content-Type: application/pgp-signature; name=\"signature.asc\"\r\n\r\n",
+            include_str!("../testdata/pgp/inner.alice.sig"), "\r\n--b1--\r\n",
+        ].concat();
+        let message = Message::parse(raw.as_bytes()).unwrap();
+        assert!(pgp_present(&message) && !pgp_wrapped(&message));
+        let keyring = [cert(include_bytes!("../testdata/pgp/alice_public.asc"), None)];
+        assert_eq!(pgp_verify_part(&message, &message.parts[0], &keyring, "alice@example.com"), "pgp-valid:alice@example.com");
+        assert_eq!(pgp_verify_part(&message, &message.parts[0], &keyring, "eve@example.com"), "pgp-mismatch:alice@example.com");
+        assert_eq!(pgp_verify_part(&message, &message.parts[0], &[], "alice@example.com"), "pgp-unknown:EF2618582833D09F");
+
+        // те же строки с LF: так письмо бывает записано на диск
+        let message = raw.replace("\r\n", "\n");
+        let message = Message::parse(message.as_bytes()).unwrap();
+        assert_eq!(pgp_verify_part(&message, &message.parts[0], &keyring, "alice@example.com"), "pgp-valid:alice@example.com");
+
+        let changed = raw.replace("Алиса", "Алиска");
+        let message = Message::parse(changed.as_bytes()).unwrap();
+        assert_eq!(pgp_verify_part(&message, &message.parts[0], &keyring, "alice@example.com"), "pgp-invalid");
+    }
+
+    #[test]
+    fn sign_encrypt_round_trip() {
+        let alice = cert(include_bytes!("../testdata/pgp/alice_secret.asc"), Some("alice-pass"));
+        let bob = cert(include_bytes!("../testdata/pgp/bob_secret.asc"), Some("bob-pass"));
+        let keyring = [alice, bob];
+
+        let signed = email(pgp_sign(&keyring[0], content()).unwrap());
+        let message = Message::parse(&signed).unwrap();
+        assert!(pgp_present(&message) && !pgp_wrapped(&message));
+        assert_eq!(pgp_verify_part(&message, &message.parts[0], &keyring, "alice@example.com"), "pgp-valid:alice@example.com");
+
+        // подписанное и зашифрованное для Боба: внутри multipart/signed с проверяемой подписью
+        let recipients = [keyring[1].encryption_key().unwrap()];
+        let encrypted = email(pgp_encrypt(&recipients, pgp_sign(&keyring[0], content()).unwrap()).unwrap());
+        let message = Message::parse(&encrypted).unwrap();
+        assert!(pgp_wrapped(&message));
+        assert!(pgp_unwrap(&message, &keyring[..1], "alice@example.com").is_none());
+        let (raw, security) = pgp_unwrap(&message, &keyring[1..], "alice@example.com").unwrap();
+        assert_eq!(security, vec!["pgp-decrypted".to_string()]);
+        let opened = Message::parse(&raw).unwrap();
+        assert_eq!(opened.get_subject(), Some("тест"));
+        assert!(opened.get_text_body(0).unwrap().contains("Привет, Боб!"));
+        assert_eq!(pgp_verify_part(&opened, &opened.parts[0], &keyring, "alice@example.com"), "pgp-valid:alice@example.com");
+    }
+
+    #[test]
+    fn inline_blocks() {
+        let alice = cert(include_bytes!("../testdata/pgp/alice_secret.asc"), Some("alice-pass"));
+        let bob = cert(include_bytes!("../testdata/pgp/bob_public.asc"), None);
+
+        let text = format!("before\n{}after", include_str!("../testdata/pgp/to_alice_from_bob.asc"));
+        let (plain, security) = pgp_inline(&text, &[alice, bob], "bob@example.org").unwrap();
+        assert!(plain.starts_with("before\nContent-Type: text/plain") && plain.contains("Привет, Алиса!") && plain.ends_with("after"));
+        assert_eq!(security, vec!["pgp-decrypted".to_string(), "pgp-valid:bob@example.org".to_string()]);
+        assert!(pgp_inline(&text, &[], "bob@example.org").is_none());
+
+        let bob = cert(include_bytes!("../testdata/pgp/bob_public.asc"), None);
+        let (signed, security) = pgp_inline(include_str!("../testdata/pgp/clear.bob.asc"), &[bob], "bob@example.org").unwrap();
+        assert!(!signed.contains("BEGIN PGP"));
+        assert_eq!(security, vec!["pgp-valid:bob@example.org".to_string()]);
+    }
+}
@@ -6,6 +6,7 @@ use std::io::Read;
 use std::iter::Iterator;
 use std::string::ToString;
 
+use bytes::Bytes;
 use lol_html::{comments, element, HtmlRewriter, Settings};
 use lol_html::html_content::ContentType;
 //use mailparse::*;
@@ -17,10 +18,18 @@ use once_cell::sync::Lazy;
 //use mailparse::MailAddr::{Group, Single};
 use uuid::Uuid;
 
-use crate::constants::{MAIL_SOURCE_PATH, path_to_attachment, path_to_saved};
+use crate::blobs::{blob_save, source_save};
+use crate::constants::{key_to_blob, MAIL_SOURCE_PATH, path_to_saved};
 use crate::db_boxes::db_box_add_received;
-use crate::db_types::{DBMailAddress, DBMailAttachmentItem, DBMailAttachments};
+use crate::db_pgp::db_pgp_keyring_by_email;
+use crate::db_smime::db_smime_signer_by_email;
+use crate::db_types::{DBBoxInsert, DBMailAddress, DBMailAttachmentItem, DBMailAttachments};
+use crate::pgp::Cert;
+use crate::pgp_mime::{pgp_inline, pgp_present, pgp_unwrap, pgp_verify_part, pgp_wrapped};
+use crate::smime::{is_smime_signed, smime_unwrap, smime_verify_part, smime_wrapped};
 use crate::state::USER_BY_EMAIL;
+use crate::storage::storage;
+use crate::thumbs::thumb_save;
 use crate::utils::{get_dir_path, get_file_name};
 
 // pub static USER_BY_ID: Lazy<Arc<Mutex<HashMap<i32, DBUserInit>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
@@ -42,7 +51,7 @@ pub async fn mail_watcher() {
                         Ok(read_dir) => {
                             for entries in read_dir.into_iter().flatten() {
                                 if let Some(path_to_file) = entries.path().to_str() {
-                                    read_email(path_to_file);
+                                    read_email(path_to_file).await;
                                 }
                             }
                         }
@@ -105,7 +114,7 @@ fn get_email_from_path(path: &str) -> String {
     format!("{}@{}", list[list.len() - 3], list[list.len() - 4])
 }
 
-fn read_email(path_to_file: &str) {
+async fn read_email(path_to_file: &str) {
     if get_file_name(path_to_file).starts_with('.') {
         return;
     }
@@ -122,8 +131,13 @@ fn read_email(path_to_file: &str) {
         d
     } else { vec![] };
 
+    let file_name = get_file_name(path_to_file);
     match Message::parse(&mail_source) {
-        Some(message) => prepare(message, &current_email),
+        Some(message) => {
+            let key = Uuid::new_v4().to_string();
+            let (flag_spam, data) = prepare(&message, &current_email, &file_name, &key, true).await;
+            db_box_add_received(flag_spam, current_email.clone(), data);
+        }
         None => tracing::error!("parse_mail error")
     }
     /*match mailparse::parse_mail(&mail_source) {
@@ -131,7 +145,17 @@ fn read_email(path_to_file: &str) {
         Err(err) => tracing::error!("parse_mail: {:?}", err)
     }*/
 
-    let target_file_path = path_to_saved(&current_email, &get_file_name(path_to_file));
+    // в хранилище -- зашифрованным при настроенном шифровании; не вышло -- на локальный диск, как раньше
+    match source_save(&current_email, &file_name, Bytes::from(mail_source)).await {
+        Ok(_) => {
+            if let Err(err) = fs::remove_file(path_to_file) {
+                tracing::error!("read_email[3]: {:?}", err);
+            }
+            return;
+        }
+        Err(err) => tracing::error!("read_email[4]: {:?}", err)
+    }
+    let target_file_path = path_to_saved(&current_email, &file_name);
     match fs::create_dir_all(get_dir_path(&target_file_path)) {
         Ok(_) => {
             if let Err(err) = fs::rename(path_to_file, &target_file_path) {
@@ -144,9 +168,36 @@ fn read_email(path_to_file: &str) {
     }
 }
 
-fn prepare(message: Message, current_email: &str) {
+// разбор письма; при save=false вложения не записываются на диск (проверка без изменений)
+pub async fn prepare(message: &Message<'_>, current_email: &str, source: &str, key: &str, save: bool) -> (bool, DBBoxInsert) {
+    // ключи OpenPGP получателя: расшифровка и проверка подписей по его связке
+    let keyring = if pgp_present(message) { db_pgp_keyring_by_email(current_email).await } else { vec![] };
+    // адрес отправителя сверяется с адресом сертификата или ключа подписи
+    let from = mail_address_from_header(message.get_from()).address;
+    // S/MIME целиком в application/pkcs7-mime: разбираем раскрытое содержимое с заголовками исходного письма
+    if smime_wrapped(message) {
+        let signer = db_smime_signer_by_email(current_email).await;
+        if let Some((raw, security)) = smime_unwrap(message, signer.as_ref(), &from) {
+            if let Some(opened) = Message::parse(&raw) {
+                return prepare_message(&opened, current_email, source, key, save, security, &keyring).await;
+            }
+        }
+    }
+    // то же для PGP/MIME: без ключа письмо остается как есть, с отметкой pgp-encrypted
+    if pgp_wrapped(message) {
+        if let Some((raw, security)) = pgp_unwrap(message, &keyring, &from) {
+            if let Some(opened) = Message::parse(&raw) {
+                return prepare_message(&opened, current_email, source, key, save, security, &keyring).await;
+            }
+        }
+    }
+    prepare_message(message, current_email, source, key, save, vec![], &keyring).await
+}
+
+async fn prepare_message(message: &Message<'_>, current_email: &str, source: &str, key: &str, save: bool, mut security: Vec<String>, keyring: &[Cert]) -> (bool, DBBoxInsert) {
     let from = message.get_from();
     let to = message.get_to();
+    let sender = mail_address_from_header(from);
     let subject = message.get_subject().unwrap_or_default().to_string();
     let html = match message.get_html_body(0) {
         Some(html) => html.to_string(),
@@ -156,8 +207,13 @@ fn prepare(message: Message, current_email: &str) {
         Some(text) => text.to_string(),
         None => "".to_string()
     };
+    // блок OpenPGP в тексте: показываем расшифрованный или проверенный текст
+    let (text, inline) = match pgp_inline(&text, keyring, &sender.address) {
+        Some((text, inline)) => (text, inline),
+        None => (text, vec![])
+    };
 
-    let content = if !html.is_empty() {
+    let content = if !html.is_empty() && inline.is_empty() {
         match clean_html(&html) {
             Ok(html) => html,
             Err(err) => {
@@ -171,7 +227,6 @@ fn prepare(message: Message, current_email: &str) {
         "".to_string()
     };
 
-    let key = Uuid::new_v4().to_string();
     let mut list: Vec<DBMailAttachmentItem> = vec![];
     for part in message.get_attachments() {
         if part.is_binary() {
@@ -180,17 +235,16 @@ fn prepare(message: Message, current_email: &str) {
                     let id = list.len() + 1;
                     let size = body.len() as u64;
 
-                    let file_path = path_to_attachment(current_email, &key, &id);
-                    match fs::create_dir_all(get_dir_path(&file_path)) {
-                        Ok(_) => {
-                            match fs::write(&file_path, body) {
-                                Ok(_) => {
-                                    list.push(DBMailAttachmentItem { id, size, file_name: file_name.to_string() });
-                                }
-                                Err(err) => {
-                                    tracing::error!("save attachment: {:?}", err);
-                                }
-                            }
+                    if !save {
+                        list.push(DBMailAttachmentItem { id, size, file_name: file_name.to_string(), cid: None, hash: None });
+                        continue;
+                    }
+
+                    let data = Bytes::copy_from_slice(body.as_ref());
+                    match blob_save(data.clone()).await {
+                        Ok(hash) => {
+                            thumb_save(storage(), &key_to_blob(&hash), data).await;
+                            list.push(DBMailAttachmentItem { id, size, file_name: file_name.to_string(), cid: None, hash: Some(hash) });
                         }
                         Err(err) => {
                             tracing::error!("save attachment: {:?}", err);
@@ -202,10 +256,9 @@ fn prepare(message: Message, current_email: &str) {
     }
 
     let attachments: Option<DBMailAttachments> = if list.is_empty() { None } else {
-        Some(DBMailAttachments { key, list })
+        Some(DBMailAttachments { key: key.to_string(), list })
     };
 
-    let sender = mail_address_from_header(from);
     let recipient = mail_address_from_header(to);
 
     //let from_text = format!("{:?}", from).to_lowercase();
@@ -220,15 +273,48 @@ fn prepare(message: Message, current_email: &str) {
     }
     tracing::info!("{:?} {:?}", sender, flag_ends_trusted);
 
-    db_box_add_received(
-        flag_spam,
-        current_email.to_string(),
+    security.extend(inline);
+    security.extend(message_security(message, &text, keyring, &sender.address));
+
+    (flag_spam, DBBoxInsert {
         sender,
         recipient,
         subject,
         content,
         attachments,
-    );
+        source: Some(source.to_string()),
+        message_id: message.get_message_id().map(|v| v.to_string()),
+        security: if security.is_empty() { None } else { Some(security.join(" ")) },
+    })
+}
+
+// отметки для просмотра через пробел: подписи S/MIME и OpenPGP проверяются,
+// pgp-encrypted -- зашифровано OpenPGP, но ключа для расшифровки нет
+fn message_security(message: &Message<'_>, text: &str, keyring: &[Cert], from: &str) -> Vec<String> {
+    let mut security = vec![];
+    for part in message.parts.iter() {
+        let content_type = match part.get_content_type() {
+            Some(content_type) if content_type.get_type().eq_ignore_ascii_case("multipart") => content_type,
+            _ => continue
+        };
+        let protocol = content_type.get_attribute("protocol").unwrap_or_default().to_lowercase();
+        match content_type.get_subtype().unwrap_or_default().to_lowercase().as_str() {
+            "encrypted" if protocol == "application/pgp-encrypted" => security.push("pgp-encrypted".to_string()),
+            "signed" if protocol == "application/pgp-signature" => security.push(pgp_verify_part(message, part, keyring, from)),
+            "signed" if is_smime_signed(&protocol) => security.push(smime_verify_part(message, part, from)),
+            _ => {}
+        }
+    }
+    if security.iter().any(|item| item.starts_with("pgp-")) {
+        return security;
+    }
+    // старый формат OpenPGP: блок в тексте, который не удалось открыть
+    if text.contains("-----BEGIN PGP MESSAGE-----") {
+        security.push("pgp-encrypted".to_string());
+    } else if text.contains("-----BEGIN PGP SIGNED MESSAGE-----") {
+        security.push("pgp-signed".to_string());
+    }
+    security
 }
 
 fn mail_address_from_header(header: &HeaderValue) -> DBMailAddress {
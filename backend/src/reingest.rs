@@ -0,0 +1,314 @@
+use std::cmp::max;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use mail_parser::Message;
+use uuid::Uuid;
+
+use crate::blobs::{blobs_release, blobs_unref, source_read, sources_list};
+use crate::db_boxes::{db_box_find, db_box_update};
+use crate::db_types::{DBBox, DBBoxInsert, DBMailAttachments};
+use crate::db_user::db_user_init;
+use crate::receive::prepare;
+use crate::state::USER_BY_EMAIL;
+
+const USAGE: &str = "reingest <email> [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--dry-run]";
+
+// построчное сравнение содержимого делаем только для писем разумного размера
+const DIFF_LIMIT: usize = 4_000_000;
+
+struct ReingestParams {
+    email: String,
+    // секунды от начала эпохи, по дате письма в базе
+    date_from: Option<i64>,
+    date_to: Option<i64>,
+    dry_run: bool,
+}
+
+#[derive(Default)]
+struct ReingestStat {
+    total: usize,
+    updated: usize,
+    unchanged: usize,
+    missing: usize,
+    failed: usize,
+}
+
+pub async fn reingest(args: &[String]) {
+    let params = match reingest_params(args) {
+        Some(params) => params,
+        None => {
+            eprintln!("usage: {USAGE}");
+            return;
+        }
+    };
+
+    db_user_init().await;
+    let idu = match USER_BY_EMAIL.lock() {
+        Ok(users) => users.get(&params.email).cloned(),
+        Err(_) => None
+    };
+    let idu = match idu {
+        Some(idu) => idu,
+        None => {
+            eprintln!("reingest: unknown email {}", params.email);
+            return;
+        }
+    };
+
+    let files = match sources_list(&params.email).await {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("reingest: {} -- {err}", params.email);
+            return;
+        }
+    };
+
+    let mut stat = ReingestStat::default();
+    for (file_name, modified) in files {
+        // время файла меняется при копировании и переразборе: по нему только ищутся старые записи без source
+        let received = match modified {
+            Some(received) => received,
+            None => continue
+        };
+
+        let mail_source = match source_read(&params.email, &file_name).await {
+            Ok(data) => data,
+            Err(err) => {
+                println!("{file_name}: {err}");
+                stat.total += 1;
+                stat.failed += 1;
+                continue;
+            }
+        };
+        let message = match Message::parse(&mail_source) {
+            Some(message) => message,
+            None => {
+                println!("{file_name}: parse_mail error");
+                stat.total += 1;
+                stat.failed += 1;
+                continue;
+            }
+        };
+
+        let (_, data) = prepare(&message, &params.email, &file_name, &Uuid::new_v4().to_string(), false).await;
+        let (row, linked) = match db_box_find(&idu, &file_name, &data.message_id, &data, &received).await {
+            Some(found) => found,
+            None => {
+                println!("{file_name}: message not found");
+                stat.total += 1;
+                stat.missing += 1;
+                continue;
+            }
+        };
+        if !date_in_range(&params, row.timestamp) {
+            continue;
+        }
+        stat.total += 1;
+
+        let changes = diff_box(&row, &data);
+        if changes.is_empty() && linked {
+            stat.unchanged += 1;
+            continue;
+        }
+
+        println!("{file_name} [idb={}]", row.idb);
+        for line in changes.iter() {
+            println!("{line}");
+        }
+
+        if params.dry_run {
+            stat.updated += 1;
+            continue;
+        }
+
+        // вложения перезаписываем под прежним ключом, ссылки в письме остаются рабочими
+        let key = match &row.attachments {
+            Some(attachments) => attachments.key.clone(),
+            None => Uuid::new_v4().to_string()
+        };
+        let (_, data) = prepare(&message, &params.email, &file_name, &key, true).await;
+        let attachments = data.attachments.clone();
+
+        // новые ссылки взяты при записи файлов, поэтому общий со старыми файл не успеет удалиться
+        if db_box_update(&idu, &row.idb, data).await {
+            if let Some(prev) = &row.attachments {
+                blobs_release(&params.email, prev).await;
+            }
+            stat.updated += 1;
+        } else {
+            if let Some(attachments) = &attachments {
+                blobs_unref(attachments).await;
+            }
+            stat.failed += 1;
+        }
+    }
+
+    // в пробном запуске ничего не записано
+    let (mode, updated) = if params.dry_run { (" (dry run)", "would update") } else { ("", "updated") };
+    println!(
+        "reingest{mode}: total {}, {updated} {}, unchanged {}, not found {}, failed {}",
+        stat.total, stat.updated, stat.unchanged, stat.missing, stat.failed
+    );
+}
+
+fn reingest_params(args: &[String]) -> Option<ReingestParams> {
+    let mut params = ReingestParams {
+        email: "".to_string(),
+        date_from: None,
+        date_to: None,
+        dry_run: false,
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--dry-run" => {
+                params.dry_run = true;
+            }
+            "--from" => {
+                params.date_from = Some(parse_date(iter.next()?)?);
+            }
+            "--to" => {
+                // включительно: до начала следующего дня
+                params.date_to = Some(parse_date(iter.next()?)? + 24 * 60 * 60);
+            }
+            _ => {
+                if !params.email.is_empty() || !arg.contains('@') {
+                    return None;
+                }
+                params.email = arg.clone();
+            }
+        }
+    }
+    if params.email.is_empty() { None } else { Some(params) }
+}
+
+fn parse_date(text: &str) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms(0, 0, 0)).timestamp())
+}
+
+fn date_in_range(params: &ReingestParams, timestamp: i64) -> bool {
+    params.date_from.map(|date| timestamp >= date).unwrap_or(true) && params.date_to.map(|date| timestamp < date).unwrap_or(true)
+}
+
+fn diff_box(row: &DBBox, data: &DBBoxInsert) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    if row.sender != data.sender {
+        lines.push(format!("  sender: {:?} -> {:?}", row.sender, data.sender));
+    }
+    if row.recipient != data.recipient {
+        lines.push(format!("  recipient: {:?} -> {:?}", row.recipient, data.recipient));
+    }
+    if row.subject != data.subject {
+        lines.push(format!("  subject: {:?} -> {:?}", row.subject, data.subject));
+    }
+    if row.security != data.security {
+        lines.push(format!("  security: {:?} -> {:?}", row.security, data.security));
+    }
+    let prev = attachments_view(&row.attachments);
+    let next = attachments_view(&data.attachments);
+    if prev != next {
+        lines.push(format!("  attachments: [{prev}] -> [{next}]"));
+    }
+    if row.content != data.content {
+        lines.push("  content:".to_string());
+        lines.extend(diff_lines(&row.content, &data.content));
+    }
+    lines
+}
+
+fn attachments_view(attachments: &Option<DBMailAttachments>) -> String {
+    match attachments {
+        Some(attachments) => attachments.list.iter()
+            .map(|item| format!("{} ({})", item.file_name, item.size))
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => "".to_string()
+    }
+}
+
+fn diff_lines(prev: &str, next: &str) -> Vec<String> {
+    let a = prev.lines().collect::<Vec<_>>();
+    let b = next.lines().collect::<Vec<_>>();
+
+    if a.len() * b.len() > DIFF_LIMIT {
+        let mut lines = a.iter().map(|line| format!("  - {line}")).collect::<Vec<_>>();
+        lines.extend(b.iter().map(|line| format!("  + {line}")));
+        return lines;
+    }
+
+    // длины общих подпоследовательностей для хвостов a[i..] и b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { max(lcs[i + 1][j], lcs[i][j + 1]) };
+        }
+    }
+
+    let mut lines: Vec<String> = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(format!("  + {}", b[j]));
+            j += 1;
+        } else {
+            lines.push(format!("  - {}", a[i]));
+            i += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn params() {
+        let params = reingest_params(&args("user@example.com --from 2024-03-01 --to 2024-03-31 --dry-run")).unwrap();
+        assert_eq!(params.email, "user@example.com");
+        assert_eq!(params.date_from, Some(1709251200));
+        // --to включительно: до начала 1 апреля
+        assert_eq!(params.date_to, Some(1711929600));
+        assert!(params.dry_run);
+        assert!(date_in_range(&params, 1709251200));
+        assert!(date_in_range(&params, 1711929599));
+        assert!(!date_in_range(&params, 1709251199));
+        assert!(!date_in_range(&params, 1711929600));
+
+        let params = reingest_params(&args("--dry-run user@example.com")).unwrap();
+        assert_eq!((params.date_from, params.date_to, params.dry_run), (None, None, true));
+        assert!(date_in_range(&params, 0));
+
+        for line in ["", "--dry-run", "user", "a@example.com b@example.com", "a@example.com --from", "a@example.com --from 01.03.2024", "a@example.com --to 2024-02-30"] {
+            assert!(reingest_params(&args(line)).is_none(), "{line}");
+        }
+    }
+
+    #[test]
+    fn lines() {
+        assert!(diff_lines("a\nb", "a\nb").is_empty());
+        assert_eq!(diff_lines("a\nb\nc", "a\nx\nc"), vec!["  + x", "  - b"]);
+        assert_eq!(diff_lines("", "a\nb"), vec!["  + a", "  + b"]);
+        assert_eq!(diff_lines("a\nb", ""), vec!["  - a", "  - b"]);
+        // общая часть не выводится, вставка и удаление -- по месту
+        assert_eq!(diff_lines("a\nb\nc\nd", "b\nc\nd\ne"), vec!["  - a", "  + e"]);
+    }
+
+    #[test]
+    fn lines_over_limit() {
+        // слишком длинные тексты сравниваются целиком: все старые строки, затем все новые
+        let prev = "a\n".repeat(2001);
+        let next = "a\n".repeat(2000);
+        let lines = diff_lines(&prev, &next);
+        assert_eq!(lines.len(), 4001);
+        assert_eq!((lines[0].as_str(), lines[2001].as_str()), ("  - a", "  + a"));
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use bytes::Buf;
+use futures_util::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+use warp::http::header::HeaderValue;
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use shared::constants::{HEADER_UPLOAD_LENGTH, HEADER_UPLOAD_OFFSET, UPLOAD_FILE_MAX};
+use shared::types::{BoxMailAttachmentItem, UploadCreateRequest, UploadFinishRequest};
+
+use crate::constants::{key_to_temp_with_ind, path_to_temp_resumable, path_to_temp_with_ind};
+use crate::storage::LOCAL;
+use crate::thumbs::thumb_save_file;
+use crate::types::SessionStruct;
+use crate::upload::{inline_cid, is_blocked, is_executable, UPLOAD_BLOCKED, UPLOAD_FAILED, UPLOAD_TOO_LARGE};
+use crate::utils::{get_dir_path, is_valid_key};
+
+const UPLOAD_INCOMPLETE: &str = "incomplete";
+
+// в одну загрузку одновременно пишет только один запрос
+static UPLOADS_BUSY: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+// номер вложения выбирается и занимается под блокировкой
+static UPLOADS_FINISH: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+// описание лежит рядом с данными, загрузку можно продолжить и после перезапуска сервера;
+// незавершенные загрузки удаляются вместе с остальными временными файлами через сутки
+#[derive(Debug, Deserialize, Serialize)]
+struct UploadMeta {
+    file_name: String,
+    size: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct UploadCreateResult {
+    result: bool,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct UploadFinishResult {
+    result: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item: Option<BoxMailAttachmentItem>,
+}
+
+struct BusyGuard(String);
+
+impl BusyGuard {
+    fn lock(id: &str) -> Option<Self> {
+        let mut busy = UPLOADS_BUSY.lock().ok()?;
+        if busy.insert(id.to_string()) { Some(Self(id.to_string())) } else { None }
+    }
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        if let Ok(mut busy) = UPLOADS_BUSY.lock() {
+            busy.remove(&self.0);
+        }
+    }
+}
+
+fn meta_path(path: &str) -> String {
+    format!("{path}.json")
+}
+
+async fn upload_meta(session: &SessionStruct, id: &str) -> Option<(String, UploadMeta)> {
+    if !is_valid_key(id) {
+        return None;
+    }
+    let path = path_to_temp_resumable(&session.idu, id);
+    let text = fs::read_to_string(meta_path(&path)).await.ok()?;
+    serde_json::from_str::<UploadMeta>(&text).ok().map(|meta| (path, meta))
+}
+
+pub fn offset_response(status: StatusCode, offset: u64, size: Option<u64>) -> Response {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(HEADER_UPLOAD_OFFSET, HeaderValue::from(offset));
+    if let Some(size) = size {
+        resp.headers_mut().insert(HEADER_UPLOAD_LENGTH, HeaderValue::from(size));
+    }
+    resp
+}
+
+pub async fn resumable_create(session: &SessionStruct, data: UploadCreateRequest) -> UploadCreateResult {
+    let file_name = data.file_name.trim().to_string();
+    let error = if file_name.is_empty() {
+        Some(UPLOAD_FAILED)
+    } else if data.size > UPLOAD_FILE_MAX {
+        Some(UPLOAD_TOO_LARGE)
+    } else if is_blocked(&file_name, None) {
+        Some(UPLOAD_BLOCKED)
+    } else { None };
+    if error.is_some() {
+        return UploadCreateResult { error, ..UploadCreateResult::default() };
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let path = path_to_temp_resumable(&session.idu, &id);
+    (fs::create_dir_all(get_dir_path(&path)).await).ok();
+    let meta = serde_json::to_string(&UploadMeta { file_name, size: data.size }).unwrap_or_default();
+    if let Err(err) = fs::write(&path, b"").await {
+        tracing::error!("resumable_create[1] {:?}", err);
+        return UploadCreateResult { error: Some(UPLOAD_FAILED), ..UploadCreateResult::default() };
+    }
+    if let Err(err) = fs::write(meta_path(&path), meta).await {
+        tracing::error!("resumable_create[2] {:?}", err);
+        (fs::remove_file(&path).await).ok();
+        return UploadCreateResult { error: Some(UPLOAD_FAILED), ..UploadCreateResult::default() };
+    }
+    UploadCreateResult { result: true, id, error: None }
+}
+
+// сколько байт уже получено и полный размер файла
+pub async fn resumable_offset(session: &SessionStruct, id: &str) -> Option<(u64, u64)> {
+    let (path, meta) = upload_meta(session, id).await?;
+    let offset = fs::metadata(&path).await.ok()?.len();
+    Some((offset, meta.size))
+}
+
+// кусок дописывается, только если клиент знает верное смещение; оборванный запрос
+// оставляет на диске все, что успело прийти, клиент спросит смещение и продолжит с него
+pub async fn resumable_append<S, B>(session: &SessionStruct, id: &str, offset: u64, body: S) -> Option<Response>
+    where
+        S: Stream<Item=Result<B, warp::Error>> + Send + 'static,
+        B: Buf, {
+    let (path, meta) = upload_meta(session, id).await?;
+    let _guard = match BusyGuard::lock(id) {
+        Some(guard) => guard,
+        None => {
+            let current = fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or_default();
+            return Some(offset_response(StatusCode::CONFLICT, current, None));
+        }
+    };
+    // размер -- только под блокировкой: до нее другой запрос еще мог дописывать файл
+    let current = fs::metadata(&path).await.ok()?.len();
+    if offset != current {
+        return Some(offset_response(StatusCode::CONFLICT, current, None));
+    }
+
+    let mut file = match fs::OpenOptions::new().append(true).open(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            tracing::error!("resumable_append[1] {:?}", err);
+            return Some(offset_response(StatusCode::INTERNAL_SERVER_ERROR, current, None));
+        }
+    };
+    let mut written = current;
+    let mut status = StatusCode::NO_CONTENT;
+    let mut body = Box::pin(body);
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                tracing::warn!("resumable_append: {err} idu={}", session.idu);
+                break;
+            }
+        };
+        let chunk = chunk.copy_to_bytes(chunk.remaining());
+        if written + chunk.len() as u64 > meta.size {
+            status = StatusCode::PAYLOAD_TOO_LARGE;
+            break;
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            tracing::error!("resumable_append[2] {:?}", err);
+            status = StatusCode::INTERNAL_SERVER_ERROR;
+            break;
+        }
+        written += chunk.len() as u64;
+    }
+    (file.flush().await).ok();
+    Some(offset_response(status, written, None))
+}
+
+pub async fn resumable_finish(session: &SessionStruct, id: &str, data: UploadFinishRequest) -> UploadFinishResult {
+    let failed = |error| UploadFinishResult { error: Some(error), ..UploadFinishResult::default() };
+    let (path, meta) = match upload_meta(session, id).await {
+        Some(found) => found,
+        None => return failed(UPLOAD_FAILED)
+    };
+    if !is_valid_key(&data.key) {
+        tracing::warn!("resumable_finish: invalid attachments key idu={}", session.idu);
+        return failed(UPLOAD_FAILED);
+    }
+    let _guard = match BusyGuard::lock(id) {
+        Some(guard) => guard,
+        None => return failed(UPLOAD_INCOMPLETE)
+    };
+    if fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or_default() != meta.size {
+        return failed(UPLOAD_INCOMPLETE);
+    }
+
+    let mut head = [0u8; 4];
+    let read = match fs::File::open(&path).await {
+        Ok(mut file) => file.read(&mut head).await.unwrap_or_default(),
+        Err(_) => 0
+    };
+    if is_executable(&head[..read]) {
+        resumable_remove(&path).await;
+        return failed(UPLOAD_BLOCKED);
+    }
+
+    let ind = match resumable_move(&session.idu, &path, &data).await {
+        Some(ind) => ind,
+        None => return failed(UPLOAD_FAILED)
+    };
+    (fs::remove_file(meta_path(&path)).await).ok();
+    thumb_save_file(&*LOCAL, &key_to_temp_with_ind(&session.idu, &data.key, &ind), &path_to_temp_with_ind(&session.idu, &data.key, &ind)).await;
+    let cid = inline_cid(data.inline, &meta.file_name);
+    let item = BoxMailAttachmentItem { id: ind, file_name: meta.file_name, size: meta.size, cid };
+    UploadFinishResult { result: true, error: None, item: Some(item) }
+}
+
+// первый свободный номер после известных редактору
+async fn resumable_move(idu: &i32, path: &str, data: &UploadFinishRequest) -> Option<usize> {
+    let _lock = UPLOADS_FINISH.lock().await;
+    let mut ind = data.after + 1;
+    while fs::metadata(path_to_temp_with_ind(idu, &data.key, &ind)).await.is_ok() {
+        ind += 1;
+    }
+    let target = path_to_temp_with_ind(idu, &data.key, &ind);
+    (fs::create_dir_all(get_dir_path(&target)).await).ok();
+    match fs::rename(path, &target).await {
+        Ok(_) => Some(ind),
+        Err(err) => {
+            tracing::error!("resumable_move[1] {:?}", err);
+            None
+        }
+    }
+}
+
+pub async fn resumable_cancel(session: &SessionStruct, id: &str) -> bool {
+    let (path, _) = match upload_meta(session, id).await {
+        Some(found) => found,
+        None => return false
+    };
+    let _guard = match BusyGuard::lock(id) {
+        Some(guard) => guard,
+        None => return false
+    };
+    resumable_remove(&path).await;
+    true
+}
+
+async fn resumable_remove(path: &str) {
+    (fs::remove_file(path).await).ok();
+    (fs::remove_file(meta_path(path)).await).ok();
+}
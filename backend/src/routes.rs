@@ -1,24 +1,37 @@
-use headers::{AcceptRanges, ContentLength, ContentType, HeaderMapExt};
+use bytes::Buf;
+use futures_util::Stream;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 use warp::{reject, Rejection, Reply, reply};
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, HeaderMap, HeaderValue, RETRY_AFTER, SET_COOKIE};
+use warp::http::StatusCode;
 use warp::hyper::Body;
-use warp::multipart::FormData;
-use warp::path::Tail;
 use warp::reply::Response;
 
 use shared::constants::TEST_USER_ID;
-use shared::types::{MessageRequest, MessagesRequest, NotesChannel};
+use shared::types::{MessageRequest, MessagesRequest, NotesChannel, PasswordRequest, SearchRequest, SessionsRequest, PgpRequest, SettingsRequest, SmimeRequest, TotpRequest, UploadCreateRequest, UploadFinishRequest};
 
-use crate::constants::{path_to_attachment_with_email_and_key, path_to_temp};
-use crate::db_boxes::{db_message_route, db_messages_route};
+use crate::constants::key_to_temp_with_ind;
+use crate::archive::{archive_body, archive_names, ArchiveFile};
+use crate::blobs::{attachment_object, source_read};
+use crate::cookie::{cookie_session, cookie_session_clear};
+use crate::download::{content_disposition, file_response};
+use crate::db_boxes::{db_box_attachments, db_box_source, db_message_route, db_messages_route};
+use crate::db_logins::{db_login_record, db_login_retry_after, db_logins_route, LOGIN_FAILURE, LOGIN_LOCKED, LOGIN_SUCCESS};
 use crate::db_notes::db_notes_route;
-use crate::db_types::DBNotes;
-use crate::db_user::{db_user_login, DBUserSelect};
-use crate::sse::sse_next_key;
-use crate::state::USER_AUTH;
-use crate::types::{DownloadStruct, SessionStruct};
+use crate::db_pgp::db_pgp_route;
+use crate::db_sessions::{db_session_by_cookie, db_session_create, db_sessions_route};
+use crate::db_search::db_search_route;
+use crate::db_smime::db_smime_route;
+use crate::db_totp::{db_totp_check, db_totp_route, TotpCheck};
+use crate::db_types::{DBMailAttachments, DBNotes};
+use crate::db_user::{db_password_route, db_settings_route, db_user_email, db_user_login, DBUserSelect};
+use crate::resumable::{offset_response, resumable_append, resumable_cancel, resumable_create, resumable_finish, resumable_offset};
+use crate::storage::{LOCAL, Storage};
+use crate::thumbs::{thumb_ensure, thumb_key};
+use crate::types::{DeviceInfo, DownloadStruct, SessionStruct, SourceStruct};
 use crate::upload::upload;
+use crate::utils::is_valid_key;
+use crate::Forbidden;
 
 #[derive(Serialize)]
 struct InitialStruct {
@@ -26,115 +39,312 @@ struct InitialStruct {
     user: DBUserSelect,
 }
 
-fn get_session(user_key: &str) -> SessionStruct {
-    match USER_AUTH.lock() {
-        Ok(user_auth) => match user_auth.get(user_key) {
-            Some(session) => session.clone(),
-            None => SessionStruct::default()
-        }
-        Err(_) => SessionStruct::default()
+pub async fn route_message(session: SessionStruct, msg: String) -> Result<impl Reply, Rejection> {
+    if let Ok(data) = serde_json::from_str::<MessageRequest>(&msg) {
+        db_message_route(&session, data).await;
     }
+    Ok(warp::reply())
 }
 
-pub async fn route_message(user_key: String, msg: String) -> Result<impl Reply, Rejection> {
-    let session = get_session(&user_key);
-    if session.idu > 0 {
-        if let Ok(data) = serde_json::from_str::<MessageRequest>(&msg) {
-            db_message_route(&session, data).await;
-        }
+pub async fn route_messages(session: SessionStruct, msg: String) -> Result<impl Reply, Rejection> {
+    if let Ok(data) = serde_json::from_str::<MessagesRequest>(&msg) {
+        db_messages_route(&session, data).await;
     }
     Ok(warp::reply())
 }
 
-pub async fn route_messages(user_key: String, msg: String) -> Result<impl Reply, Rejection> {
-    let session = get_session(&user_key);
-    if session.idu > 0 {
-        if let Ok(data) = serde_json::from_str::<MessagesRequest>(&msg) {
-            db_messages_route(&session, data).await;
-        }
+pub async fn route_notes_update(session: SessionStruct, msg: String) -> Result<impl Reply, Rejection> {
+    if let Ok(data) = serde_json::from_str::<NotesChannel>(&msg) {
+        db_notes_route(&session, data).await;
     }
     Ok(warp::reply())
 }
 
-pub async fn route_notes_update(user_key: String, msg: String) -> Result<impl Reply, Rejection> {
-    let session = get_session(&user_key);
-    if session.idu > 0 {
-        if let Ok(data) = serde_json::from_str::<NotesChannel>(&msg) {
-            db_notes_route(&session, data).await;
-        }
+pub async fn route_settings(session: SessionStruct, msg: String) -> Result<impl Reply, Rejection> {
+    if let Ok(data) = serde_json::from_str::<SettingsRequest>(&msg) {
+        db_settings_route(&session, data).await;
     }
     Ok(warp::reply())
 }
 
-pub async fn route_login(hash: String, source: Vec<String>) -> Result<impl Reply, Rejection> {
+pub async fn route_password(session: SessionStruct, data: PasswordRequest) -> Result<impl Reply, Rejection> {
+    let result = db_password_route(&session, data).await;
+    Ok(reply::json(&LoginResult { result, retry_after: None, totp: None }))
+}
+
+pub async fn route_sessions(session: SessionStruct, data: SessionsRequest) -> Result<Response, Rejection> {
+    let logout = data.logout.unwrap_or_default();
+    let result = db_sessions_route(&session, data).await;
+    let mut resp = reply::json(&result).into_response();
+    if logout {
+        if let Ok(value) = HeaderValue::from_str(&cookie_session_clear()) {
+            resp.headers_mut().insert(SET_COOKIE, value);
+        }
+    }
+    Ok(resp)
+}
+
+// source: [ящик] -- вход по cookie сеанса, [ящик, имя, пароль] -- вход по паролю,
+// [ящик, имя, пароль, код] -- второй шаг, если включена двухфакторная аутентификация
+pub async fn route_login(device: DeviceInfo, cookie: Option<String>, source: Vec<String>) -> Result<Response, Rejection> {
     let mut idu = 0;
+    let mut token = None;
 
     if source.len() == 1 {
-        let user_key = source[0].clone();
-        let session = get_session(&user_key);
-        idu = session.idu;
-        if idu > 0 {
-            if let Ok(mut user_auth) = USER_AUTH.lock() {
-                user_auth.remove(&user_key);
+        let mail_box = mail_box_decode(&source[0]);
+        if let Some(session) = db_session_by_cookie(&cookie, &device).await {
+            if db_user_email(&session.idu).await.as_deref() == Some(mail_box.as_str()) {
+                idu = session.idu;
             }
-        } else if TEST_USER_ID > 0 {
+        }
+        if idu == 0 && TEST_USER_ID > 0 {
             idu = TEST_USER_ID;
+            token = db_session_create(&idu, &device).await.map(|(_, token)| token);
+        }
+    } else if source.len() == 3 || source.len() == 4 {
+        let mail_box = mail_box_decode(&source[0]);
+        // при блокировке пароль не проверяем
+        let retry_after = db_login_retry_after(&mail_box, &device).await;
+        if retry_after > 0 {
+            db_login_record(&mail_box, &device, LOGIN_LOCKED).await;
+            let mut resp = reply::with_status(
+                reply::json(&LoginResult { result: false, retry_after: Some(retry_after), totp: None }),
+                StatusCode::TOO_MANY_REQUESTS,
+            ).into_response();
+            resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            return Ok(resp);
         }
-    } else if source.len() == 3 {
-        let mail_box = source[0]
-            .split('!')
-            .map(|t| t.split('+').collect::<Vec<_>>().join("."))
-            .collect::<Vec<_>>().join("@");
         let user_name = source[1].clone();
         let user_pass = source[2].clone();
-        idu = db_user_login(mail_box, user_name, user_pass).await;
+        idu = db_user_login(mail_box.clone(), user_name, user_pass).await;
+        if idu > 0 {
+            match db_totp_check(&idu, source.get(3)).await {
+                TotpCheck::Disabled | TotpCheck::Passed => {}
+                // пароль верный, нужен код -- попытку не записываем, клиент повторит вход с кодом
+                TotpCheck::Required => return Ok(reply::json(&LoginResult { result: false, retry_after: None, totp: Some(true) }).into_response()),
+                TotpCheck::Failed => idu = 0
+            }
+        }
+        db_login_record(&mail_box, &device, if idu > 0 { LOGIN_SUCCESS } else { LOGIN_FAILURE }).await;
+        if idu > 0 {
+            token = db_session_create(&idu, &device).await.map(|(_, token)| token);
+        }
     }
-    if idu > 0 {
-        if let Ok(mut user_auth) = USER_AUTH.lock() {
-            user_auth.insert(hash, SessionStruct::new(&idu));
+
+    let mut resp = reply::json(&LoginResult { result: idu > 0, retry_after: None, totp: None }).into_response();
+    if let Some(token) = token {
+        if let Ok(value) = HeaderValue::from_str(&cookie_session(&token)) {
+            resp.headers_mut().insert(SET_COOKIE, value);
         }
     }
+    Ok(resp)
+}
 
-    Ok(reply::json(&LoginResult { result: idu > 0 }))
+// ящик в адресе страницы закодирован: "." -> "+", "@" -> "!"
+fn mail_box_decode(source: &str) -> String {
+    source
+        .split('!')
+        .map(|t| t.split('+').collect::<Vec<_>>().join("."))
+        .collect::<Vec<_>>().join("@")
 }
 
+// retry_after -- секунды до снятия блокировки входа, totp -- нужен код из приложения
 #[derive(Serialize, Deserialize)]
 struct LoginResult {
     result: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    totp: Option<bool>,
+}
+
+pub async fn route_totp(session: SessionStruct, data: TotpRequest) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&db_totp_route(&session, data).await))
+}
+
+pub async fn route_smime(session: SessionStruct, data: SmimeRequest) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&db_smime_route(&session, data).await))
+}
+
+pub async fn route_pgp(session: SessionStruct, data: PgpRequest) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&db_pgp_route(&session, data).await))
+}
+
+pub async fn route_search(session: SessionStruct, data: SearchRequest) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&db_search_route(&session, data).await))
+}
+
+pub async fn route_logins(session: SessionStruct) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&db_logins_route(&session).await))
+}
+
+pub async fn files_handler<S, B>(session: SessionStruct, content_type: String, body: S) -> Result<impl Reply, Rejection>
+    where
+        S: Stream<Item=Result<B, warp::Error>> + Send + 'static,
+        B: Buf, {
+    Ok(reply::json(&upload(&session, &content_type, body).await))
+}
+
+pub async fn route_upload_create(session: SessionStruct, data: UploadCreateRequest) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&resumable_create(&session, data).await))
+}
+
+pub async fn route_upload_offset(id: String, session: SessionStruct) -> Result<Response, Rejection> {
+    let (offset, size) = resumable_offset(&session, &id).await.ok_or_else(reject::not_found)?;
+    Ok(offset_response(StatusCode::OK, offset, Some(size)))
 }
 
-pub async fn files_handler(user_key: String, form: FormData) -> Result<impl Reply, Rejection> {
-    let session = get_session(&user_key);
-    if session.idu > 0 {
-        upload(&session, form).await;
-        sse_next_key(&session);
+pub async fn route_upload_append<S, B>(id: String, session: SessionStruct, offset: u64, body: S) -> Result<Response, Rejection>
+    where
+        S: Stream<Item=Result<B, warp::Error>> + Send + 'static,
+        B: Buf, {
+    resumable_append(&session, &id, offset, body).await.ok_or_else(reject::not_found)
+}
+
+pub async fn route_upload_finish(id: String, session: SessionStruct, data: UploadFinishRequest) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&resumable_finish(&session, &id, data).await))
+}
+
+pub async fn route_upload_cancel(id: String, session: SessionStruct) -> Result<impl Reply, Rejection> {
+    if resumable_cancel(&session, &id).await {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(reject::not_found())
     }
-    Ok(warp::reply())
 }
 
 pub async fn file_handler(
-    tail: Tail,
+    idb: i64,
+    id: usize,
+    q: DownloadStruct,
+    session: SessionStruct,
+    headers: HeaderMap,
+) -> Result<Response, Rejection> {
+    let (email, attachments) = message_attachments(idb, &session).await?;
+    let item = attachments.list.iter().find(|item| item.id == id).ok_or_else(reject::not_found)?;
+    let (storage, key) = attachment_object(&email, &attachments.key, item);
+    file_response(storage, &key, &item.file_name, q.inline.unwrap_or_default() == 1, &headers).await
+}
+
+// миниатюра картинки из вложений
+pub async fn thumb_handler(
+    idb: i64,
+    id: usize,
+    session: SessionStruct,
+    headers: HeaderMap,
+) -> Result<Response, Rejection> {
+    let (email, attachments) = message_attachments(idb, &session).await?;
+    let item = attachments.list.iter().find(|item| item.id == id).ok_or_else(reject::not_found)?;
+    let (storage, key) = attachment_object(&email, &attachments.key, item);
+    thumb_response(storage, &key, &item.file_name, &headers).await
+}
+
+// миниатюры нет и построить ее нельзя (webp, большой файл) -- отдаем саму картинку
+async fn thumb_response(storage: &dyn Storage, key: &str, file_name: &str, headers: &HeaderMap) -> Result<Response, Rejection> {
+    if thumb_ensure(storage, key).await {
+        file_response(storage, &thumb_key(key), "thumb.jpg", true, headers).await
+    } else {
+        file_response(storage, key, file_name, true, headers).await
+    }
+}
+
+// все вложения письма одним zip-архивом, кроме картинок из текста
+pub async fn zip_handler(idb: i64, session: SessionStruct) -> Result<Response, Rejection> {
+    let (email, attachments) = message_attachments(idb, &session).await?;
+    let list = attachments.list.iter().filter(|item| item.cid.is_none()).collect::<Vec<_>>();
+    if list.is_empty() {
+        return Err(reject::not_found());
+    }
+    let names = archive_names(&list.iter().map(|item| item.file_name.clone()).collect::<Vec<_>>());
+    let files = list.iter().zip(names)
+        .map(|(item, name)| {
+            let (storage, key) = attachment_object(&email, &attachments.key, item);
+            ArchiveFile { storage, key, name }
+        })
+        .collect::<Vec<_>>();
+    // пропавшее вложение -- ошибка до начала ответа, а не молча неполный архив
+    for file in files.iter() {
+        if let Err(err) = file.storage.head(&file.key).await {
+            tracing::error!("zip_handler: {} {err}", file.key);
+            return Err(reject::not_found());
+        }
+    }
+
+    let mut resp = Response::new(archive_body(files));
+    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    if let Some(value) = content_disposition(false, &format!("attachments-{idb}.zip")) {
+        resp.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+    Ok(resp)
+}
+
+// вложения письма отдаются только его владельцу
+async fn message_attachments(idb: i64, session: &SessionStruct) -> Result<(String, DBMailAttachments), Rejection> {
+    let row = db_box_attachments(&idb).await.ok_or_else(reject::not_found)?;
+    if row.idu != session.idu {
+        tracing::warn!("message_attachments: idu={} requested idb={idb}", session.idu);
+        return Err(reject::custom(Forbidden));
+    }
+    let attachments = row.attachments.ok_or_else(reject::not_found)?;
+    if !is_valid_key(&attachments.key) {
+        return Err(reject::custom(Forbidden));
+    }
+    let email = db_user_email(&session.idu).await.ok_or_else(reject::not_found)?;
+    Ok((email, attachments))
+}
+
+// вложения черновика: только из директории пользователя
+pub async fn temp_file_handler(
+    key: String,
+    id: usize,
+    q: DownloadStruct,
+    session: SessionStruct,
+    headers: HeaderMap,
+) -> Result<Response, Rejection> {
+    if !is_valid_key(&key) {
+        return Err(reject::custom(Forbidden));
+    }
+    let file_name = q.filename.clone().unwrap_or_default();
+    file_response(&*LOCAL, &key_to_temp_with_ind(&session.idu, &key, &id), &file_name, q.inline.unwrap_or_default() == 1, &headers).await
+}
+
+pub async fn temp_thumb_handler(
+    key: String,
+    id: usize,
     q: DownloadStruct,
+    session: SessionStruct,
+    headers: HeaderMap,
 ) -> Result<Response, Rejection> {
-    let session = get_session(&q.user);
-    if session.idu > 0 {
-        let is_temp = q.temp.unwrap_or_default() == 1;
-        let filename = match is_temp {
-            true => path_to_temp(tail.as_str()),
-            false => path_to_attachment_with_email_and_key(&q.email, tail.as_str())
-        };
-        sse_next_key(&session);
-
-        if let Ok(meta) = fs::metadata(&filename).await {
-            if let Ok(body) = fs::read(&filename).await {
+    if !is_valid_key(&key) {
+        return Err(reject::custom(Forbidden));
+    }
+    let file_name = q.filename.clone().unwrap_or_default();
+    thumb_response(&*LOCAL, &key_to_temp_with_ind(&session.idu, &key, &id), &file_name, &headers).await
+}
+
+pub async fn source_handler(
+    idb: i64,
+    q: SourceStruct,
+    session: SessionStruct,
+) -> Result<Response, Rejection> {
+    if let Some(source) = db_box_source(&session.idu, &idb).await {
+        if let Some(email) = db_user_email(&session.idu).await {
+            if let Ok(body) = source_read(&email, &source).await {
+                // просмотр в браузере только для текста в UTF-8, иначе -- как файл письма
+                let is_download = q.download.unwrap_or_default() == 1 || std::str::from_utf8(&body).is_err();
                 let mut resp = Response::new(Body::from(body));
-                resp.headers_mut().typed_insert(ContentLength(meta.len()));
-                resp.headers_mut().typed_insert(ContentType::octet_stream());
-                resp.headers_mut().typed_insert(AcceptRanges::bytes());
+                if is_download {
+                    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("message/rfc822"));
+                    if let Some(value) = content_disposition(false, &format!("message-{idb}.eml")) {
+                        resp.headers_mut().insert(CONTENT_DISPOSITION, value);
+                    }
+                } else {
+                    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+                }
                 return Ok(resp);
             }
         }
     }
 
     Err(reject())
-}
\ No newline at end of file
+}
@@ -0,0 +1,199 @@
+use chrono::NaiveDate;
+
+use shared::types::MailBoxes;
+use shared::utils::box_type_index;
+
+use crate::crypt::crypt_enabled;
+use crate::db_types::DBMailAddress;
+use crate::html_text::html_to_text;
+
+// tsvector не больше 1 МБ: длинные письма индексируются по началу
+const SEARCH_TEXT_MAX: usize = 100 * 1024;
+
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    pub words: Vec<String>,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub has_attachment: bool,
+    pub before: Option<NaiveDate>,
+    pub after: Option<NaiveDate>,
+    pub email_box: Option<usize>,
+}
+
+impl SearchQuery {
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.from.is_empty() && self.to.is_empty() && !self.has_attachment
+            && self.before.is_none() && self.after.is_none() && self.email_box.is_none()
+    }
+}
+
+// слова -- для websearch_to_tsquery как есть ("фраза", -слово, or); фильтр с неверным значением считаем словом
+pub fn search_parse(query: &str) -> SearchQuery {
+    let mut result = SearchQuery::default();
+    for token in search_tokens(query) {
+        let filtered = match token.split_once(':') {
+            Some((key, value)) => search_filter(&mut result, &key.to_lowercase(), value.trim_matches('"')),
+            None => false
+        };
+        if !filtered {
+            result.words.push(token);
+        }
+    }
+    result
+}
+
+// пробелы в кавычках не разделяют: from:"Иван Петров"
+fn search_tokens(query: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn search_filter(result: &mut SearchQuery, key: &str, value: &str) -> bool {
+    if value.is_empty() {
+        return false;
+    }
+    match key {
+        "from" => result.from.push(value.to_string()),
+        "to" => result.to.push(value.to_string()),
+        "has" if value.eq_ignore_ascii_case("attachment") => result.has_attachment = true,
+        "before" => match search_date(value) {
+            Some(date) => result.before = Some(date),
+            None => return false
+        },
+        "after" => match search_date(value) {
+            Some(date) => result.after = Some(date),
+            None => return false
+        },
+        "in" => match search_box(value) {
+            Some(email_box) => result.email_box = Some(email_box),
+            None => return false
+        },
+        _ => return false
+    }
+    true
+}
+
+fn search_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%d.%m.%Y"))
+        .ok()
+}
+
+fn search_box(value: &str) -> Option<usize> {
+    let mb = match value.to_lowercase().as_str() {
+        "inbox" | "входящие" => MailBoxes::Inbox,
+        "read" | "ready" | "прочтенные" => MailBoxes::Ready,
+        "sent" | "отправленные" => MailBoxes::Sent,
+        "trash" | "spam" | "корзина" => MailBoxes::Trash,
+        _ => return None
+    };
+    Some(box_type_index(&mb))
+}
+
+// в индекс: тема и адреса с большим весом, текст письма без разметки
+// при шифровании тема и текст в индекс не попадают: из tsvector они читаются открытым текстом
+pub fn search_document(sender: &DBMailAddress, recipient: &DBMailAddress, subject: &str, content: &str) -> (String, String) {
+    let addresses = [
+        sender.name.as_deref().unwrap_or_default(),
+        &sender.address,
+        recipient.name.as_deref().unwrap_or_default(),
+        &recipient.address,
+    ].join(" ");
+    if crypt_enabled() {
+        return (addresses, String::new());
+    }
+    let text = html_to_text(content).chars().take(SEARCH_TEXT_MAX).collect();
+    (format!("{subject} {addresses}"), text)
+}
+
+// значение столбца search; head и text -- номера параметров запроса с документом
+pub fn search_vector(head: usize, text: usize) -> String {
+    format!(
+        "setweight(to_tsvector('russian', ${head}::text) || to_tsvector('english', ${head}::text), 'A') || to_tsvector('russian', ${text}::text) || to_tsvector('english', ${text}::text)"
+    )
+}
+
+// для ilike: % и _ из запроса ищутся как есть
+pub fn search_like(value: &str) -> String {
+    format!("%{}%", value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        assert_eq!(search_tokens("  отчет   from:\"Иван Петров\" \"точная фраза\" "), vec!["отчет", "from:\"Иван Петров\"", "\"точная фраза\""]);
+        assert_eq!(search_tokens("to:\"без закрывающей кавычки"), vec!["to:\"без закрывающей кавычки"]);
+        assert!(search_tokens("   ").is_empty());
+    }
+
+    #[test]
+    fn parse() {
+        let query = search_parse("отчет From:\"Иван Петров\" to:bob@example.com has:attachment \"точная фраза\" -черновик");
+        assert_eq!(query.words, vec!["отчет", "\"точная фраза\"", "-черновик"]);
+        assert_eq!(query.from, vec!["Иван Петров"]);
+        assert_eq!(query.to, vec!["bob@example.com"]);
+        assert!(query.has_attachment);
+        assert!(query.email_box.is_none());
+
+        let query = search_parse("after:2024-01-31 before:01.03.2024");
+        assert_eq!(query.after, NaiveDate::from_ymd_opt(2024, 1, 31));
+        assert_eq!(query.before, NaiveDate::from_ymd_opt(2024, 3, 1));
+        assert!(query.words.is_empty());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // фильтр с неверным значением остается словом
+        let query = search_parse("before:2024-13-01 after:вчера from: has:link in:архив");
+        assert_eq!(query.words, vec!["before:2024-13-01", "after:вчера", "from:", "has:link", "in:архив"]);
+        assert!(query.before.is_none() && query.after.is_none() && query.from.is_empty() && !query.has_attachment);
+        assert!(query.email_box.is_none());
+        assert!(!query.is_empty());
+        assert!(search_parse("").is_empty());
+    }
+
+    #[test]
+    fn parse_box() {
+        let cases = [
+            ("in:inbox", MailBoxes::Inbox),
+            ("in:Входящие", MailBoxes::Inbox),
+            ("in:read", MailBoxes::Ready),
+            ("in:ready", MailBoxes::Ready),
+            ("in:прочтенные", MailBoxes::Ready),
+            ("in:sent", MailBoxes::Sent),
+            ("in:отправленные", MailBoxes::Sent),
+            ("in:trash", MailBoxes::Trash),
+            ("in:spam", MailBoxes::Trash),
+            ("IN:корзина", MailBoxes::Trash),
+        ];
+        for (query, mb) in cases {
+            assert_eq!(search_parse(query).email_box, Some(box_type_index(&mb)), "{query}");
+        }
+    }
+
+    #[test]
+    fn like() {
+        assert_eq!(search_like("50%_a\\b"), "%50\\%\\_a\\\\b%");
+    }
+}
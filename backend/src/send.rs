@@ -1,37 +1,89 @@
 use std::fs;
 
 use lettre::{Message, SendmailTransport, Transport};
-use lettre::message::{header, MultiPart, SinglePart};
-use lol_html::{comments, element, HtmlRewriter, Settings};
-use lol_html::html_content::ContentType;
+use lettre::message::{Attachment, Body, header, MultiPart, SinglePart};
+use lol_html::{element, HtmlRewriter, Settings};
 
 use shared::types::BoxMailAttachments;
 
 use crate::constants::path_to_temp_with_ind;
+use crate::dkim::dkim_sign;
+use crate::html_text::html_to_text;
+use crate::pgp::{Cert, Key};
+use crate::pgp_mime::{pgp_encrypt, pgp_sign};
+use crate::smime::{smime_sign, SmimeSigner};
 
-pub async fn send_message(sender: &str, recipient: &str, subject: &str, message: &str, attachments: &Option<BoxMailAttachments>) -> bool {
-    let mut result = false;
+// пересылаемое письмо целиком, вкладывается как message/rfc822
+pub struct MessageForwarded {
+    pub file_name: String,
+    pub source: Vec<u8>,
+}
+
+// отправитель: адрес, адрес для ответа, сертификат для подписи S/MIME,
+// свой ключ для подписи OpenPGP и ключи, которыми письмо шифруется
+pub struct MessageSender<'a> {
+    pub mailbox: &'a str,
+    pub reply_to: &'a Option<String>,
+    pub smime: Option<&'a SmimeSigner>,
+    pub pgp_sign: Option<&'a Cert>,
+    pub pgp_encrypt: Option<&'a [&'a Key]>,
+}
+
+// вложения черновика лежат во временной директории его владельца
+pub struct DraftAttachments<'a> {
+    pub idu: &'a i32,
+    pub attachments: &'a Option<BoxMailAttachments>,
+}
+
+// при успешной отправке возвращает исходный текст письма (RFC 5322)
+pub async fn send_message(sender: MessageSender<'_>, recipient: &str, subject: &str, message: &str, draft: DraftAttachments<'_>, forwarded: &Option<MessageForwarded>) -> Option<Vec<u8>> {
+    let mut result = None;
+    let MessageSender { mailbox: sender, reply_to, smime, pgp_sign: pgp_signer, pgp_encrypt: pgp_recipients } = sender;
+    let DraftAttachments { idu, attachments } = draft;
 
-    let text = to_text(message);
+    let text = html_to_text(message);
+
+    let html_part = SinglePart::builder()
+        .header(header::ContentType::TEXT_HTML)
+        .body(message.to_string());
+
+    // картинки из текста письма -- в multipart/related рядом с html
+    let inline = attachments.as_ref()
+        .map(|attachments| attachments.list.iter()
+            .filter(|item| item.cid.as_ref().map(|cid| message.contains(&format!("cid:{cid}"))).unwrap_or_default())
+            .map(|item| (attachments.key.clone(), item))
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let text_part = SinglePart::builder()
+        .header(header::ContentType::parse("text/plain; charset=utf-8; format=flowed").unwrap_or(header::ContentType::TEXT_PLAIN))
+        .body(text);
+
+    let mut multipart = if inline.is_empty() {
+        MultiPart::alternative().singlepart(text_part).singlepart(html_part)
+    } else {
+        let mut related = MultiPart::related().singlepart(html_part);
+        for (key, item) in inline.iter() {
+            let mime = mime_guess::from_path(&item.file_name).first_or_octet_stream();
+            if let Ok(content_type) = header::ContentType::parse(mime.as_ref()) {
+                if let Ok(f) = fs::read(path_to_temp_with_ind(idu, key, &item.id)) {
+                    related = related.singlepart(Attachment::new_inline(item.cid.clone().unwrap_or_default()).body(f, content_type));
+                }
+            }
+        }
+        MultiPart::alternative().singlepart(text_part).multipart(related)
+    };
 
-    let mut multipart = MultiPart::alternative()
-        .singlepart(
-            SinglePart::builder()
-                .header(header::ContentType::TEXT_PLAIN)
-                .body(text),
-        )
-        .singlepart(
-            SinglePart::builder()
-                .header(header::ContentType::TEXT_HTML)
-                .body(message.to_string()),
-        );
+    let with_attachments = attachments.as_ref().map(|attachments| attachments.list.iter().any(|item| item.cid.is_none())).unwrap_or_default();
+    if with_attachments || forwarded.is_some() {
+        multipart = MultiPart::mixed().multipart(multipart);
+    }
 
     if let Some(attachments) = attachments {
-        if !attachments.list.is_empty() {
+        if with_attachments {
             let key = attachments.key.clone();
-            multipart = MultiPart::mixed().multipart(multipart);
-            for item in attachments.list.iter() {
-                let path_to_file = path_to_temp_with_ind(&key, &item.id);
+            for item in attachments.list.iter().filter(|item| item.cid.is_none()) {
+                let path_to_file = path_to_temp_with_ind(idu, &key, &item.id);
                 let mime = mime_guess::from_path(&item.file_name).first_or_octet_stream();
                 if let Ok(content_type) = header::ContentType::parse(mime.as_ref()) {
                     if let Ok(f) = fs::read(path_to_file) {
@@ -48,20 +100,81 @@ pub async fn send_message(sender: &str, recipient: &str, subject: &str, message:
         }
     }
 
+    if let Some(forwarded) = forwarded {
+        if let Ok(content_type) = header::ContentType::parse("message/rfc822") {
+            // для message/rfc822 кодирование содержимого не допускается (RFC 2046): 7bit, 8bit или binary
+            let body = Body::new_with_encoding(forwarded.source.clone(), header::ContentTransferEncoding::SevenBit)
+                .or_else(|source| Body::new_with_encoding(source, header::ContentTransferEncoding::EightBit))
+                .or_else(|source| Body::new_with_encoding(source, header::ContentTransferEncoding::Binary));
+            let body = match body {
+                Ok(body) => body,
+                Err(_) => {
+                    tracing::error!("send_message: forwarded message encoding");
+                    return None;
+                }
+            };
+            multipart = multipart.singlepart(
+                SinglePart::builder()
+                    .header(content_type)
+                    .header(header::ContentDisposition::attachment(&forwarded.file_name))
+                    .body(body)
+            );
+        }
+    }
+
+    if let Some(signer) = smime {
+        multipart = match smime_sign(signer, multipart) {
+            Ok(signed) => signed,
+            Err(err) => {
+                // неподписанным письмо не уходит: пользователь просил подпись
+                tracing::error!("send_message smime {err}");
+                return None;
+            }
+        };
+    }
+
+    // сначала подпись, затем шифрование подписанного (RFC 3156, 6.1)
+    if let Some(cert) = pgp_signer {
+        multipart = match pgp_sign(cert, multipart) {
+            Ok(signed) => signed,
+            Err(err) => {
+                tracing::error!("send_message pgp_sign {err}");
+                return None;
+            }
+        };
+    }
+    if let Some(recipients) = pgp_recipients {
+        multipart = match pgp_encrypt(recipients, multipart) {
+            Ok(encrypted) => encrypted,
+            Err(err) => {
+                // открытым письмо не уходит: пользователь просил шифрование
+                tracing::error!("send_message pgp_encrypt {err}");
+                return None;
+            }
+        };
+    }
+
     if let Ok(sender) = sender.parse() {
         if let Ok(recipient) = recipient.parse() {
-            let message = Message::builder()
+            let mut builder = Message::builder()
                 .from(sender)
                 .to(recipient)
-                .subject(subject)
-                .multipart(multipart);
+                .subject(subject);
+            if let Some(reply_to) = reply_to {
+                match reply_to.parse() {
+                    Ok(reply_to) => builder = builder.reply_to(reply_to),
+                    Err(err) => tracing::warn!("send_message reply_to {reply_to}: {:?}", err)
+                }
+            }
+            let message = builder.multipart(multipart);
 
             match message {
                 Ok(email) => {
+                    let email = dkim_sign(email);
                     let mailer = SendmailTransport::new();
                     match mailer.send(&email) {
                         Ok(_) => {
-                            result = true;
+                            result = Some(email.formatted());
                         }
                         Err(err) => {
                             tracing::error!("Could not send email: {:?}", err);
@@ -78,42 +191,29 @@ pub async fn send_message(sender: &str, recipient: &str, subject: &str, message:
     result
 }
 
-fn to_text(html: &str) -> String {
-    let mut output = vec![];
+// картинки с data-cid ссылаются на свою часть письма: src="cid:..."
+// картинки без вложения (например, из цитаты) убираем
+pub fn inline_images(html: &str, attachments: &Option<BoxMailAttachments>) -> String {
+    let cids = attachments.as_ref()
+        .map(|attachments| attachments.list.iter().filter_map(|item| item.cid.clone()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if cids.is_empty() && !html.contains("data-cid") {
+        return html.to_string();
+    }
 
+    let mut output = vec![];
     let mut rewriter = HtmlRewriter::new(
         Settings {
             element_content_handlers: vec![
-                element!("a", |el| {
-                    el.remove_and_keep_content();
-                    if let Some(href) = el.get_attribute("href") {
-                        el.after(&format!(" [{href}]"), ContentType::Text);
+                element!("img[data-cid]", |el| {
+                    match el.get_attribute("data-cid") {
+                        Some(cid) if cids.contains(&cid) => {
+                            el.set_attribute("src", &format!("cid:{cid}"))?;
+                        }
+                        _ => el.remove()
                     }
                     Ok(())
                 }),
-                element!("li", |el| {
-                    el.before("* ", ContentType::Text);
-                    el.after("\n", ContentType::Text);
-                    el.remove_and_keep_content();
-                    Ok(())
-                }),
-                element!("script,style,meta,base,link,title", |el| {
-                    el.remove();
-                    Ok(())
-                }),
-                element!("p,h1,h2,h3,h4,h5,h6,div,blockquote,pre,br", |el| {
-                    el.after("\n", ContentType::Text);
-                    el.remove_and_keep_content();
-                    Ok(())
-                }),
-                element!("*", |el| {
-                    el.remove_and_keep_content();
-                    Ok(())
-                }),
-                comments!("*", |c| {
-                    c.remove();
-                    Ok(())
-                }),
             ],
             ..Settings::default()
         },
@@ -121,18 +221,13 @@ fn to_text(html: &str) -> String {
     );
 
     if let Err(err) = rewriter.write(html.as_bytes()) {
-        tracing::error!("to_text[1] {:?}", err);
+        tracing::error!("inline_images[1] {:?}", err);
+        return html.to_string();
     }
     if let Err(err) = rewriter.end() {
-        tracing::error!("to_text[2] {:?}", err);
+        tracing::error!("inline_images[2] {:?}", err);
+        return html.to_string();
     }
 
-    let text = String::from_utf8(output).unwrap_or_default();
-
-    text.replace("&gt;", ">")
-        .split('\n')
-        .into_iter()
-        .filter(|t| !t.trim().is_empty())
-        .collect::<Vec<_>>()
-        .join("\n")
-}
\ No newline at end of file
+    String::from_utf8(output).unwrap_or_else(|_| html.to_string())
+}
@@ -0,0 +1,376 @@
+use std::fs;
+
+use lettre::message::{header, MultiPart, SinglePart};
+use mail_parser::{Message, MessagePart, MimeHeaders};
+use once_cell::sync::Lazy;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509Ref};
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use serde::Deserialize;
+
+const ENV_PARAMS: &str = include_str!("../../env.json");
+
+// конверты внутри конвертов: обычно зашифрованное, внутри подписанное
+const UNWRAP_DEPTH: usize = 3;
+
+#[derive(Deserialize, Default)]
+struct EnvParams {
+    #[serde(default)]
+    smime: SmimeParams,
+}
+
+// "smime": {"trust_store": "/etc/ssl/certs/ca-certificates.crt"}; без настройки -- системные корневые сертификаты
+#[derive(Deserialize, Default)]
+struct SmimeParams {
+    #[serde(default)]
+    trust_store: Option<String>,
+}
+
+static TRUST: Lazy<Option<X509Store>> = Lazy::new(|| {
+    let params = match serde_json::from_str::<EnvParams>(ENV_PARAMS) {
+        Ok(params) => params,
+        Err(err) => {
+            tracing::error!("smime[1] {err}");
+            EnvParams::default()
+        }
+    };
+    match trust_store(params.smime.trust_store) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            // подписи все равно проверяются, но ни одна не будет доверенной
+            tracing::error!("smime[2] trust store: {err}");
+            None
+        }
+    }
+});
+
+// файл PEM, сертификатов может быть несколько подряд
+fn trust_store(path: Option<String>) -> Result<X509Store, String> {
+    let mut builder = X509StoreBuilder::new().map_err(|err| err.to_string())?;
+    match path {
+        Some(path) => {
+            let pem = fs::read(&path).map_err(|err| format!("{path}: {err}"))?;
+            for cert in X509::stack_from_pem(&pem).map_err(|err| format!("{path}: {err}"))? {
+                builder.add_cert(cert).map_err(|err| err.to_string())?;
+            }
+        }
+        None => builder.set_default_paths().map_err(|err| err.to_string())?
+    }
+    Ok(builder.build())
+}
+
+// сертификат пользователя с закрытым ключом: подпись исходящих и расшифровка входящих
+pub struct SmimeSigner {
+    pub cert: X509,
+    pub key: PKey<Private>,
+    pub chain: Stack<X509>,
+}
+
+impl SmimeSigner {
+    // в базе: сертификат, за ним цепочка -- одним PEM; ключ отдельно
+    pub fn from_pem(cert: &str, key: &str) -> Result<Self, String> {
+        let mut certs = X509::stack_from_pem(cert.as_bytes()).map_err(|err| err.to_string())?.into_iter();
+        let cert = certs.next().ok_or("no certificate")?;
+        let mut chain = Stack::new().map_err(|err| err.to_string())?;
+        for item in certs {
+            chain.push(item).map_err(|err| err.to_string())?;
+        }
+        let key = PKey::private_key_from_pem(key.as_bytes()).map_err(|err| err.to_string())?;
+        Ok(Self { cert, key, chain })
+    }
+
+    pub fn to_pem(&self) -> Result<(String, String), String> {
+        let mut cert = self.cert.to_pem().map_err(|err| err.to_string())?;
+        for item in self.chain.iter() {
+            cert.extend(item.to_pem().map_err(|err| err.to_string())?);
+        }
+        let key = self.key.private_key_to_pem_pkcs8().map_err(|err| err.to_string())?;
+        Ok((String::from_utf8_lossy(&cert).to_string(), String::from_utf8_lossy(&key).to_string()))
+    }
+}
+
+pub fn pkcs12_import(der: &[u8], password: &str) -> Result<SmimeSigner, String> {
+    let parsed = Pkcs12::from_der(der)
+        .and_then(|pkcs12| pkcs12.parse(password))
+        .map_err(|_| "invalid file or password")?;
+    let matches = parsed.cert.public_key().map(|key| key.public_eq(&parsed.pkey)).unwrap_or_default();
+    if !matches {
+        return Err("key does not match certificate".to_string());
+    }
+    let chain = match parsed.chain {
+        Some(chain) => chain,
+        None => Stack::new().map_err(|err| err.to_string())?
+    };
+    Ok(SmimeSigner { cert: parsed.cert, key: parsed.pkey, chain })
+}
+
+pub fn cert_email(cert: &X509Ref) -> Option<String> {
+    cert.subject_alt_names()
+        .and_then(|names| names.iter().find_map(|name| name.email().map(str::to_string)))
+        .or_else(|| cert_entry(cert, Nid::PKCS9_EMAILADDRESS))
+}
+
+// все адреса сертификата, в нижнем регистре
+fn cert_emails(cert: &X509Ref) -> Vec<String> {
+    let mut result = cert.subject_alt_names()
+        .map(|names| names.iter().filter_map(|name| name.email().map(str::to_lowercase)).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if let Some(email) = cert_entry(cert, Nid::PKCS9_EMAILADDRESS) {
+        result.push(email.to_lowercase());
+    }
+    result
+}
+
+pub fn cert_subject(cert: &X509Ref) -> String {
+    cert_entry(cert, Nid::COMMONNAME).or_else(|| cert_email(cert)).unwrap_or_default()
+}
+
+pub fn cert_expires(cert: &X509Ref) -> String {
+    cert.not_after().to_string()
+}
+
+fn cert_entry(cert: &X509Ref, nid: Nid) -> Option<String> {
+    cert.subject_name()
+        .entries_by_nid(nid)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|data| data.to_string())
+}
+
+// multipart/signed (RFC 8551): подписываются ровно те байты, которые lettre запишет в письмо,
+// без последнего CRLF -- он принадлежит следующей границе
+pub fn smime_sign(signer: &SmimeSigner, content: MultiPart) -> Result<MultiPart, String> {
+    let formatted = content.formatted();
+    let signed = formatted.strip_suffix(b"\r\n").unwrap_or(&formatted);
+    let signature = Pkcs7::sign(&signer.cert, &signer.key, &signer.chain, signed, Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY)
+        .and_then(|pkcs7| pkcs7.to_der())
+        .map_err(|err| err.to_string())?;
+    let content_type = header::ContentType::parse("application/pkcs7-signature; name=smime.p7s").map_err(|err| err.to_string())?;
+    Ok(MultiPart::signed("application/pkcs7-signature".to_string(), "sha-256".to_string())
+        .multipart(content)
+        .singlepart(
+            SinglePart::builder()
+                .header(content_type)
+                .header(header::ContentDisposition::attachment("smime.p7s"))
+                .header(header::ContentTransferEncoding::Base64)
+                .body(signature)
+        ))
+}
+
+// отметка для просмотра: smime-valid:адрес, smime-untrusted:адрес (подпись цела, цепочка не доверенная),
+// smime-mismatch:адрес (сертификат выдан не на адрес отправителя), smime-invalid
+// второе значение -- подписанное содержимое, если оно внутри подписи
+fn smime_verify(pkcs7: &Pkcs7, content: Option<&[u8]>, from: &str) -> (String, Option<Vec<u8>>) {
+    let (certs, empty) = match (Stack::<X509>::new(), X509StoreBuilder::new()) {
+        (Ok(certs), Ok(empty)) => (certs, empty.build()),
+        _ => return ("smime-invalid".to_string(), None)
+    };
+    let mut out = vec![];
+    if pkcs7.verify(&certs, &empty, content, Some(&mut out), Pkcs7Flags::NOVERIFY).is_err() {
+        return ("smime-invalid".to_string(), None);
+    }
+    let signer = pkcs7.signers(&certs, Pkcs7Flags::empty()).ok()
+        .and_then(|signers| signers.iter().next().map(|cert| (cert_email(cert).unwrap_or_default(), cert_emails(cert))));
+    let (signer, emails) = signer.unwrap_or_default();
+    if !emails.contains(&from.to_lowercase()) {
+        return (format!("smime-mismatch:{signer}"), Some(out));
+    }
+    let trusted = TRUST.as_ref()
+        .map(|store| pkcs7.verify(&certs, store, content, None, Pkcs7Flags::empty()).is_ok())
+        .unwrap_or_default();
+    (format!("{}:{signer}", if trusted { "smime-valid" } else { "smime-untrusted" }), Some(out))
+}
+
+pub fn is_smime_signed(protocol: &str) -> bool {
+    protocol == "application/pkcs7-signature" || protocol == "application/x-pkcs7-signature"
+}
+
+// multipart/signed с отдельной подписью: openssl сам разбирает часть письма целиком
+pub fn smime_verify_part(message: &Message<'_>, part: &MessagePart<'_>, from: &str) -> String {
+    let entity = match message.raw_message.get(part.offset_header..part.offset_end) {
+        Some(entity) => entity,
+        None => return "smime-invalid".to_string()
+    };
+    match Pkcs7::from_smime(entity) {
+        Ok((pkcs7, Some(content))) => smime_verify(&pkcs7, Some(&content), from).0,
+        _ => "smime-invalid".to_string()
+    }
+}
+
+fn pkcs7_mime_type(part: &MessagePart<'_>) -> Option<String> {
+    let content_type = part.get_content_type()?;
+    let subtype = content_type.get_subtype().unwrap_or_default().to_lowercase();
+    if !content_type.get_type().eq_ignore_ascii_case("application") || (subtype != "pkcs7-mime" && subtype != "x-pkcs7-mime") {
+        return None;
+    }
+    Some(content_type.get_attribute("smime-type").unwrap_or_default().to_lowercase())
+}
+
+pub fn smime_wrapped(message: &Message<'_>) -> bool {
+    message.parts.first().and_then(pkcs7_mime_type).is_some()
+}
+
+// письмо целиком в application/pkcs7-mime: зашифрованное (нужен ключ получателя) или подписанное с содержимым внутри
+// возвращает письмо для разбора -- заголовки исходного с раскрытым содержимым -- и отметки для просмотра
+pub fn smime_unwrap(message: &Message<'_>, signer: Option<&SmimeSigner>, from: &str) -> Option<(Vec<u8>, Vec<String>)> {
+    let mut entity = message.raw_message.to_vec();
+    let mut security = vec![];
+    let mut opened = false;
+    for _ in 0..UNWRAP_DEPTH {
+        let kind = match Message::parse(&entity).and_then(|parsed| parsed.parts.first().and_then(pkcs7_mime_type)) {
+            Some(kind) => kind,
+            None => break
+        };
+        let pkcs7 = match Pkcs7::from_smime(&entity) {
+            Ok((pkcs7, _)) => pkcs7,
+            Err(err) => {
+                tracing::warn!("smime_unwrap: {err}");
+                security.push("smime-invalid".to_string());
+                break;
+            }
+        };
+        let decrypted = match (kind.as_str(), signer) {
+            ("signed-data", _) | (_, None) => None,
+            (_, Some(signer)) => pkcs7.decrypt(&signer.key, &signer.cert, Pkcs7Flags::empty()).ok()
+        };
+        if let Some(content) = decrypted {
+            security.push("smime-encrypted".to_string());
+            entity = content;
+            opened = true;
+            continue;
+        }
+        if kind == "enveloped-data" {
+            security.push("smime-undecryptable".to_string());
+            break;
+        }
+        // без smime-type: не расшифровалось -- пробуем как подписанное
+        match smime_verify(&pkcs7, None, from) {
+            (status, Some(content)) => {
+                security.push(status);
+                entity = content;
+                opened = true;
+            }
+            (status, None) => {
+                security.push(if kind.is_empty() { "smime-undecryptable".to_string() } else { status });
+                break;
+            }
+        }
+    }
+    if security.is_empty() {
+        return None;
+    }
+    let raw = if opened {
+        [outer_headers(&message.raw_message), entity].concat()
+    } else {
+        message.raw_message.to_vec()
+    };
+    Some((raw, security))
+}
+
+// заголовки письма без Content-*: их место займут заголовки раскрытого содержимого
+pub fn outer_headers(raw: &[u8]) -> Vec<u8> {
+    let mut headers = vec![];
+    let mut skip = false;
+    for line in raw.split_inclusive(|b| *b == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            skip = line.len() >= 8 && line[..8].eq_ignore_ascii_case(b"content-");
+        }
+        if !skip {
+            headers.extend_from_slice(line);
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use lettre::Message as Letter;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::symm::Cipher;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    use super::*;
+
+    // самоподписанный сертификат: подпись цела, цепочка не доверенная
+    fn signer(email: &str) -> SmimeSigner {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "Alice").unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let san = SubjectAlternativeName::new().email(email).build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        SmimeSigner { cert: builder.build(), key, chain: Stack::new().unwrap() }
+    }
+
+    fn signed_content(signer: &SmimeSigner) -> MultiPart {
+        smime_sign(signer, MultiPart::mixed().singlepart(SinglePart::plain("Привет, Боб!".to_string()))).unwrap()
+    }
+
+    fn signed_letter(signer: &SmimeSigner) -> Vec<u8> {
+        Letter::builder()
+            .from("alice@example.com".parse().unwrap())
+            .to("bob@example.com".parse().unwrap())
+            .subject("test")
+            .multipart(signed_content(signer))
+            .unwrap()
+            .formatted()
+    }
+
+    #[test]
+    fn sign_verify() {
+        let signer = signer("Alice@example.com");
+        let raw = signed_letter(&signer);
+        let message = Message::parse(&raw).unwrap();
+        let part = message.parts.first().unwrap();
+        assert_eq!(smime_verify_part(&message, part, "alice@example.com"), "smime-untrusted:Alice@example.com");
+        assert_eq!(smime_verify_part(&message, part, "mallory@example.com"), "smime-mismatch:Alice@example.com");
+
+        // содержимое изменено после подписи
+        let tampered = String::from_utf8(raw).unwrap().replacen("text/plain", "text/html", 1);
+        let message = Message::parse(tampered.as_bytes()).unwrap();
+        assert_eq!(smime_verify_part(&message, message.parts.first().unwrap(), "alice@example.com"), "smime-invalid");
+    }
+
+    #[test]
+    fn encrypt_unwrap() {
+        let signer = signer("alice@example.com");
+        // подписанное содержимое с заголовком Content-Type шифруется целиком
+        let body = signed_content(&signer).formatted();
+        let mut recipients = Stack::new().unwrap();
+        recipients.push(signer.cert.clone()).unwrap();
+        let pkcs7 = Pkcs7::encrypt(&recipients, &body, Cipher::aes_256_cbc(), Pkcs7Flags::BINARY).unwrap();
+        let wrapped = [
+            b"From: alice@example.com\r\nTo: bob@example.com\r\nSubject: test\r\n".as_slice(),
+            &pkcs7.to_smime(&body, Pkcs7Flags::BINARY).unwrap(),
+        ].concat();
+        let message = Message::parse(&wrapped).unwrap();
+        assert!(smime_wrapped(&message));
+        assert_eq!(smime_unwrap(&message, None, "alice@example.com").unwrap().1, vec!["smime-undecryptable"]);
+
+        let (raw, security) = smime_unwrap(&message, Some(&signer), "alice@example.com").unwrap();
+        assert_eq!(security, vec!["smime-encrypted"]);
+        let opened = Message::parse(&raw).unwrap();
+        assert_eq!(opened.get_subject(), Some("test"));
+        assert_eq!(smime_verify_part(&opened, opened.parts.first().unwrap(), "alice@example.com"), "smime-untrusted:alice@example.com");
+    }
+}
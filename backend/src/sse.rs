@@ -9,19 +9,17 @@ use once_cell::sync::Lazy;
 use serde::Serialize;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use uuid::Uuid;
 use warp::sse::Event;
 
-use shared::constants::{CHANNEL_INIT, CHANNEL_MESSAGE, CHANNEL_MESSAGES, CHANNEL_NOTES, CHANNEL_USER_KEY};
+use shared::constants::{CHANNEL_INIT, CHANNEL_MESSAGE, CHANNEL_MESSAGES, CHANNEL_NOTES, CHANNEL_ID, CHANNEL_SETTINGS};
 use shared::types::MessagesRequest;
 
 use crate::db_boxes::db_messages_route;
 use crate::db_notes::db_notes_select;
 use crate::db_types::DBNotes;
-use crate::db_user::{db_user_select, DBUserSelect};
+use crate::db_user::{db_identities, db_user_select, DBIdentity, DBUserSelect};
 use crate::state::USER_AUTH;
 use crate::types::SessionStruct;
-use crate::utils::get_hash;
 
 static NEXT_CHANNEL_ID: AtomicUsize = AtomicUsize::new(1);
 
@@ -32,18 +30,21 @@ pub enum Message {
     Messages(String),
     Message(String),
     Init(String),
-    User(String),
+    Channel(String),
+    Settings(String),
 }
 
 pub struct Client {
     pub idu: i32,
+    pub sid: i32,
     pub sender: mpsc::UnboundedSender<Message>,
 }
 
 impl Client {
-    pub fn new(idu: i32, sender: mpsc::UnboundedSender<Message>) -> Self {
+    pub fn new(idu: i32, sid: i32, sender: mpsc::UnboundedSender<Message>) -> Self {
         Self {
             idu,
+            sid,
             sender,
         }
     }
@@ -55,16 +56,18 @@ static USERS_SSE: Lazy<UsersSse> = Lazy::new(|| {
     UsersSse::default()
 });
 
-pub fn user_sse_connected(idu: i32) -> impl Stream<Item=Result<Event, warp::Error>> + Send + 'static {
+pub fn user_sse_connected(session: SessionStruct) -> impl Stream<Item=Result<Event, warp::Error>> + Send + 'static {
     let channel_id = NEXT_CHANNEL_ID.fetch_add(1, Ordering::Relaxed);
 
     let (tx, rx) = mpsc::unbounded_channel();
     let rx = UnboundedReceiverStream::new(rx);
 
     tx.send(Message::Reply("".to_string())).unwrap();
-    USERS_SSE.lock().unwrap().insert(channel_id, Client::new(idu, tx));
+    // номер канала вкладка передает в заголовке запросов, чтобы ответы приходили только ей
+    tx.send(Message::Channel(channel_id.to_string())).unwrap();
+    USERS_SSE.lock().unwrap().insert(channel_id, Client::new(session.idu, session.sid, tx));
 
-    init_data(SessionStruct::with_key(&idu, &channel_id, ""));
+    init_data(SessionStruct { channel_id, ..session });
 
     rx.map(|msg| match msg {
         Message::Notes(reply) => {
@@ -79,8 +82,11 @@ pub fn user_sse_connected(idu: i32) -> impl Stream<Item=Result<Event, warp::Erro
         Message::Init(reply) => {
             Ok(Event::default().event(CHANNEL_INIT).data(reply))
         }
-        Message::User(reply) => {
-            Ok(Event::default().event(CHANNEL_USER_KEY).data(reply))
+        Message::Channel(reply) => {
+            Ok(Event::default().event(CHANNEL_ID).data(reply))
+        }
+        Message::Settings(reply) => {
+            Ok(Event::default().event(CHANNEL_SETTINGS).data(reply))
         }
         Message::Reply(reply) => {
             Ok(Event::default().data(reply))
@@ -105,6 +111,46 @@ pub fn sse_cleaner() {
     }
 }
 
+// закрываем каналы завершенных сеансов
+pub fn sse_close_sessions(idu: &i32, filter: impl Fn(i32) -> bool) {
+    match USERS_SSE.lock() {
+        Ok(mut sse) => {
+            sse.retain(|_uid, client| client.idu != *idu || client.sid == 0 || !filter(client.sid));
+        }
+        Err(err) => tracing::error!("sse_close_sessions: {:?}", err)
+    }
+}
+
+// каналы сеансов любых пользователей, например удаленных из базы не этим процессом
+pub fn sse_close_sids(filter: impl Fn(i32) -> bool) {
+    match USERS_SSE.lock() {
+        Ok(mut sse) => {
+            sse.retain(|_uid, client| client.sid == 0 || !filter(client.sid));
+        }
+        Err(err) => tracing::error!("sse_close_sids: {:?}", err)
+    }
+}
+
+pub fn sse_channel_owner(channel_id: &usize, session: &SessionStruct) -> bool {
+    match USERS_SSE.lock() {
+        Ok(sse) => sse.get(channel_id).map(|client| client.idu == session.idu && client.sid == session.sid).unwrap_or_default(),
+        Err(_) => false
+    }
+}
+
+// сеансы с открытыми каналами
+pub fn sse_active_sessions() -> Vec<i32> {
+    match USERS_SSE.lock() {
+        Ok(sse) => {
+            let mut active = sse.values().map(|client| client.sid).collect::<Vec<_>>();
+            active.sort_unstable();
+            active.dedup();
+            active
+        }
+        Err(_) => vec![]
+    }
+}
+
 pub fn sse_channel(session: &SessionStruct, msg: Message) {
     let send_to = session.idu;
     match USERS_SSE.lock() {
@@ -119,27 +165,10 @@ pub fn sse_channel(session: &SessionStruct, msg: Message) {
         }
         Err(err) => tracing::error!("sse_channel: {:?}", err)
     }
-
-    if session.channel_id > 0 {
-        sse_next_key(session);
-    }
-}
-
-pub fn sse_next_key(session: &SessionStruct) {
-    let key = get_hash(format!("{}-{}", session.idu, Uuid::new_v4()));
-    if let Ok(mut user_auth) = USER_AUTH.lock() {
-        if sse_personal(session, Message::User(key.clone())) {
-            user_auth.insert(key.clone(), SessionStruct::with_key(&session.idu, &session.channel_id, &key));
-            user_auth.remove(&session.current);
-        } else {
-            tracing::warn!("key not delivered");
-        }
-    }
 }
 
 pub fn sse_personal_channel(session: &SessionStruct, msg: Message) {
     sse_personal(session, msg);
-    sse_next_key(session);
 }
 
 fn sse_personal(session: &SessionStruct, msg: Message) -> bool {
@@ -174,6 +203,7 @@ fn sse_personal(session: &SessionStruct, msg: Message) -> bool {
 struct InitialStruct {
     notes: Vec<DBNotes>,
     user: DBUserSelect,
+    identities: Vec<DBIdentity>,
 }
 
 fn init_data(session: SessionStruct) {
@@ -182,11 +212,11 @@ fn init_data(session: SessionStruct) {
         let data = InitialStruct {
             notes: db_notes_select(&session.idu).await,
             user: db_user_select(&session.idu).await,
+            identities: db_identities(&session.idu).await,
         };
         match serde_json::to_string(&data) {
             Ok(text) => {
                 sse_personal(&session, Message::Init(text));
-                sse_next_key(&session);
             }
             Err(err) => {
                 tracing::error!("serde_json[init_data] {:?}", err);
@@ -4,9 +4,10 @@ use std::sync::{Arc, Mutex};
 use once_cell::sync::Lazy;
 
 use crate::db_user::DBUserInit;
-use crate::types::SessionStruct;
+use crate::types::SessionCached;
 
-pub static USER_AUTH: Lazy<Arc<Mutex<HashMap<String, SessionStruct>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+// сеансы по хешу токена из cookie
+pub static USER_AUTH: Lazy<Arc<Mutex<HashMap<String, SessionCached>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
 pub static USER_BY_EMAIL: Lazy<Arc<Mutex<HashMap<String, i32>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
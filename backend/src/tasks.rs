@@ -1,6 +1,8 @@
 use std::process::Command;
 
 use crate::constants::path_to_temp_upload;
+use crate::db_logins::db_logins_cleanup;
+use crate::db_sessions::db_sessions_cleanup;
 use crate::sse::sse_cleaner;
 
 pub async fn run_tasks() {
@@ -11,6 +13,12 @@ pub async fn run_tasks() {
             // закрываем отвалившиеся соединения
             sse_cleaner();
 
+            // продлеваем подключенные сеансы и удаляем просроченные
+            db_sessions_cleanup().await;
+
+            // журнал входов храним 90 дней
+            db_logins_cleanup().await;
+
             // удаляем файлы из временной директории, которым более суток
             let temp_dir = path_to_temp_upload("");
             Command::new("sh")
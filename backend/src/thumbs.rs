@@ -0,0 +1,220 @@
+use std::io::Cursor;
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat, Rgb, RgbImage};
+use image::io::{Limits, Reader};
+use tokio::fs;
+
+use crate::storage::Storage;
+
+// миниатюра вписывается в квадрат; хранится рядом с файлом под ключом с суффиксом
+pub const THUMB_SIZE: u32 = 320;
+const THUMB_SUFFIX: &str = ".thumb";
+const THUMB_QUALITY: u8 = 80;
+
+// большие файлы и картинки огромного размера не разбираем: это не стоит памяти сервера
+const SOURCE_MAX: u64 = 20 * 1024 * 1024;
+const SIDE_MAX: u32 = 10_000;
+const ALLOC_MAX: u64 = 256 * 1024 * 1024;
+
+pub fn thumb_key(key: &str) -> String {
+    format!("{key}{THUMB_SUFFIX}")
+}
+
+// ключ файла, к которому относится миниатюра
+pub fn thumb_source(key: &str) -> Option<&str> {
+    key.strip_suffix(THUMB_SUFFIX)
+}
+
+// png, jpeg и gif (первый кадр) -- в jpeg; прозрачное -- на белом фоне
+pub fn thumb_make(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = Reader::new(Cursor::new(data)).with_guessed_format().ok()?;
+    if !matches!(reader.format(), Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif)) {
+        return None;
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(SIDE_MAX);
+    limits.max_image_height = Some(SIDE_MAX);
+    limits.max_alloc = Some(ALLOC_MAX);
+    reader.limits(limits);
+    let image = match reader.decode() {
+        Ok(image) => image,
+        Err(err) => {
+            tracing::warn!("thumb_make: {err}");
+            return None;
+        }
+    };
+    let image = if image.width() > THUMB_SIZE || image.height() > THUMB_SIZE {
+        image.thumbnail(THUMB_SIZE, THUMB_SIZE)
+    } else {
+        image
+    };
+    let rgba = image.to_rgba8();
+    let rgb = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    });
+    let mut result = Cursor::new(Vec::new());
+    match DynamicImage::ImageRgb8(rgb).write_to(&mut result, ImageOutputFormat::Jpeg(THUMB_QUALITY)) {
+        Ok(_) => Some(result.into_inner()),
+        Err(err) => {
+            tracing::error!("thumb_make: {err}");
+            None
+        }
+    }
+}
+
+// миниатюра для файла key из его содержимого; не картинка -- ничего не пишется, уже построенная -- не строится снова
+pub async fn thumb_save(storage: &dyn Storage, key: &str, data: Bytes) -> bool {
+    if data.len() as u64 > SOURCE_MAX || image::guess_format(&data).is_err() {
+        return false;
+    }
+    if storage.head(&thumb_key(key)).await.is_ok() {
+        return true;
+    }
+    let thumb = match tokio::task::spawn_blocking(move || thumb_make(&data)).await {
+        Ok(Some(thumb)) => thumb,
+        Ok(None) => return false,
+        Err(err) => {
+            tracing::error!("thumb_save: {err}");
+            return false;
+        }
+    };
+    match storage.put_bytes(&thumb_key(key), Bytes::from(thumb)).await {
+        Ok(_) => true,
+        Err(err) => {
+            tracing::error!("thumb_save: {key} {err}");
+            false
+        }
+    }
+}
+
+// файл черновика на локальном диске
+pub async fn thumb_save_file(storage: &dyn Storage, key: &str, path: &str) -> bool {
+    match fs::metadata(path).await {
+        Ok(meta) if meta.len() <= SOURCE_MAX => {}
+        _ => return false
+    }
+    match fs::read(path).await {
+        Ok(data) => thumb_save(storage, key, Bytes::from(data)).await,
+        Err(err) => {
+            tracing::error!("thumb_save_file: {path} {err}");
+            false
+        }
+    }
+}
+
+// файл черновика уходит в хранилище: его миниатюра -- вместе с ним, локальная копия удаляется
+pub async fn thumb_move_file(storage: &dyn Storage, key: &str, path: &str) {
+    let source = thumb_key(path);
+    if fs::metadata(&source).await.is_err() {
+        return;
+    }
+    if storage.head(&thumb_key(key)).await.is_err() {
+        if let Err(err) = storage.put(&thumb_key(key), &source).await {
+            tracing::error!("thumb_move_file: {key} {err}");
+        }
+    }
+    (fs::remove_file(&source).await).ok();
+}
+
+// для файлов, записанных до появления миниатюр: строится при первом запросе
+pub async fn thumb_ensure(storage: &dyn Storage, key: &str) -> bool {
+    if storage.head(&thumb_key(key)).await.is_ok() {
+        return true;
+    }
+    match storage.head(key).await {
+        Ok(meta) if meta.size <= SOURCE_MAX => {}
+        _ => return false
+    }
+    let mut stream = match storage.get(key, None).await {
+        Ok(stream) => stream,
+        Err(_) => return false
+    };
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => data.extend_from_slice(&chunk),
+            Err(err) => {
+                tracing::error!("thumb_ensure: {key} {err}");
+                return false;
+            }
+        }
+    }
+    thumb_save(storage, key, Bytes::from(data)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgba};
+
+    use super::*;
+
+    fn encoded(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let image = ImageBuffer::from_fn(width, height, |x, y| Rgba([(x % 256) as u8, (y % 256) as u8, 128, if x < width / 2 { 0 } else { 255 }]));
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image).write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    fn decoded(data: &[u8]) -> DynamicImage {
+        assert_eq!(image::guess_format(data).unwrap(), ImageFormat::Jpeg);
+        image::load_from_memory(data).unwrap()
+    }
+
+    #[test]
+    fn png_scaled_down() {
+        let thumb = decoded(&thumb_make(&encoded(1000, 500, ImageOutputFormat::Png)).unwrap());
+        assert_eq!((thumb.width(), thumb.height()), (THUMB_SIZE, THUMB_SIZE / 2));
+        // прозрачная половина -- на белом фоне
+        let pixel = thumb.to_rgb8().get_pixel(5, 5).0;
+        assert!(pixel.iter().all(|c| *c > 240), "{pixel:?}");
+    }
+
+    #[test]
+    fn jpeg_and_gif() {
+        let thumb = decoded(&thumb_make(&encoded(400, 1600, ImageOutputFormat::Jpeg(90))).unwrap());
+        assert_eq!((thumb.width(), thumb.height()), (THUMB_SIZE / 4, THUMB_SIZE));
+        let thumb = decoded(&thumb_make(&encoded(640, 640, ImageOutputFormat::Gif)).unwrap());
+        assert_eq!((thumb.width(), thumb.height()), (THUMB_SIZE, THUMB_SIZE));
+    }
+
+    #[test]
+    fn small_image_kept() {
+        let thumb = decoded(&thumb_make(&encoded(40, 30, ImageOutputFormat::Png)).unwrap());
+        assert_eq!((thumb.width(), thumb.height()), (40, 30));
+    }
+
+    #[test]
+    fn not_an_image() {
+        assert!(thumb_make(b"%PDF-1.4\n").is_none());
+        assert!(thumb_make(b"").is_none());
+        // заголовок png без данных
+        assert!(thumb_make(&encoded(100, 100, ImageOutputFormat::Png)[..60]).is_none());
+    }
+
+    #[tokio::test]
+    async fn saved_next_to_file() {
+        let root = std::env::temp_dir().join(format!("thumbs-test-{}", uuid::Uuid::new_v4())).to_string_lossy().to_string();
+        let storage = crate::storage::LocalStorage::new(&root);
+        let image = Bytes::from(encoded(600, 600, ImageOutputFormat::Png));
+        storage.put_bytes("blobs/aa/image", image.clone()).await.unwrap();
+        storage.put_bytes("blobs/aa/text", Bytes::from_static(b"plain text")).await.unwrap();
+
+        assert!(thumb_ensure(&storage, "blobs/aa/image").await);
+        assert!(storage.head("blobs/aa/image.thumb").await.is_ok());
+        assert!(!thumb_ensure(&storage, "blobs/aa/text").await);
+        assert!(storage.head("blobs/aa/text.thumb").await.is_err());
+        assert!(!thumb_ensure(&storage, "blobs/aa/missing").await);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(thumb_key("blobs/ab/abc"), "blobs/ab/abc.thumb");
+        assert_eq!(thumb_source("blobs/ab/abc.thumb"), Some("blobs/ab/abc"));
+        assert_eq!(thumb_source("blobs/ab/abc"), None);
+    }
+}
@@ -0,0 +1,100 @@
+use hmac::{Hmac, Mac};
+use qrcode::QrCode;
+use qrcode::render::svg;
+use rand_core::{OsRng, RngCore};
+use sha1::Sha1;
+
+use crate::password::password_generate;
+
+// RFC 6238: HMAC-SHA1, 6 цифр, шаг 30 секунд, допускаем расхождение часов на один шаг
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: i64 = 30;
+const TOTP_SKEW: i64 = 1;
+const TOTP_ISSUER: &str = "rs-app-mail";
+
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODES: usize = 10;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+pub fn totp_secret_generate() -> String {
+    let mut bytes = [0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    base32::encode(BASE32, &bytes)
+}
+
+pub fn totp_uri(secret: &str, email: &str) -> String {
+    let label = format!("{TOTP_ISSUER}:{email}").replace('@', "%40");
+    format!("otpauth://totp/{label}?secret={secret}&issuer={TOTP_ISSUER}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_PERIOD}")
+}
+
+pub fn totp_qr_svg(uri: &str) -> String {
+    match QrCode::new(uri.as_bytes()) {
+        Ok(code) => code.render::<svg::Color>().min_dimensions(200, 200).build(),
+        Err(err) => {
+            tracing::error!("totp_qr_svg {:?}", err);
+            "".to_string()
+        }
+    }
+}
+
+// номер шага, которым подтвержден код; шаги не новее last_step отклоняем, чтобы код нельзя было повторить
+pub fn totp_verify(secret: &str, code: &str, last_step: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let key = base32::decode(BASE32, secret)?;
+    let step = chrono::Utc::now().timestamp() / TOTP_PERIOD;
+    (step - TOTP_SKEW..=step + TOTP_SKEW)
+        .filter(|step| *step > last_step)
+        .find(|step| totp_code(&key, *step).as_deref() == Some(code))
+}
+
+fn totp_code(key: &[u8], step: i64) -> Option<String> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).ok()?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    Some(format!("{:0width$}", value % 10u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize))
+}
+
+// одноразовые коды восстановления показываются пользователю один раз
+pub fn recovery_codes_generate() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| password_generate().chars().take(10).collect::<String>().to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238, приложение B: ключ SHA1 "12345678901234567890", там 8 цифр -- сравниваем последние 6
+    #[test]
+    fn rfc6238_vectors() {
+        let key = b"12345678901234567890";
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(totp_code(key, time / TOTP_PERIOD).as_deref(), Some(&code[2..]), "T={time}");
+        }
+    }
+
+    #[test]
+    fn verify_rejects_used_step() {
+        let secret = base32::encode(BASE32, b"12345678901234567890");
+        let step = chrono::Utc::now().timestamp() / TOTP_PERIOD;
+        let code = totp_code(b"12345678901234567890", step).unwrap();
+        assert_eq!(totp_verify(&secret, &code, 0), Some(step));
+        assert_eq!(totp_verify(&secret, &code, step), None);
+        assert_eq!(totp_verify(&secret, "12345", 0), None);
+    }
+}
@@ -1,35 +1,49 @@
+use std::time::SystemTime;
+
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
+// filename -- только для вложений черновика, у сохраненных имя берется из письма
 pub struct DownloadStruct {
-    pub filename: String,
-    pub email: String,
-    pub user: String,
-    pub temp: Option<usize>,
+    pub filename: Option<String>,
+    pub inline: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SourceStruct {
+    pub download: Option<usize>,
 }
 
+// sid -- сеанс в emails.sessions, channel_id -- канал sse этой вкладки (0 -- не указан)
 #[derive(Clone, Default, Debug)]
 pub struct SessionStruct {
     pub idu: i32,
+    pub sid: i32,
     pub channel_id: usize,
-    pub current: String,
 }
 
 impl SessionStruct {
     pub fn new(idu: &i32) -> Self {
         Self {
             idu: *idu,
+            sid: 0,
             channel_id: 0,
-            current: "".to_string(),
-        }
-    }
-    pub fn with_key(idu: &i32, channel_id: &usize, current: &str) -> Self {
-        Self {
-            idu: *idu,
-            channel_id: *channel_id,
-            current: current.to_string(),
         }
     }
 }
 
+// сеанс в кеше: сроки проверяются при каждом запросе, с базой сверяется раз в SESSION_RECHECK_SECS
+#[derive(Clone, Debug)]
+pub struct SessionCached {
+    pub session: SessionStruct,
+    pub created: SystemTime,
+    pub checked: SystemTime,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct DeviceInfo {
+    pub user_agent: String,
+    pub ip: String,
+}
+
 
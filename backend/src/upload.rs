@@ -1,79 +1,227 @@
 use bytes::Buf;
-use futures_util::TryStreamExt;
+use futures_util::Stream;
+use serde::Serialize;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
-use warp::multipart::{FormData, Part};
 
+use shared::constants::UPLOAD_FILE_MAX;
 use shared::types::{BoxMailAttachmentItem, BoxMailAttachments, MessageRequest};
 
-use crate::constants::{path_to_temp_upload, path_to_temp_with_ind};
+use crate::constants::{key_to_temp_with_ind, path_to_temp_upload, path_to_temp_with_ind};
+use crate::multipart::Multipart;
 use crate::sse::{Message, sse_personal_channel};
+use crate::storage::LOCAL;
+use crate::thumbs::thumb_save_file;
 use crate::types::SessionStruct;
+use crate::utils::{get_dir_path, is_valid_key};
 
-pub async fn upload(session: &SessionStruct, form: FormData) {
-    let parts: Result<Vec<Part>, ()> = form.try_collect().await.map_err(|e| {
-        tracing::warn!("files_handler: {}", e);
-    });
-    if let Ok(parts) = parts {
-        let mut files: Vec<(String, String)> = vec![];
-        let mut current = "".to_string();
-        for mut p in parts {
-            match p.name() {
-                "current" => {
-                    current = part_as_string(&mut p).await;
+pub const UPLOAD_TOO_LARGE: &str = "size";
+pub const UPLOAD_BLOCKED: &str = "type";
+pub const UPLOAD_FAILED: &str = "failed";
+
+const FIELD_MAX: usize = 1024 * 1024;
+
+// исполняемые файлы почтовые серверы получателей все равно отклоняют
+const BLOCKED_EXTENSIONS: [&str; 12] = ["exe", "com", "scr", "pif", "bat", "cmd", "msi", "vbs", "js", "jar", "ps1", "lnk"];
+const BLOCKED_TYPES: [&str; 4] = ["application/x-msdownload", "application/x-msdos-program", "application/x-dosexec", "application/x-executable"];
+
+#[derive(Debug, Serialize)]
+pub struct UploadFileResult {
+    file_name: String,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct UploadResult {
+    result: bool,
+    files: Vec<UploadFileResult>,
+}
+
+#[derive(Default)]
+struct UploadForm {
+    current: String,
+    inline: bool,
+    // принятые файлы: временное имя и номер в результатах
+    saved: Vec<(String, usize)>,
+    files: Vec<UploadFileResult>,
+}
+
+pub async fn upload<S, B>(session: &SessionStruct, content_type: &str, body: S) -> UploadResult
+    where
+        S: Stream<Item=Result<B, warp::Error>> + Send + 'static,
+        B: Buf, {
+    let boundary = content_type.parse::<mime_guess::Mime>().ok()
+        .and_then(|mime| mime.get_param("boundary").map(|boundary| boundary.to_string()));
+    let boundary = match boundary {
+        Some(boundary) => boundary,
+        None => {
+            tracing::warn!("upload: no boundary idu={}", session.idu);
+            return UploadResult::default();
+        }
+    };
+
+    let mut form = UploadForm::default();
+    if let Err(err) = read_form(&mut Multipart::new(body, &boundary), &mut form).await {
+        tracing::warn!("upload: {err} idu={}", session.idu);
+        remove_saved(&form.saved).await;
+        return UploadResult::default();
+    }
+    if form.saved.is_empty() {
+        return UploadResult { result: true, files: form.files };
+    }
+
+    let attachments = serde_json::from_str::<BoxMailAttachments>(&form.current).ok().filter(|attachments| is_valid_key(&attachments.key));
+    let attachments = match attachments {
+        Some(attachments) => attachments,
+        None => {
+            tracing::warn!("upload: invalid attachments key idu={}", session.idu);
+            remove_saved(&form.saved).await;
+            return UploadResult::default();
+        }
+    };
+
+    let key = attachments.key.clone();
+    let mut list = attachments.list.clone();
+    let mut ind = if let Some(v) = list.iter().map(|r| r.id).max() {
+        v
+    } else { 0 };
+    ind += 1;
+    (fs::create_dir_all(get_dir_path(&path_to_temp_with_ind(&session.idu, &key, &ind))).await).ok();
+    for (file_name_temp, pos) in form.saved.iter() {
+        let item = &mut form.files[*pos];
+        let target = path_to_temp_with_ind(&session.idu, &key, &ind);
+        if (fs::rename(&path_to_temp_upload(file_name_temp), &target).await).is_ok() {
+            thumb_save_file(&*LOCAL, &key_to_temp_with_ind(&session.idu, &key, &ind), &target).await;
+            let cid = inline_cid(form.inline, &item.file_name);
+            list.push(BoxMailAttachmentItem { file_name: item.file_name.clone(), id: ind, size: item.size, cid });
+            ind += 1;
+        } else {
+            tracing::error!("upload[1] rename failed idu={}", session.idu);
+            (fs::remove_file(&path_to_temp_upload(file_name_temp)).await).ok();
+            item.error = Some(UPLOAD_FAILED);
+        }
+    }
+    let attachments = Some(BoxMailAttachments { key, list });
+    let data = MessageRequest { idb: 0, attachments, ..MessageRequest::default() };
+    if let Ok(text) = serde_json::to_string(&data) {
+        sse_personal_channel(session, Message::Message(text));
+    }
+    UploadResult { result: true, files: form.files }
+}
+
+async fn read_form(multipart: &mut Multipart, form: &mut UploadForm) -> Result<(), String> {
+    while let Some(part) = multipart.next_part().await? {
+        match part.name.as_str() {
+            "current" => {
+                form.current = multipart.read_text(FIELD_MAX).await?;
+            }
+            "inline" => {
+                form.inline = multipart.read_text(FIELD_MAX).await? == "1";
+            }
+            "files" => {
+                let file_name = part.file_name.unwrap_or_default();
+                if file_name.is_empty() {
+                    continue;
                 }
-                "files" => {
-                    let file_name_temp = part_as_file(&mut p).await;
-                    let file_name = p.filename().unwrap_or_default();
-                    if !file_name_temp.is_empty() && !file_name.is_empty() {
-                        files.push((file_name_temp, file_name.to_string()));
+                let error = if is_blocked(&file_name, part.content_type.as_deref()) {
+                    Some(UPLOAD_BLOCKED)
+                } else { None };
+                let mut item = UploadFileResult { file_name, size: 0, error };
+                if item.error.is_none() {
+                    let file_name_temp = Uuid::new_v4().to_string();
+                    match part_as_file(multipart, &path_to_temp_upload(&file_name_temp)).await {
+                        Ok(Ok(size)) => {
+                            item.size = size;
+                            form.saved.push((file_name_temp, form.files.len()));
+                        }
+                        Ok(Err(error)) => item.error = Some(error),
+                        Err(err) => {
+                            (fs::remove_file(&path_to_temp_upload(&file_name_temp)).await).ok();
+                            return Err(err);
+                        }
                     }
                 }
-                _ => {}
+                form.files.push(item);
             }
+            _ => {}
         }
-        if !files.is_empty() {
-            if let Ok(attachments) = serde_json::from_str::<BoxMailAttachments>(&current) {
-                let key = attachments.key.clone();
-                let mut list = attachments.list.clone();
-                let mut ind = if let Some(v) = list.iter().map(|r| r.id).max() {
-                    v
-                } else { 0 };
-                ind += 1;
-                for (file_name_temp, file_name) in files.iter() {
-                    if let Ok(metadata) = fs::metadata(&path_to_temp_upload(file_name_temp)).await {
-                        if (fs::rename(&path_to_temp_upload(file_name_temp), &path_to_temp_with_ind(&key, &ind)).await).is_ok() {
-                            list.push(BoxMailAttachmentItem { file_name: file_name.to_string(), id: ind, size: metadata.len() });
-                            ind += 1;
-                        }
-                    }
-                }
-                let attachments = Some(BoxMailAttachments { key, list });
-                let data = MessageRequest { idb: 0, attachments, ..MessageRequest::default() };
-                if let Ok(text) = serde_json::to_string(&data) {
-                    sse_personal_channel(session, Message::Message(text));
-                }
+    }
+    Ok(())
+}
+
+// файл пишется на диск по кускам; отклоненный файл удаляется, остаток части пропускается
+async fn part_as_file(multipart: &mut Multipart, path: &str) -> Result<Result<u64, &'static str>, String> {
+    (fs::create_dir_all(get_dir_path(path)).await).ok();
+    let mut file = match fs::File::create(path).await {
+        Ok(file) => file,
+        Err(err) => {
+            tracing::error!("part_as_file[1] {:?}", err);
+            return Ok(Err(UPLOAD_FAILED));
+        }
+    };
+    let mut size = 0u64;
+    let mut head = Vec::with_capacity(4);
+    let mut error = None;
+    while let Some(chunk) = multipart.next_chunk().await? {
+        if head.len() < 4 {
+            head.extend(chunk.iter().take(4 - head.len()));
+            if is_executable(&head) {
+                error = Some(UPLOAD_BLOCKED);
+                break;
             }
         }
+        size += chunk.len() as u64;
+        if size > UPLOAD_FILE_MAX {
+            error = Some(UPLOAD_TOO_LARGE);
+            break;
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            tracing::error!("part_as_file[2] {:?}", err);
+            error = Some(UPLOAD_FAILED);
+            break;
+        }
     }
+    if error.is_none() {
+        if let Err(err) = file.flush().await {
+            tracing::error!("part_as_file[3] {:?}", err);
+            error = Some(UPLOAD_FAILED);
+        }
+    }
+    drop(file);
+    match error {
+        Some(error) => {
+            (fs::remove_file(path).await).ok();
+            Ok(Err(error))
+        }
+        None => Ok(Ok(size))
+    }
+}
+
+pub fn is_blocked(file_name: &str, content_type: Option<&str>) -> bool {
+    let ext = file_name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+    BLOCKED_EXTENSIONS.contains(&ext.as_str()) || content_type.map(|content_type| BLOCKED_TYPES.contains(&content_type)).unwrap_or_default()
 }
 
-async fn part_as_string(p: &mut Part) -> String {
-    if let Some(Ok(v1)) = p.data().await {
-        let v2 = v1.chunk();
-        return String::from_utf8_lossy(v2).to_string();
+// Windows PE и ELF, как бы файл ни назывался
+pub fn is_executable(head: &[u8]) -> bool {
+    head.starts_with(b"MZ") || head.starts_with(b"\x7fELF")
+}
+
+async fn remove_saved(saved: &[(String, usize)]) {
+    for (file_name_temp, _) in saved.iter() {
+        (fs::remove_file(&path_to_temp_upload(file_name_temp)).await).ok();
     }
-    "".to_string()
 }
 
-async fn part_as_file(p: &mut Part) -> String {
-    let fine_name = Uuid::new_v4().to_string();
-    if (fs::create_dir_all(&path_to_temp_upload("")).await).is_ok() {
-        if let Some(Ok(e)) = p.data().await {
-            if (fs::write(&path_to_temp_upload(&fine_name), &e.chunk()).await).is_ok() {
-                return fine_name;
-            }
-        }
+// картинка для вставки в текст письма получает Content-ID
+pub fn inline_cid(inline: bool, file_name: &str) -> Option<String> {
+    let mime = mime_guess::from_path(file_name).first_or_octet_stream();
+    if inline && mime.type_() == mime_guess::mime::IMAGE {
+        Some(format!("{}@inline", Uuid::new_v4()))
+    } else {
+        None
     }
-    "".to_string()
-}
\ No newline at end of file
+}
@@ -1,6 +1,7 @@
 use std::fmt::LowerHex;
 
 use sha2::{Digest, Sha512};
+use uuid::Uuid;
 
 pub fn get_hash(text: String) -> String {
     format!("{:x}", hash_prepare(text))
@@ -12,6 +13,11 @@ fn hash_prepare(text: String) -> impl LowerHex {
     hasher.finalize()
 }
 
+// ключ вложений приходит от клиента и становится частью пути -- допускаем только uuid
+pub fn is_valid_key(key: &str) -> bool {
+    Uuid::parse_str(key).is_ok()
+}
+
 pub fn get_dir_path(file_path: &str) -> String {
     if let Some(pos) = file_path.rfind('/') {
         file_path[..pos].to_string()
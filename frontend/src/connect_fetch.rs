@@ -2,12 +2,12 @@ use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::{JsFuture, spawn_local};
 use web_sys::{Request, RequestInit, Response};
 
-use shared::constants::{HEADER_USER_KEY, ROOT_API};
+use shared::constants::{HEADER_CHANNEL, ROOT_API};
 
-use crate::state::USER_KEY;
+use crate::state::CHANNEL_ID;
 
 pub fn connect_json_send<T: serde::Serialize>(url: &str, data: T) {
-    if USER_KEY.get_cloned().is_empty() {
+    if CHANNEL_ID.get_cloned().is_empty() {
         return;
     }
     match serde_wasm_bindgen::to_value(&data) {
@@ -67,9 +67,9 @@ async fn send(url: &str, data: Option<JsValue>) -> Result<JsValue, JsValue> {
 
     let request = Request::new_with_str_and_init(&format!("/{ROOT_API}/{url}"), &opts)?;
     request.headers().set("Content-Type", "application/json")?;
-    let user_key = USER_KEY.get_cloned();
-    if !user_key.is_empty() {
-        request.headers().set(HEADER_USER_KEY, &user_key)?;
+    let channel_id = CHANNEL_ID.get_cloned();
+    if !channel_id.is_empty() {
+        request.headers().set(HEADER_CHANNEL, &channel_id)?;
     }
 
     let window = web_sys::window().unwrap();
@@ -1,51 +1,316 @@
+use std::sync::Arc;
+
+use futures::future::{Either, ready, select};
+use futures::StreamExt;
+use futures_signals::signal::{Mutable, SignalExt};
+use futures_signals::signal_vec::MutableVec;
+use gloo_timers::future::TimeoutFuture;
+use js_sys::Promise;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
-use web_sys::{ErrorEvent, FormData, ProgressEvent, XmlHttpRequest};
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::{Blob, File, ProgressEvent, XmlHttpRequest};
+
+use shared::constants::{API_UPLOADS, HEADER_CHANNEL, HEADER_UPLOAD_OFFSET, ROOT_API, UPLOAD_FILE_MAX};
+use shared::types::{BoxMailAttachmentItem, UploadCreateRequest, UploadFinishRequest};
+
+use crate::editor::app_editor::{get_editor, set_editor_attachments};
+use crate::state::CHANNEL_ID;
+use crate::utils::obj_to_string;
+
+// на мобильной связи при обрыве теряется не больше одного куска
+const CHUNK_SIZE: f64 = 1024.0 * 1024.0;
+const RETRY_MAX: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadState {
+    Running,
+    Paused,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug)]
+pub struct UploadItem {
+    pub file_name: String,
+    pub size: f64,
+    pub loaded: Mutable<f64>,
+    pub state: Mutable<UploadState>,
+    pub error: Mutable<Option<String>>,
+}
+
+pub static UPLOADS: Lazy<MutableVec<Arc<UploadItem>>> = Lazy::new(MutableVec::new);
 
-use shared::constants::{API_FILES, HEADER_USER_KEY, ROOT_API};
+#[derive(Debug, Clone, Deserialize, Default)]
+struct UploadCreateResult {
+    result: bool,
+    id: String,
+    error: Option<String>,
+}
 
-use crate::state::USER_KEY;
+#[derive(Debug, Clone, Deserialize, Default)]
+struct UploadFinishResult {
+    result: bool,
+    error: Option<String>,
+    item: Option<BoxMailAttachmentItem>,
+}
+
+enum RequestBody {
+    Empty,
+    Json(String),
+    Blob(Blob),
+}
+
+fn upload_error(error: &str) -> String {
+    match error {
+        "size" => format!("больше {} МБ", UPLOAD_FILE_MAX / 1024 / 1024),
+        "type" => "исполняемые файлы не пересылаются".to_string(),
+        _ => "ошибка записи".to_string()
+    }
+}
+
+// файл загружается в черновик, открытый в редакторе в момент выбора
+pub fn upload_file(file: File, inline: bool) {
+    let key = get_editor().and_then(|editor| editor.attachments.get_cloned()).map(|attachments| attachments.key);
+    let key = match key {
+        Some(key) => key,
+        None => return
+    };
+    let item = Arc::new(UploadItem {
+        file_name: file.name(),
+        size: file.size(),
+        loaded: Mutable::new(0.0),
+        state: Mutable::new(UploadState::Running),
+        error: Mutable::new(None),
+    });
+    UPLOADS.lock_mut().push_cloned(item.clone());
+    spawn_local(async move {
+        upload_run(&file, &item, &key, inline).await;
+    });
+}
+
+pub fn upload_toggle(item: &UploadItem) {
+    match item.state.get() {
+        UploadState::Running => item.state.set(UploadState::Paused),
+        UploadState::Paused => {
+            item.error.set(None);
+            item.state.set(UploadState::Running);
+        }
+        _ => {}
+    }
+}
 
-pub fn connect_files(form: &FormData, progress: fn(u32), full: fn(bool)) {
-    let user_key = USER_KEY.get_cloned();
-    if user_key.is_empty() {
-        return;
+pub fn upload_cancel(item: &Arc<UploadItem>) {
+    if item.state.get() == UploadState::Failed {
+        upload_remove(item);
+    } else {
+        item.state.set(UploadState::Cancelled);
     }
-    if let Ok(xhr) = XmlHttpRequest::new() {
-        if xhr.open_with_async("POST", &format!("/{ROOT_API}/{API_FILES}/"), true).is_err() {
-            return;
+}
+
+fn upload_remove(item: &Arc<UploadItem>) {
+    UPLOADS.lock_mut().retain(|row| !Arc::ptr_eq(row, item));
+}
+
+fn upload_failed(item: &UploadItem, error: String) {
+    item.error.set(Some(error));
+    item.state.set(UploadState::Failed);
+}
+
+async fn upload_run(file: &File, item: &Arc<UploadItem>, key: &str, inline: bool) {
+    let data = UploadCreateRequest { file_name: item.file_name.clone(), size: item.size as u64 };
+    let created = request("POST", "", &[], RequestBody::Json(obj_to_string(&data)), None).await.ok()
+        .and_then(|xhr| response_json::<UploadCreateResult>(&xhr));
+    let id = match created {
+        Some(created) if created.result => created.id,
+        Some(created) => return upload_failed(item, upload_error(&created.error.unwrap_or_default())),
+        None => return upload_failed(item, "сервер недоступен".to_string())
+    };
+    let url = format!("/{id}");
+
+    let mut offset = 0.0;
+    let mut need_offset = false;
+    let mut retry = 0;
+    loop {
+        match item.state.get() {
+            UploadState::Cancelled => {
+                (request("DELETE", &url, &[], RequestBody::Empty, None).await).ok();
+                return upload_remove(item);
+            }
+            UploadState::Paused => {
+                state_change(item, |state| state != UploadState::Paused).await;
+                need_offset = true;
+                continue;
+            }
+            _ => {}
+        }
+        if retry > 0 {
+            if retry > RETRY_MAX {
+                retry = 0;
+                item.error.set(Some("нет связи".to_string()));
+                item.state.set(UploadState::Paused);
+                continue;
+            }
+            TimeoutFuture::new(1000 * 2u32.pow(retry.min(5))).await;
         }
-        xhr.set_request_header(HEADER_USER_KEY, &user_key).ok();
 
+        // после обрыва или паузы продолжаем с того места, до которого дошел сервер
+        if need_offset {
+            match request("HEAD", &url, &[], RequestBody::Empty, None).await {
+                Ok(xhr) if xhr.status() == Ok(200) => {
+                    offset = response_offset(&xhr).unwrap_or(offset);
+                    need_offset = false;
+                }
+                Ok(xhr) if xhr.status() == Ok(404) => return upload_failed(item, "загрузка устарела, прикрепите файл заново".to_string()),
+                _ => {
+                    retry += 1;
+                    continue;
+                }
+            }
+        }
+        item.loaded.set(offset);
+
+        if offset >= item.size {
+            match upload_finish(&url, key, inline).await {
+                Ok(Some(attachment)) => {
+                    upload_attach(key, attachment);
+                    return upload_remove(item);
+                }
+                Ok(None) => return upload_failed(item, upload_error("")),
+                Err(incomplete) => {
+                    need_offset = incomplete;
+                    retry += 1;
+                    continue;
+                }
+            }
+        }
+
+        let end = (offset + CHUNK_SIZE).min(item.size);
+        let chunk = match file.slice_with_f64_and_f64(offset, end) {
+            Ok(chunk) => chunk,
+            Err(_) => return upload_failed(item, "файл недоступен".to_string())
+        };
+        let headers = [(HEADER_UPLOAD_OFFSET, (offset as u64).to_string()), ("Content-Type", "application/offset+octet-stream".to_string())];
+        match request("PATCH", &url, &headers, RequestBody::Blob(chunk), Some((item.clone(), offset))).await {
+            Ok(xhr) if matches!(xhr.status(), Ok(204) | Ok(409)) => {
+                offset = response_offset(&xhr).unwrap_or(offset);
+                retry = 0;
+            }
+            Ok(xhr) if xhr.status() == Ok(404) => return upload_failed(item, "загрузка устарела, прикрепите файл заново".to_string()),
+            Ok(_) => {
+                need_offset = true;
+                retry += 1;
+            }
+            // пауза или отмена прерывают запрос, это не ошибка связи
+            Err(_) if item.state.get() != UploadState::Running => need_offset = true,
+            Err(_) => {
+                need_offset = true;
+                retry += 1;
+            }
+        }
+    }
+}
+
+// Err(true) -- сервер получил не все, Err(false) -- нет связи
+async fn upload_finish(url: &str, key: &str, inline: bool) -> Result<Option<BoxMailAttachmentItem>, bool> {
+    let after = get_editor().and_then(|editor| editor.attachments.get_cloned())
+        .and_then(|attachments| attachments.list.iter().map(|item| item.id).max())
+        .unwrap_or_default();
+    let data = UploadFinishRequest { key: key.to_string(), after, inline };
+    let xhr = request("POST", url, &[], RequestBody::Json(obj_to_string(&data)), None).await.map_err(|_| false)?;
+    match response_json::<UploadFinishResult>(&xhr) {
+        Some(result) if result.result => Ok(result.item),
+        Some(result) if result.error.as_deref() == Some("incomplete") => Err(true),
+        Some(_) => Ok(None),
+        None => Err(false)
+    }
+}
+
+// вложение добавляется к списку, каким он стал к концу загрузки; редактор с другим черновиком не трогаем
+fn upload_attach(key: &str, attachment: BoxMailAttachmentItem) {
+    let attachments = get_editor().and_then(|editor| editor.attachments.get_cloned());
+    if let Some(mut attachments) = attachments.filter(|attachments| attachments.key == key) {
+        attachments.list.push(attachment);
+        set_editor_attachments(attachments);
+    }
+}
+
+async fn state_change(item: &UploadItem, filter: fn(UploadState) -> bool) {
+    item.state.signal().to_stream().filter(|state| ready(filter(*state))).next().await;
+}
+
+fn response_offset(xhr: &XmlHttpRequest) -> Option<f64> {
+    xhr.get_response_header(HEADER_UPLOAD_OFFSET).ok().flatten().and_then(|offset| offset.parse::<f64>().ok())
+}
+
+fn response_json<R: serde::de::DeserializeOwned>(xhr: &XmlHttpRequest) -> Option<R> {
+    xhr.response_text().ok().flatten()
+        .and_then(|text| js_sys::JSON::parse(&text).ok())
+        .and_then(|data| serde_wasm_bindgen::from_value::<R>(data).ok())
+}
+
+// XHR вместо fetch ради событий о ходе отправки; при паузе или отмене запрос прерывается
+async fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, String)],
+    body: RequestBody,
+    progress: Option<(Arc<UploadItem>, f64)>,
+) -> Result<XmlHttpRequest, ()> {
+    let xhr = XmlHttpRequest::new().map_err(|_| ())?;
+    xhr.open_with_async(method, &format!("/{ROOT_API}/{API_UPLOADS}{url}"), true).map_err(|_| ())?;
+    xhr.set_request_header(HEADER_CHANNEL, &CHANNEL_ID.get_cloned()).ok();
+    for (name, value) in headers.iter() {
+        xhr.set_request_header(name, value).ok();
+    }
+
+    let mut onprogress_callback = None;
+    if let Some((item, base)) = progress.clone() {
         if let Ok(upload) = xhr.upload() {
-            let onprogress_callback = Closure::<dyn FnMut(_)>::new(move |e: ProgressEvent| {
-                let value = (e.loaded() / e.total() * 1000.0).round() as u32;
-                progress(value);
+            let callback = Closure::<dyn FnMut(_)>::new(move |e: ProgressEvent| {
+                item.loaded.set(base + e.loaded());
             });
-            upload.set_onprogress(Some(onprogress_callback.as_ref().unchecked_ref()));
-            onprogress_callback.forget();
-        }
-
-        xhr.send_with_opt_form_data(Some(form)).ok();
-
-        let onerror_callback = Closure::<dyn FnMut(_)>::new(move |_e: ErrorEvent| {
-            log::error!("[connect_files]");
-            full(false);
-        });
-        xhr.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
-        onerror_callback.forget();
-
-        let xhr_load = xhr.clone();
-        let onload_callback = Closure::<dyn FnMut()>::new(move || {
-            if let Ok(status) = xhr_load.status() {
-                if status > 199 && status < 300 {
-                    full(true);
-                } else {
-                    full(false);
+            upload.set_onprogress(Some(callback.as_ref().unchecked_ref()));
+            onprogress_callback = Some(callback);
+        }
+    }
+
+    let promise = Promise::new(&mut |resolve, reject| {
+        xhr.set_onload(Some(&resolve));
+        xhr.set_onerror(Some(&reject));
+        xhr.set_onabort(Some(&reject));
+        xhr.set_ontimeout(Some(&reject));
+    });
+    let sent = match body {
+        RequestBody::Empty => xhr.send(),
+        RequestBody::Json(data) => {
+            xhr.set_request_header("Content-Type", "application/json").ok();
+            xhr.send_with_opt_str(Some(&data))
+        }
+        RequestBody::Blob(data) => xhr.send_with_opt_blob(Some(&data))
+    };
+    sent.map_err(|_| ())?;
+
+    let loaded = JsFuture::from(promise);
+    let result = match progress {
+        Some((item, _)) => {
+            let stopped = Box::pin(state_change(&item, |state| state != UploadState::Running));
+            match select(loaded, stopped).await {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => {
+                    xhr.abort().ok();
+                    Err(wasm_bindgen::JsValue::NULL)
                 }
             }
-        });
-        xhr.set_onload(Some(onload_callback.as_ref().unchecked_ref()));
-        onload_callback.forget();
+        }
+        None => loaded.await
+    };
+    if onprogress_callback.is_some() {
+        if let Ok(upload) = xhr.upload() {
+            upload.set_onprogress(None);
+        }
     }
-}
\ No newline at end of file
+    result.map(|_| xhr).map_err(|_| ())
+}
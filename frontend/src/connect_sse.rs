@@ -8,11 +8,11 @@ use wasm_bindgen::JsValue;
 use wasm_bindgen::prelude::wasm_bindgen;
 use web_sys::{ErrorEvent, EventSource, MessageEvent};
 
-use shared::constants::{API_EVENT, CHANNEL_INIT, CHANNEL_MESSAGE, CHANNEL_MESSAGES, CHANNEL_NOTES, CHANNEL_USER_KEY, ROOT_API};
+use shared::constants::{API_EVENT, CHANNEL_INIT, CHANNEL_MESSAGE, CHANNEL_MESSAGES, CHANNEL_NOTES, CHANNEL_ID, CHANNEL_SETTINGS, ROOT_API};
 
 use crate::elements::app_login::login_after_error;
 use crate::elements::app_message::{message_channel, messages_channel};
-use crate::loader::{init_channel, notes_channel, user_channel};
+use crate::loader::{init_channel, notes_channel, settings_channel, channel_id_channel};
 
 #[wasm_bindgen]
 pub fn start_sse() -> Result<(), JsValue> {
@@ -35,7 +35,8 @@ pub fn start_sse() -> Result<(), JsValue> {
     sse_data_event_channel(&sse, CHANNEL_MESSAGE, message_channel);
     sse_data_event_channel(&sse, CHANNEL_NOTES, notes_channel);
     sse_data_event_channel(&sse, CHANNEL_INIT, init_channel);
-    sse_text_event_channel(&sse, CHANNEL_USER_KEY, user_channel);
+    sse_data_event_channel(&sse, CHANNEL_SETTINGS, settings_channel);
+    sse_text_event_channel(&sse, CHANNEL_ID, channel_id_channel);
 
     let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
         if let Ok(_txt) = e.data().dyn_into::<js_sys::JsString>() {
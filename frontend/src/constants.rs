@@ -15,6 +15,7 @@ pub const TAG_SPAN: &str = "span";
 pub const TAG_INPUT: &str = "input";
 pub const TAG_BUTTON: &str = "button";
 pub const TAG_OPTION: &str = "option";
+pub const TAG_SELECT: &str = "select";
 
 pub const EMAIL_DATALIST: & str = "email-datalist";
 
@@ -1,15 +1,24 @@
-use dominator::{Dom, events, html};
+use dominator::{Dom, EventOptions, events, html};
 use futures_signals::signal::{Mutable, Signal, SignalExt};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{DataTransfer, Event, HtmlElement};
 
 use shared::types::{BoxMailAttachments, MailBoxes, MessageRequest};
 use shared::utils::box_type_index;
 
-use crate::constants::{EMAIL_DATALIST, PROP_EDITABLE, PROP_HTML, PROP_NAME, PROP_PLACEHOLDER, PROP_TITLE, PROP_TYPE, PROP_VALUE, TAG_DIV, TAG_INPUT};
-use crate::editor::editor_tools::{editor_preview_tools, editor_tools};
+use crate::constants::{EMAIL_DATALIST, PROP_EDITABLE, PROP_HTML, PROP_NAME, PROP_PLACEHOLDER, PROP_SELECTED, PROP_TITLE, PROP_TYPE, PROP_VALUE, TAG_DIV, TAG_INPUT, TAG_OPTION, TAG_SELECT};
+use crate::editor::editor_tools::{editor_preview_tools, editor_tools, return_focus, upload_files};
 use crate::editor::state::{EDITOR, EditorState};
-use crate::elements::attachment::{attachments_active, attachments_preview};
+use crate::elements::attachment::{attachment_forwarded, attachments_active, attachments_preview, inline_image_src, uploads_active};
+use crate::elements::app_settings::header_settings;
 use crate::loader::message_update;
 use crate::state::{CURRENT_BOX, USER};
+use crate::types::BoxMessage;
+use crate::utils::{attr_data, email_addresses, exec_command_full, get_input_value, query_selector, query_selector_all};
+
+const ATTR_CID: &str = "cid";
+const ATTR_SIGNATURE: &str = "signature";
 
 fn css_class(label: &str) -> String {
     format!("app-editor__{label}")
@@ -41,45 +50,147 @@ pub fn open_note_editor(is_note: bool, content: String) {
     }));
 }
 
+// подпись редактируется тем же редактором, остальные настройки -- в шапке
+pub fn open_settings_editor() {
+    let signature = USER.lock().map(|user| user.settings.signature.clone()).unwrap_or_default();
+    EDITOR.set(Some(EditorState {
+        is_settings: true,
+        content: if signature.trim().is_empty() { "<p><br></p>".to_string() } else { signature },
+        editable: true,
+        ..EditorState::default()
+    }));
+}
+
 pub fn set_editor_attachments(attachments: BoxMailAttachments) {
     if let Some(editor) = EDITOR.get_cloned() {
-        editor.attachments.set(Some(attachments));
+        let known = editor.attachments.get_cloned()
+            .map(|prev| prev.list.iter().filter_map(|item| item.cid.clone()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if editor.editable {
+            insert_inline_images(&attachments, &known);
+        }
+        let attachments = Some(attachments);
+        show_inline_images(&attachments, if editor.editable { 0 } else { editor.idb });
+        editor.attachments.set(attachments);
     }
 }
 
-pub fn open_email_editor(idb: u64, mail_to: String, subject: String, content: String) {
-    let signature = match USER.lock() {
-        Ok(user) => user.signature.clone(),
-        Err(_) => "".to_string()
+// только что загруженные картинки вставляем в текст на место курсора
+fn insert_inline_images(attachments: &BoxMailAttachments, known: &[String]) {
+    for item in attachments.list.iter() {
+        if let Some(cid) = &item.cid {
+            let selector = format!("[{PROP_EDITABLE}] img[{}=\"{cid}\"]", attr_data(ATTR_CID));
+            if known.contains(cid) || query_selector(&selector).is_some() {
+                continue;
+            }
+            return_focus();
+            let alt = item.file_name.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+            exec_command_full("insertHTML", false, &format!("<img {}=\"{cid}\" alt=\"{alt}\">", attr_data(ATTR_CID)));
+        }
+    }
+}
+
+// idb == 0 -- вложения черновика
+fn show_inline_images(attachments: &Option<BoxMailAttachments>, idb: u64) {
+    if let Some(attachments) = attachments {
+        for element in query_selector_all(&format!("[{PROP_EDITABLE}] img[{}]", attr_data(ATTR_CID))) {
+            let cid = element.get_attribute(&attr_data(ATTR_CID)).unwrap_or_default();
+            if let Some(item) = attachments.list.iter().find(|item| item.cid.as_ref() == Some(&cid)) {
+                element.set_attribute("src", &inline_image_src(item, &attachments.key, idb)).ok();
+            }
+        }
+    }
+}
+
+fn handle_drop(e: events::Drop) {
+    if let Some(files) = e.data_transfer().and_then(|data| data.files()) {
+        let files = (0..files.length()).filter_map(|ind| files.item(ind)).collect::<Vec<_>>();
+        if !files.is_empty() {
+            e.prevent_default();
+            upload_files(files, true);
+        }
+    }
+}
+
+// ClipboardEvent в web-sys пока нестабилен, clipboardData берем напрямую
+fn handle_paste(e: Event) {
+    let data = js_sys::Reflect::get(&e, &JsValue::from_str("clipboardData")).ok()
+        .and_then(|data| data.dyn_into::<DataTransfer>().ok());
+    if let Some(files) = data.and_then(|data| data.files()) {
+        let files = (0..files.length()).filter_map(|ind| files.item(ind)).collect::<Vec<_>>();
+        if files.iter().any(|file| file.type_().starts_with("image/")) {
+            e.prevent_default();
+            upload_files(files, true);
+        }
+    }
+}
+
+fn listen_paste(element: &HtmlElement) {
+    let callback = Closure::<dyn FnMut(_)>::new(handle_paste);
+    element.add_event_listener_with_callback("paste", callback.as_ref().unchecked_ref()).ok();
+    callback.forget();
+}
+
+// via -- адрес, на который пришло исходное письмо: отвечаем с него же
+pub fn open_email_editor(idb: u64, mail_to: String, subject: String, content: String, via: &str) {
+    EDITOR.set(Some(email_editor_state(idb, mail_to, subject, content, via)));
+}
+
+pub fn open_forward_editor(forward_idb: u64, subject: String, via: &str) {
+    let forward_name = format!("{subject}.eml");
+    EDITOR.set(Some(EditorState {
+        forward_idb: Mutable::new(Some(forward_idb)),
+        forward_name,
+        ..email_editor_state(0, "".to_string(), format!("FW: {subject}"), "".to_string(), via)
+    }));
+}
+
+fn email_editor_state(idb: u64, mail_to: String, subject: String, content: String, via: &str) -> EditorState {
+    let (identity, signature) = match USER.lock() {
+        Ok(user) => {
+            // ответ -- с того адреса, на который пришло письмо
+            let via = email_addresses(via);
+            let identity = user.identities.iter()
+                .find(|item| via.contains(&item.email.to_lowercase()))
+                .or_else(|| user.identities.first());
+            match identity {
+                Some(identity) => (Some(identity.idi), identity.signature.clone()),
+                None => (None, user.signature.clone())
+            }
+        }
+        Err(_) => (None, "".to_string())
     };
     message_update(MessageRequest {
         idb,
         attachments: Some(BoxMailAttachments { key: "".to_string(), list: vec![] }),
         ..MessageRequest::default()
     });
-    EDITOR.set(Some(EditorState {
+    EditorState {
         recipient: Some(mail_to),
         subject: Some(subject),
-        content: format!("{content}<p><br></p>{signature}"),
+        content: format!("{content}<p><br></p><div {}=\"1\">{signature}</div>", attr_data(ATTR_SIGNATURE)),
         editable: true,
+        identity,
         ..EditorState::default()
-    }));
+    }
 }
 
-pub fn open_message_preview(idb: &u64, attachments: &Option<BoxMailAttachments>, mail_from: String, mail_to: String, subject: String, content: String) {
+pub fn open_message_preview(message: &BoxMessage, mail_from: String, mail_to: String) {
     let with_unread = CURRENT_BOX.get() == MailBoxes::Inbox;
     EDITOR.set(Some(EditorState {
-        idb: *idb,
+        idb: message.idb,
         with_unread,
+        source: message.source,
+        security: message.security.clone(),
         sender: Some(mail_from),
         recipient: Some(mail_to),
-        subject: Some(subject),
-        attachments: Mutable::new(attachments.clone()),
-        content,
+        subject: Some(message.subject.clone()),
+        attachments: Mutable::new(message.attachments.clone()),
+        content: message.content.clone(),
         ..EditorState::default()
     }));
     message_update(MessageRequest {
-        idb: *idb,
+        idb: message.idb,
         unread: Some(false),
         box_current: Some(box_type_index(&CURRENT_BOX.get()) as i32),
         ..MessageRequest::default()
@@ -95,7 +206,10 @@ pub fn editor_version() {
 fn editor(state: EditorState) -> Dom {
     let mut top: Vec<Dom> = vec![];
 
-    if state.editable {
+    if state.is_settings {
+        top.push(header_settings());
+        top.push(editor_tools(true));
+    } else if state.editable {
         let is_note = state.is_note;
         if !is_note {
             top.push(header_active(&state));
@@ -103,15 +217,21 @@ fn editor(state: EditorState) -> Dom {
         top.push(editor_tools(is_note));
         if !is_note {
             top.push(attachments_active(state.attachments.signal_cloned()));
+            top.push(uploads_active());
+            top.push(attachment_forwarded(state.forward_idb.clone(), state.forward_name.clone()));
         }
     } else {
         top.push(editor_preview_tools());
         top.push(header_preview(&state));
         if let Some(attachments) = state.attachments.get_cloned() {
-            top.push(attachments_preview(&attachments));
+            top.push(attachments_preview(&attachments, state.idb));
         }
     }
 
+    let with_images = state.editable && !state.is_note && !state.is_settings;
+    let images_idb = if state.editable { 0 } else { state.idb };
+    let attachments = state.attachments.get_cloned();
+
     html!(TAG_DIV, {
         .class(css_class("back"))
         .child(html!(TAG_DIV, {
@@ -126,6 +246,15 @@ fn editor(state: EditorState) -> Dom {
                     .class(css_class("content"))
                     .attr(PROP_EDITABLE, &state.editable.to_string())
                     .prop(PROP_HTML, state.content)
+                    .apply_if(with_images, |dom| dom
+                        .event_with_options(&EventOptions::preventable(), handle_drop)
+                    )
+                    .after_inserted(move |element| {
+                        if with_images {
+                            listen_paste(&element);
+                        }
+                        show_inline_images(&attachments, images_idb);
+                    })
                 })
             ])
         }))
@@ -135,7 +264,26 @@ fn editor(state: EditorState) -> Dom {
 fn header_active(state: &EditorState) -> Dom {
     let recipient = state.recipient.clone().unwrap_or_default();
     let subject = state.subject.clone().unwrap_or_default();
+    let identities = USER.lock().map(|user| user.identities.clone()).unwrap_or_default();
+    let identity = state.identity;
+    let smime = USER.lock().map(|user| user.settings.smime).unwrap_or_default();
+    let pgp = USER.lock().map(|user| user.settings.pgp).unwrap_or_default();
     html!(TAG_DIV, {
+        .apply_if(identities.len() > 1, |dom| dom
+            .child(html!(TAG_DIV, {
+                .child(html!(TAG_SELECT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "отправитель")
+                    .attr(PROP_NAME, "identity")
+                    .children(identities.iter().map(|item| html!(TAG_OPTION, {
+                        .attr(PROP_VALUE, &item.idi.to_string())
+                        .prop(PROP_SELECTED, identity == Some(item.idi))
+                        .text(&item.mailbox())
+                    })))
+                    .event(handle_identity)
+                }))
+            }))
+        )
         .children([
             html!(TAG_DIV, {
                 .child(html!(TAG_INPUT, {
@@ -159,9 +307,48 @@ fn header_active(state: &EditorState) -> Dom {
                 }))
             })
         ])
+        .apply_if(smime, |dom| dom
+            .child(html!("label", {
+                .child(html!(TAG_INPUT, {
+                    .attr(PROP_TYPE, "checkbox")
+                    .attr(PROP_NAME, "smime_sign")
+                }))
+                .text(" подписать S/MIME")
+            }))
+        )
+        .apply_if(pgp, |dom| dom
+            .children([
+                html!("label", {
+                    .child(html!(TAG_INPUT, {
+                        .attr(PROP_TYPE, "checkbox")
+                        .attr(PROP_NAME, "pgp_sign")
+                    }))
+                    .text(" подписать OpenPGP ")
+                }),
+                html!("label", {
+                    .child(html!(TAG_INPUT, {
+                        .attr(PROP_TYPE, "checkbox")
+                        .attr(PROP_NAME, "pgp_encrypt")
+                    }))
+                    .text(" зашифровать OpenPGP")
+                }),
+            ])
+        )
     })
 }
 
+// при смене отправителя меняем и подпись
+fn handle_identity(_: events::Change) {
+    let idi = get_input_value("identity").parse::<i32>().ok();
+    let signature = USER.lock().ok()
+        .and_then(|user| user.identities.iter().find(|item| Some(item.idi) == idi).map(|item| item.signature.clone()));
+    if let Some(signature) = signature {
+        if let Some(element) = query_selector(&format!("[{PROP_EDITABLE}] [{}]", attr_data(ATTR_SIGNATURE))) {
+            element.set_inner_html(&signature);
+        }
+    }
+}
+
 fn animation_end(_: events::AnimationEnd) {
     editor_version();
 }
@@ -170,6 +357,7 @@ fn header_preview(state: &EditorState) -> Dom {
     let sender = state.sender.clone().unwrap_or_default();
     let recipient = state.recipient.clone().unwrap_or_default();
     let subject = state.subject.clone().unwrap_or_default();
+    let security = state.security.as_deref().and_then(security_label);
     html!(TAG_DIV, {
         .children([
             html!(TAG_DIV, {
@@ -185,5 +373,38 @@ fn header_preview(state: &EditorState) -> Dom {
                 .text(&subject)
             }),
         ])
+        .apply_if(security.is_some(), |dom| {
+            dom.child(html!(TAG_DIV, {
+                .class(css_class("security"))
+                .text(&security.unwrap_or_default())
+            }))
+        })
     })
 }
+
+// отметки через пробел, у подписи после двоеточия -- адрес из сертификата или ключа, для неизвестного ключа -- его идентификатор
+fn security_label(security: &str) -> Option<String> {
+    let labels = security.split_whitespace().filter_map(|item| {
+        let (kind, email) = item.split_once(':').unwrap_or((item, ""));
+        match kind {
+            "pgp-encrypted" => Some("Зашифровано OpenPGP: нет ключа для расшифровки, сообщение во вложении".to_string()),
+            "pgp-decrypted" => Some("Расшифровано OpenPGP".to_string()),
+            "pgp-signed" => Some("Подписано OpenPGP: подпись не проверялась".to_string()),
+            "pgp-valid" => Some(format!("Подпись OpenPGP верна: {email}")),
+            "pgp-mismatch" => Some(format!("Подпись OpenPGP цела, но ключ другого адреса: {email}")),
+            "pgp-unknown" => Some(format!("Подписано OpenPGP ключом {email}: ключа нет в связке")),
+            "pgp-invalid" => Some("Подпись OpenPGP неверна: письмо изменено после подписи".to_string()),
+            "pgp-sent-signed" => Some("Отправлено с подписью OpenPGP".to_string()),
+            "pgp-sent-encrypted" => Some("Отправлено зашифрованным OpenPGP".to_string()),
+            "smime-valid" => Some(format!("Подпись S/MIME верна: {email}")),
+            "smime-untrusted" => Some(format!("Подпись S/MIME цела, но сертификат не доверенный: {email}")),
+            "smime-mismatch" => Some(format!("Подпись S/MIME цела, но сертификат другого адреса: {email}")),
+            "smime-invalid" => Some("Подпись S/MIME неверна: письмо изменено после подписи".to_string()),
+            "smime-encrypted" => Some("Расшифровано S/MIME".to_string()),
+            "smime-undecryptable" => Some("Зашифровано S/MIME: нет ключа для расшифровки".to_string()),
+            "smime-signed" => Some("Отправлено с подписью S/MIME".to_string()),
+            _ => None
+        }
+    }).collect::<Vec<_>>();
+    if labels.is_empty() { None } else { Some(labels.join("; ")) }
+}
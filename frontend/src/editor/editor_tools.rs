@@ -1,22 +1,24 @@
 use dominator::{Dom, events, html};
-use futures_signals::signal::{Mutable, SignalExt};
+use futures_signals::signal::Mutable;
 use once_cell::sync::Lazy;
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::spawn_local;
-use web_sys::{Element, FormData, HtmlInputElement};
+use web_sys::{Element, File, HtmlInputElement};
 
+use shared::constants::{API_SOURCE, ROOT_API};
 use shared::types::{MessageRequest, NotesChannel};
 use shared::utils::box_type_index;
 
-use crate::connect_files::connect_files;
+use crate::connect_files::upload_file;
 use crate::constants::{PROP_EDITABLE, PROP_HTML, PROP_NAME, PROP_PLACEHOLDER, PROP_TITLE, TAG_BUTTON, TAG_DIV, TAG_INPUT, TAG_SPAN};
 use crate::dialog::dialogs::Dialog;
-use crate::editor::app_editor::{editor_close, open_email_editor};
-use crate::editor::icons::{icon_attach, icon_close, icon_envelope, icon_eraser, icon_font_bold, icon_font_italic, icon_font_underline, icon_forward, icon_heading, icon_link, icon_list_ol, icon_list_ul, icon_paragraph, icon_reply, icon_save, icon_send, icon_unlink};
+use crate::editor::app_editor::{editor_close, open_email_editor, open_forward_editor};
+use crate::editor::icons::{icon_attach, icon_close, icon_download, icon_envelope, icon_eraser, icon_font_bold, icon_font_italic, icon_font_underline, icon_forward, icon_heading, icon_link, icon_list_ol, icon_list_ul, icon_paragraph, icon_reply, icon_save, icon_send, icon_source, icon_unlink};
 use crate::editor::state::EDITOR;
+use crate::elements::app_settings::settings_save;
 use crate::loader::{message_update, notes_update};
 use crate::state::{CURRENT_BOX, NOTES_SELECTED};
-use crate::utils::{drop_element, exec_command, exec_command_full, get_element_from_node, get_html_element, get_input_value, get_selection, node_parent, obj_to_string, query_selector};
+use crate::utils::{drop_element, exec_command, exec_command_full, get_element_from_node, get_html_element, get_input_checked, get_input_value, get_selection, node_parent, query_selector, window_open};
 
 static LINK: Lazy<Mutable<String>> = Lazy::new(|| {
     Mutable::new("".to_string())
@@ -176,6 +178,10 @@ fn handle_save(_: events::Click) {
     return_focus();
     if let Some(elem) = query_selector(&format!("[{PROP_EDITABLE}]")) {
         let content: String = elem.inner_html();
+        if EDITOR.get_cloned().map(|editor| editor.is_settings).unwrap_or_default() {
+            settings_save(content);
+            return;
+        }
         let idn = NOTES_SELECTED.get();
         notes_update(NotesChannel {
             idn,
@@ -203,7 +209,7 @@ fn handle_close(_: events::Click) {
     editor_close();
 }
 
-fn return_focus() {
+pub fn return_focus() {
     if let Some(element) = get_html_element(query_selector(&format!("[{PROP_EDITABLE}]"))) { if element.focus().is_ok() {} };
 }
 
@@ -217,72 +223,35 @@ fn button(title: &str, click: fn(events::Click), icon: fn()->Dom) -> Dom {
 
 // ===
 
-pub static UPLOAD_STARTED: Lazy<Mutable<bool>> = Lazy::new(|| {
-    Mutable::new(false)
-});
-
-pub static UPLOAD_PROGRESS: Lazy<Mutable<u32>> = Lazy::new(|| {
-    Mutable::new(0)
-});
-
-fn handle_progress(val: u32) {
-    UPLOAD_PROGRESS.set(val);
-}
-
-fn handle_progress_final(success: bool) {
-    if !success {
-        Dialog::alert("Ошибка при загрузке файла.");
-    }
-    UPLOAD_STARTED.set(false);
-    UPLOAD_PROGRESS.set(0);
-}
-
 fn handle_change(e: events::Change) {
     if let Some(target) = e.target() {
         if let Some(input) = JsValue::from(target).dyn_ref::<HtmlInputElement>() {
             if let Some(files) = input.files() {
-                if let Some(editor) = EDITOR.get_cloned() {
-                    if let Some(attachments) = editor.attachments.get_cloned() {
-                        let current = obj_to_string(&attachments);
-                        if let Ok(form) = FormData::new() {
-                            form.append_with_str("current", &current).ok();
-                            for ind in 0..files.length() {
-                                if let Some(file) = files.item(ind) {
-                                    form.append_with_blob("files", &file).ok();
-                                }
-                            }
-                            connect_files(&form, handle_progress, handle_progress_final);
-                            UPLOAD_STARTED.set(true);
-                        }
-                    }
-                }
+                let files = (0..files.length()).filter_map(|ind| files.item(ind)).collect::<Vec<_>>();
+                upload_files(files, false);
             }
         }
     }
 }
 
+// inline -- картинки для вставки в текст письма
+pub fn upload_files(files: Vec<File>, inline: bool) {
+    for file in files.into_iter() {
+        upload_file(file, inline);
+    }
+}
+
 fn button_attach() -> Dom {
     html!(TAG_BUTTON, {
         .attr(PROP_TITLE, "прикрепить файлы")
-        .child_signal(UPLOAD_STARTED.signal().map(|flag|
-            if flag {Some(html!(TAG_DIV, {
-                .class(css_class("progress"))
-                .child(html!(TAG_SPAN, {
-                    .class(css_class("progress-text"))
-                    .text_signal(UPLOAD_PROGRESS.signal_cloned().map(|val| format!("{val}%")))
-                }))
-            }))}
-            else {None}
-        ))
         .child(html!(TAG_DIV, {
             .class(css_class("icon-block"))
-            .child_signal(UPLOAD_STARTED.signal().map(move|flag|if flag {None} else {Some(icon_attach())}))
+            .child(icon_attach())
         }))
         .child(html!(TAG_INPUT, {
             .class(css_class("input-file"))
             .attr("type", "file")
             .attr("multiple", "")
-            .prop_signal("disabled", UPLOAD_STARTED.signal())
             .event(handle_change)
         }))
     })
@@ -291,7 +260,9 @@ fn button_attach() -> Dom {
 // ===
 
 pub fn editor_preview_tools() -> Dom {
-    let with_unread = EDITOR.get_cloned().unwrap_or_default().with_unread;
+    let editor = EDITOR.get_cloned().unwrap_or_default();
+    let with_unread = editor.with_unread;
+    let source = editor.source;
     let mut buttons = vec![
         button("закрыть", handle_close, icon_close),
         button("ответить", handle_reply, icon_reply),
@@ -301,6 +272,11 @@ pub fn editor_preview_tools() -> Dom {
     if with_unread {
         buttons.push(button("отменить прочтение", handle_unread, icon_envelope));
     }
+    if source {
+        buttons.insert(3, button("переслать вложением", handle_forward_attached, icon_attach));
+        buttons.push(button("исходный текст письма", handle_source, icon_source));
+        buttons.push(button("скачать .eml", handle_source_download, icon_download));
+    }
 
     html!(TAG_DIV, {
         .class(css_class("container"))
@@ -313,17 +289,24 @@ fn handle_reply(_: events::Click) {
         let recipient = editor.sender.unwrap_or_default();
         let subject = format!("RE: {}", editor.subject.unwrap_or_default());
         let content = format!("<p><br></p><blockquote>{}</blockquote>", editor.content);
-        open_email_editor(0, recipient, subject, content);
+        open_email_editor(0, recipient, subject, content, &editor.recipient.unwrap_or_default());
     }
 }
 
 fn handle_forward(_: events::Click) {
     if let Some(editor) = EDITOR.get_cloned() {
         let sender = to_html(editor.sender.unwrap_or_default());
-        let recipient = to_html(editor.recipient.unwrap_or_default());
+        let via = editor.recipient.unwrap_or_default();
+        let recipient = to_html(via.clone());
         let subject = format!("FW: {}", editor.subject.unwrap_or_default());
         let content = format!("<p><b>Отправитель:</b> {sender}<br><b>Переадресовано с:</b> {recipient}</p><p><br></p><hr>{}", editor.content);
-        open_email_editor(editor.idb, "".to_string(), subject, content);
+        open_email_editor(editor.idb, "".to_string(), subject, content, &via);
+    }
+}
+
+fn handle_forward_attached(_: events::Click) {
+    if let Some(editor) = EDITOR.get_cloned() {
+        open_forward_editor(editor.idb, editor.subject.unwrap_or_default(), &editor.recipient.unwrap_or_default());
     }
 }
 
@@ -345,6 +328,25 @@ fn handle_unread(_: events::Click) {
     }
 }
 
+fn handle_source(_: events::Click) {
+    open_source(false);
+}
+
+fn handle_source_download(_: events::Click) {
+    open_source(true);
+}
+
+fn open_source(download: bool) {
+    if let Some(editor) = EDITOR.get_cloned() {
+        let idb = editor.idb;
+        let mut href = format!("/{ROOT_API}/{API_SOURCE}/{idb}");
+        if download {
+            href.push_str("?download=1");
+        }
+        window_open(&href);
+    }
+}
+
 fn handle_send(_: events::Click) {
     return_focus();
     log::info!("handle_send");
@@ -374,6 +376,11 @@ fn handle_send(_: events::Click) {
             subject: Some(subject),
             content: Some(content),
             recipient: Some(recipient),
+            forward_idb: editor.forward_idb.get(),
+            identity: get_input_value("identity").parse().ok().or(editor.identity),
+            smime_sign: Some(get_input_checked("smime_sign")),
+            pgp_sign: Some(get_input_checked("pgp_sign")),
+            pgp_encrypt: Some(get_input_checked("pgp_encrypt")),
             ..MessageRequest::default()
         });
     }
@@ -62,6 +62,30 @@ pub fn icon_forward() ->Dom{
     })
 }
 
+pub fn icon_source() ->Dom{
+    svg!(TAG_SVG, {
+        .attr(ATTR_VIEW_BOX, "0 0 640 512")
+        .child(
+            svg!(TAG_PATH, {
+                .attr(ATTR_FILL, COLOR_CURRENT)
+                .attr(ATTR_D, "M278.9 511.5l-61-17.7c-6.4-1.8-10-8.5-8.2-14.9L346.2 8.7c1.8-6.4 8.5-10 14.9-8.2l61 17.7c6.4 1.8 10 8.5 8.2 14.9L293.8 503.3c-1.9 6.4-8.5 10.1-14.9 8.2zm-114-112.2l43.5-46.4c4.6-4.9 4.3-12.7-.8-17.2L117 256l90.6-79.7c5.1-4.5 5.5-12.3.8-17.2l-43.5-46.4c-4.5-4.8-12.1-5.1-17-.5L3.8 247.2c-5.1 4.7-5.1 12.8 0 17.5l144.1 135.1c4.9 4.6 12.5 4.4 17-.5zm327.2.6l144.1-135.1c5.1-4.7 5.1-12.8 0-17.5L492.1 112.1c-4.8-4.5-12.4-4.3-17 .5L431.6 159c-4.6 4.9-4.3 12.7.8 17.2L523 256l-90.6 79.7c-5.1 4.5-5.5 12.3-.8 17.2l43.5 46.4c4.5 4.9 12.1 5.1 17 .6z")
+            })
+        )
+    })
+}
+
+pub fn icon_download() ->Dom{
+    svg!(TAG_SVG, {
+        .attr(ATTR_VIEW_BOX, "0 0 512 512")
+        .child(
+            svg!(TAG_PATH, {
+                .attr(ATTR_FILL, COLOR_CURRENT)
+                .attr(ATTR_D, "M216 0h80c13.3 0 24 10.7 24 24v168h87.7c17.8 0 26.7 21.5 14.1 34.1L269.7 378.3c-7.5 7.5-19.8 7.5-27.3 0L90.1 226.1c-12.6-12.6-3.7-34.1 14.1-34.1H192V24c0-13.3 10.7-24 24-24zm296 376v112c0 13.3-10.7 24-24 24H24c-13.3 0-24-10.7-24-24V376c0-13.3 10.7-24 24-24h146.7l49 49c20.1 20.1 52.5 20.1 72.6 0l49-49H488c13.3 0 24 10.7 24 24zm-124 88c0-11-9-20-20-20s-20 9-20 20 9 20 20 20 20-9 20-20zm64 0c0-11-9-20-20-20s-20 9-20 20 9 20 20 20 20-9 20-20z")
+            })
+        )
+    })
+}
+
 pub fn icon_envelope() ->Dom{
     svg!(TAG_SVG, {
         .attr(ATTR_VIEW_BOX, "0 0 512 512")
@@ -17,7 +17,13 @@ pub struct EditorState {
     pub is_note: bool,
     pub idb: u64,
     pub with_unread: bool,
+    pub source: bool,
+    pub security: Option<String>,
     pub version: Mutable<usize>,
     pub attachments: Mutable<Option<BoxMailAttachments>>,
+    pub forward_idb: Mutable<Option<u64>>,
+    pub forward_name: String,
+    pub identity: Option<i32>,
+    pub is_settings: bool,
 }
 
@@ -4,7 +4,7 @@ use shared::types::{MailBoxes, MessagesRequest};
 use shared::utils::box_type_index;
 
 use crate::constants::TAG_DIV;
-use crate::elements::app_message::{box_view, message_content};
+use crate::elements::app_message::{box_view, message_content, search_next};
 use crate::loader::messages_load;
 use crate::notes::app_notes::app_notes;
 use crate::notes::notes_content::notes_content;
@@ -39,6 +39,7 @@ pub fn app_body() -> Dom {
                     box_view(MailBoxes::Ready),
                     box_view(MailBoxes::Sent),
                     box_view(MailBoxes::Trash),
+                    box_view(MailBoxes::Search),
                 ])
             })
         ])
@@ -54,7 +55,11 @@ fn handle_scroll(e: events::Scroll) {
             let box_index = box_type_index(&mb);
             if !BOX_STATE[box_index].fully_loaded.get() {
                 let page = BOX_STATE[box_index].page.get() + 1;
-                messages_load(MessagesRequest { page, email_box: box_index as i32 });
+                if mb == MailBoxes::Search {
+                    search_next(page);
+                } else {
+                    messages_load(MessagesRequest { page, email_box: box_index as i32 });
+                }
             }
         }
     }
@@ -2,15 +2,19 @@ use dominator::{Dom, events, html, svg};
 use futures_signals::signal::{Signal, SignalExt};
 use wasm_bindgen_futures::spawn_local;
 
-use shared::types::MailBoxes;
+use shared::constants::API_SESSIONS;
+use shared::types::{MailBoxes, SessionsRequest};
 use shared::utils::box_type_index;
 
-use crate::constants::{TAG_BUTTON, TAG_DIV};
-use crate::editor::app_editor::open_email_editor;
+use crate::connect_fetch::connect_json_data;
+use crate::constants::{PROP_NAME, PROP_PLACEHOLDER, PROP_TITLE, PROP_TYPE, TAG_BUTTON, TAG_DIV, TAG_INPUT};
+use crate::editor::app_editor::{open_email_editor, open_settings_editor};
 use crate::elements::app_login::get_user_box;
+use crate::elements::app_message::search_start;
+use crate::elements::app_settings::SessionItem;
 use crate::notes::notes_events::handle_events;
-use crate::state::{BOX_STATE, CURRENT_BOX, EVENTS, LOADING_NEXT, USER_KEY};
-use crate::utils::{location_reload, query_selector};
+use crate::state::{BOX_STATE, CURRENT_BOX, CHANNEL_ID, EVENTS, LOADING_NEXT};
+use crate::utils::{get_input_value, location_reload, query_selector};
 
 fn css_class(label: &str) -> String {
     format!("app-header__{label}")
@@ -27,7 +31,9 @@ pub fn app_header() -> Dom {
             button_typed("отправленные", MailBoxes::Sent),
             button_typed("корзина", MailBoxes::Trash),
             button_typed("заметки", MailBoxes::Notes),
+            search_input(),
             button(&get_user_box(), location_reload),
+            button("настройки", open_settings_editor),
             button_icon(icon_exit(), handle_exit)
         ])
     })
@@ -38,11 +44,16 @@ fn events() -> impl Signal<Item=Option<Dom>> {
 }
 
 fn new_mail() {
-    open_email_editor(0, "".to_string(), "".to_string(), "".to_string());
+    open_email_editor(0, "".to_string(), "".to_string(), "".to_string(), "");
 }
 
+// сервер удаляет сеанс и cookie
 fn handle_exit() {
-    USER_KEY.set("".to_string());
+    connect_json_data(API_SESSIONS, SessionsRequest { logout: Some(true), ..SessionsRequest::default() }, exit_result);
+}
+
+fn exit_result(_: Vec<SessionItem>) {
+    CHANNEL_ID.set("".to_string());
     location_reload();
 }
 
@@ -78,6 +89,25 @@ fn button_typed(label: &str, mb: MailBoxes) -> Dom {
     })
 }
 
+fn search_input() -> Dom {
+    html!(TAG_INPUT, {
+        .class(css_class("search"))
+        .class_signal("active", CURRENT_BOX.signal().map(|mb| mb == MailBoxes::Search))
+        .attr(PROP_TYPE, "search")
+        .attr(PROP_NAME, "search")
+        .attr(PROP_PLACEHOLDER, "поиск")
+        .attr(PROP_TITLE, "слова или \"фраза\"; from:адрес, to:адрес, has:attachment, before:2024-01-31, after:2024-01-01, in:inbox|sent|trash")
+        .event(|e: events::KeyDown| {
+            if e.key() == "Enter" {
+                let query = get_input_value("search").trim().to_string();
+                if !query.is_empty() {
+                    search_start(query);
+                }
+            }
+        })
+    })
+}
+
 fn button(label: &str, click: fn()) -> Dom {
     html!(TAG_BUTTON, {
         .class(css_class("button"))
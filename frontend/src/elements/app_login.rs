@@ -2,72 +2,43 @@ use dominator::{Dom, events, html};
 use futures_signals::signal::{Mutable, SignalExt};
 use once_cell::sync::Lazy;
 use serde::Deserialize;
-use wasm_bindgen::closure::Closure;
-use wasm_bindgen::JsCast;
-use web_sys::Window;
 
-use shared::constants::{API_LOGIN, TEST_USER_ID};
+use shared::constants::API_LOGIN;
 
 use crate::connect_fetch::connect_json_data;
 use crate::connect_sse::start_sse;
 use crate::constants::{PROP_NAME, PROP_PLACEHOLDER, PROP_TITLE, TAG_DIV, TAG_INPUT};
 use crate::elements::app_root::app_root;
-use crate::state::USER_KEY;
 use crate::utils::{get_html_element, get_input_value, get_location, query_selector, set_title};
 
 const KEY_ENTER: &str = "Enter";
 const FIELD_NAME: &str = "a";
 const FIELD_PASS: &str = "b";
+const FIELD_CODE: &str = "c";
 
 const AUTH_STATE_DEFAULT: usize = 1;
 const AUTH_STATE_LOGIN: usize = 2;
 const AUTH_STATE_AUTHORIZED: usize = 3;
 
-static AUTH_ERR: Lazy<Mutable<bool>> = Lazy::new(|| Mutable::new(false));
+static AUTH_ERR: Lazy<Mutable<String>> = Lazy::new(|| Mutable::new("".to_string()));
 static AUTH_STATE: Lazy<Mutable<usize>> = Lazy::new(|| Mutable::new(0));
+// пароль принят, ждем код из приложения
+static AUTH_TOTP: Lazy<Mutable<bool>> = Lazy::new(|| Mutable::new(false));
 
 fn css_class(label: &str) -> String {
     format!("app-login__{label}")
 }
 
-fn init_storage() {
-    if let Some(w) = web_sys::window() {
-        let mail_box = get_user_box();
-        if let Ok(Some(local_storage)) = w.local_storage() {
-            if let Ok(Some(value)) = local_storage.get_item(&mail_box) {
-                connect_json_data(API_LOGIN, vec![value], login_connect_result);
-                local_storage.remove_item(&mail_box).ok();
-            } else {
-                if TEST_USER_ID > 0 {
-                    connect_json_data(API_LOGIN, vec![""], login_connect_result);
-                }
-                AUTH_STATE.set(AUTH_STATE_LOGIN);
-            }
-        }
-
-        let w1: Window = w.clone();
-        let listener_callback = Closure::<dyn FnMut()>::new(move || {
-            if let Ok(Some(local_storage)) = w1.local_storage() {
-                let key = USER_KEY.get_cloned();
-                if !key.is_empty() {
-                    local_storage.set_item(&mail_box, &key).ok();
-                }
-            }
-        });
-
-        let is_iphone:bool = match w.navigator().user_agent() {
-            Ok(ua)=>ua.contains("iPhone"),
-            Err(_)=>false
-        };
-
-        if is_iphone {
-            w.set_onpagehide(Some(listener_callback.as_ref().unchecked_ref()));
-        }
-        else{
-            w.set_onbeforeunload(Some(listener_callback.as_ref().unchecked_ref()));
-        }
+// вход по cookie сеанса; если она не подошла -- форма входа без сообщения об ошибке
+fn init_session() {
+    connect_json_data(API_LOGIN, vec![login_box()], login_init_result);
+}
 
-        listener_callback.forget();
+fn login_init_result(data: LoginResult) {
+    if data.result {
+        AUTH_STATE.set(AUTH_STATE_AUTHORIZED);
+    } else {
+        AUTH_STATE.set(AUTH_STATE_LOGIN);
     }
 }
 
@@ -75,7 +46,7 @@ pub fn app_state() -> Dom {
     if get_user_box().is_empty() {
         AUTH_STATE.set(AUTH_STATE_DEFAULT);
     } else {
-        init_storage();
+        init_session();
     }
     html!(TAG_DIV, {
         .child_signal(AUTH_STATE.signal().map(|state|{
@@ -120,9 +91,18 @@ fn login_page() -> Dom {
                     .attr(PROP_NAME, FIELD_PASS)
                     .event(handle_key_pass)
                 }),
+                html!(TAG_INPUT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "Код из приложения или код восстановления")
+                    .attr(PROP_PLACEHOLDER, "Код из приложения")
+                    .attr(PROP_NAME, FIELD_CODE)
+                    .attr("autocomplete", "one-time-code")
+                    .visible_signal(AUTH_TOTP.signal())
+                    .event(handle_key_pass)
+                }),
                 html!(TAG_DIV, {
                     .class(css_class("message"))
-                    .text_signal(AUTH_ERR.signal().map(|flag| if flag {"...ошибка авторизации..."} else {""}))
+                    .text_signal(AUTH_ERR.signal_cloned())
                 }),
             ])
         }))
@@ -146,28 +126,47 @@ fn handle_key_pass(ev: events::KeyDown) {
 fn login_connect() {
     let user_name = get_input_value(FIELD_NAME).trim().to_string();
     let user_pass = get_input_value(FIELD_PASS).trim().to_string();
-    let source = get_user_box().split('@').map(|row| row.split('.')
-        .collect::<Vec<_>>().join("+")).collect::<Vec<_>>().join("!");
-    connect_json_data(API_LOGIN, vec![source, user_name, user_pass], login_connect_result);
+    let mut source = vec![login_box(), user_name, user_pass];
+    if AUTH_TOTP.get() {
+        source.push(get_input_value(FIELD_CODE).trim().to_string());
+    }
+    connect_json_data(API_LOGIN, source, login_connect_result);
+}
+
+fn login_box() -> String {
+    get_user_box().split('@').map(|row| row.split('.')
+        .collect::<Vec<_>>().join("+")).collect::<Vec<_>>().join("!")
 }
 
 #[derive(Deserialize)]
 struct LoginResult {
     result: bool,
+    #[serde(default)]
+    retry_after: Option<i64>,
+    #[serde(default)]
+    totp: Option<bool>,
 }
 
 pub fn login_after_error() {
     log::info!("login_after_error");
-    let key = USER_KEY.get_cloned();
-    connect_json_data(API_LOGIN, vec![key], login_connect_result);
+    connect_json_data(API_LOGIN, vec![login_box()], login_connect_result);
 }
 
 fn login_connect_result(data: LoginResult) {
     log::info!("login_connect_result: {}", data.result);
     if data.result {
         AUTH_STATE.set(AUTH_STATE_AUTHORIZED);
+    } else if data.totp.unwrap_or_default() {
+        AUTH_TOTP.set_neq(true);
+        AUTH_ERR.set_neq("...введите код из приложения...".to_string());
+        if let Some(elem) = get_html_element(query_selector(&format!("[name={FIELD_CODE}]"))) {
+            if elem.focus().is_ok() {}
+        }
     } else {
-        AUTH_ERR.set_neq(true);
+        AUTH_ERR.set_neq(match data.retry_after {
+            Some(secs) => format!("...слишком много попыток, повторите через {} мин...", (secs + 59) / 60),
+            None => "...ошибка авторизации...".to_string()
+        });
         AUTH_STATE.set(AUTH_STATE_LOGIN);
     }
 }
@@ -4,25 +4,30 @@ use futures_signals::signal::{Mutable, Signal, SignalExt};
 use futures_signals::signal_vec::{MutableVec, SignalVecExt};
 use once_cell::sync::Lazy;
 
-use shared::types::{MailBoxes, MessageRequest, MessagesRequest};
+use shared::constants::API_SEARCH;
+use shared::types::{MailBoxes, MessageRequest, MessagesRequest, SearchRequest};
 use shared::utils::box_type_index;
 
+use crate::connect_fetch::connect_json_data;
 use crate::constants::{PROP_HTML, PROP_ROLE, PROP_ROLE_BUTTON, PROP_TITLE, TAG_DIV, TAG_SPAN};
 use crate::dialog::dialogs::Dialog;
 use crate::editor::app_editor::{editor_close, open_message_preview, set_editor_attachments};
 use crate::elements::attachment::attachments_preview;
 use crate::elements::icons::{icon_envelope, icon_envelope_open, icon_inbox, icon_note, icon_read, icon_trash};
 use crate::loader::{message_update, messages_load};
-use crate::state::{BOX_STATE, CURRENT_BOX, LOADING_NEXT, NOTES};
+use crate::state::{BOX_STATE, CURRENT_BOX, LOADING_NEXT, NOTES, SEARCH_QUERY, TIMEZONE};
 use crate::types::{BoxMailAddress, BoxMessage, MessagesResponse};
-use crate::utils::{attr_data, from_dataset, view_email};
+use crate::utils::{attr_data, from_dataset, view_date, view_email};
 
+// по номеру ящика; у заметок свой список, на его месте пустой
 static BOXES: Lazy<Vec<MutableVec<BoxMessage>>> = Lazy::new(|| {
     vec![
         MutableVec::new(),
         MutableVec::new(),
         MutableVec::new(),
         MutableVec::new(),
+        MutableVec::new(),
+        MutableVec::new(),
     ]
 });
 
@@ -49,12 +54,29 @@ pub fn messages_channel(data: MessagesResponse) {
     LOADING_NEXT.set(false);
 }
 
+// новый поиск: прежние результаты убираем, страницы дальше подгружаются прокруткой
+pub fn search_start(query: String) {
+    let index = box_type_index(&MailBoxes::Search);
+    BOXES[index].lock_mut().clear();
+    BOX_STATE[index].selected.set(0);
+    BOX_STATE[index].page.set(0);
+    BOX_STATE[index].fully_loaded.set(false);
+    BOX_STATE[index].initialized.set(true);
+    SEARCH_QUERY.set(query.clone());
+    CURRENT_BOX.set(MailBoxes::Search);
+    connect_json_data(API_SEARCH, SearchRequest { query, page: 0 }, messages_channel);
+}
+
+pub fn search_next(page: usize) {
+    connect_json_data(API_SEARCH, SearchRequest { query: SEARCH_QUERY.get_cloned(), page }, messages_channel);
+}
+
 pub fn message_channel(data: MessageRequest) {
     if let Some(send) = data.send {
         if send {
             editor_close();
         } else {
-            Dialog::alert("Ошибка при отправке...");
+            Dialog::alert(&data.send_error.unwrap_or_else(|| "Ошибка при отправке...".to_string()));
         }
     } else if let Some(box_current) = data.box_current {
         let box_current = box_current as usize;
@@ -68,6 +90,12 @@ pub fn message_channel(data: MessageRequest) {
             let pos = BOXES[box_current].lock_ref().iter().position(|row| row.idb == data.idb);
             if let Some(pos) = pos {
                 let message = BOXES[box_current].lock_mut().remove(pos);
+                // из результатов поиска: письмо уходит и из своего прежнего ящика
+                if box_current == box_type_index(&MailBoxes::Search) {
+                    for list in BOXES.iter() {
+                        list.lock_mut().retain(|row| row.idb != data.idb);
+                    }
+                }
                 BOXES[box_target].lock_mut().insert_cloned(0, message);
             }
         }
@@ -81,9 +109,22 @@ pub fn box_view(mbox: MailBoxes) -> Dom {
     html!(TAG_DIV, {
         .visible_signal(CURRENT_BOX.signal().map(move|b| box_visible(b==mbox, &mbox)))
         .children_signal_vec(BOXES[box_type_index(&mbox)].signal_vec_cloned().map(move |row| message(&mbox_2, row)))
+        .apply_if(mbox == MailBoxes::Search, |dom| dom.child_signal(search_empty()))
     })
 }
 
+fn search_empty() -> impl Signal<Item=Option<Dom>> {
+    let index = box_type_index(&MailBoxes::Search);
+    map_ref! {
+        let fully_loaded = BOX_STATE[index].fully_loaded.signal(),
+        let count = BOXES[index].signal_vec_cloned().len() =>
+        (*fully_loaded && *count == 0).then(|| html!(TAG_DIV, {
+            .class(css_class("empty"))
+            .text("ничего не найдено")
+        }))
+    }
+}
+
 const ATTR_DATA_KEY: &str = "key";
 const DATA_KEY_NOTE: &str = "note";
 const DATA_KEY_BOX: &str = "box";
@@ -99,6 +140,8 @@ fn message(mbox: &MailBoxes, row: BoxMessage) -> Dom {
     let mbox_over = *mbox;
 
     let idb_selected = row.idb;
+    let date = row.date.clone();
+    let timestamp = row.timestamp;
 
     html!(TAG_DIV, {
         .class(css_class("container"))
@@ -125,7 +168,7 @@ fn message(mbox: &MailBoxes, row: BoxMessage) -> Dom {
                         .class(css_class("date-block"))
                         .child(html!(TAG_DIV, {
                             .class(css_class("date-elem"))
-                            .text(&row.date)
+                            .text_signal(TIMEZONE.signal_cloned().map(move |timezone| view_date(&date, timestamp, &timezone)))
                         }))
                     }),
                     email_view(mbox, &row),
@@ -176,7 +219,8 @@ fn email_elem(data: &BoxMailAddress) -> Dom {
 fn box_visible(selected: bool, mb_type: &MailBoxes) -> bool {
     if selected {
         let box_index = box_type_index(mb_type);
-        if !BOX_STATE[box_index].initialized.get() {
+        // результаты поиска загружает сам поиск
+        if !BOX_STATE[box_index].initialized.get() && mb_type != &MailBoxes::Search {
             messages_load(MessagesRequest { page: 0, email_box: box_type_index(mb_type) as i32 });
         }
     }
@@ -287,7 +331,7 @@ fn handle_click(data_key: String, mbox: &MailBoxes, idb: &u64) {
         let sender = view_email(&sender, &message.sender.address);
         let recipient = if let Some(label) = &message.recipient.name { label.clone() } else { "".to_string() };
         let recipient = view_email(&recipient, &message.recipient.address);
-        open_message_preview(&idb, &message.attachments, sender, recipient, message.subject.clone(), message.content.clone());
+        open_message_preview(message, sender, recipient);
     }
 }
 
@@ -307,7 +351,7 @@ pub fn message_content() -> Dom {
 }
 
 fn attachments_signal() -> impl Signal<Item=Option<Dom>> {
-    common_signal().map(|item: BoxMessage| item.attachments.map(|attachments| attachments_preview(&attachments)))
+    common_signal().map(|item: BoxMessage| item.attachments.map(|attachments| attachments_preview(&attachments, item.idb)))
 }
 
 
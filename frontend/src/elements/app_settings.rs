@@ -0,0 +1,590 @@
+use dominator::{Dom, events, html};
+use futures_signals::signal::Mutable;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use wasm_bindgen_futures::spawn_local;
+
+use shared::constants::{API_LOGINS, API_PASSWORD, API_PGP, API_SESSIONS, API_SMIME, API_TOTP};
+use shared::types::{PasswordRequest, PgpRequest, SessionsRequest, SettingsRequest, SmimeRequest, TotpRequest};
+
+use crate::connect_fetch::connect_json_data;
+use crate::constants::{PROP_NAME, PROP_PLACEHOLDER, PROP_SELECTED, PROP_TITLE, PROP_TYPE, PROP_VALUE, TAG_BUTTON, TAG_DIV, TAG_INPUT, TAG_OPTION, TAG_SELECT};
+use crate::dialog::dialogs::{Dialog, DialogButton, DialogType};
+use crate::loader::settings_update;
+use crate::state::USER;
+use crate::utils::{file_to_base64, get_input_checked, get_input_file, get_input_value};
+
+static SESSIONS: Lazy<Mutable<Vec<SessionItem>>> = Lazy::new(|| Mutable::new(vec![]));
+static LOGINS: Lazy<Mutable<Vec<LoginItem>>> = Lazy::new(|| Mutable::new(vec![]));
+static TOTP: Lazy<Mutable<TotpResult>> = Lazy::new(|| Mutable::new(TotpResult::default()));
+static SMIME: Lazy<Mutable<SmimeResult>> = Lazy::new(|| Mutable::new(SmimeResult::default()));
+static PGP: Lazy<Mutable<PgpResult>> = Lazy::new(|| Mutable::new(PgpResult::default()));
+
+fn css_class(label: &str) -> String {
+    format!("app-editor__{label}")
+}
+
+const BOX_LABELS: [&str; 5] = ["входящие", "прочтенные", "отправленные", "корзина", "заметки"];
+
+pub fn header_settings() -> Dom {
+    let settings = USER.lock().map(|user| user.settings.clone()).unwrap_or_default();
+    html!(TAG_DIV, {
+        .children([
+            html!(TAG_DIV, {
+                .child(html!(TAG_INPUT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "отображаемое имя")
+                    .attr(PROP_PLACEHOLDER, &format!("отображаемое имя (по умолчанию {})", settings.prefix))
+                    .attr(PROP_TYPE, "string")
+                    .attr(PROP_NAME, "display_name")
+                    .attr(PROP_VALUE, &settings.display_name)
+                }))
+            }),
+            html!(TAG_DIV, {
+                .child(html!(TAG_INPUT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "писем на странице")
+                    .attr(PROP_PLACEHOLDER, "писем на странице")
+                    .attr(PROP_TYPE, "number")
+                    .attr(PROP_NAME, "by_page")
+                    .attr(PROP_VALUE, &settings.by_page.to_string())
+                    .attr("min", "10")
+                    .attr("max", "200")
+                }))
+            }),
+            html!(TAG_DIV, {
+                .child(html!(TAG_INPUT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "часовой пояс")
+                    .attr(PROP_PLACEHOLDER, "часовой пояс, например Europe/Moscow (пусто -- UTC)")
+                    .attr(PROP_TYPE, "string")
+                    .attr(PROP_NAME, "timezone")
+                    .attr(PROP_VALUE, &settings.timezone)
+                }))
+            }),
+            html!(TAG_DIV, {
+                .child(html!(TAG_SELECT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "ящик по умолчанию")
+                    .attr(PROP_NAME, "default_box")
+                    .children(BOX_LABELS.iter().enumerate().map(|(ind, label)| html!(TAG_OPTION, {
+                        .attr(PROP_VALUE, &ind.to_string())
+                        .prop(PROP_SELECTED, ind as i32 == settings.default_box)
+                        .text(label)
+                    })))
+                }))
+            }),
+            html!(TAG_DIV, {
+                .children([
+                    html!(TAG_BUTTON, {
+                        .text("сменить пароль")
+                        .event(|_: events::Click| Dialog::form("Смена пароля", dlg_password_init, dlg_password_result, || {}))
+                    }),
+                    html!(TAG_BUTTON, {
+                        .text("сеансы")
+                        .event(handle_sessions)
+                    }),
+                    html!(TAG_BUTTON, {
+                        .text("история входов")
+                        .event(handle_logins)
+                    }),
+                    html!(TAG_BUTTON, {
+                        .text("двухфакторная защита")
+                        .event(handle_totp)
+                    }),
+                    html!(TAG_BUTTON, {
+                        .text("сертификат S/MIME")
+                        .event(handle_smime)
+                    }),
+                    html!(TAG_BUTTON, {
+                        .text("ключи OpenPGP")
+                        .event(handle_pgp)
+                    }),
+                ])
+            }),
+        ])
+    })
+}
+
+const PASSWORD_MIN: usize = 8;
+
+fn dlg_password_init() -> Dom {
+    html!(TAG_DIV, {
+        .children([
+            password_input("password_current", "текущий пароль"),
+            password_input("password_new", "новый пароль"),
+            password_input("password_repeat", "новый пароль еще раз"),
+        ])
+    })
+}
+
+fn password_input(name: &str, title: &str) -> Dom {
+    html!(TAG_DIV, {
+        .child(html!(TAG_INPUT, {
+            .class(css_class("input"))
+            .attr(PROP_TITLE, title)
+            .attr(PROP_PLACEHOLDER, title)
+            .attr(PROP_TYPE, "password")
+            .attr(PROP_NAME, name)
+        }))
+    })
+}
+
+fn dlg_password_result() {
+    let current = get_input_value("password_current").trim().to_string();
+    let password = get_input_value("password_new").trim().to_string();
+    if password != get_input_value("password_repeat").trim() {
+        Dialog::alert("Новые пароли не совпадают");
+        return;
+    }
+    if password.chars().count() < PASSWORD_MIN {
+        Dialog::alert(&format!("Пароль должен быть не короче {PASSWORD_MIN} символов"));
+        return;
+    }
+    connect_json_data(API_PASSWORD, PasswordRequest { current, password }, password_result);
+}
+
+#[derive(Deserialize)]
+struct PasswordResult {
+    result: bool,
+}
+
+fn password_result(data: PasswordResult) {
+    Dialog::alert(if data.result { "Пароль изменен, остальные сеансы завершены" } else { "Пароль не изменен: неверный текущий пароль" });
+}
+
+pub fn settings_save(signature: String) {
+    settings_update(SettingsRequest {
+        display_name: Some(get_input_value("display_name")),
+        signature: Some(signature),
+        by_page: get_input_value("by_page").parse().ok(),
+        timezone: Some(get_input_value("timezone")),
+        default_box: get_input_value("default_box").parse().ok(),
+    });
+}
+
+// === сеансы
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionItem {
+    pub created: String,
+    pub last_active: String,
+    pub user_agent: String,
+    pub ip: String,
+    pub current: bool,
+}
+
+fn handle_sessions(_: events::Click) {
+    connect_json_data(API_SESSIONS, SessionsRequest::default(), sessions_result);
+}
+
+fn sessions_result(data: Vec<SessionItem>) {
+    SESSIONS.set(data);
+    Dialog::custom(Dialog {
+        type_: DialogType::Form,
+        title: "Сеансы".to_string(),
+        form: dlg_sessions_init,
+        before: vec![DialogButton { label: "завершить другие".to_string(), click: sessions_logout_others }],
+        ..Dialog::default()
+    });
+}
+
+fn dlg_sessions_init() -> Dom {
+    html!(TAG_DIV, {
+        .children(SESSIONS.get_cloned().iter().map(|item| html!(TAG_DIV, {
+            .class(css_class("session"))
+            .children([
+                html!("b", {.text(if item.current { "этот сеанс" } else { &item.user_agent })}),
+                html!(TAG_DIV, {
+                    .text(&format!("вход {}, активность {}, {}", item.created, item.last_active, item.ip))
+                }),
+            ])
+        })))
+    })
+}
+
+fn sessions_logout_others() -> bool {
+    connect_json_data(API_SESSIONS, SessionsRequest { logout_others: Some(true), ..SessionsRequest::default() }, sessions_logout_result);
+    true
+}
+
+fn sessions_logout_result(data: Vec<SessionItem>) {
+    SESSIONS.set(data);
+    Dialog::alert("Другие сеансы завершены");
+}
+
+// === история входов
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoginItem {
+    pub created: String,
+    pub ip: String,
+    pub user_agent: String,
+    pub result: String,
+}
+
+fn handle_logins(_: events::Click) {
+    connect_json_data(API_LOGINS, (), logins_result);
+}
+
+fn logins_result(data: Vec<LoginItem>) {
+    LOGINS.set(data);
+    Dialog::form("История входов", dlg_logins_init, || {}, || {});
+}
+
+fn dlg_logins_init() -> Dom {
+    html!(TAG_DIV, {
+        .children(LOGINS.get_cloned().iter().map(|item| html!(TAG_DIV, {
+            .class(css_class("session"))
+            .children([
+                html!("b", {.text(match item.result.as_str() {
+                    "success" => "вход",
+                    "locked" => "отклонено: блокировка",
+                    _ => "неверный пароль",
+                })}),
+                html!(TAG_DIV, {
+                    .text(&format!("{}, {}, {}", item.created, item.ip, item.user_agent))
+                }),
+            ])
+        })))
+    })
+}
+
+// === двухфакторная аутентификация
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TotpResult {
+    pub result: bool,
+    pub enabled: bool,
+    #[serde(default)]
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub qr: Option<String>,
+    #[serde(default)]
+    pub codes: Option<Vec<String>>,
+}
+
+fn handle_totp(_: events::Click) {
+    connect_json_data(API_TOTP, TotpRequest::default(), totp_status_result);
+}
+
+fn totp_status_result(data: TotpResult) {
+    if data.enabled {
+        Dialog::form("Отключение двухфакторной защиты", dlg_totp_code_init, dlg_totp_disable, || {});
+    } else {
+        connect_json_data(API_TOTP, TotpRequest { enroll: Some(true), ..TotpRequest::default() }, totp_enroll_result);
+    }
+}
+
+fn totp_enroll_result(data: TotpResult) {
+    if !data.result {
+        Dialog::alert("Не удалось начать подключение");
+        return;
+    }
+    TOTP.set(data);
+    Dialog::form("Подключение двухфакторной защиты", dlg_totp_enroll_init, dlg_totp_confirm, || {});
+}
+
+fn dlg_totp_enroll_init() -> Dom {
+    let data = TOTP.get_cloned();
+    let qr = data.qr.unwrap_or_default();
+    html!(TAG_DIV, {
+        .children([
+            html!(TAG_DIV, {
+                .text("Отсканируйте код в приложении-аутентификаторе или добавьте ссылку вручную, затем введите код из приложения.")
+            }),
+            html!(TAG_DIV, {
+                .after_inserted(move |elem| elem.set_inner_html(&qr))
+            }),
+            html!(TAG_DIV, {
+                .class(css_class("session"))
+                .text(&data.uri.unwrap_or_default())
+            }),
+            dlg_totp_code_init(),
+        ])
+    })
+}
+
+fn dlg_totp_code_init() -> Dom {
+    html!(TAG_DIV, {
+        .child(html!(TAG_INPUT, {
+            .class(css_class("input"))
+            .attr(PROP_TITLE, "код из приложения")
+            .attr(PROP_PLACEHOLDER, "код из приложения")
+            .attr(PROP_NAME, "totp_code")
+            .attr("autocomplete", "one-time-code")
+        }))
+    })
+}
+
+fn dlg_totp_confirm() {
+    let code = get_input_value("totp_code").trim().to_string();
+    connect_json_data(API_TOTP, TotpRequest { confirm: Some(code), ..TotpRequest::default() }, totp_confirm_result);
+}
+
+fn totp_confirm_result(data: TotpResult) {
+    if !data.result {
+        Dialog::alert("Неверный код, защита не подключена");
+        return;
+    }
+    TOTP.set(data);
+    Dialog::form("Коды восстановления", dlg_totp_codes_init, || {}, || {});
+}
+
+fn dlg_totp_codes_init() -> Dom {
+    html!(TAG_DIV, {
+        .child(html!(TAG_DIV, {
+            .text("Защита подключена. Сохраните коды восстановления: каждый можно использовать вместо кода из приложения один раз, больше они не будут показаны.")
+        }))
+        .children(TOTP.get_cloned().codes.unwrap_or_default().iter().map(|code| html!("code", {
+            .class(css_class("session"))
+            .text(code)
+        })))
+    })
+}
+
+fn dlg_totp_disable() {
+    let code = get_input_value("totp_code").trim().to_string();
+    connect_json_data(API_TOTP, TotpRequest { disable: Some(code), ..TotpRequest::default() }, totp_disable_result);
+}
+
+fn totp_disable_result(data: TotpResult) {
+    Dialog::alert(if data.enabled { "Неверный код, защита не отключена" } else { "Двухфакторная защита отключена" });
+}
+
+// === сертификат S/MIME
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SmimeResult {
+    pub result: bool,
+    pub present: bool,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+fn handle_smime(_: events::Click) {
+    connect_json_data(API_SMIME, SmimeRequest::default(), smime_status_result);
+}
+
+fn smime_status_result(data: SmimeResult) {
+    smime_state(data);
+    Dialog::form("Сертификат S/MIME", dlg_smime_init, dlg_smime_save, || {});
+}
+
+// без сертификата в редакторе не показываем флажок подписи
+fn smime_state(data: SmimeResult) {
+    if let Ok(mut user) = USER.lock() {
+        user.settings.smime = data.present;
+    }
+    SMIME.set(data);
+}
+
+fn dlg_smime_init() -> Dom {
+    let data = SMIME.get_cloned();
+    let status = if data.present {
+        format!(
+            "Сертификат: {}, {}, действует до {}",
+            data.subject.unwrap_or_default(),
+            data.email.unwrap_or_default(),
+            data.expires.unwrap_or_default(),
+        )
+    } else {
+        "Сертификат не загружен. Загрузите файл PKCS#12 (.p12, .pfx) с сертификатом и закрытым ключом: им подписываются отправляемые письма и расшифровываются входящие.".to_string()
+    };
+    html!(TAG_DIV, {
+        .children([
+            html!(TAG_DIV, {
+                .class(css_class("session"))
+                .text(&status)
+            }),
+            html!(TAG_DIV, {
+                .child(html!(TAG_INPUT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "файл PKCS#12")
+                    .attr(PROP_TYPE, "file")
+                    .attr(PROP_NAME, "smime_file")
+                    .attr("accept", ".p12,.pfx")
+                }))
+            }),
+            password_input("smime_password", "пароль файла"),
+        ])
+        .apply_if(data.present, |dom| dom
+            .child(html!("label", {
+                .child(html!(TAG_INPUT, {
+                    .attr(PROP_TYPE, "checkbox")
+                    .attr(PROP_NAME, "smime_remove")
+                }))
+                .text(" удалить сертификат")
+            }))
+        )
+    })
+}
+
+fn dlg_smime_save() {
+    if get_input_checked("smime_remove") {
+        connect_json_data(API_SMIME, SmimeRequest { remove: Some(true), ..SmimeRequest::default() }, smime_save_result);
+        return;
+    }
+    let file = match get_input_file("smime_file") {
+        Some(file) => file,
+        None => return
+    };
+    let password = get_input_value("smime_password");
+    spawn_local(async move {
+        match file_to_base64(&file).await {
+            Some(pkcs12) => connect_json_data(
+                API_SMIME,
+                SmimeRequest { pkcs12: Some(pkcs12), password: Some(password), ..SmimeRequest::default() },
+                smime_save_result,
+            ),
+            None => Dialog::alert("Не удалось прочитать файл")
+        }
+    });
+}
+
+fn smime_save_result(data: SmimeResult) {
+    let message = match (data.result, data.present, &data.error) {
+        (true, true, _) => "Сертификат S/MIME сохранен".to_string(),
+        (true, false, _) => "Сертификат S/MIME удален".to_string(),
+        (false, _, Some(error)) => error.clone(),
+        _ => "Сертификат не сохранен: неверный файл или пароль".to_string(),
+    };
+    smime_state(data);
+    Dialog::alert(&message);
+}
+
+// === ключи OpenPGP
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PgpKeyItem {
+    pub fingerprint: String,
+    pub emails: Vec<String>,
+    pub secret: bool,
+    pub usable: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PgpResult {
+    pub result: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub keys: Vec<PgpKeyItem>,
+}
+
+fn handle_pgp(_: events::Click) {
+    connect_json_data(API_PGP, PgpRequest::default(), pgp_status_result);
+}
+
+fn pgp_status_result(data: PgpResult) {
+    pgp_state(data);
+    Dialog::form("Ключи OpenPGP", dlg_pgp_init, dlg_pgp_save, || {});
+}
+
+// без своего ключа в редакторе не показываем флажки подписи и шифрования
+fn pgp_state(data: PgpResult) {
+    if let Ok(mut user) = USER.lock() {
+        user.settings.pgp = data.keys.iter().any(|item| item.secret);
+    }
+    PGP.set(data);
+}
+
+fn dlg_pgp_init() -> Dom {
+    let data = PGP.get_cloned();
+    html!(TAG_DIV, {
+        .apply_if(data.keys.is_empty(), |dom| dom
+            .child(html!(TAG_DIV, {
+                .class(css_class("session"))
+                .text("Ключей нет. Свой ключ с закрытой частью подписывает отправляемые письма и расшифровывает входящие, ключи получателей -- для шифрования и проверки подписей.")
+            }))
+        )
+        .children(data.keys.iter().map(|item| html!(TAG_DIV, {
+            .class(css_class("session"))
+            .children([
+                html!("b", {.text(&item.emails.join(", "))}),
+                html!(TAG_DIV, {
+                    .text(&format!(
+                        "{}{}, {}",
+                        if item.secret { "свой ключ" } else { "ключ получателя" },
+                        if item.usable { "" } else { ", истек или отозван" },
+                        item.fingerprint,
+                    ))
+                }),
+            ])
+        })))
+        .children([
+            html!(TAG_DIV, {
+                .child(html!(TAG_INPUT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "файл ключа")
+                    .attr(PROP_TYPE, "file")
+                    .attr(PROP_NAME, "pgp_file")
+                    .attr("accept", ".asc,.gpg,.pgp,.key,.pub")
+                }))
+            }),
+            password_input("pgp_passphrase", "пароль закрытого ключа"),
+        ])
+        .apply_if(!data.keys.is_empty(), |dom| dom
+            .child(html!(TAG_DIV, {
+                .child(html!(TAG_SELECT, {
+                    .class(css_class("input"))
+                    .attr(PROP_TITLE, "удалить ключ")
+                    .attr(PROP_NAME, "pgp_remove")
+                    .child(html!(TAG_OPTION, {
+                        .attr(PROP_VALUE, "")
+                        .text("не удалять")
+                    }))
+                    .children(data.keys.iter().map(|item| html!(TAG_OPTION, {
+                        .attr(PROP_VALUE, &item.fingerprint)
+                        .text(&format!("удалить {}", item.emails.join(", ")))
+                    })))
+                }))
+            }))
+        )
+    })
+}
+
+fn dlg_pgp_save() {
+    let remove = get_input_value("pgp_remove");
+    if !remove.is_empty() {
+        connect_json_data(API_PGP, PgpRequest { remove: Some(remove), ..PgpRequest::default() }, pgp_remove_result);
+        return;
+    }
+    let file = match get_input_file("pgp_file") {
+        Some(file) => file,
+        None => return
+    };
+    let passphrase = get_input_value("pgp_passphrase");
+    spawn_local(async move {
+        match file_to_base64(&file).await {
+            Some(key) => connect_json_data(
+                API_PGP,
+                PgpRequest { key: Some(key), passphrase: Some(passphrase), ..PgpRequest::default() },
+                pgp_save_result,
+            ),
+            None => Dialog::alert("Не удалось прочитать файл")
+        }
+    });
+}
+
+fn pgp_remove_result(data: PgpResult) {
+    let message = if data.result { "Ключ OpenPGP удален" } else { "Ключ не удален" };
+    pgp_state(data);
+    Dialog::alert(message);
+}
+
+pub fn pgp_save_result(data: PgpResult) {
+    let message = match (data.result, &data.error) {
+        (true, _) => "Ключи OpenPGP сохранены".to_string(),
+        (false, Some(error)) => error.clone(),
+        _ => "Ключ не сохранен".to_string(),
+    };
+    pgp_state(data);
+    Dialog::alert(&message);
+}
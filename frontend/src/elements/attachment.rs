@@ -1,52 +1,131 @@
+use std::sync::Arc;
+
 use dominator::{Dom, events, html};
-use futures_signals::signal::{MutableSignalCloned, SignalExt};
+use futures_signals::map_ref;
+use futures_signals::signal::{Mutable, MutableSignalCloned, SignalExt};
+use futures_signals::signal_vec::SignalVecExt;
 use web_sys::UrlSearchParams;
 
-use shared::constants::{API_FILE, ROOT_API};
-use shared::types::{BoxMailAttachmentItem, BoxMailAttachments, MessageRequest};
+use shared::constants::{API_FILE, API_FILE_TEMP, API_FILE_THUMB, API_FILE_ZIP, API_PGP, ROOT_API};
+use shared::types::{BoxMailAttachmentItem, BoxMailAttachments, MessageRequest, PgpRequest};
 
+use crate::connect_fetch::connect_json_data;
+use crate::connect_files::{upload_cancel, upload_toggle, UploadItem, UploadState, UPLOADS};
 use crate::constants::{PROP_TITLE, TAG_DIV, TAG_SPAN};
 use crate::editor::app_editor::get_editor;
-use crate::elements::app_login::get_user_box;
+use crate::elements::app_settings::pgp_save_result;
 use crate::elements::icons::icon_remove;
+use crate::elements::lightbox::{preview_kind, preview_strip, PreviewItem};
 use crate::loader::message_update;
-use crate::state::USER_KEY;
 use crate::utils::{attr_data, from_dataset};
 
 const ATTR_ID: &str = "id";
+const VIEWABLE: [&str; 9] = ["pdf", "png", "jpg", "jpeg", "gif", "webp", "txt", "mp3", "mp4"];
+const PGP_KEY: [&str; 5] = ["asc", "gpg", "pgp", "key", "pub"];
 
 fn css_class(label: &str) -> String {
     format!("attachments__{label}")
 }
 
-pub fn attachments_preview(attachments: &BoxMailAttachments) -> Dom {
+pub fn attachments_preview(attachments: &BoxMailAttachments, idb: u64) -> Dom {
+    let count = attachments.list.iter().filter(|item| item.cid.is_none()).count();
+    let previews = attachments.list.iter()
+        .filter(|item| item.cid.is_none())
+        .filter_map(|item| preview_kind(&item.file_name).map(|kind| PreviewItem {
+            file_name: item.file_name.clone(),
+            src: inline_image_src(item, &attachments.key, idb),
+            thumb: thumb_src(item, &attachments.key, idb),
+            kind,
+        }))
+        .collect::<Vec<_>>();
     html!(TAG_DIV, {
         .class(css_class("container-preview"))
-        .children(attachments.list.iter().map(|item|{
+        .apply_if(!previews.is_empty(), |dom| dom.child(preview_strip(previews)))
+        .children(attachments.list.iter().filter(|item| item.cid.is_none()).map(|item|{
             html!(TAG_DIV, {
-                .child(item_link(item, &attachments.key, false))
+                .child(item_link(item, &attachments.key, idb))
+                .apply_if(is_viewable(&item.file_name), |dom| dom.child(item_open(item, &attachments.key, idb)))
+                .apply_if(idb > 0 && is_pgp_key(&item.file_name), |dom| dom.child(item_pgp_import(item, idb)))
             })
         }))
+        .apply_if(idb > 0 && count > 1, |dom| dom.child(html!("a", {
+            .class(css_class("zip"))
+            .attr("href", &format!("/{ROOT_API}/{API_FILE}/{idb}/{API_FILE_ZIP}"))
+            .attr("download", &format!("attachments-{idb}.zip"))
+            .text(&format!("скачать все ({count}) zip"))
+        })))
     })
 }
 
-fn item_link(row: &BoxMailAttachmentItem, key: &str, is_temp: bool) -> Dom {
+// вложения письма запрашиваются по номеру письма, вложения черновика (idb == 0) -- по ключу
+fn item_href(row: &BoxMailAttachmentItem, key: &str, idb: u64) -> String {
+    let id = &row.id;
+    if idb > 0 {
+        return format!("/{ROOT_API}/{API_FILE}/{idb}/{id}");
+    }
     let params: String = if let Ok(params) = UrlSearchParams::new() {
-        //params.append("user", user_key);
         params.append("filename", &row.file_name);
-        params.append("email", &get_user_box());
-        if is_temp {
-            params.append("temp", "1");
-        }
         params.to_string().into()
     } else { "".to_string() };
+    format!("/{ROOT_API}/{API_FILE}/{API_FILE_TEMP}/{key}/{id}?{params}")
+}
 
-    let id = &row.id;
-    let filename = format!("{key}-{id}");
-    let href = format!("/{ROOT_API}/{API_FILE}/{filename}?{params}");
+// адрес картинки, вставленной в текст письма
+pub fn inline_image_src(row: &BoxMailAttachmentItem, key: &str, idb: u64) -> String {
+    let href = item_href(row, key, idb);
+    let separator = if href.contains('?') { '&' } else { '?' };
+    format!("{href}{separator}inline=1")
+}
+
+// миниатюру строит сервер; картинки, которые он не разбирает, он отдает как есть
+fn thumb_src(row: &BoxMailAttachmentItem, key: &str, idb: u64) -> String {
+    let href = item_href(row, key, idb);
+    match href.split_once('?') {
+        Some((path, params)) => format!("{path}/{API_FILE_THUMB}?{params}"),
+        None => format!("{href}/{API_FILE_THUMB}")
+    }
+}
+
+// то, что браузер покажет сам; остальное сервер все равно отдаст как вложение
+fn is_viewable(file_name: &str) -> bool {
+    let ext = file_name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+    VIEWABLE.contains(&ext.as_str())
+}
+
+// подпись и зашифрованная часть PGP/MIME -- тоже .asc, но не ключи
+fn is_pgp_key(file_name: &str) -> bool {
+    let file_name = file_name.to_lowercase();
+    let ext = file_name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+    PGP_KEY.contains(&ext) && file_name != "signature.asc" && file_name != "encrypted.asc"
+}
+
+// ключ отправителя во вложении -- в свою связку
+fn item_pgp_import(row: &BoxMailAttachmentItem, idb: u64) -> Dom {
+    let id = row.id;
+    html!(TAG_SPAN, {
+        .class(css_class("open"))
+        .text("импортировать ключ")
+        .event(move |_: events::Click| {
+            connect_json_data(API_PGP, PgpRequest { idb: Some(idb), id: Some(id), ..PgpRequest::default() }, pgp_save_result);
+        })
+    })
+}
 
+fn item_open(row: &BoxMailAttachmentItem, key: &str, idb: u64) -> Dom {
     html!("a", {
-        .attr_signal("href", USER_KEY.signal_cloned().map(move|key|format!("{href}&user={key}")))
+        .class(css_class("open"))
+        .attr("href", &inline_image_src(row, key, idb))
+        .attr("target", "_blank")
+        .attr("rel", "noopener")
+        .text("открыть")
+    })
+}
+
+fn item_link(row: &BoxMailAttachmentItem, key: &str, idb: u64) -> Dom {
+    let href = item_href(row, key, idb);
+
+    html!("a", {
+        .attr("href", &href)
         .attr("download", &row.file_name)
         .attr(PROP_TITLE, &row.file_name)
         .children([
@@ -71,7 +150,7 @@ pub fn attachments_active(attachments_signal: MutableSignalCloned<Option<BoxMail
                     Some(attachments)=>{
                         Some(html!(TAG_DIV, {
                             .class(css_class("container-active"))
-                            .children(attachments.list.iter().map(|item|item_active(item, &attachments.key)))
+                            .children(attachments.list.iter().filter(|item| item.cid.is_none()).map(|item|item_active(item, &attachments.key)))
                         }))
                     }
                     None=>None
@@ -85,7 +164,7 @@ fn item_active(row: &BoxMailAttachmentItem, key: &str) -> Dom {
     html!(TAG_DIV, {
         .class(css_class("item"))
         .children([
-            item_link(row, key, true),
+            item_link(row, key, 0),
             html!(TAG_SPAN, {
                 .class(css_class("item-icon"))
                 .attr(attr_data(ATTR_ID), &row.id.to_string())
@@ -96,6 +175,86 @@ fn item_active(row: &BoxMailAttachmentItem, key: &str) -> Dom {
     })
 }
 
+// файлы, которые еще загружаются: ход загрузки, пауза и отмена
+pub fn uploads_active() -> Dom {
+    html!(TAG_DIV, {
+        .class(css_class("container-active"))
+        .children_signal_vec(UPLOADS.signal_vec_cloned().map(|item| item_upload(&item)))
+    })
+}
+
+fn item_upload(item: &Arc<UploadItem>) -> Dom {
+    let item_toggle = item.clone();
+    let item_cancel = item.clone();
+    let size = item.size.max(1.0);
+    html!(TAG_DIV, {
+        .class(css_class("item"))
+        .class(css_class("upload"))
+        .style_signal("background", item.loaded.signal().map(move |loaded| {
+            let percent = (loaded / size * 100.0).round();
+            format!("linear-gradient(to right, #e3f0ff {percent}%, transparent {percent}%)")
+        }))
+        .children([
+            html!(TAG_SPAN, {
+                .class(css_class("filename"))
+                .attr(PROP_TITLE, &item.file_name)
+                .text(&item.file_name)
+            }),
+            html!(TAG_SPAN, {
+                .class(css_class("upload-status"))
+                .text_signal(map_ref! {
+                    let loaded = item.loaded.signal(),
+                    let error = item.error.signal_cloned() => move {
+                        match error {
+                            Some(error) => format!(" {error}"),
+                            None => format!(" {}%", (loaded / size * 100.0).floor())
+                        }
+                    }
+                })
+            }),
+            html!(TAG_SPAN, {
+                .class(css_class("open"))
+                .visible_signal(item.state.signal().map(|state| state == UploadState::Running || state == UploadState::Paused))
+                .text_signal(item.state.signal().map(|state| if state == UploadState::Paused { "продолжить" } else { "пауза" }))
+                .event(move |_: events::Click| upload_toggle(&item_toggle))
+            }),
+            html!(TAG_SPAN, {
+                .class(css_class("item-icon"))
+                .attr(PROP_TITLE, "отменить")
+                .child(icon_remove())
+                .event(move |_: events::Click| upload_cancel(&item_cancel))
+            })
+        ])
+    })
+}
+
+pub fn attachment_forwarded(forward_idb: Mutable<Option<u64>>, file_name: String) -> Dom {
+    let forward_remove = forward_idb.clone();
+    html!(TAG_DIV, {
+        .child_signal(forward_idb.signal().map(move|idb| idb.map(|_| {
+            let forward_remove = forward_remove.clone();
+            html!(TAG_DIV, {
+                .class(css_class("container-active"))
+                .child(html!(TAG_DIV, {
+                    .class(css_class("item"))
+                    .attr(PROP_TITLE, "исходное письмо во вложении")
+                    .children([
+                        html!(TAG_SPAN, {
+                            .class(css_class("filename"))
+                            .text(&file_name)
+                        }),
+                        html!(TAG_SPAN, {
+                            .class(css_class("item-icon"))
+                            .child(icon_remove())
+                            .event(move|_: events::Click| forward_remove.set(None))
+                        })
+                    ])
+                }))
+            })
+        })))
+    })
+}
+
 fn handle_remove(e: events::Click) {
     let id = from_dataset(e.target(), ATTR_ID).parse::<usize>().unwrap_or_default();
     if let Some(editor) = get_editor() {
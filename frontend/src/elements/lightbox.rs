@@ -0,0 +1,139 @@
+use std::rc::Rc;
+
+use dominator::{Dom, events, html};
+use futures_signals::signal::{Mutable, SignalExt};
+
+use crate::constants::{PROP_TITLE, TAG_DIV, TAG_SPAN};
+use crate::elements::icons::icon_remove;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewKind {
+    Image,
+    Text,
+    Pdf,
+}
+
+#[derive(Debug, Clone)]
+pub struct PreviewItem {
+    pub file_name: String,
+    pub src: String,
+    pub thumb: String,
+    pub kind: PreviewKind,
+}
+
+fn css_class(label: &str) -> String {
+    format!("lightbox__{label}")
+}
+
+// то, что браузер показывает сам; svg не показываем -- сервер отдает его только как вложение
+pub fn preview_kind(file_name: &str) -> Option<PreviewKind> {
+    let ext = file_name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+    match ext.as_str() {
+        "png" | "jpg" | "jpeg" | "gif" | "webp" => Some(PreviewKind::Image),
+        "txt" => Some(PreviewKind::Text),
+        "pdf" => Some(PreviewKind::Pdf),
+        _ => None
+    }
+}
+
+// миниатюры вложений; по щелчку файл открывается поверх письма
+pub fn preview_strip(items: Vec<PreviewItem>) -> Dom {
+    let items = Rc::new(items);
+    let current = Mutable::new(None::<usize>);
+    let items_lightbox = items.clone();
+    let current_lightbox = current.clone();
+    html!(TAG_DIV, {
+        .class(css_class("strip"))
+        .children(items.iter().enumerate().map(|(ind, item)| thumbnail(item, ind, current.clone())))
+        .child_signal(current.signal().map(move |ind| {
+            ind.map(|ind| lightbox(items_lightbox.clone(), ind, current_lightbox.clone()))
+        }))
+    })
+}
+
+fn thumbnail(item: &PreviewItem, ind: usize, current: Mutable<Option<usize>>) -> Dom {
+    html!(TAG_DIV, {
+        .class(css_class("thumbnail"))
+        .attr(PROP_TITLE, &item.file_name)
+        .child(match item.kind {
+            PreviewKind::Image => html!("img", {
+                .attr("src", &item.thumb)
+                .attr("alt", &item.file_name)
+                .attr("loading", "lazy")
+            }),
+            _ => html!(TAG_SPAN, {
+                .class(css_class("ext"))
+                .text(&item.file_name.rsplit_once('.').map(|(_, ext)| ext.to_uppercase()).unwrap_or_default())
+            })
+        })
+        .event(move |_: events::Click| current.set(Some(ind)))
+    })
+}
+
+fn lightbox(items: Rc<Vec<PreviewItem>>, ind: usize, current: Mutable<Option<usize>>) -> Dom {
+    let count = items.len();
+    let item = &items[ind];
+    let current_close = current.clone();
+    let current_back = current.clone();
+    let current_prev = current.clone();
+    let current_next = current.clone();
+    html!(TAG_DIV, {
+        .class(css_class("back"))
+        .event(move |_: events::Click| current_back.set(None))
+        .global_event(move |e: events::KeyDown| {
+            match e.key().as_str() {
+                "Escape" => current.set(None),
+                "ArrowLeft" => current.set(Some((ind + count - 1) % count)),
+                "ArrowRight" => current.set(Some((ind + 1) % count)),
+                _ => {}
+            }
+        })
+        .child(html!(TAG_DIV, {
+            .class(css_class("container"))
+            .event(|e: events::Click| e.stop_propagation())
+            .children([
+                html!(TAG_DIV, {
+                    .class(css_class("header"))
+                    .children([
+                        html!(TAG_SPAN, {
+                            .class(css_class("title"))
+                            .text(&format!("{} ({}/{count})", item.file_name, ind + 1))
+                        }),
+                        html!(TAG_SPAN, {
+                            .class(css_class("button"))
+                            .attr(PROP_TITLE, "закрыть")
+                            .child(icon_remove())
+                            .event(move |_: events::Click| current_close.set(None))
+                        }),
+                    ])
+                }),
+                match item.kind {
+                    PreviewKind::Image => html!("img", {
+                        .class(css_class("image"))
+                        .attr("src", &item.src)
+                        .attr("alt", &item.file_name)
+                    }),
+                    _ => html!("iframe", {
+                        .class(css_class("frame"))
+                        .attr("src", &item.src)
+                        .attr(PROP_TITLE, &item.file_name)
+                    })
+                },
+            ])
+            .apply_if(count > 1, |dom| dom.children([
+                html!(TAG_SPAN, {
+                    .class(css_class("prev"))
+                    .attr(PROP_TITLE, "предыдущий")
+                    .text("‹")
+                    .event(move |_: events::Click| current_prev.set(Some((ind + count - 1) % count)))
+                }),
+                html!(TAG_SPAN, {
+                    .class(css_class("next"))
+                    .attr(PROP_TITLE, "следующий")
+                    .text("›")
+                    .event(move |_: events::Click| current_next.set(Some((ind + 1) % count)))
+                }),
+            ]))
+        }))
+    })
+}
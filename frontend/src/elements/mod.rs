@@ -5,3 +5,5 @@ pub mod app_login;
 pub mod app_message;
 pub mod attachment;
 mod icons;
+mod lightbox;
+pub mod app_settings;
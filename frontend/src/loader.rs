@@ -3,32 +3,64 @@ use std::fmt::Debug;
 use futures_signals::signal::Mutable;
 use serde::Serialize;
 
-use shared::constants::{API_NOTES, CHANNEL_MESSAGE, CHANNEL_MESSAGES};
-use shared::types::{MessageRequest, MessagesRequest, NotesChannel};
+use shared::constants::{API_NOTES, API_SETTINGS, CHANNEL_MESSAGE, CHANNEL_MESSAGES};
+use shared::types::{MessageRequest, MessagesRequest, NotesChannel, SettingsRequest};
+use shared::utils::box_type_from_index;
 
 use crate::connect_fetch::connect_json_send;
-use crate::editor::app_editor::editor_version;
+use crate::editor::app_editor::{editor_version, get_editor};
 use crate::elements::app_login::get_user_box;
 use crate::notes::notes_events::events_reload;
-use crate::state::{NOTES, USER, USER_KEY};
-use crate::types::{InitialStruct, NoteStruct, UserKey};
+use crate::state::{CURRENT_BOX, NOTES, TIMEZONE, USER, CHANNEL_ID};
+use crate::types::{IdentitySource, InitialStruct, NoteStruct, SettingsResponse, ChannelId, UserSource};
 
 pub fn init_channel(data: InitialStruct) {
+    let first = NOTES.lock_ref().len() == 0;
+    set_user(&data.user, &data.identities);
     if let Ok(mut user) = USER.lock() {
-        user.signature = data.user.signature.clone();
-        user.label = data.user.prefix.clone();
         user.email = get_user_box();
     }
+    if first {
+        // открываем ящик по умолчанию только при первой загрузке
+        if let Some(mb) = box_type_from_index(data.user.default_box as usize) {
+            CURRENT_BOX.set_neq(mb);
+        }
+    }
     if NOTES.lock_mut().len() == 0 {
         NOTES.lock_mut().extend(data.notes.iter().map(|item| NoteStruct::from(item.clone())));
         events_reload();
     }
 }
 
+fn set_user(data: &UserSource, identities: &[IdentitySource]) {
+    if let Ok(mut user) = USER.lock() {
+        user.signature = data.signature.clone();
+        user.label = data.prefix.clone();
+        user.identities = identities.to_vec();
+        user.settings = data.clone();
+    }
+    TIMEZONE.set_neq(data.timezone.clone());
+}
+
+// ===
+
+pub fn settings_update(data: SettingsRequest) {
+    connect_json_send(API_SETTINGS, data);
+}
+
+pub fn settings_channel(data: SettingsResponse) {
+    set_user(&data.user, &data.identities);
+    if let Some(editor) = get_editor() {
+        if editor.is_settings {
+            editor_version();
+        }
+    }
+}
+
 // ===
 
-pub fn user_channel(data: UserKey) {
-    USER_KEY.set_neq(data);
+pub fn channel_id_channel(data: ChannelId) {
+    CHANNEL_ID.set_neq(data);
 }
 
 // ===
@@ -301,7 +301,7 @@ fn handle_create_email(_: events::Click) {
         }
     }
 
-    open_email_editor(0, view_email(&label, &email), subject, content.join(""));
+    open_email_editor(0, view_email(&label, &email), subject, content.join(""), "");
 }
 
 // ===
@@ -14,7 +14,7 @@ pub static CURRENT_BOX: Lazy<Mutable<MailBoxes>> = Lazy::new(|| {
 });
 
 pub static BOX_STATE: Lazy<Vec<BoxState>> = Lazy::new(|| {
-    vec![BoxState::default(), BoxState::default(), BoxState::default(), BoxState::default(), BoxState::default()]
+    vec![BoxState::default(), BoxState::default(), BoxState::default(), BoxState::default(), BoxState::default(), BoxState::default()]
 });
 
 pub static NOTES: Lazy<MutableVec<NoteStruct>> = Lazy::new(|| {
@@ -25,10 +25,17 @@ pub static EVENTS: Lazy<Mutable<Vec<EventItemStruct>>> = Lazy::new(|| {
     Mutable::new(vec![])
 });
 
+// запрос последнего поиска, для следующих страниц
+pub static SEARCH_QUERY: Lazy<Mutable<String>> = Lazy::new(|| Mutable::new("".to_string()));
+
 pub static LOADING_NEXT: Lazy<Mutable<bool>> = Lazy::new(|| Mutable::new(false));
 
 pub static NOTES_SELECTED: Lazy<Mutable<i32>> = Lazy::new(|| Mutable::new(0));
 
-pub static USER_KEY: Lazy<Mutable<String>> = Lazy::new(|| Mutable::new("".to_string()));
+// канал sse этой вкладки, передается в заголовке запросов
+pub static CHANNEL_ID: Lazy<Mutable<String>> = Lazy::new(|| Mutable::new("".to_string()));
+
+// часовой пояс из настроек; пустой -- даты как есть (UTC)
+pub static TIMEZONE: Lazy<Mutable<String>> = Lazy::new(|| Mutable::new("".to_string()));
 
 pub static USER: Lazy<Arc<Mutex<UserStruct>>> = Lazy::new(|| Arc::new(Mutex::new(UserStruct::default())));
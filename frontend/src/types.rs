@@ -18,21 +18,59 @@ pub struct NotesSource {
 pub struct UserSource {
     pub prefix: String,
     pub signature: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub by_page: i32,
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub default_box: i32,
+    #[serde(default)]
+    pub smime: bool,
+    // есть свой ключ OpenPGP
+    #[serde(default)]
+    pub pgp: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct IdentitySource {
+    pub idi: i32,
+    pub email: String,
+    pub name: String,
+    pub signature: String,
+    pub reply_to: Option<String>,
+}
+
+impl IdentitySource {
+    pub fn mailbox(&self) -> String {
+        if self.name.is_empty() { self.email.clone() } else { format!("{} <{}>", self.name, self.email) }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct InitialStruct {
     pub notes: Vec<NotesSource>,
     pub user: UserSource,
+    #[serde(default)]
+    pub identities: Vec<IdentitySource>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SettingsResponse {
+    pub user: UserSource,
+    pub identities: Vec<IdentitySource>,
 }
 
-pub type UserKey = String;
+pub type ChannelId = String;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct UserStruct {
     pub label: String,
     pub email: String,
     pub signature: String,
+    pub identities: Vec<IdentitySource>,
+    pub settings: UserSource,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -96,6 +134,8 @@ pub struct MessagesResponse {
 pub struct BoxMessageSource {
     pub idb: u64,
     pub date: String,
+    #[serde(default)]
+    pub timestamp: i64,
     pub order: u64,
     pub unread: bool,
     pub sender: BoxMailAddress,
@@ -103,12 +143,17 @@ pub struct BoxMessageSource {
     pub subject: String,
     pub content: String,
     pub attachments: Option<BoxMailAttachments>,
+    #[serde(default)]
+    pub source: bool,
+    #[serde(default)]
+    pub security: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct BoxMessage {
     pub idb: u64,
     pub date: String,
+    pub timestamp: i64,
     pub order: u64,
     pub unread: Mutable<bool>,
     pub sender: BoxMailAddress,
@@ -116,6 +161,8 @@ pub struct BoxMessage {
     pub subject: String,
     pub content: String,
     pub attachments: Option<BoxMailAttachments>,
+    pub source: bool,
+    pub security: Option<String>,
 }
 
 impl From<BoxMessageSource> for BoxMessage {
@@ -123,6 +170,7 @@ impl From<BoxMessageSource> for BoxMessage {
         Self {
             idb: src.idb,
             date: src.date,
+            timestamp: src.timestamp,
             order: src.order,
             unread: Mutable::new(src.unread),
             sender: src.sender,
@@ -130,6 +178,8 @@ impl From<BoxMessageSource> for BoxMessage {
             subject: src.subject,
             content: src.content,
             attachments: src.attachments,
+            source: src.source,
+            security: src.security,
         }
     }
 }
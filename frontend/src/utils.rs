@@ -1,5 +1,6 @@
 use wasm_bindgen::{JsCast, JsValue, UnwrapThrowExt};
-use web_sys::{Document, Element, EventTarget, HtmlDocument, HtmlElement, HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement, Location, Node, Selection, Window};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Document, Element, EventTarget, File, HtmlDocument, HtmlElement, HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement, Location, Node, Selection, Window};
 
 fn get_window() -> Option<Window> {
     web_sys::window()
@@ -51,6 +52,27 @@ pub fn get_input_value(name: &str) -> String {
     get_value_by_query(&format!("[name={name}]"))
 }
 
+pub fn get_input_checked(name: &str) -> bool {
+    query_selector(&format!("[name={name}]"))
+        .and_then(|element| element.dyn_into::<HtmlInputElement>().ok())
+        .map(|element| element.checked())
+        .unwrap_or_default()
+}
+
+pub fn get_input_file(name: &str) -> Option<File> {
+    query_selector(&format!("[name={name}]"))
+        .and_then(|element| element.dyn_into::<HtmlInputElement>().ok())
+        .and_then(|element| element.files())
+        .and_then(|files| files.item(0))
+}
+
+// содержимое файла в base64 для отправки в json
+pub async fn file_to_base64(file: &File) -> Option<String> {
+    let buffer = JsFuture::from(file.array_buffer()).await.ok()?;
+    let binary: String = js_sys::Uint8Array::new(&buffer).to_vec().into_iter().map(char::from).collect();
+    get_window().and_then(|w| w.btoa(&binary).ok())
+}
+
 pub fn query_selector(selectors: &str) -> Option<Element> {
     get_document().and_then(|d| d.query_selector(selectors).ok()).and_then(|e|e)
 }
@@ -84,6 +106,23 @@ pub fn exec_command_full(command_id: &str, show_ui: bool, value: &str) -> bool {
     get_html_document().and_then(|d|d.exec_command_with_show_ui_and_value(command_id, show_ui, value).ok()).unwrap_or_default()
 }
 
+// дата письма в часовом поясе пользователя; неизвестный браузеру пояс -- исходная строка
+pub fn view_date(date: &str, timestamp: i64, timezone: &str) -> String {
+    if timezone.is_empty() || timestamp == 0 {
+        return date.to_string();
+    }
+    let value = js_sys::Date::new(&JsValue::from_f64(timestamp as f64 * 1000.0));
+    let options = js_sys::Object::new();
+    js_sys::Reflect::set(&options, &JsValue::from_str("timeZone"), &JsValue::from_str(timezone)).ok();
+    let args = js_sys::Array::of2(&JsValue::from_str("ru-RU"), &options);
+    js_sys::Reflect::get(&value, &JsValue::from_str("toLocaleString")).ok()
+        .and_then(|func| func.dyn_into::<js_sys::Function>().ok())
+        .and_then(|func| js_sys::Reflect::apply(&func, &value, &args).ok())
+        .and_then(|text| text.as_string())
+        .map(|text| text.replace(',', ""))
+        .unwrap_or_else(|| date.to_string())
+}
+
 pub fn attr_data(key: &str) -> String {
     format!("data-{key}")
 }
@@ -110,6 +149,12 @@ pub fn location_reload() {
     }
 }
 
+pub fn window_open(url: &str) {
+    if let Some(w) = get_window() {
+        w.open_with_url_and_target(url, "_blank").ok();
+    }
+}
+
 pub fn node_parent(node: Node, node_name: &str) -> Option<Node> {
     let node_name = node_name.to_uppercase();
     let mut node = Some(node);
@@ -131,6 +176,18 @@ pub fn drop_element(elem: Element) {
     };
 }
 
+// адреса из строки вида "Имя <a@b.ru>, c@d.ru", в нижнем регистре
+pub fn email_addresses(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|item| match (item.rfind('<'), item.rfind('>')) {
+            (Some(start), Some(end)) if start < end => &item[start + 1..end],
+            _ => item
+        })
+        .map(|address| address.trim().to_lowercase())
+        .filter(|address| address.contains('@'))
+        .collect()
+}
+
 pub fn view_email(label: &str, email: &str) -> String {
     let email = if email.contains('@') { email } else { "" };
     if email.is_empty() {
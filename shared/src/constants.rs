@@ -5,18 +5,39 @@ pub const BY_PAGE: i64 = 30;
 pub const ROOT_API: &str = "api";
 pub const API_NOTES: &str = "notes";
 pub const API_FILE: &str = "file";
+pub const API_FILE_TEMP: &str = "temp";
+pub const API_FILE_ZIP: &str = "zip";
+pub const API_FILE_THUMB: &str = "thumb";
 pub const API_FILES: &str = "files";
+pub const API_SOURCE: &str = "source";
 pub const API_LOGIN: &str = "login";
 pub const API_EVENT: &str = "event";
+pub const API_SETTINGS: &str = "settings";
+pub const API_PASSWORD: &str = "password";
+pub const API_SESSIONS: &str = "sessions";
+pub const API_LOGINS: &str = "logins";
+pub const API_TOTP: &str = "totp";
+pub const API_SMIME: &str = "smime";
+pub const API_PGP: &str = "pgp";
+pub const API_SEARCH: &str = "search";
+pub const API_UPLOADS: &str = "uploads";
+
+// размер вложений: одного файла и всех файлов одной загрузки
+pub const UPLOAD_FILE_MAX: u64 = 25 * 1024 * 1024;
+pub const UPLOAD_REQUEST_MAX: u64 = 50 * 1024 * 1024;
+pub const UPLOAD_CHUNK_MAX: u64 = 8 * 1024 * 1024;
 
 pub const CHANNEL_NOTES: &str = "notes";
 pub const CHANNEL_BOXES: &str = "boxes";
 pub const CHANNEL_MESSAGES: &str = "msg-list";
 pub const CHANNEL_MESSAGE: &str = "msg-update";
 pub const CHANNEL_INIT: &str = "init";
-pub const CHANNEL_USER_KEY: &str = "user";
+pub const CHANNEL_ID: &str = "channel";
+pub const CHANNEL_SETTINGS: &str = "settings";
 
-pub const HEADER_USER_KEY: &str = "User-Key";
+pub const HEADER_CHANNEL: &str = "Channel-Id";
+pub const HEADER_UPLOAD_OFFSET: &str = "Upload-Offset";
+pub const HEADER_UPLOAD_LENGTH: &str = "Upload-Length";
 
 #[cfg(target_os = "macos")]
 pub const TEST_USER_ID: i32 = 1;
@@ -7,6 +7,8 @@ pub enum MailBoxes {
     Sent,
     Trash,
     Notes,
+    // результаты поиска, в базе такого ящика нет
+    Search,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -47,6 +49,94 @@ pub struct MessagesRequest {
     pub page: usize
 }
 
+// слова и фильтры from:, to:, has:attachment, before:, after:, in:
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SearchRequest {
+    pub query: String,
+    pub page: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SettingsRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_page: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_box: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PasswordRequest {
+    pub current: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SessionsRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logout: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logout_others: Option<bool>,
+}
+
+// пустой запрос -- только состояние; enroll -- новый секрет, confirm/disable -- с кодом из приложения
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TotpRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enroll: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<String>,
+}
+
+// пустой запрос -- только состояние; pkcs12 (base64) с паролем -- новый сертификат вместо прежнего
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SmimeRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs12: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove: Option<bool>,
+}
+
+// пустой запрос -- только список ключей; key (файл в base64) с паролем закрытого ключа,
+// вложение письма (idb, id) -- только открытые ключи; remove -- отпечаток удаляемого ключа
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PgpRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove: Option<String>,
+}
+
+// возобновляемая загрузка: создание и перенос готового файла во вложения черновика
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct UploadCreateRequest {
+    pub file_name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct UploadFinishRequest {
+    pub key: String,
+    // наибольший номер вложения, известный редактору
+    pub after: usize,
+    pub inline: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct MessageRequest {
     pub idb: u64,
@@ -60,6 +150,13 @@ pub struct MessageRequest {
     pub content: Option<String>,
     pub subject: Option<String>,
     pub recipient: Option<String>,
+    pub forward_idb: Option<u64>,
+    pub identity: Option<i32>,
+    pub smime_sign: Option<bool>,
+    pub pgp_sign: Option<bool>,
+    pub pgp_encrypt: Option<bool>,
+    // причина, по которой письмо не отправлено
+    pub send_error: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -74,4 +171,7 @@ pub struct BoxMailAttachmentItem {
     #[serde(rename = "fileName")]
     pub file_name: String,
     pub size: u64,
+    // Content-ID картинки, вставленной в текст письма
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
 }
\ No newline at end of file
@@ -7,5 +7,17 @@ pub fn box_type_index(mb: &MailBoxes) -> usize {
         MailBoxes::Sent => 2,
         MailBoxes::Trash => 3,
         MailBoxes::Notes => 4,
+        MailBoxes::Search => 5,
+    }
+}
+
+pub fn box_type_from_index(ind: usize) -> Option<MailBoxes> {
+    match ind {
+        0 => Some(MailBoxes::Inbox),
+        1 => Some(MailBoxes::Ready),
+        2 => Some(MailBoxes::Sent),
+        3 => Some(MailBoxes::Trash),
+        4 => Some(MailBoxes::Notes),
+        _ => None
     }
 }
\ No newline at end of file